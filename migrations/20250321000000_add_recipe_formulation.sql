@@ -0,0 +1,16 @@
+-- Per-ingredient quantities and units for recipe formulations
+ALTER TABLE recipe_ingredients ADD COLUMN quantity NUMERIC(14, 4);
+ALTER TABLE recipe_ingredients ADD COLUMN unit VARCHAR(20);
+
+-- Declared batch yield for a recipe
+ALTER TABLE recipes ADD COLUMN yield_quantity NUMERIC(14, 4);
+ALTER TABLE recipes ADD COLUMN yield_unit VARCHAR(20);
+
+-- Ordered process steps for a recipe
+CREATE TABLE IF NOT EXISTS recipe_steps (
+    id SERIAL PRIMARY KEY,
+    recipe_id INTEGER NOT NULL REFERENCES recipes(id) ON DELETE CASCADE,
+    step_number INTEGER NOT NULL,
+    instruction TEXT NOT NULL,
+    UNIQUE (recipe_id, step_number)
+);
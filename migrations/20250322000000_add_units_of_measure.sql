@@ -0,0 +1,43 @@
+-- Ingredient density in grams per milliliter, used for mass/volume conversion
+ALTER TABLE ingredients ADD COLUMN density NUMERIC(10, 4);
+
+-- Batch ingredient amounts become decimal values with a unit
+ALTER TABLE batch_ingredients ALTER COLUMN amount TYPE NUMERIC(14, 4);
+ALTER TABLE batch_ingredients ADD COLUMN unit VARCHAR(20);
+
+-- Split the free-text batch amount (e.g. "20 lbs") into a value and unit
+ALTER TABLE batches RENAME COLUMN amount_made TO amount_made_legacy;
+ALTER TABLE batches ALTER COLUMN amount_made_legacy DROP NOT NULL;
+ALTER TABLE batches ADD COLUMN amount_made NUMERIC(14, 4);
+ALTER TABLE batches ADD COLUMN amount_made_unit VARCHAR(20);
+
+UPDATE batches
+SET amount_made = substring(amount_made_legacy from '^\s*([0-9]+(?:\.[0-9]+)?)')::NUMERIC,
+    amount_made_unit = CASE lower(trim(regexp_replace(amount_made_legacy, '^\s*[0-9]+(\.[0-9]+)?\s*', '')))
+        WHEN 'mg' THEN 'mg'
+        WHEN 'g' THEN 'g'
+        WHEN 'grams' THEN 'g'
+        WHEN 'kg' THEN 'kg'
+        WHEN 'kgs' THEN 'kg'
+        WHEN 'oz' THEN 'oz'
+        WHEN 'lb' THEN 'lb'
+        WHEN 'lbs' THEN 'lb'
+        WHEN 'pounds' THEN 'lb'
+        WHEN 'ml' THEN 'ml'
+        WHEN 'l' THEN 'l'
+        WHEN 'liters' THEN 'l'
+        WHEN 'gal' THEN 'gal'
+        WHEN 'gallons' THEN 'gal'
+        WHEN 'each' THEN 'each'
+        WHEN 'ea' THEN 'each'
+        WHEN 'units' THEN 'each'
+        WHEN 'dozen' THEN 'dozen'
+    END
+WHERE amount_made_legacy ~ '^\s*[0-9]+(\.[0-9]+)?';
+
+-- Rows whose unit could not be recognised keep only the legacy text
+UPDATE batches SET amount_made = NULL WHERE amount_made_unit IS NULL;
+
+-- Received quantity for receiving log entries
+ALTER TABLE receiving_log ADD COLUMN quantity NUMERIC(14, 4);
+ALTER TABLE receiving_log ADD COLUMN quantity_unit VARCHAR(20);
@@ -0,0 +1,55 @@
+-- Immutable snapshots of a recipe's formulation, one per edit
+CREATE TABLE IF NOT EXISTS recipe_versions (
+    id SERIAL PRIMARY KEY,
+    recipe_id INTEGER NOT NULL REFERENCES recipes(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    name VARCHAR NOT NULL,
+    description TEXT,
+    yield_quantity NUMERIC(14, 4),
+    yield_unit VARCHAR(20),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (recipe_id, version)
+);
+
+CREATE TABLE IF NOT EXISTS recipe_version_ingredients (
+    version_id INTEGER REFERENCES recipe_versions(id) ON DELETE CASCADE,
+    ingredient_id INTEGER NOT NULL,
+    quantity NUMERIC(14, 4),
+    unit VARCHAR(20),
+    PRIMARY KEY (version_id, ingredient_id)
+);
+
+CREATE TABLE IF NOT EXISTS recipe_version_steps (
+    version_id INTEGER REFERENCES recipe_versions(id) ON DELETE CASCADE,
+    step_number INTEGER NOT NULL,
+    instruction TEXT NOT NULL,
+    PRIMARY KEY (version_id, step_number)
+);
+
+ALTER TABLE recipes ADD COLUMN current_version INTEGER NOT NULL DEFAULT 0;
+
+-- Batches pin the recipe version they were made with
+ALTER TABLE batches ADD COLUMN recipe_version_id INTEGER REFERENCES recipe_versions(id);
+
+-- Existing recipes become version 1 of themselves
+INSERT INTO recipe_versions (recipe_id, version, name, description, yield_quantity, yield_unit)
+SELECT id, 1, name, description, yield_quantity, yield_unit FROM recipes;
+
+INSERT INTO recipe_version_ingredients (version_id, ingredient_id, quantity, unit)
+SELECT rv.id, ri.ingredient_id, ri.quantity, ri.unit
+FROM recipe_ingredients ri
+JOIN recipe_versions rv ON rv.recipe_id = ri.recipe_id AND rv.version = 1;
+
+INSERT INTO recipe_version_steps (version_id, step_number, instruction)
+SELECT rv.id, rs.step_number, rs.instruction
+FROM recipe_steps rs
+JOIN recipe_versions rv ON rv.recipe_id = rs.recipe_id AND rv.version = 1;
+
+UPDATE recipes SET current_version = 1;
+
+-- Existing batches are pinned to the first version of the recipe with the same lot code
+UPDATE batches b
+SET recipe_version_id = rv.id
+FROM recipes r
+JOIN recipe_versions rv ON rv.recipe_id = r.id AND rv.version = 1
+WHERE r.lotcode = b.recipe_lotcode AND r.org_id = b.org_id;
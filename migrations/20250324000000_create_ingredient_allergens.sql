@@ -0,0 +1,8 @@
+-- Allergens declared on each ingredient lot, either as an ingredient
+-- ("contains") or as a cross-contact risk ("may_contain")
+CREATE TABLE IF NOT EXISTS ingredient_allergens (
+    ingredient_id INTEGER REFERENCES ingredients(id) ON DELETE CASCADE,
+    allergen VARCHAR(100) NOT NULL,
+    presence VARCHAR(20) NOT NULL DEFAULT 'contains' CHECK (presence IN ('contains', 'may_contain')),
+    PRIMARY KEY (ingredient_id, allergen)
+);
@@ -0,0 +1,22 @@
+-- Production line each batch was made on
+ALTER TABLE batches ADD COLUMN line VARCHAR(100);
+
+-- Cleaning and verification records for allergen changeovers between
+-- sequential batches on the same line
+CREATE TABLE IF NOT EXISTS allergen_changeovers (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE,
+    line VARCHAR(100) NOT NULL,
+    from_batch_id INTEGER NOT NULL REFERENCES batches(id) ON DELETE CASCADE,
+    -- Set when the next batch on the line is created
+    to_batch_id INTEGER REFERENCES batches(id) ON DELETE SET NULL,
+    cleaning_steps TEXT[] NOT NULL DEFAULT '{}',
+    verification_method VARCHAR(20) NOT NULL CHECK (verification_method IN ('visual', 'atp', 'allergen_swab')),
+    result VARCHAR(10) NOT NULL CHECK (result IN ('pass', 'fail')),
+    employee_id INTEGER NOT NULL REFERENCES employees(id),
+    performed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    notes TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_batches_org_line ON batches (org_id, line);
+CREATE INDEX IF NOT EXISTS idx_allergen_changeovers_from_batch ON allergen_changeovers (from_batch_id);
@@ -0,0 +1,20 @@
+-- Unit that on-hand stock of an ingredient lot is kept in. Lots without one
+-- have never been received or adjusted and are not stock-tracked.
+ALTER TABLE ingredients ADD COLUMN inventory_unit VARCHAR(20);
+
+-- Inventory ledger. Quantities are signed and in the lot's inventory unit;
+-- on-hand is the sum of a lot's transactions.
+CREATE TABLE IF NOT EXISTS inventory_transactions (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE,
+    ingredient_id INTEGER NOT NULL REFERENCES ingredients(id) ON DELETE CASCADE,
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('receipt', 'consumption', 'adjustment', 'waste')),
+    quantity NUMERIC(14, 4) NOT NULL,
+    unit VARCHAR(20) NOT NULL,
+    reason TEXT,
+    batch_id INTEGER REFERENCES batches(id) ON DELETE CASCADE,
+    receiving_log_id INTEGER REFERENCES receiving_log(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_inventory_transactions_ingredient ON inventory_transactions (ingredient_id);
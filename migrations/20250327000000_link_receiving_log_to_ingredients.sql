@@ -0,0 +1,14 @@
+-- Receiving records reference the ingredient lot they brought in
+ALTER TABLE receiving_log ADD COLUMN ingredient_id INTEGER REFERENCES ingredients(id) ON DELETE SET NULL;
+
+-- Link existing records by lot code within the organization, preferring a lot
+-- whose name matches the received item
+UPDATE receiving_log r
+SET ingredient_id = (
+    SELECT i.id FROM ingredients i
+    WHERE i.org_id = r.org_id AND i.lotcode = r.lotcode
+    ORDER BY (LOWER(i.name) = LOWER(r.item_name)) DESC, i.id
+    LIMIT 1
+);
+
+CREATE INDEX IF NOT EXISTS idx_receiving_log_ingredient ON receiving_log (ingredient_id);
@@ -0,0 +1,18 @@
+-- Approved supplier program
+CREATE TABLE IF NOT EXISTS suppliers (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    contact_name VARCHAR(255),
+    contact_email VARCHAR(255),
+    contact_phone VARCHAR(50),
+    address TEXT,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'suspended')),
+    approval_date DATE,
+    audit_expiry DATE,
+    products TEXT[] NOT NULL DEFAULT '{}'
+);
+
+ALTER TABLE receiving_log ADD COLUMN supplier_id INTEGER REFERENCES suppliers(id) ON DELETE SET NULL;
+-- Why a delivery from an unapproved or expired supplier was accepted anyway
+ALTER TABLE receiving_log ADD COLUMN supplier_warning TEXT;
@@ -0,0 +1,18 @@
+-- Certificates of analysis, spec sheets and other supplier documents. The
+-- file contents live in the configured document storage under storage_key.
+CREATE TABLE IF NOT EXISTS documents (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE,
+    entity_type VARCHAR(20) NOT NULL CHECK (entity_type IN ('receiving_log', 'ingredient', 'supplier')),
+    entity_id INTEGER NOT NULL,
+    document_type VARCHAR(30) NOT NULL CHECK (document_type IN ('coa', 'spec_sheet', 'letter_of_guarantee', 'other')),
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    storage_key VARCHAR(255) NOT NULL UNIQUE,
+    expires_on DATE,
+    uploaded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_documents_entity ON documents (org_id, entity_type, entity_id);
@@ -0,0 +1,57 @@
+-- Typed receiving temperatures
+ALTER TABLE receiving_log ADD COLUMN temperature_value NUMERIC(6, 2);
+ALTER TABLE receiving_log ADD COLUMN temperature_unit VARCHAR(1) CHECK (temperature_unit IN ('C', 'F'));
+
+-- Backfill the free-text temperatures that can be read, e.g. "3C" or "38 °F"
+UPDATE receiving_log
+SET temperature_value = substring(temperature from '-?[0-9]+(?:\.[0-9]+)?')::numeric,
+    temperature_unit = CASE WHEN temperature ~* 'f' THEN 'F' ELSE 'C' END
+WHERE temperature ~* '^\s*-?[0-9]+(\.[0-9]+)?\s*°?\s*[cf]\s*$';
+
+-- Configurable inspection checklists for incoming deliveries
+CREATE TABLE IF NOT EXISTS receiving_checklists (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    -- Applied to deliveries that do not name a checklist
+    is_default BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_receiving_checklists_default
+    ON receiving_checklists(org_id) WHERE is_default;
+
+CREATE TABLE IF NOT EXISTS receiving_checklist_items (
+    id SERIAL PRIMARY KEY,
+    checklist_id INTEGER NOT NULL REFERENCES receiving_checklists(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    label VARCHAR(255) NOT NULL,
+    -- 'temperature' items are checked against the delivery temperature,
+    -- 'yes_no' items are answered by the receiver
+    kind VARCHAR(20) NOT NULL CHECK (kind IN ('temperature', 'yes_no')),
+    min_temperature NUMERIC(6, 2),
+    max_temperature NUMERIC(6, 2),
+    temperature_unit VARCHAR(1) CHECK (temperature_unit IN ('C', 'F'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_receiving_checklist_items_checklist ON receiving_checklist_items(checklist_id);
+
+-- Inspection outcome of each delivery
+ALTER TABLE receiving_log ADD COLUMN checklist_id INTEGER REFERENCES receiving_checklists(id) ON DELETE SET NULL;
+ALTER TABLE receiving_log ADD COLUMN disposition VARCHAR(10) NOT NULL DEFAULT 'accept'
+    CHECK (disposition IN ('accept', 'reject', 'hold'));
+ALTER TABLE receiving_log ADD COLUMN disposition_reason TEXT;
+-- Problem log opened automatically when the delivery was rejected
+ALTER TABLE receiving_log ADD COLUMN problem_log_id INTEGER REFERENCES problem_logs(id) ON DELETE SET NULL;
+
+-- Checklist results; the label is copied so results survive checklist edits
+CREATE TABLE IF NOT EXISTS receiving_checks (
+    id SERIAL PRIMARY KEY,
+    receiving_log_id INTEGER NOT NULL REFERENCES receiving_log(id) ON DELETE CASCADE,
+    checklist_item_id INTEGER REFERENCES receiving_checklist_items(id) ON DELETE SET NULL,
+    label VARCHAR(255) NOT NULL,
+    passed BOOLEAN NOT NULL,
+    notes TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_receiving_checks_receiving_log ON receiving_checks(receiving_log_id);
@@ -0,0 +1,23 @@
+-- Hold and quarantine status of ingredient lots and batches
+ALTER TABLE ingredients ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'released'
+    CHECK (status IN ('released', 'on_hold', 'quarantined', 'rejected', 'destroyed'));
+ALTER TABLE batches ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'released'
+    CHECK (status IN ('released', 'on_hold', 'quarantined', 'rejected', 'destroyed'));
+
+-- Every status change, with the reason and who made it
+CREATE TABLE IF NOT EXISTS lot_status_changes (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    ingredient_id INTEGER REFERENCES ingredients(id) ON DELETE CASCADE,
+    batch_id INTEGER REFERENCES batches(id) ON DELETE CASCADE,
+    from_status VARCHAR(20) NOT NULL,
+    status VARCHAR(20) NOT NULL,
+    reason TEXT NOT NULL,
+    -- Empty for changes made automatically, e.g. a delivery held on receiving
+    employee_id INTEGER REFERENCES employees(id) ON DELETE SET NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((ingredient_id IS NULL) <> (batch_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS idx_lot_status_changes_ingredient ON lot_status_changes(ingredient_id);
+CREATE INDEX IF NOT EXISTS idx_lot_status_changes_batch ON lot_status_changes(batch_id);
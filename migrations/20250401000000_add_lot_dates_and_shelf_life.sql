@@ -0,0 +1,24 @@
+-- Name the ingredient lot date and add the other dates printed on a lot
+ALTER TABLE ingredients RENAME COLUMN date TO received_date;
+ALTER TABLE ingredients ADD COLUMN manufactured_date DATE;
+ALTER TABLE ingredients ADD COLUMN best_by_date DATE;
+ALTER TABLE ingredients ADD COLUMN expiry_date DATE;
+-- Used to look up the default shelf life; the ingredient name is used when empty
+ALTER TABLE ingredients ADD COLUMN ingredient_type VARCHAR(255);
+
+CREATE INDEX IF NOT EXISTS idx_ingredients_expiry ON ingredients(org_id, expiry_date);
+
+-- Default shelf life per ingredient type, counted from the manufactured date
+-- when known and the received date otherwise
+CREATE TABLE IF NOT EXISTS shelf_life_defaults (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    ingredient_type VARCHAR(255) NOT NULL,
+    shelf_life_days INTEGER NOT NULL CHECK (shelf_life_days > 0)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_shelf_life_defaults_type
+    ON shelf_life_defaults(org_id, LOWER(ingredient_type));
+
+-- Earliest expiry of the ingredient lots a batch was made from
+ALTER TABLE batches ADD COLUMN expiry_date DATE;
@@ -0,0 +1,35 @@
+-- Customers that batches are shipped to
+CREATE TABLE IF NOT EXISTS customers (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    contact_name VARCHAR(255),
+    contact_email VARCHAR(255),
+    contact_phone VARCHAR(50),
+    address TEXT
+);
+
+-- Outbound shipments. Customers and batches with shipments cannot be deleted
+-- so forward traceability is kept.
+CREATE TABLE IF NOT EXISTS shipments (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    customer_id INTEGER NOT NULL REFERENCES customers(id),
+    ship_date DATE NOT NULL,
+    -- Purchase order, bill of lading or invoice number
+    reference VARCHAR(255),
+    notes TEXT
+);
+
+CREATE TABLE IF NOT EXISTS shipment_lines (
+    id SERIAL PRIMARY KEY,
+    shipment_id INTEGER NOT NULL REFERENCES shipments(id) ON DELETE CASCADE,
+    batch_id INTEGER NOT NULL REFERENCES batches(id),
+    quantity NUMERIC(14, 4) NOT NULL,
+    unit VARCHAR(20) NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_shipments_customer ON shipments(customer_id);
+CREATE INDEX IF NOT EXISTS idx_shipment_lines_batch ON shipment_lines(batch_id);
+
+ALTER TABLE problem_logs ADD COLUMN customer_id INTEGER REFERENCES customers(id) ON DELETE SET NULL;
@@ -0,0 +1,12 @@
+-- EPCIS events already imported, so a document sent twice does not record
+-- the same delivery twice
+CREATE TABLE IF NOT EXISTS epcis_imports (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    event_id TEXT NOT NULL,
+    lotcode VARCHAR(255) NOT NULL,
+    receiving_log_id INTEGER REFERENCES receiving_log(id) ON DELETE SET NULL,
+    imported_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_epcis_imports_event ON epcis_imports (org_id, event_id, lotcode);
@@ -0,0 +1,5 @@
+-- Trade item and case serial number read from scanned case labels
+ALTER TABLE receiving_log ADD COLUMN gtin VARCHAR(14);
+ALTER TABLE receiving_log ADD COLUMN serial_number VARCHAR(20);
+
+CREATE INDEX IF NOT EXISTS idx_receiving_log_gtin ON receiving_log (org_id, gtin);
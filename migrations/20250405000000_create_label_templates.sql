@@ -0,0 +1,26 @@
+-- Trade item number printed in batch label barcodes
+ALTER TABLE recipes ADD COLUMN gtin VARCHAR(14);
+
+-- Per-organization batch label layouts
+CREATE TABLE IF NOT EXISTS label_templates (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    -- Used for labels that do not name a template
+    is_default BOOLEAN NOT NULL DEFAULT FALSE,
+    width_mm NUMERIC(6, 1) NOT NULL CHECK (width_mm > 0),
+    height_mm NUMERIC(6, 1) NOT NULL CHECK (height_mm > 0),
+    dpi INTEGER NOT NULL CHECK (dpi IN (152, 203, 300, 600)),
+    header TEXT,
+    footer TEXT,
+    show_allergens BOOLEAN NOT NULL DEFAULT TRUE,
+    show_barcode BOOLEAN NOT NULL DEFAULT TRUE,
+    show_qr BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Best-by as days after the date made; otherwise the batch expiry
+    best_by_days INTEGER CHECK (best_by_days > 0),
+    -- QR code link with {id} and {lot} placeholders
+    link_url TEXT
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_label_templates_default
+    ON label_templates(org_id) WHERE is_default;
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS lot_code_schemes (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    template VARCHAR(255) NOT NULL,
+    is_default BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS idx_lot_code_schemes_org_id ON lot_code_schemes (org_id);
+
+-- At most one default scheme per organization
+CREATE UNIQUE INDEX IF NOT EXISTS idx_lot_code_schemes_default ON lot_code_schemes (org_id) WHERE is_default;
+
+-- Running number of generated lot codes per organization and production day
+CREATE TABLE IF NOT EXISTS lot_code_sequences (
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    sequence_date DATE NOT NULL,
+    last_value INTEGER NOT NULL,
+    PRIMARY KEY (org_id, sequence_date)
+);
+
+-- Lot codes identify a single lot within an organization
+CREATE UNIQUE INDEX IF NOT EXISTS idx_batches_org_lot_code ON batches (org_id, batch_lot_code);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_ingredients_org_lotcode ON ingredients (org_id, lotcode);
@@ -0,0 +1,79 @@
+CREATE TABLE IF NOT EXISTS haccp_plans (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    -- The product the plan covers
+    recipe_id INTEGER REFERENCES recipes(id) ON DELETE SET NULL,
+    current_version INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_haccp_plans_org_id ON haccp_plans (org_id);
+
+-- Immutable snapshots of a plan, one per edit
+CREATE TABLE IF NOT EXISTS haccp_plan_versions (
+    id SERIAL PRIMARY KEY,
+    plan_id INTEGER NOT NULL REFERENCES haccp_plans(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    product_description TEXT,
+    change_summary TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (plan_id, version)
+);
+
+CREATE TABLE IF NOT EXISTS haccp_steps (
+    version_id INTEGER NOT NULL REFERENCES haccp_plan_versions(id) ON DELETE CASCADE,
+    step_number INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    PRIMARY KEY (version_id, step_number)
+);
+
+CREATE TABLE IF NOT EXISTS haccp_hazards (
+    id SERIAL PRIMARY KEY,
+    version_id INTEGER NOT NULL REFERENCES haccp_plan_versions(id) ON DELETE CASCADE,
+    step_number INTEGER NOT NULL,
+    hazard_type VARCHAR(20) NOT NULL CHECK (hazard_type IN ('biological', 'chemical', 'physical')),
+    description TEXT NOT NULL,
+    likelihood INTEGER NOT NULL CHECK (likelihood BETWEEN 1 AND 5),
+    severity INTEGER NOT NULL CHECK (severity BETWEEN 1 AND 5),
+    control_measure TEXT,
+    justification TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_haccp_hazards_version_id ON haccp_hazards (version_id);
+
+CREATE TABLE IF NOT EXISTS haccp_ccps (
+    id SERIAL PRIMARY KEY,
+    version_id INTEGER NOT NULL REFERENCES haccp_plan_versions(id) ON DELETE CASCADE,
+    ccp_number VARCHAR(50) NOT NULL,
+    step_number INTEGER NOT NULL,
+    hazard TEXT NOT NULL,
+    monitoring_what TEXT NOT NULL,
+    monitoring_how TEXT NOT NULL,
+    monitoring_frequency VARCHAR(255) NOT NULL,
+    monitoring_responsible VARCHAR(255),
+    corrective_actions TEXT[] NOT NULL DEFAULT '{}',
+    UNIQUE (version_id, ccp_number)
+);
+
+CREATE TABLE IF NOT EXISTS haccp_critical_limits (
+    id SERIAL PRIMARY KEY,
+    ccp_id INTEGER NOT NULL REFERENCES haccp_ccps(id) ON DELETE CASCADE,
+    parameter VARCHAR(255) NOT NULL,
+    min_value NUMERIC(14, 4),
+    max_value NUMERIC(14, 4),
+    unit VARCHAR(20),
+    CHECK (min_value IS NOT NULL OR max_value IS NOT NULL)
+);
+
+CREATE INDEX IF NOT EXISTS idx_haccp_critical_limits_ccp_id ON haccp_critical_limits (ccp_id);
+
+CREATE TABLE IF NOT EXISTS haccp_verification_activities (
+    id SERIAL PRIMARY KEY,
+    ccp_id INTEGER NOT NULL REFERENCES haccp_ccps(id) ON DELETE CASCADE,
+    activity TEXT NOT NULL,
+    frequency VARCHAR(255) NOT NULL,
+    responsible VARCHAR(255)
+);
+
+CREATE INDEX IF NOT EXISTS idx_haccp_verification_activities_ccp_id ON haccp_verification_activities (ccp_id);
@@ -0,0 +1,37 @@
+-- Problem logs opened for a batch, e.g. by a CCP deviation, and the
+-- corrective action taken. Logs that require one cannot close without it.
+ALTER TABLE problem_logs ADD COLUMN batch_id INTEGER REFERENCES batches(id) ON DELETE SET NULL;
+ALTER TABLE problem_logs ADD COLUMN corrective_action TEXT;
+ALTER TABLE problem_logs ADD COLUMN corrective_action_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- A check of a critical control point. Records are kept as logged and tie
+-- the CCP version in force, so plans with monitoring records cannot be deleted.
+CREATE TABLE IF NOT EXISTS ccp_monitoring_records (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    ccp_id INTEGER NOT NULL REFERENCES haccp_ccps(id),
+    batch_id INTEGER REFERENCES batches(id) ON DELETE SET NULL,
+    employee_id INTEGER REFERENCES employees(id) ON DELETE SET NULL,
+    monitored_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    within_limits BOOLEAN NOT NULL,
+    -- Immediate action taken at the line
+    corrective_action TEXT,
+    notes TEXT,
+    -- Opened when a reading is outside its critical limit
+    problem_log_id INTEGER REFERENCES problem_logs(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_ccp_monitoring_records_org_id ON ccp_monitoring_records (org_id);
+CREATE INDEX IF NOT EXISTS idx_ccp_monitoring_records_ccp_id ON ccp_monitoring_records (ccp_id);
+CREATE INDEX IF NOT EXISTS idx_ccp_monitoring_records_batch_id ON ccp_monitoring_records (batch_id);
+
+-- One reading per critical limit, in the limit's unit
+CREATE TABLE IF NOT EXISTS ccp_monitoring_readings (
+    record_id INTEGER NOT NULL REFERENCES ccp_monitoring_records(id) ON DELETE CASCADE,
+    limit_id INTEGER NOT NULL REFERENCES haccp_critical_limits(id),
+    value NUMERIC(14, 4) NOT NULL,
+    -- As entered, e.g. "160F" for a limit in C
+    measured TEXT NOT NULL,
+    within_limit BOOLEAN NOT NULL,
+    PRIMARY KEY (record_id, limit_id)
+);
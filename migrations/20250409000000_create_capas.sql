@@ -0,0 +1,33 @@
+-- Corrective and preventive actions (CAPA) raised on a problem log. A problem
+-- log cannot close while it has CAPAs that are not verified or cancelled.
+CREATE TABLE IF NOT EXISTS capas (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    problem_log_id INTEGER NOT NULL REFERENCES problem_logs(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'open'
+        CHECK (status IN ('open', 'in_progress', 'implemented', 'verified', 'cancelled')),
+    root_cause TEXT,
+    -- Effectiveness verification
+    effectiveness_check TEXT,
+    effective BOOLEAN,
+    verified_by INTEGER REFERENCES employees(id) ON DELETE SET NULL,
+    verified_on DATE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_capas_org_id ON capas (org_id);
+CREATE INDEX IF NOT EXISTS idx_capas_problem_log_id ON capas (problem_log_id);
+
+-- Owners keep their actions, so employees with CAPA actions cannot be deleted
+CREATE TABLE IF NOT EXISTS capa_actions (
+    id SERIAL PRIMARY KEY,
+    capa_id INTEGER NOT NULL REFERENCES capas(id) ON DELETE CASCADE,
+    action_type VARCHAR(20) NOT NULL CHECK (action_type IN ('corrective', 'preventive')),
+    description TEXT NOT NULL,
+    owner_id INTEGER NOT NULL REFERENCES employees(id),
+    due_date DATE NOT NULL,
+    completed_on DATE
+);
+
+CREATE INDEX IF NOT EXISTS idx_capa_actions_capa_id ON capa_actions (capa_id);
+CREATE INDEX IF NOT EXISTS idx_capa_actions_owner_id ON capa_actions (owner_id);
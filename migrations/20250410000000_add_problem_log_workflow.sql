@@ -0,0 +1,21 @@
+-- Problem logs move through explicit states; is_open is kept in step with
+-- the status (open unless closed)
+ALTER TABLE problem_logs ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'new'
+    CHECK (status IN ('new', 'investigating', 'awaiting_customer', 'resolved', 'closed', 'reopened'));
+ALTER TABLE problem_logs ADD COLUMN resolution_notes TEXT;
+
+UPDATE problem_logs SET status = 'closed' WHERE NOT is_open;
+
+-- Append-only thread of comments and status changes
+CREATE TABLE IF NOT EXISTS problem_log_activity (
+    id SERIAL PRIMARY KEY,
+    problem_log_id INTEGER NOT NULL REFERENCES problem_logs(id) ON DELETE CASCADE,
+    activity_type VARCHAR(20) NOT NULL CHECK (activity_type IN ('created', 'comment', 'status_change')),
+    from_status VARCHAR(20),
+    to_status VARCHAR(20),
+    body TEXT,
+    employee_id INTEGER REFERENCES employees(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_problem_log_activity_problem_log_id ON problem_log_activity (problem_log_id);
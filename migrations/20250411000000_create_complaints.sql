@@ -0,0 +1,29 @@
+-- Organizations opt in to public complaint intake by choosing a slug
+ALTER TABLE organizations ADD COLUMN public_slug VARCHAR(63) UNIQUE;
+
+-- Problem logs raised for an organization. Older logs have no organization.
+ALTER TABLE problem_logs ADD COLUMN org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS idx_problem_logs_org_id ON problem_logs (org_id);
+
+-- Complaints received through the public intake form, as submitted
+CREATE TABLE IF NOT EXISTS complaints (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    problem_log_id INTEGER NOT NULL REFERENCES problem_logs(id) ON DELETE CASCADE,
+    product VARCHAR(255) NOT NULL,
+    lot_code VARCHAR(255),
+    -- The lot code resolved to one of the organization's batches
+    batch_id INTEGER REFERENCES batches(id) ON DELETE SET NULL,
+    purchase_location VARCHAR(255),
+    purchase_date DATE,
+    description TEXT NOT NULL,
+    contact_name VARCHAR(255) NOT NULL,
+    contact_email VARCHAR(255),
+    contact_phone VARCHAR(50),
+    client_address VARCHAR(64),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_complaints_org_id ON complaints (org_id);
+CREATE INDEX IF NOT EXISTS idx_complaints_batch_id ON complaints (batch_id);
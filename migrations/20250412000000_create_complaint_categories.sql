@@ -0,0 +1,18 @@
+-- Each organization's taxonomy of complaint categories. Categories in use
+-- cannot be deleted; retire them by setting active to false.
+CREATE TABLE IF NOT EXISTS complaint_categories (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    description TEXT,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Alert when more than alert_threshold complaints arrive within alert_window_days
+    alert_threshold INTEGER CHECK (alert_threshold > 0),
+    alert_window_days INTEGER NOT NULL DEFAULT 30 CHECK (alert_window_days > 0)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_complaint_categories_org_name ON complaint_categories (org_id, LOWER(name));
+
+ALTER TABLE problem_logs ADD COLUMN category_id INTEGER REFERENCES complaint_categories(id);
+
+CREATE INDEX IF NOT EXISTS idx_problem_logs_category_id ON problem_logs (category_id);
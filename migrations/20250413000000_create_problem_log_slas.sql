@@ -0,0 +1,49 @@
+-- Response and resolution targets for each organization's problem types
+CREATE TABLE IF NOT EXISTS problem_log_slas (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    problem_type VARCHAR(100) NOT NULL,
+    response_hours INTEGER NOT NULL CHECK (response_hours > 0),
+    resolution_hours INTEGER NOT NULL CHECK (resolution_hours > 0),
+    CHECK (resolution_hours >= response_hours)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_problem_log_slas_org_type ON problem_log_slas (org_id, LOWER(problem_type));
+
+-- Due dates count from when a log was recorded. Older logs count from the
+-- day they were opened.
+ALTER TABLE problem_logs ADD COLUMN opened_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+UPDATE problem_logs SET opened_at = date_opened::timestamp with time zone;
+
+-- The first comment or status change after a log was opened
+ALTER TABLE problem_logs ADD COLUMN responded_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE problem_logs ADD COLUMN response_due_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE problem_logs ADD COLUMN resolution_due_at TIMESTAMP WITH TIME ZONE;
+-- 0 not escalated, 1 assigned employees notified, 2 QA notified
+ALTER TABLE problem_logs ADD COLUMN escalation_level SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE problem_logs ADD COLUMN escalated_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_problem_logs_resolution_due_at ON problem_logs (resolution_due_at)
+    WHERE status NOT IN ('resolved', 'closed');
+
+UPDATE problem_logs pl SET responded_at = (
+    SELECT MIN(a.created_at) FROM problem_log_activity a
+    WHERE a.problem_log_id = pl.id AND a.activity_type <> 'created'
+);
+
+ALTER TABLE problem_log_activity DROP CONSTRAINT IF EXISTS problem_log_activity_activity_type_check;
+ALTER TABLE problem_log_activity ADD CONSTRAINT problem_log_activity_activity_type_check
+    CHECK (activity_type IN ('created', 'comment', 'status_change', 'escalation'));
+
+-- Messages for employees, such as escalated problem logs
+CREATE TABLE IF NOT EXISTS notifications (
+    id SERIAL PRIMARY KEY,
+    org_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    employee_id INTEGER NOT NULL REFERENCES employees(id) ON DELETE CASCADE,
+    problem_log_id INTEGER REFERENCES problem_logs(id) ON DELETE CASCADE,
+    message TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_notifications_employee_id ON notifications (employee_id);
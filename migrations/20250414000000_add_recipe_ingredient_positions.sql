@@ -0,0 +1,19 @@
+-- Recipe ingredient lines keep the order they were entered in. Existing
+-- lines are numbered in ingredient order, which is how they were listed.
+ALTER TABLE recipe_ingredients ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE recipe_ingredients ri SET position = numbered.position
+FROM (
+    SELECT recipe_id, ingredient_id, ROW_NUMBER() OVER (PARTITION BY recipe_id ORDER BY ingredient_id) - 1 as position
+    FROM recipe_ingredients
+) numbered
+WHERE ri.recipe_id = numbered.recipe_id AND ri.ingredient_id = numbered.ingredient_id;
+
+ALTER TABLE recipe_version_ingredients ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE recipe_version_ingredients rvi SET position = numbered.position
+FROM (
+    SELECT version_id, ingredient_id, ROW_NUMBER() OVER (PARTITION BY version_id ORDER BY ingredient_id) - 1 as position
+    FROM recipe_version_ingredients
+) numbered
+WHERE rvi.version_id = numbered.version_id AND rvi.ingredient_id = numbered.ingredient_id;
@@ -0,0 +1,2 @@
+-- Why a delivery from an unapproved, expired or unregistered supplier was accepted
+ALTER TABLE receiving_log ADD COLUMN supplier_override_reason TEXT;
@@ -0,0 +1,44 @@
+-- Storage keys of deleted documents whose contents still have to be removed
+-- from document storage
+CREATE TABLE IF NOT EXISTS document_purges (
+    storage_key VARCHAR(255) PRIMARY KEY,
+    deleted_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE OR REPLACE FUNCTION queue_document_purge() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO document_purges (storage_key) VALUES (OLD.storage_key) ON CONFLICT DO NOTHING;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS documents_queue_purge ON documents;
+CREATE TRIGGER documents_queue_purge AFTER DELETE ON documents
+    FOR EACH ROW EXECUTE FUNCTION queue_document_purge();
+
+-- Documents are attached by entity id, so remove them with the record they
+-- belong to, including records removed by cascades
+CREATE OR REPLACE FUNCTION delete_entity_documents() RETURNS trigger AS $$
+BEGIN
+    DELETE FROM documents WHERE entity_type = TG_ARGV[0] AND entity_id = OLD.id;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS receiving_log_delete_documents ON receiving_log;
+CREATE TRIGGER receiving_log_delete_documents AFTER DELETE ON receiving_log
+    FOR EACH ROW EXECUTE FUNCTION delete_entity_documents('receiving_log');
+
+DROP TRIGGER IF EXISTS ingredients_delete_documents ON ingredients;
+CREATE TRIGGER ingredients_delete_documents AFTER DELETE ON ingredients
+    FOR EACH ROW EXECUTE FUNCTION delete_entity_documents('ingredient');
+
+DROP TRIGGER IF EXISTS suppliers_delete_documents ON suppliers;
+CREATE TRIGGER suppliers_delete_documents AFTER DELETE ON suppliers
+    FOR EACH ROW EXECUTE FUNCTION delete_entity_documents('supplier');
+
+-- Documents left behind by records deleted earlier
+DELETE FROM documents d
+WHERE (d.entity_type = 'receiving_log' AND NOT EXISTS (SELECT 1 FROM receiving_log r WHERE r.id = d.entity_id))
+   OR (d.entity_type = 'ingredient' AND NOT EXISTS (SELECT 1 FROM ingredients i WHERE i.id = d.entity_id))
+   OR (d.entity_type = 'supplier' AND NOT EXISTS (SELECT 1 FROM suppliers s WHERE s.id = d.entity_id));
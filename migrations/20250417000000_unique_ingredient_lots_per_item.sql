@@ -0,0 +1,7 @@
+-- The lot code scheme migration indexed ingredient lot codes as unique within
+-- an organization. They come from suppliers and may repeat across items, so
+-- an ingredient lot is unique by item name and lot code instead.
+DROP INDEX IF EXISTS idx_ingredients_org_lotcode;
+DROP INDEX IF EXISTS idx_ingredients_org_lotcode_lookup;
+CREATE UNIQUE INDEX idx_ingredients_org_name_lotcode ON ingredients (org_id, name, lotcode);
+CREATE INDEX idx_ingredients_org_lotcode_lookup ON ingredients (org_id, lotcode);
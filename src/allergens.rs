@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// The nine major food allergens (FALCPA as amended by the FASTER Act)
+pub const MAJOR_ALLERGENS: [&str; 9] = [
+    "milk",
+    "eggs",
+    "fish",
+    "crustacean shellfish",
+    "tree nuts",
+    "peanuts",
+    "wheat",
+    "soybeans",
+    "sesame",
+];
+
+// Whether an allergen is an ingredient or only a cross-contact risk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    MayContain,
+    Contains,
+}
+
+impl Presence {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Presence::Contains => "contains",
+            Presence::MayContain => "may_contain",
+        }
+    }
+
+    // Unknown values are treated as "contains" so nothing is under-declared
+    pub fn parse(value: &str) -> Presence {
+        match value {
+            "may_contain" => Presence::MayContain,
+            _ => Presence::Contains,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AllergenDeclaration {
+    pub allergen: String,
+    #[serde(default = "default_presence")]
+    pub presence: Presence,
+    // Set on output for the nine major allergens
+    #[serde(default)]
+    pub major: bool,
+}
+
+fn default_presence() -> Presence {
+    Presence::Contains
+}
+
+impl AllergenDeclaration {
+    pub fn new(allergen: &str, presence: Presence) -> AllergenDeclaration {
+        let allergen = normalize_allergen(allergen);
+        let major = MAJOR_ALLERGENS.contains(&allergen.as_str());
+        AllergenDeclaration { allergen, presence, major }
+    }
+}
+
+// Map common spellings onto the major allergen names; custom allergens are
+// kept as lowercase text
+pub fn normalize_allergen(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    let normalized = match name.as_str() {
+        "dairy" | "lactose" => "milk",
+        "egg" => "eggs",
+        "shellfish" | "crustacean" | "crustaceans" => "crustacean shellfish",
+        "tree nut" | "nuts" => "tree nuts",
+        "peanut" => "peanuts",
+        "soy" | "soya" | "soybean" => "soybeans",
+        "sesame seeds" => "sesame",
+        other => other,
+    };
+    normalized.to_string()
+}
+
+// Combine declarations into one profile. "contains" wins over "may contain".
+pub fn merge_profile<I>(declarations: I) -> Vec<AllergenDeclaration>
+where
+    I: IntoIterator<Item = AllergenDeclaration>,
+{
+    let mut profile: BTreeMap<String, Presence> = BTreeMap::new();
+    for declaration in declarations {
+        let presence = profile.entry(declaration.allergen).or_insert(declaration.presence);
+        *presence = (*presence).max(declaration.presence);
+    }
+
+    profile.into_iter()
+        .map(|(allergen, presence)| AllergenDeclaration::new(&allergen, presence))
+        .collect()
+}
+
+// Allergens in `actual` that `declared` does not cover, including allergens
+// declared only as "may contain" that are actually contained
+pub fn undeclared_allergens(
+    actual: &[AllergenDeclaration],
+    declared: &[AllergenDeclaration],
+) -> Vec<AllergenDeclaration> {
+    actual.iter()
+        .filter(|allergen| {
+            match declared.iter().find(|d| d.allergen == allergen.allergen) {
+                Some(declaration) => declaration.presence < allergen.presence,
+                None => true,
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+// Allergens contained in the previous batch on a line that the next batch
+// does not contain. Any of these means the line needs a verified changeover.
+pub fn changeover_allergens(
+    previous: &[AllergenDeclaration],
+    next: &[AllergenDeclaration],
+) -> Vec<String> {
+    previous.iter()
+        .filter(|allergen| allergen.presence == Presence::Contains)
+        .filter(|allergen| {
+            !next.iter().any(|n| n.allergen == allergen.allergen && n.presence == Presence::Contains)
+        })
+        .map(|allergen| allergen.allergen.clone())
+        .collect()
+}
+
+// How a changeover clean was verified
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMethod {
+    Visual,
+    Atp,
+    AllergenSwab,
+}
+
+impl VerificationMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VerificationMethod::Visual => "visual",
+            VerificationMethod::Atp => "atp",
+            VerificationMethod::AllergenSwab => "allergen_swab",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationResult {
+    Pass,
+    Fail,
+}
+
+impl VerificationResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VerificationResult::Pass => "pass",
+            VerificationResult::Fail => "fail",
+        }
+    }
+}
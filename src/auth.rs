@@ -1,4 +1,4 @@
-use actix_web::{web, HttpResponse, Responder, HttpRequest, Error as ActixWebError};
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
 use actix_web::dev::ServiceRequest;
 use actix_web::http::header;
 use argon2::{
@@ -8,7 +8,7 @@ use argon2::{
     },
     Argon2
 };
-use chrono::{DateTime, Duration, Utc};
+use chrono::{Duration, Utc};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
@@ -50,6 +50,7 @@ pub struct AuthResponse {
 }
 
 // Error types
+#[allow(dead_code)]
 #[derive(Debug, thiserror::Error)]
 pub enum AuthError {
     #[error("Invalid credentials")]
@@ -130,13 +131,16 @@ pub fn verify_token(token: &str) -> Result<Claims, AuthError> {
 pub fn get_token_from_request(req: &HttpRequest) -> Option<String> {
     req.headers().get(header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
-        .and_then(|auth_header| {
-            if auth_header.starts_with("Bearer ") {
-                Some(auth_header[7..].to_string())
-            } else {
-                None
-            }
-        })
+        .and_then(|auth_header| auth_header.strip_prefix("Bearer "))
+        .map(|token| token.to_string())
+}
+
+// Extract the authenticated organization ID from the request's bearer token
+pub fn org_id_from_request(req: &HttpRequest) -> Result<i32, AuthError> {
+    let token = get_token_from_request(req)
+        .ok_or_else(|| AuthError::Unauthorized("Missing or invalid authorization header".to_string()))?;
+    let claims = verify_token(&token)?;
+    Ok(claims.org_id)
 }
 
 // Registration handler
@@ -349,6 +353,7 @@ pub async fn logout_organization(
 }
 
 // Middleware to verify tokens and extract organization ID
+#[allow(dead_code)]
 pub async fn verify_auth(
     req: &ServiceRequest,
     db_pool: &Pool<Postgres>,
@@ -357,11 +362,10 @@ pub async fn verify_auth(
         .headers()
         .get(header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
+        .and_then(|auth_header| auth_header.strip_prefix("Bearer "))
     {
-        Some(auth_header) if auth_header.starts_with("Bearer ") => {
-            auth_header[7..].to_string()
-        }
-        _ => return Err(AuthError::Unauthorized("Missing or invalid authorization header".to_string())),
+        Some(token) => token.to_string(),
+        None => return Err(AuthError::Unauthorized("Missing or invalid authorization header".to_string())),
     };
     
     // Verify token is not revoked
@@ -404,6 +408,7 @@ pub async fn verify_auth(
 }
 
 // Authorization middleware to check if user has access to requested organization data
+#[allow(dead_code)]
 pub async fn check_org_authorization(
     org_id: i32,
     auth_org_id: i32,
@@ -0,0 +1,185 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+// Error types
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum CapaError {
+    #[error("A CAPA cannot move from {} to {}", .from.as_str(), .to.as_str())]
+    InvalidTransition { from: CapaStatus, to: CapaStatus },
+
+    #[error("New CAPAs start open")]
+    NotOpen,
+
+    #[error("The CAPA is {} and can no longer be changed", .0.as_str())]
+    Closed(CapaStatus),
+
+    #[error("Each action needs a description")]
+    EmptyAction,
+
+    #[error("A root cause is required before the CAPA is implemented")]
+    MissingRootCause,
+
+    #[error("At least one corrective action is required before the CAPA is implemented")]
+    MissingCorrectiveAction,
+
+    #[error("All actions must be completed before the CAPA is implemented")]
+    IncompleteActions,
+
+    #[error("Effectiveness verification needs effectiveness_check, effective and verified_by")]
+    MissingVerification,
+
+    #[error("Actions found not effective go back to in_progress")]
+    NotEffective,
+}
+
+// open -> in_progress -> implemented -> verified, with cancelled reachable
+// until implementation. Implemented CAPAs that do not prove effective go
+// back to in_progress.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CapaStatus {
+    Open,
+    InProgress,
+    Implemented,
+    Verified,
+    Cancelled,
+}
+
+impl CapaStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CapaStatus::Open => "open",
+            CapaStatus::InProgress => "in_progress",
+            CapaStatus::Implemented => "implemented",
+            CapaStatus::Verified => "verified",
+            CapaStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<CapaStatus> {
+        match value {
+            "open" => Some(CapaStatus::Open),
+            "in_progress" => Some(CapaStatus::InProgress),
+            "implemented" => Some(CapaStatus::Implemented),
+            "verified" => Some(CapaStatus::Verified),
+            "cancelled" => Some(CapaStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    // Verified and cancelled CAPAs no longer hold a problem log open
+    pub fn is_closed(self) -> bool {
+        matches!(self, CapaStatus::Verified | CapaStatus::Cancelled)
+    }
+
+    pub fn can_move_to(self, to: CapaStatus) -> bool {
+        use CapaStatus::*;
+        matches!(
+            (self, to),
+            (Open, InProgress)
+                | (Open, Cancelled)
+                | (InProgress, Implemented)
+                | (InProgress, Cancelled)
+                | (Implemented, Verified)
+                | (Implemented, InProgress)
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionType {
+    Corrective,
+    Preventive,
+}
+
+impl ActionType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActionType::Corrective => "corrective",
+            ActionType::Preventive => "preventive",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ActionType> {
+        match value {
+            "corrective" => Some(ActionType::Corrective),
+            "preventive" => Some(ActionType::Preventive),
+            _ => None,
+        }
+    }
+}
+
+// A corrective or preventive action, owned by an employee
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CapaAction {
+    // Set on output
+    #[serde(default)]
+    pub id: Option<i32>,
+    pub action_type: ActionType,
+    pub description: String,
+    pub owner_id: i32,
+    pub due_date: NaiveDate,
+    pub completed_on: Option<NaiveDate>,
+}
+
+impl CapaAction {
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.completed_on.is_none() && self.due_date < today
+    }
+}
+
+// What a CAPA records: the root cause analysis, the actions taken and how
+// their effectiveness was verified
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CapaDetails {
+    pub root_cause: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<CapaAction>,
+    // How effectiveness was checked, e.g. "No recurrence in 30 days of swabs"
+    pub effectiveness_check: Option<String>,
+    pub effective: Option<bool>,
+    pub verified_by: Option<i32>,
+    pub verified_on: Option<NaiveDate>,
+}
+
+fn is_blank(value: &Option<String>) -> bool {
+    value.as_deref().is_none_or(|value| value.trim().is_empty())
+}
+
+// Check a CAPA may be saved in status `to` when it is currently `from`
+// (`None` for a new CAPA) and has the fields the new status needs
+pub fn check_transition(from: Option<CapaStatus>, to: CapaStatus, details: &CapaDetails) -> Result<(), CapaError> {
+    match from {
+        Some(from) if from.is_closed() => return Err(CapaError::Closed(from)),
+        Some(from) if from != to && !from.can_move_to(to) => return Err(CapaError::InvalidTransition { from, to }),
+        None if to != CapaStatus::Open => return Err(CapaError::NotOpen),
+        _ => {}
+    }
+
+    if details.actions.iter().any(|action| action.description.trim().is_empty()) {
+        return Err(CapaError::EmptyAction);
+    }
+
+    if matches!(to, CapaStatus::Implemented | CapaStatus::Verified) {
+        if is_blank(&details.root_cause) {
+            return Err(CapaError::MissingRootCause);
+        }
+        if !details.actions.iter().any(|action| action.action_type == ActionType::Corrective) {
+            return Err(CapaError::MissingCorrectiveAction);
+        }
+        if details.actions.iter().any(|action| action.completed_on.is_none()) {
+            return Err(CapaError::IncompleteActions);
+        }
+    }
+
+    if to == CapaStatus::Verified {
+        if is_blank(&details.effectiveness_check) || details.effective.is_none() || details.verified_by.is_none() {
+            return Err(CapaError::MissingVerification);
+        }
+        if details.effective == Some(false) {
+            return Err(CapaError::NotEffective);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,347 @@
+use crate::units::{Dimension, Quantity, Unit};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Error types
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ComplaintError {
+    #[error("Public slugs are 3 to 63 lowercase letters, digits or hyphens, not starting or ending with a hyphen")]
+    InvalidSlug,
+
+    // Deliberately vague so bots learn nothing from it
+    #[error("Complaint could not be accepted")]
+    Rejected,
+
+    #[error("Please tell us which product the complaint is about")]
+    MissingProduct,
+
+    #[error("Please describe the problem in at least {} characters", MIN_DESCRIPTION_CHARS)]
+    DescriptionTooShort,
+
+    #[error("Descriptions are limited to {} characters", MAX_DESCRIPTION_CHARS)]
+    DescriptionTooLong,
+
+    #[error("{0} is limited to {} characters", MAX_FIELD_CHARS)]
+    FieldTooLong(&'static str),
+
+    #[error("Please leave your name and an email address or phone number")]
+    MissingContact,
+
+    #[error("Invalid email address")]
+    InvalidEmail,
+
+    #[error("Complaint categories need a name")]
+    MissingCategoryName,
+
+    #[error("alert_threshold and alert_window_days must be positive")]
+    InvalidAlertThreshold,
+}
+
+pub const MIN_DESCRIPTION_CHARS: usize = 10;
+pub const MAX_DESCRIPTION_CHARS: usize = 5000;
+// Product, lot code, location and contact fields
+pub const MAX_FIELD_CHARS: usize = 200;
+// Complaints rarely link anywhere; spam almost always does
+const MAX_LINKS: usize = 2;
+
+// A complaint sent through the public intake form
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ComplaintSubmission {
+    pub product: String,
+    pub lot_code: Option<String>,
+    pub purchase_location: Option<String>,
+    // YYYY-MM-DD
+    pub purchase_date: Option<String>,
+    pub description: String,
+    pub contact_name: String,
+    pub contact_email: Option<String>,
+    pub contact_phone: Option<String>,
+    // Honeypot: hidden from people by the form, filled in by bots
+    #[serde(default)]
+    pub website: Option<String>,
+}
+
+fn filled(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+}
+
+// Check a public slug, returning it lowercased
+pub fn normalize_slug(slug: &str) -> Result<String, ComplaintError> {
+    let slug = slug.trim().to_ascii_lowercase();
+    let valid = (3..=63).contains(&slug.len())
+        && slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-');
+    if valid { Ok(slug) } else { Err(ComplaintError::InvalidSlug) }
+}
+
+// Reject incomplete submissions and likely spam
+pub fn check_submission(complaint: &ComplaintSubmission) -> Result<(), ComplaintError> {
+    if filled(&complaint.website).is_some() {
+        return Err(ComplaintError::Rejected);
+    }
+    if complaint.product.trim().is_empty() {
+        return Err(ComplaintError::MissingProduct);
+    }
+
+    let description = complaint.description.trim();
+    let length = description.chars().count();
+    if length < MIN_DESCRIPTION_CHARS {
+        return Err(ComplaintError::DescriptionTooShort);
+    }
+    if length > MAX_DESCRIPTION_CHARS {
+        return Err(ComplaintError::DescriptionTooLong);
+    }
+
+    let fields = [
+        ("product", Some(complaint.product.as_str())),
+        ("lot_code", filled(&complaint.lot_code)),
+        ("purchase_location", filled(&complaint.purchase_location)),
+        ("contact_name", Some(complaint.contact_name.as_str())),
+        ("contact_email", filled(&complaint.contact_email)),
+        ("contact_phone", filled(&complaint.contact_phone)),
+    ];
+    for (field, value) in fields {
+        if value.is_some_and(|value| value.trim().chars().count() > MAX_FIELD_CHARS) {
+            return Err(ComplaintError::FieldTooLong(field));
+        }
+    }
+
+    let links = |text: &str| text.to_ascii_lowercase().matches("://").count();
+    let location_links = filled(&complaint.purchase_location).map_or(0, links);
+    if links(description) + location_links > MAX_LINKS || links(&complaint.contact_name) > 0 {
+        return Err(ComplaintError::Rejected);
+    }
+
+    if complaint.contact_name.trim().is_empty()
+        || (filled(&complaint.contact_email).is_none() && filled(&complaint.contact_phone).is_none())
+    {
+        return Err(ComplaintError::MissingContact);
+    }
+    if let Some(email) = filled(&complaint.contact_email) {
+        let valid = email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+        });
+        if !valid || email.contains(char::is_whitespace) {
+            return Err(ComplaintError::InvalidEmail);
+        }
+    }
+    Ok(())
+}
+
+// Sliding window limit on submissions per key, e.g. per organization and
+// client address
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        RateLimiter { limit, window, hits: Mutex::new(HashMap::new()) }
+    }
+
+    // Count a submission, returning false once the key is over its limit
+    pub fn allow(&self, key: &str, now: Instant) -> bool {
+        let mut hits = self.hits.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Forget keys with nothing left in their window
+        hits.retain(|_, times| times.back().is_some_and(|last| now.duration_since(*last) < self.window));
+
+        let times = hits.entry(key.to_string()).or_default();
+        while times.front().is_some_and(|first| now.duration_since(*first) >= self.window) {
+            times.pop_front();
+        }
+        if times.len() >= self.limit {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+// The limiter shared by every worker: COMPLAINT_RATE_LIMIT submissions
+// (default 5) per client and organization per hour
+pub fn limiter_from_env() -> Arc<RateLimiter> {
+    static LIMITER: OnceLock<Arc<RateLimiter>> = OnceLock::new();
+    LIMITER
+        .get_or_init(|| {
+            let limit = env::var("COMPLAINT_RATE_LIMIT").ok().and_then(|limit| limit.parse().ok()).unwrap_or(5);
+            Arc::new(RateLimiter::new(limit, Duration::from_secs(3600)))
+        })
+        .clone()
+}
+
+// Check a complaint category's name and alert settings
+pub fn check_category(name: &str, alert_threshold: Option<i32>, alert_window_days: i32) -> Result<(), ComplaintError> {
+    if name.trim().is_empty() {
+        return Err(ComplaintError::MissingCategoryName);
+    }
+    if alert_threshold.is_some_and(|threshold| threshold <= 0) || alert_window_days <= 0 {
+        return Err(ComplaintError::InvalidAlertThreshold);
+    }
+    Ok(())
+}
+
+// How complaint trends are broken down
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TrendGrouping {
+    #[default]
+    Category,
+    Product,
+    Batch,
+    Month,
+}
+
+// A complaint as counted in trends
+#[derive(Debug, Clone)]
+pub struct ComplaintFact {
+    pub category_id: Option<i32>,
+    pub category: Option<String>,
+    pub batch_id: Option<i32>,
+    pub product: Option<String>,
+    pub date_opened: NaiveDate,
+}
+
+// A batch made by the organization. Batches made outside the period are
+// only counted against the complaints about them.
+#[derive(Debug, Clone)]
+pub struct ProductionFact {
+    pub batch_id: i32,
+    pub batch_lot_code: String,
+    pub product: String,
+    pub date_made: NaiveDate,
+    pub amount: Option<Quantity>,
+    pub in_period: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComplaintTrend {
+    // The category or batch id; None for uncategorized complaints, products and months
+    pub id: Option<i32>,
+    pub group: String,
+    pub complaints: i64,
+    // None when nothing was produced or production mixes mass, volume and counts
+    pub units_produced: Option<Quantity>,
+    pub complaints_per_million: Option<Decimal>,
+}
+
+const UNCATEGORIZED: &str = "Uncategorized";
+const UNKNOWN_PRODUCT: &str = "Unknown product";
+
+// Production in the base unit of its dimension (kg, L or each) so batches
+// measured differently add up
+fn base_quantity(quantity: Quantity) -> Quantity {
+    let base = match quantity.unit.dimension() {
+        Dimension::Mass => Unit::Kilogram,
+        Dimension::Volume => Unit::Liter,
+        Dimension::Count => Unit::Each,
+    };
+    quantity.convert(base, None).unwrap_or(quantity)
+}
+
+// Total production, or None when there is none or it cannot be added up
+pub fn total_production<'a>(amounts: impl IntoIterator<Item = &'a Quantity>) -> Option<Quantity> {
+    let mut total: Option<Quantity> = None;
+    for amount in amounts {
+        let amount = base_quantity(*amount);
+        total = match total {
+            None => Some(amount),
+            Some(total) if total.unit == amount.unit => Some(Quantity::new(total.value + amount.value, total.unit)),
+            Some(_) => return None,
+        };
+    }
+    total
+}
+
+// Complaints per million units produced, to two decimal places
+pub fn complaints_per_million(complaints: i64, units_produced: Decimal) -> Option<Decimal> {
+    if units_produced <= Decimal::ZERO {
+        return None;
+    }
+    Some((Decimal::from(complaints) * Decimal::from(1_000_000) / units_produced).round_dp(2).normalize())
+}
+
+// Count complaints per group and set them against the production of the
+// group: the batch itself, the product's batches, the batches made that
+// month, or for categories everything made in the period
+pub fn complaint_trends(
+    grouping: TrendGrouping,
+    complaints: &[ComplaintFact],
+    production: &[ProductionFact],
+) -> Vec<ComplaintTrend> {
+    let batches: HashMap<i32, &ProductionFact> = production.iter().map(|batch| (batch.batch_id, batch)).collect();
+    // Products typed into the complaint form are matched to the products made ignoring case
+    let product_of = |complaint: &ComplaintFact| {
+        complaint.batch_id.and_then(|batch_id| batches.get(&batch_id)).map(|batch| batch.product.clone())
+            .or_else(|| {
+                complaint.product.as_deref().map(str::trim).map(|product| {
+                    production.iter()
+                        .find(|batch| batch.product.to_lowercase() == product.to_lowercase())
+                        .map_or_else(|| product.to_string(), |batch| batch.product.clone())
+                })
+            })
+            .unwrap_or_else(|| UNKNOWN_PRODUCT.to_string())
+    };
+
+    // Keyed by (id, group) so months and names sort naturally
+    let mut counts: BTreeMap<(Option<i32>, String), i64> = BTreeMap::new();
+    for complaint in complaints {
+        let key = match grouping {
+            TrendGrouping::Category => (
+                complaint.category_id,
+                complaint.category.clone().unwrap_or_else(|| UNCATEGORIZED.to_string()),
+            ),
+            TrendGrouping::Product => (None, product_of(complaint)),
+            TrendGrouping::Batch => match complaint.batch_id {
+                Some(batch_id) => (
+                    Some(batch_id),
+                    batches.get(&batch_id).map_or_else(|| batch_id.to_string(), |batch| batch.batch_lot_code.clone()),
+                ),
+                None => continue,
+            },
+            TrendGrouping::Month => (None, complaint.date_opened.format("%Y-%m").to_string()),
+        };
+        *counts.entry(key).or_default() += 1;
+    }
+
+    let in_period = || production.iter().filter(|batch| batch.in_period);
+    let mut trends: Vec<ComplaintTrend> = counts
+        .into_iter()
+        .map(|((id, group), count)| {
+            let units_produced = match grouping {
+                TrendGrouping::Category => total_production(in_period().filter_map(|batch| batch.amount.as_ref())),
+                TrendGrouping::Product => total_production(
+                    in_period().filter(|batch| batch.product == group).filter_map(|batch| batch.amount.as_ref()),
+                ),
+                TrendGrouping::Batch => {
+                    id.and_then(|batch_id| batches.get(&batch_id)).and_then(|batch| batch.amount).map(base_quantity)
+                }
+                TrendGrouping::Month => total_production(
+                    in_period()
+                        .filter(|batch| batch.date_made.format("%Y-%m").to_string() == group)
+                        .filter_map(|batch| batch.amount.as_ref()),
+                ),
+            };
+            ComplaintTrend {
+                id,
+                group,
+                complaints: count,
+                complaints_per_million: units_produced.and_then(|units| complaints_per_million(count, units.value)),
+                units_produced,
+            }
+        })
+        .collect();
+
+    // Months read best in order; everything else worst first
+    if grouping != TrendGrouping::Month {
+        trends.sort_by(|a, b| b.complaints.cmp(&a.complaints).then_with(|| a.group.cmp(&b.group)));
+    }
+    trends
+}
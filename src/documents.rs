@@ -0,0 +1,148 @@
+use actix_web::web;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Error types
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("Document not found: {0}")]
+    NotFound(String),
+
+    #[error("Storage I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Unsupported DOCUMENT_STORAGE backend: {0}")]
+    UnsupportedBackend(String),
+
+    #[error("Storage task failed: {0}")]
+    Blocking(#[from] actix_web::error::BlockingError),
+}
+
+// Where uploaded document contents live. Metadata stays in the database;
+// backends only store bytes under a server-generated key. Backends may block,
+// so handlers go through `put`, `get` and `delete` below.
+pub trait DocumentStorage: Send + Sync {
+    fn put(&self, key: &str, contents: &[u8]) -> Result<(), StorageError>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+    fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+// Store contents on the blocking thread pool
+pub async fn put(
+    storage: Arc<dyn DocumentStorage>,
+    key: String,
+    contents: impl AsRef<[u8]> + Send + 'static,
+) -> Result<(), StorageError> {
+    web::block(move || storage.put(&key, contents.as_ref())).await?
+}
+
+// Read contents on the blocking thread pool
+pub async fn get(storage: Arc<dyn DocumentStorage>, key: String) -> Result<Vec<u8>, StorageError> {
+    web::block(move || storage.get(&key)).await?
+}
+
+// Delete contents on the blocking thread pool
+pub async fn delete(storage: Arc<dyn DocumentStorage>, key: String) -> Result<(), StorageError> {
+    web::block(move || storage.delete(&key)).await?
+}
+
+// Stores documents as files below a root directory
+pub struct LocalFsStorage {
+    root: PathBuf,
+}
+
+impl LocalFsStorage {
+    pub fn new(root: impl Into<PathBuf>) -> LocalFsStorage {
+        LocalFsStorage { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl DocumentStorage for LocalFsStorage {
+    fn put(&self, key: &str, contents: &[u8]) -> Result<(), StorageError> {
+        let path = self.path_for(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        match fs::read(self.path_for(key)) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(StorageError::NotFound(key.to_string())),
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.path_for(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(StorageError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Pick the storage backend from DOCUMENT_STORAGE (only "local" for now),
+// rooted at DOCUMENT_STORAGE_DIR
+pub fn storage_from_env() -> Result<Arc<dyn DocumentStorage>, StorageError> {
+    let backend = env::var("DOCUMENT_STORAGE").unwrap_or_else(|_| "local".to_string());
+    let root = env::var("DOCUMENT_STORAGE_DIR").unwrap_or_else(|_| "./documents".to_string());
+    match backend.as_str() {
+        "local" => Ok(Arc::new(LocalFsStorage::new(root))),
+        other => Err(StorageError::UnsupportedBackend(other.to_string())),
+    }
+}
+
+// Largest accepted upload, from DOCUMENT_MAX_BYTES (default 10 MiB)
+pub fn max_bytes_from_env() -> usize {
+    env::var("DOCUMENT_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(10 * 1024 * 1024)
+}
+
+pub const ALLOWED_CONTENT_TYPES: [&str; 7] = [
+    "application/pdf",
+    "image/png",
+    "image/jpeg",
+    "text/plain",
+    "text/csv",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+];
+
+// Check the declared content type is allowed and, for binary formats with a
+// known signature, that the contents actually match it
+pub fn check_content(content_type: &str, contents: &[u8]) -> Result<(), String> {
+    if !ALLOWED_CONTENT_TYPES.contains(&content_type) {
+        return Err(format!("Content type {} is not allowed", content_type));
+    }
+
+    let signature: &[u8] = match content_type {
+        "application/pdf" => b"%PDF-",
+        "image/png" => b"\x89PNG\r\n\x1a\n",
+        "image/jpeg" => b"\xff\xd8\xff",
+        // Office Open XML files are zip archives
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        | "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => b"PK\x03\x04",
+        _ => b"",
+    };
+    if !contents.starts_with(signature) {
+        return Err(format!("File contents do not match content type {}", content_type));
+    }
+
+    Ok(())
+}
+
+// Hex-encoded SHA-256 checksum
+pub fn sha256_hex(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+}
@@ -0,0 +1,301 @@
+use crate::units::{Quantity, Temperature, TemperatureUnit, Unit};
+use rust_decimal::Decimal;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+pub const EPCIS_CONTEXT: &str = "https://ref.gs1.org/standards/epcis/2.0.0/epcis-context.jsonld";
+
+// Namespace for identifiers and extension fields of this service
+pub const NAMESPACE: &str = "urn:crud-hz:";
+pub const EXTENSION_PREFIX: &str = "hz";
+
+// Wrap events in an EPCIS 2.0 JSON-LD document
+pub fn document(events: Vec<Value>, creation_date: &str) -> Value {
+    json!({
+        "@context": [EPCIS_CONTEXT, {EXTENSION_PREFIX: format!("{}epcis:", NAMESPACE)}],
+        "type": "EPCISDocument",
+        "schemaVersion": "2.0",
+        "creationDate": creation_date,
+        "epcisBody": {"eventList": events}
+    })
+}
+
+// A URI for a value of this service, e.g. urn:crud-hz:lot:1:LOT%2042
+pub fn identifier(kind: &str, value: &str) -> String {
+    format!("{}{}:{}", NAMESPACE, kind, percent_encode(value))
+}
+
+// EPC class of a lot of this service
+pub fn lot_class(org_id: i32, lot: &str) -> String {
+    format!("{}lot:{}:{}", NAMESPACE, org_id, percent_encode(lot))
+}
+
+// Event times are dates only; they are written as midnight UTC
+pub fn event_time(date: &str) -> String {
+    format!("{}T00:00:00.000Z", date)
+}
+
+// UN/CEFACT Recommendation 20 codes used for EPCIS quantities
+pub fn uom_code(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Milligram => "MGM",
+        Unit::Gram => "GRM",
+        Unit::Kilogram => "KGM",
+        Unit::Ounce => "ONZ",
+        Unit::Pound => "LBR",
+        Unit::Milliliter => "MLT",
+        Unit::Liter => "LTR",
+        Unit::Teaspoon => "G25",
+        Unit::Tablespoon => "G24",
+        Unit::FluidOunce => "OZA",
+        Unit::Cup => "G21",
+        Unit::Pint => "PTL",
+        Unit::Quart => "QTL",
+        Unit::Gallon => "GLL",
+        Unit::Each => "H87",
+        Unit::Dozen => "DZN",
+    }
+}
+
+pub fn unit_from_uom(code: &str) -> Option<Unit> {
+    let unit = match code.trim().to_uppercase().as_str() {
+        "MGM" => Unit::Milligram,
+        "GRM" => Unit::Gram,
+        "KGM" => Unit::Kilogram,
+        "ONZ" => Unit::Ounce,
+        "LBR" => Unit::Pound,
+        "MLT" => Unit::Milliliter,
+        "LTR" => Unit::Liter,
+        "G25" => Unit::Teaspoon,
+        "G24" => Unit::Tablespoon,
+        "OZA" => Unit::FluidOunce,
+        "G21" => Unit::Cup,
+        "PTL" => Unit::Pint,
+        "QTL" => Unit::Quart,
+        "GLL" => Unit::Gallon,
+        "H87" | "EA" | "C62" => Unit::Each,
+        "DZN" => Unit::Dozen,
+        _ => return None,
+    };
+    Some(unit)
+}
+
+// An EPCIS quantityList entry for a quantity, or without one when unknown
+pub fn quantity_element(epc_class: &str, quantity: Option<Quantity>) -> Value {
+    match quantity {
+        Some(quantity) => json!({
+            "epcClass": epc_class,
+            "quantity": quantity.value,
+            "uom": uom_code(quantity.unit)
+        }),
+        None => json!({"epcClass": epc_class}),
+    }
+}
+
+// A lot delivered by a supplier's shipping event
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShippedLot {
+    pub event_id: Option<String>,
+    pub lot: String,
+    pub item_name: String,
+    pub quantity: Option<Quantity>,
+    // The shipping party, when the event names one
+    pub source: Option<String>,
+    // The receiving party, when the event names one
+    pub destination: Option<String>,
+    // Day of the eventTime, in the event's own time zone
+    pub event_date: Option<String>,
+    pub expiry_date: Option<String>,
+    pub temperature: Option<Temperature>,
+}
+
+// The lots shipped by the shipping events of an EPCIS document. Other events
+// are ignored. Errors name the offending event by its position.
+pub fn shipped_lots(document: &Value) -> Result<Vec<ShippedLot>, String> {
+    if document.get("type").and_then(Value::as_str) != Some("EPCISDocument") {
+        return Err("Not an EPCIS document".to_string());
+    }
+    let events = document
+        .pointer("/epcisBody/eventList")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "EPCIS document has no epcisBody.eventList".to_string())?;
+
+    let mut lots = Vec::new();
+    for (index, event) in events.iter().enumerate() {
+        let is_shipping = event.get("bizStep")
+            .and_then(Value::as_str)
+            .is_some_and(|biz_step| biz_step == "shipping" || biz_step.ends_with(":shipping"));
+        if event.get("type").and_then(Value::as_str) != Some("ObjectEvent") || !is_shipping {
+            continue;
+        }
+
+        let event_id = event.get("eventID").and_then(Value::as_str).map(str::to_string);
+        let quantities = event.get("quantityList").and_then(Value::as_array).filter(|list| !list.is_empty());
+        let quantities = match quantities {
+            Some(quantities) => quantities,
+            None => return Err(format!("Shipping event {} has no quantityList with lots", index + 1)),
+        };
+        let source = party(event, "sourceList", "source");
+        let destination = party(event, "destinationList", "destination");
+        let event_date = match event.get("eventTime").and_then(Value::as_str) {
+            Some(event_time) => Some(
+                event_date(event_time, event.get("eventTimeZoneOffset").and_then(Value::as_str))
+                    .ok_or_else(|| format!("Shipping event {}: invalid eventTime {:?}", index + 1, event_time))?,
+            ),
+            None => None,
+        };
+        let expiry_date = event.pointer("/ilmd/cbvmda:itemExpirationDate")
+            .or_else(|| event.pointer("/ilmd/itemExpirationDate"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let temperature = sensor_temperature(event);
+        let product_name = event.get(format!("{}:productName", EXTENSION_PREFIX).as_str()).and_then(Value::as_str);
+
+        for element in quantities {
+            let epc_class = element.get("epcClass").and_then(Value::as_str).unwrap_or_default();
+            let (product, lot) = match split_epc_class(epc_class) {
+                Some(parts) => parts,
+                None => return Err(format!("Shipping event {}: no lot in epcClass {:?}", index + 1, epc_class)),
+            };
+            let quantity = match element.get("quantity") {
+                Some(value) => {
+                    let value = Decimal::from_str(&value.to_string())
+                        .map_err(|_| format!("Shipping event {}: invalid quantity {}", index + 1, value))?;
+                    let uom = element.get("uom").and_then(Value::as_str).unwrap_or("H87");
+                    let unit = unit_from_uom(uom)
+                        .ok_or_else(|| format!("Shipping event {}: unsupported uom {:?}", index + 1, uom))?;
+                    Some(Quantity::new(value, unit))
+                }
+                None => None,
+            };
+            lots.push(ShippedLot {
+                event_id: event_id.clone(),
+                lot,
+                item_name: product_name.map(str::to_string).unwrap_or(product),
+                quantity,
+                source: source.clone(),
+                destination: destination.clone(),
+                event_date: event_date.clone(),
+                expiry_date: expiry_date.clone(),
+                temperature,
+            });
+        }
+    }
+    Ok(lots)
+}
+
+// Product and lot of an EPC class. Understands GS1 LGTIN URNs, GS1 Digital
+// Link URIs and lot classes of this service.
+pub fn split_epc_class(epc_class: &str) -> Option<(String, String)> {
+    if let Some(rest) = epc_class.strip_prefix("urn:epc:class:lgtin:") {
+        let mut parts = rest.splitn(3, '.');
+        let (prefix, item, lot) = (parts.next()?, parts.next()?, parts.next()?);
+        return Some((format!("{}.{}", prefix, item), percent_decode(lot)));
+    }
+    if let Some(rest) = epc_class.strip_prefix(&format!("{}lot:", NAMESPACE)) {
+        let (_, lot) = rest.split_once(':')?;
+        let lot = percent_decode(lot);
+        return Some((lot.clone(), lot));
+    }
+    let (before, after) = epc_class.split_once("/01/")?;
+    if !before.starts_with("http") {
+        return None;
+    }
+    let (gtin, lot) = after.split_once("/10/")?;
+    let lot = lot.split(['/', '?']).next()?;
+    Some((format!("GTIN {}", gtin), percent_decode(lot)))
+}
+
+// The day of an eventTime in the event's time zone offset, e.g. "+02:00"
+fn event_date(event_time: &str, offset: Option<&str>) -> Option<String> {
+    let time = chrono::DateTime::parse_from_rfc3339(event_time).ok()?;
+    let offset = offset
+        .and_then(|offset| chrono::DateTime::parse_from_rfc3339(&format!("2000-01-01T00:00:00{}", offset)).ok())
+        .map(|anchor| *anchor.offset())
+        .unwrap_or(*time.offset());
+    Some(time.with_timezone(&offset).date_naive().to_string())
+}
+
+// Whether a shipping event's destination party is this organization: its own
+// identifier or its name. Identifiers from other schemes, such as GS1 PGLNs,
+// cannot be resolved and are accepted.
+pub fn is_own_destination(destination: &str, org_id: i32, org_name: &str) -> bool {
+    if let Some(id) = destination.strip_prefix(&format!("{}org:", NAMESPACE)) {
+        return id == org_id.to_string();
+    }
+    if destination.starts_with("urn:") || destination.starts_with("http") {
+        return true;
+    }
+    destination.trim().to_lowercase() == org_name.trim().to_lowercase()
+}
+
+// The owning or possessing party of a source or destination list
+fn party(event: &Value, list: &str, field: &str) -> Option<String> {
+    let entries = event.get(list)?.as_array()?;
+    let entry = entries.iter()
+        .find(|entry| entry.get("type").and_then(Value::as_str).is_some_and(|kind| kind.ends_with("owning_party")))
+        .or_else(|| entries.first())?;
+    let value = entry.get(field)?.as_str()?;
+    let name = match value.strip_prefix(&format!("{}party:", NAMESPACE)) {
+        Some(name) => percent_decode(name),
+        None => value.to_string(),
+    };
+    Some(name)
+}
+
+// The first temperature reading of an event's sensor data
+fn sensor_temperature(event: &Value) -> Option<Temperature> {
+    let elements = event.get("sensorElementList")?.as_array()?;
+    elements.iter()
+        .filter_map(|element| element.get("sensorReport")?.as_array())
+        .flatten()
+        .filter(|report| report.get("type").and_then(Value::as_str).is_some_and(|kind| kind.ends_with("Temperature")))
+        .find_map(|report| {
+            let value = Decimal::from_str(&report.get("value")?.to_string()).ok()?;
+            let unit = match report.get("uom").and_then(Value::as_str)? {
+                "CEL" => TemperatureUnit::Celsius,
+                "FAH" => TemperatureUnit::Fahrenheit,
+                _ => return None,
+            };
+            Some(Temperature::new(value, unit))
+        })
+}
+
+// Extension fields of this service, e.g. hz:productName
+pub fn extensions<'a, I>(fields: I) -> Map<String, Value>
+where
+    I: IntoIterator<Item = (&'a str, Value)>,
+{
+    fields.into_iter()
+        .map(|(name, value)| (format!("{}:{}", EXTENSION_PREFIX, name), value))
+        .collect()
+}
+
+pub fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = value.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
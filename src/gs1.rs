@@ -0,0 +1,256 @@
+use crate::units::{Quantity, Unit};
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// Error types
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Gs1Error {
+    #[error("Scan is empty")]
+    Empty,
+
+    #[error("Unsupported application identifier ({0})")]
+    UnsupportedAi(String),
+
+    #[error("Scan ends inside application identifier ({0})")]
+    Truncated(String),
+
+    #[error("Invalid value for application identifier ({ai}): {reason}")]
+    InvalidValue { ai: String, reason: String },
+
+    #[error("Application identifier ({0}) appears more than once")]
+    Duplicate(String),
+}
+
+// FNC1 separator ending variable-length fields in raw scans
+const GROUP_SEPARATOR: char = '\u{1d}';
+
+// GS1 AI encodable character set 82, allowed in alphanumeric fields such as
+// (10) batch/lot and (21) serial number
+pub const GS1_CHARACTERS: &str = "!\"%&'()*+,-./0123456789:;<=>?ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+// Symbology identifiers scanners may prefix: GS1-128, GS1 DataMatrix, GS1 QR
+const SYMBOLOGY_IDENTIFIERS: [&str; 3] = ["]C1", "]d2", "]Q3"];
+
+// Element strings read from a case label
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Gs1Scan {
+    pub gtin: Option<String>,
+    pub lot: Option<String>,
+    pub expiry_date: Option<NaiveDate>,
+    pub net_weight: Option<Quantity>,
+    pub serial: Option<String>,
+}
+
+enum Length {
+    Fixed(usize),
+    Variable(usize),
+}
+
+// Supported AIs: (01) GTIN, (10) batch/lot, (17) expiry date, (21) serial,
+// (310n) net weight in kg and (320n) net weight in lb with n decimals
+fn ai_length(ai: &str) -> Option<Length> {
+    match ai {
+        "01" => Some(Length::Fixed(14)),
+        "10" | "21" => Some(Length::Variable(20)),
+        "17" => Some(Length::Fixed(6)),
+        _ if is_weight_ai(ai) => Some(Length::Fixed(6)),
+        _ => None,
+    }
+}
+
+// Length of an AI from its first two digits, per the GS1 General
+// Specifications prefix table; unknown prefixes are read as two digits
+fn ai_prefix_length(scan: &str) -> usize {
+    match scan.get(..2) {
+        Some("23" | "24" | "25" | "40" | "41" | "42" | "71") => 3,
+        Some("31" | "32" | "33" | "34" | "35" | "36" | "39" | "43" | "70" | "72" | "80" | "81" | "82") => 4,
+        _ => 2,
+    }
+}
+
+fn is_weight_ai(ai: &str) -> bool {
+    ai.len() == 4 && (ai.starts_with("310") || ai.starts_with("320")) && ai.as_bytes()[3].is_ascii_digit()
+}
+
+// Parse a raw scan (FNC1 as the ASCII group separator, or "<GS>") or the
+// human-readable form with AIs in parentheses, e.g. "(01)09501101530003(10)AB12"
+pub fn parse(scan: &str) -> Result<Gs1Scan, Gs1Error> {
+    let mut scan = scan.trim().replace("<GS>", &GROUP_SEPARATOR.to_string());
+    if let Some(identifier) = SYMBOLOGY_IDENTIFIERS.iter().find(|identifier| scan.starts_with(*identifier)) {
+        scan = scan[identifier.len()..].to_string();
+    }
+    let scan = scan.trim_start_matches(GROUP_SEPARATOR);
+    if scan.is_empty() {
+        return Err(Gs1Error::Empty);
+    }
+
+    let elements = if scan.starts_with('(') {
+        split_bracketed(scan)?
+    } else {
+        split_raw(scan)?
+    };
+
+    let mut parsed = Gs1Scan::default();
+    for (ai, value) in elements {
+        apply(&mut parsed, &ai, &value)?;
+    }
+    Ok(parsed)
+}
+
+fn split_bracketed(scan: &str) -> Result<Vec<(String, String)>, Gs1Error> {
+    let mut elements = Vec::new();
+    let mut rest = scan;
+    while !rest.is_empty() {
+        let close = rest.find(')').filter(|_| rest.starts_with('(')).ok_or_else(|| Gs1Error::InvalidValue {
+            ai: rest.chars().take(4).collect(),
+            reason: "expected an application identifier in parentheses".to_string(),
+        })?;
+        let ai = &rest[1..close];
+        let value_end = rest[close + 1..].find('(').map_or(rest.len(), |i| close + 1 + i);
+        let value = &rest[close + 1..value_end];
+        match ai_length(ai) {
+            Some(Length::Fixed(length)) if value.len() != length => {
+                return Err(Gs1Error::InvalidValue {
+                    ai: ai.to_string(),
+                    reason: format!("expected {} characters", length),
+                });
+            }
+            Some(_) => {}
+            None => return Err(Gs1Error::UnsupportedAi(ai.to_string())),
+        }
+        elements.push((ai.to_string(), value.to_string()));
+        rest = &rest[value_end..];
+    }
+    Ok(elements)
+}
+
+fn split_raw(scan: &str) -> Result<Vec<(String, String)>, Gs1Error> {
+    let mut elements = Vec::new();
+    let mut rest = scan;
+    while !rest.is_empty() {
+        let ai = rest.get(..ai_prefix_length(rest)).ok_or_else(|| Gs1Error::Truncated(rest.to_string()))?;
+        let ai_len = ai.len();
+        let after = &rest[ai_len..];
+        let value_len = match ai_length(ai) {
+            Some(Length::Fixed(length)) => {
+                if after.len() < length {
+                    return Err(Gs1Error::Truncated(ai.to_string()));
+                }
+                length
+            }
+            Some(Length::Variable(_)) => after.find(GROUP_SEPARATOR).unwrap_or(after.len()),
+            None => return Err(Gs1Error::UnsupportedAi(ai.to_string())),
+        };
+        let value = after.get(..value_len).ok_or_else(|| Gs1Error::InvalidValue {
+            ai: ai.to_string(),
+            reason: "contains characters outside the GS1 character set".to_string(),
+        })?;
+        elements.push((ai.to_string(), value.to_string()));
+        rest = after[value_len..].trim_start_matches(GROUP_SEPARATOR);
+    }
+    Ok(elements)
+}
+
+fn apply(parsed: &mut Gs1Scan, ai: &str, value: &str) -> Result<(), Gs1Error> {
+    let invalid = |reason: &str| Gs1Error::InvalidValue { ai: ai.to_string(), reason: reason.to_string() };
+
+    match ai {
+        "01" => {
+            if !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid("a GTIN is 14 digits"));
+            }
+            if !check_digit_valid(value) {
+                return Err(invalid("check digit does not match"));
+            }
+            set(&mut parsed.gtin, ai, value.to_string())
+        }
+        "10" | "21" => {
+            if let Some(Length::Variable(max)) = ai_length(ai) {
+                if value.is_empty() || value.len() > max {
+                    return Err(invalid(&format!("expected 1 to {} characters", max)));
+                }
+            }
+            if !value.chars().all(|c| GS1_CHARACTERS.contains(c)) {
+                return Err(invalid("contains characters outside the GS1 character set"));
+            }
+            let field = if ai == "10" { &mut parsed.lot } else { &mut parsed.serial };
+            set(field, ai, value.to_string())
+        }
+        "17" => {
+            let today = chrono::Local::now().date_naive();
+            let date = parse_date(value, today).ok_or_else(|| invalid("expected a date as YYMMDD"))?;
+            set(&mut parsed.expiry_date, ai, date)
+        }
+        _ if is_weight_ai(ai) => {
+            // Digits only: a sign would otherwise parse as a negative weight
+            if !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid("a weight is 6 digits"));
+            }
+            let digits: i64 = value.parse().map_err(|_| invalid("a weight is 6 digits"))?;
+            let decimals = u32::from(ai.as_bytes()[3] - b'0');
+            if decimals > 5 {
+                return Err(invalid("at most 5 decimal places"));
+            }
+            let unit = if ai.starts_with("310") { Unit::Kilogram } else { Unit::Pound };
+            set(&mut parsed.net_weight, ai, Quantity::new(Decimal::new(digits, decimals).normalize(), unit))
+        }
+        _ => Err(Gs1Error::UnsupportedAi(ai.to_string())),
+    }
+}
+
+fn set<T>(field: &mut Option<T>, ai: &str, value: T) -> Result<(), Gs1Error> {
+    if field.is_some() {
+        return Err(Gs1Error::Duplicate(ai.to_string()));
+    }
+    *field = Some(value);
+    Ok(())
+}
+
+// A GTIN-8, -12, -13 or -14 as 14 digits, checking its check digit
+pub fn normalize_gtin(gtin: &str) -> Result<String, Gs1Error> {
+    let gtin = gtin.trim();
+    let invalid = |reason: &str| Gs1Error::InvalidValue { ai: "01".to_string(), reason: reason.to_string() };
+    if !matches!(gtin.len(), 8 | 12 | 13 | 14) || !gtin.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid("a GTIN is 8, 12, 13 or 14 digits"));
+    }
+    let gtin = format!("{:0>14}", gtin);
+    if !check_digit_valid(&gtin) {
+        return Err(invalid("check digit does not match"));
+    }
+    Ok(gtin)
+}
+
+// GS1 mod-10 check digit over the 13 leading digits
+fn check_digit_valid(gtin: &str) -> bool {
+    let digits: Vec<u32> = gtin.chars().filter_map(|c| c.to_digit(10)).collect();
+    let (body, check) = digits.split_at(digits.len() - 1);
+    let sum: u32 = body.iter().rev().enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+    (10 - sum % 10) % 10 == check[0]
+}
+
+// YYMMDD. The century follows the GS1 sliding window: a year more than 50
+// years ahead of today is in the last century, one more than 49 years
+// behind is in the next. A day of 00 means the last day of the month.
+pub fn parse_date(value: &str, today: NaiveDate) -> Option<NaiveDate> {
+    if value.len() != 6 || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let two_digit_year = value[0..2].parse::<i32>().ok()?;
+    let century = today.year() - today.year().rem_euclid(100);
+    let year = match two_digit_year - today.year().rem_euclid(100) {
+        difference if difference >= 51 => century - 100 + two_digit_year,
+        difference if difference <= -50 => century + 100 + two_digit_year,
+        _ => century + two_digit_year,
+    };
+    let month = value[2..4].parse::<u32>().ok()?;
+    let day = value[4..6].parse::<u32>().ok()?;
+    if day == 0 {
+        let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let next_month = first.with_month(month % 12 + 1)?.with_year(if month == 12 { year + 1 } else { year })?;
+        return next_month.pred_opt();
+    }
+    NaiveDate::from_ymd_opt(year, month, day)
+}
@@ -0,0 +1,245 @@
+use crate::units::{Quantity, Temperature, TemperatureUnit, Unit};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+// Likelihood and severity are rated on a 1-5 scale
+pub const RATING_RANGE: std::ops::RangeInclusive<i32> = 1..=5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HazardType {
+    Biological,
+    Chemical,
+    Physical,
+}
+
+impl HazardType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HazardType::Biological => "biological",
+            HazardType::Chemical => "chemical",
+            HazardType::Physical => "physical",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<HazardType> {
+        match value {
+            "biological" => Some(HazardType::Biological),
+            "chemical" => Some(HazardType::Chemical),
+            "physical" => Some(HazardType::Physical),
+            _ => None,
+        }
+    }
+}
+
+// A step of the process flow diagram
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProcessStep {
+    pub step_number: i32,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+// A hazard identified at a process step
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hazard {
+    pub step_number: i32,
+    pub hazard_type: HazardType,
+    pub description: String,
+    pub likelihood: i32,
+    pub severity: i32,
+    // Set on output: likelihood x severity
+    #[serde(default)]
+    pub risk_score: i32,
+    pub control_measure: Option<String>,
+    pub justification: Option<String>,
+}
+
+impl Hazard {
+    pub fn with_risk_score(mut self) -> Hazard {
+        self.risk_score = self.likelihood * self.severity;
+        self
+    }
+}
+
+// A measurable bound a CCP must stay within, e.g. core temperature >= 74 C
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CriticalLimit {
+    // Set on output
+    #[serde(default)]
+    pub id: Option<i32>,
+    pub parameter: String,
+    pub min_value: Option<Decimal>,
+    pub max_value: Option<Decimal>,
+    // e.g. "C", "F", "kg", "min" or "pH"
+    pub unit: Option<String>,
+}
+
+// What is checked, how, how often and by whom
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MonitoringProcedure {
+    pub what: String,
+    pub how: String,
+    pub frequency: String,
+    pub responsible: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VerificationActivity {
+    pub activity: String,
+    pub frequency: String,
+    pub responsible: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CriticalControlPoint {
+    // Set on output
+    #[serde(default)]
+    pub id: Option<i32>,
+    // e.g. "CCP-1B"
+    pub ccp_number: String,
+    pub step_number: i32,
+    // The hazard the CCP controls
+    pub hazard: String,
+    pub critical_limits: Vec<CriticalLimit>,
+    pub monitoring: MonitoringProcedure,
+    pub corrective_actions: Vec<String>,
+    #[serde(default)]
+    pub verification_activities: Vec<VerificationActivity>,
+}
+
+// Check a plan hangs together: hazards and CCPs sit on known steps, ratings
+// are on the scale and every CCP has limits, monitoring and a corrective action
+pub fn validate_plan(
+    steps: &[ProcessStep],
+    hazards: &[Hazard],
+    ccps: &[CriticalControlPoint],
+) -> Result<(), String> {
+    let mut step_numbers = HashSet::new();
+    for step in steps {
+        if step.step_number <= 0 {
+            return Err("Step numbers must be positive".to_string());
+        }
+        if step.name.trim().is_empty() {
+            return Err(format!("Step {} needs a name", step.step_number));
+        }
+        if !step_numbers.insert(step.step_number) {
+            return Err("Step numbers must be unique".to_string());
+        }
+    }
+
+    for hazard in hazards {
+        if !step_numbers.contains(&hazard.step_number) {
+            return Err(format!("Hazard {:?} is at unknown step {}", hazard.description, hazard.step_number));
+        }
+        if hazard.description.trim().is_empty() {
+            return Err(format!("Hazards at step {} need a description", hazard.step_number));
+        }
+        if !RATING_RANGE.contains(&hazard.likelihood) || !RATING_RANGE.contains(&hazard.severity) {
+            return Err(format!("Hazard {:?}: likelihood and severity must be between 1 and 5", hazard.description));
+        }
+    }
+
+    let mut ccp_numbers = HashSet::new();
+    for ccp in ccps {
+        let ccp_number = ccp.ccp_number.trim();
+        if ccp_number.is_empty() {
+            return Err("Each CCP needs a ccp_number".to_string());
+        }
+        if !ccp_numbers.insert(ccp_number.to_lowercase()) {
+            return Err(format!("CCP number {} is used twice", ccp_number));
+        }
+        if !step_numbers.contains(&ccp.step_number) {
+            return Err(format!("{} is at unknown step {}", ccp_number, ccp.step_number));
+        }
+        if ccp.hazard.trim().is_empty() {
+            return Err(format!("{} needs the hazard it controls", ccp_number));
+        }
+        if ccp.critical_limits.is_empty() {
+            return Err(format!("{} needs at least one critical limit", ccp_number));
+        }
+        for limit in &ccp.critical_limits {
+            if limit.parameter.trim().is_empty() {
+                return Err(format!("{}: critical limits need a parameter", ccp_number));
+            }
+            match (limit.min_value, limit.max_value) {
+                (None, None) => {
+                    return Err(format!("{}: {} needs a min_value or max_value", ccp_number, limit.parameter));
+                }
+                (Some(min), Some(max)) if min > max => {
+                    return Err(format!("{}: {} min_value is above max_value", ccp_number, limit.parameter));
+                }
+                _ => {}
+            }
+        }
+        let monitoring = &ccp.monitoring;
+        if [&monitoring.what, &monitoring.how, &monitoring.frequency].iter().any(|field| field.trim().is_empty()) {
+            return Err(format!("{}: monitoring needs what, how and frequency", ccp_number));
+        }
+        if ccp.corrective_actions.iter().all(|action| action.trim().is_empty()) {
+            return Err(format!("{} needs at least one corrective action", ccp_number));
+        }
+        if ccp.verification_activities.iter().any(|verification| {
+            verification.activity.trim().is_empty() || verification.frequency.trim().is_empty()
+        }) {
+            return Err(format!("{}: verification activities need an activity and frequency", ccp_number));
+        }
+    }
+    Ok(())
+}
+
+// A value measured at a CCP check: a number in the limit's unit, text with a
+// unit such as "72C" or "2.5 kg" that is converted to the limit's unit, or
+// true/false for pass/fail checks, read as 1/0
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MeasuredValue {
+    Flag(bool),
+    Number(Decimal),
+    Text(String),
+}
+
+impl fmt::Display for MeasuredValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeasuredValue::Flag(passed) => write!(f, "{}", passed),
+            MeasuredValue::Number(number) => write!(f, "{}", number.normalize()),
+            MeasuredValue::Text(text) => f.write_str(text.trim()),
+        }
+    }
+}
+
+// The measured value in the unit of the critical limit
+pub fn value_in_limit_unit(limit: &CriticalLimit, value: &MeasuredValue) -> Result<Decimal, String> {
+    let text = match value {
+        MeasuredValue::Flag(passed) => return Ok(if *passed { Decimal::ONE } else { Decimal::ZERO }),
+        MeasuredValue::Number(number) => return Ok(*number),
+        MeasuredValue::Text(text) => text.trim(),
+    };
+    let unit = limit.unit.as_deref().map(str::trim).unwrap_or_default();
+    let unreadable = || format!("{}: cannot read {:?} as a value in {}", limit.parameter, text, unit);
+
+    if let Ok(number) = Decimal::from_str(text) {
+        return Ok(number);
+    }
+    if let Ok(target) = TemperatureUnit::parse(unit) {
+        let temperature = Temperature::parse(text).map_err(|_| unreadable())?;
+        return temperature.convert(target).map(|temperature| temperature.value).map_err(|e| format!("{}: {}", limit.parameter, e));
+    }
+    if let Ok(target) = Unit::parse(unit) {
+        let quantity = Quantity::parse(text).map_err(|_| unreadable())?;
+        return quantity.convert(target, None).map(|quantity| quantity.value).map_err(|e| format!("{}: {}", limit.parameter, e));
+    }
+    // Other units, e.g. "4.2 pH", must match the limit's unit
+    text.strip_suffix(unit)
+        .filter(|_| !unit.is_empty())
+        .and_then(|number| Decimal::from_str(number.trim()).ok())
+        .ok_or_else(unreadable)
+}
+
+pub fn within_limit(limit: &CriticalLimit, value: Decimal) -> bool {
+    limit.min_value.is_none_or(|min| value >= min) && limit.max_value.is_none_or(|max| value <= max)
+}
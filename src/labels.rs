@@ -0,0 +1,359 @@
+use crate::gs1::GS1_CHARACTERS;
+use chrono::NaiveDate;
+use qrcode::{Color, EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+
+// Error types
+#[derive(Debug, thiserror::Error)]
+pub enum LabelError {
+    #[error("Lot code {0:?} cannot be encoded in a GS1-128 barcode")]
+    UnencodableLot(String),
+
+    #[error("QR code link is too long: {0}")]
+    QrCode(String),
+}
+
+// What goes on a batch label
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LabelContent {
+    pub product: String,
+    pub lot_code: String,
+    pub date_made: NaiveDate,
+    pub best_by: Option<NaiveDate>,
+    // Allergens the product contains, then those it may contain
+    pub contains: Vec<String>,
+    pub may_contain: Vec<String>,
+    pub gtin: Option<String>,
+    pub link: String,
+}
+
+// Size and elements of a label, from an organization's label template
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LabelLayout {
+    pub width_mm: f64,
+    pub height_mm: f64,
+    // Printer resolution in dots per inch
+    pub dpi: i32,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    pub show_allergens: bool,
+    pub show_barcode: bool,
+    pub show_qr: bool,
+}
+
+// GS1-128 element strings: (01) GTIN when known, (11) production date,
+// (15) best before and (10) batch/lot. The lot is variable length and last.
+pub fn gs1_elements(content: &LabelContent) -> Vec<(&'static str, String)> {
+    let mut elements = Vec::new();
+    if let Some(gtin) = &content.gtin {
+        elements.push(("01", gtin.clone()));
+    }
+    elements.push(("11", content.date_made.format("%y%m%d").to_string()));
+    if let Some(best_by) = content.best_by {
+        elements.push(("15", best_by.format("%y%m%d").to_string()));
+    }
+    elements.push(("10", content.lot_code.clone()));
+    elements
+}
+
+// Human-readable form, e.g. (01)09501101530003(11)250401(10)B-42
+pub fn gs1_text(elements: &[(&str, String)]) -> String {
+    elements.iter().map(|(ai, value)| format!("({}){}", ai, value)).collect()
+}
+
+// Text lines printed above the barcode
+fn text_lines(content: &LabelContent, layout: &LabelLayout) -> Vec<(String, bool)> {
+    let mut lines = Vec::new();
+    if let Some(header) = &layout.header {
+        lines.push((header.clone(), false));
+    }
+    lines.push((content.product.clone(), true));
+    lines.push((format!("Lot: {}", content.lot_code), false));
+    lines.push((format!("Made: {}", content.date_made), false));
+    if let Some(best_by) = content.best_by {
+        lines.push((format!("Best by: {}", best_by), false));
+    }
+    if layout.show_allergens {
+        if !content.contains.is_empty() {
+            lines.push((format!("Contains: {}", content.contains.join(", ")), true));
+        }
+        if !content.may_contain.is_empty() {
+            lines.push((format!("May contain: {}", content.may_contain.join(", ")), false));
+        }
+    }
+    lines
+}
+
+// Code 128 bar and space widths for symbol values 0 to 106 (stop)
+const CODE128_PATTERNS: [&str; 107] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+// (10) holds at most 20 characters
+const MAX_LOT_LENGTH: usize = 20;
+
+const CODE_B: u8 = 100;
+const FNC1: u8 = 102;
+const START_C: u8 = 105;
+const STOP: u8 = 106;
+
+// Symbol values of a GS1-128 barcode: start C, FNC1, the numeric element
+// strings in code set C and the lot in code set B unless it is an even
+// number of digits
+pub fn code128_values(elements: &[(&str, String)]) -> Result<Vec<u8>, LabelError> {
+    let mut values = vec![START_C, FNC1];
+    let mut digits = String::new();
+    let mut text = String::new();
+    for (index, (ai, value)) in elements.iter().enumerate() {
+        if *ai == "10" && (value.len() > MAX_LOT_LENGTH || !value.chars().all(|c| GS1_CHARACTERS.contains(c))) {
+            return Err(LabelError::UnencodableLot(value.clone()));
+        }
+        let numeric = value.bytes().all(|byte| byte.is_ascii_digit()) && value.len() % 2 == 0;
+        if numeric && text.is_empty() {
+            digits.push_str(ai);
+            digits.push_str(value);
+        } else if index == elements.len() - 1 {
+            digits.push_str(ai);
+            text.push_str(value);
+        } else {
+            return Err(LabelError::UnencodableLot(value.clone()));
+        }
+    }
+
+    for pair in digits.as_bytes().chunks(2) {
+        values.push((pair[0] - b'0') * 10 + (pair[1] - b'0'));
+    }
+    if !text.is_empty() {
+        values.push(CODE_B);
+        for byte in text.bytes() {
+            values.push(byte - 32);
+        }
+    }
+
+    let checksum = values.iter().enumerate()
+        .map(|(position, value)| u32::from(*value) * (position.max(1) as u32))
+        .sum::<u32>() % 103;
+    values.push(checksum as u8);
+    values.push(STOP);
+    Ok(values)
+}
+
+// Bars of a barcode as module widths, alternating bar and space, starting
+// with a bar
+pub fn code128_modules(values: &[u8]) -> Vec<u8> {
+    values.iter()
+        .flat_map(|value| CODE128_PATTERNS[usize::from(*value)].bytes().map(|width| width - b'0'))
+        .collect()
+}
+
+// Dark modules of the QR code for a link, row by row
+fn qr_matrix(link: &str) -> Result<(usize, Vec<bool>), LabelError> {
+    let code = QrCode::with_error_correction_level(link, EcLevel::M).map_err(|e| LabelError::QrCode(e.to_string()))?;
+    let colors = code.to_colors().into_iter().map(|color| color == Color::Dark).collect();
+    Ok((code.width(), colors))
+}
+
+// ZPL II for thermal printers
+pub fn to_zpl(content: &LabelContent, layout: &LabelLayout) -> Result<String, LabelError> {
+    let dots = |mm: f64| (mm / 25.4 * f64::from(layout.dpi)).round() as i64;
+    let width = dots(layout.width_mm);
+    let height = dots(layout.height_mm);
+    let margin = dots(3.0);
+    let line_height = dots(4.0);
+
+    let mut zpl = format!("^XA\n^CI28\n^PW{}\n^LL{}\n", width, height);
+    let mut y = margin;
+    for (text, bold) in text_lines(content, layout) {
+        let size = if bold { line_height } else { line_height * 4 / 5 };
+        zpl.push_str(&format!(
+            "^FO{},{}^A0N,{},{}^FB{},1,0,L^FH^FD{}^FS\n",
+            margin, y, size, size, width - 2 * margin, zpl_text(&text)
+        ));
+        y += size + dots(1.0);
+    }
+
+    if layout.show_barcode {
+        let elements = gs1_elements(content);
+        // Validate the data can be encoded before handing it to the printer
+        code128_values(&elements)?;
+        let mut data = String::from(">;>8");
+        for (ai, value) in &elements {
+            if *ai == "10" && !(value.bytes().all(|byte| byte.is_ascii_digit()) && value.len() % 2 == 0) {
+                data.push_str(ai);
+                data.push_str(">6");
+                data.push_str(&value.replace('>', "><"));
+            } else {
+                data.push_str(ai);
+                data.push_str(value);
+            }
+        }
+        zpl.push_str(&format!(
+            "^FO{},{}^BY2^BCN,{},N,N,N^FH^FD{}^FS\n",
+            margin, y, dots(12.0), zpl_text(&data)
+        ));
+        zpl.push_str(&format!(
+            "^FO{},{}^A0N,{},{}^FH^FD{}^FS\n",
+            margin, y + dots(13.0), line_height * 3 / 5, line_height * 3 / 5, zpl_text(&gs1_text(&elements))
+        ));
+    }
+
+    if layout.show_qr {
+        qr_matrix(&content.link)?;
+        let magnification = (dots(20.0) / 33).clamp(1, 10);
+        zpl.push_str(&format!(
+            "^FO{},{}^BQN,2,{}^FH^FDMA,{}^FS\n",
+            width - margin - dots(22.0), margin, magnification, zpl_text(&content.link)
+        ));
+    }
+
+    if let Some(footer) = &layout.footer {
+        zpl.push_str(&format!(
+            "^FO{},{}^A0N,{},{}^FH^FD{}^FS\n",
+            margin, height - margin - line_height, line_height * 4 / 5, line_height * 4 / 5, zpl_text(footer)
+        ));
+    }
+    zpl.push_str("^XZ\n");
+    Ok(zpl)
+}
+
+// Field data with ZPL control characters hex-escaped for ^FH
+fn zpl_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '_' => "_5F".to_string(),
+            '^' => "_5E".to_string(),
+            '~' => "_7E".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+// A single-page PDF of the label at its actual size
+pub fn to_pdf(content: &LabelContent, layout: &LabelLayout) -> Result<Vec<u8>, LabelError> {
+    let points = |mm: f64| mm * 72.0 / 25.4;
+    let width = points(layout.width_mm);
+    let height = points(layout.height_mm);
+    let margin = points(3.0);
+    let line_height = points(4.0);
+
+    let mut page = String::new();
+    let mut y = height - margin;
+    for (text, bold) in text_lines(content, layout) {
+        let size = if bold { line_height } else { line_height * 0.8 };
+        y -= size;
+        page.push_str(&format!(
+            "BT /{} {:.2} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+            if bold { "F2" } else { "F1" }, size, margin, y, pdf_text(&text)
+        ));
+        y -= points(1.0);
+    }
+
+    if layout.show_barcode {
+        let elements = gs1_elements(content);
+        let modules = code128_modules(&code128_values(&elements)?);
+        let total: u32 = modules.iter().map(|width| u32::from(*width)).sum::<u32>() + 20;
+        let module = ((width - 2.0 * margin) / f64::from(total)).min(points(0.5));
+        let bar_height = points(12.0);
+        let mut x = margin + 10.0 * module;
+        y -= bar_height;
+        for (index, modules_wide) in modules.iter().enumerate() {
+            let bar_width = f64::from(*modules_wide) * module;
+            if index % 2 == 0 {
+                page.push_str(&format!("{:.3} {:.3} {:.3} {:.3} re f\n", x, y, bar_width, bar_height));
+            }
+            x += bar_width;
+        }
+        y -= line_height * 0.6 + points(1.0);
+        page.push_str(&format!(
+            "BT /F1 {:.2} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+            line_height * 0.6, margin, y, pdf_text(&gs1_text(&elements))
+        ));
+    }
+
+    if layout.show_qr {
+        let (size, dark) = qr_matrix(&content.link)?;
+        let module = points(20.0) / size as f64;
+        let left = width - margin - points(20.0);
+        let top = height - margin;
+        for (index, _) in dark.iter().enumerate().filter(|(_, dark)| **dark) {
+            let (row, column) = (index / size, index % size);
+            page.push_str(&format!(
+                "{:.3} {:.3} {:.3} {:.3} re f\n",
+                left + column as f64 * module, top - (row + 1) as f64 * module, module, module
+            ));
+        }
+    }
+
+    if let Some(footer) = &layout.footer {
+        page.push_str(&format!(
+            "BT /F1 {:.2} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+            line_height * 0.8, margin, margin, pdf_text(footer)
+        ));
+    }
+
+    Ok(pdf_document(width, height, &page))
+}
+
+// Text for a PDF string in WinAnsi encoding. Characters outside Latin-1 are
+// replaced.
+fn pdf_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' | '(' | ')' => format!("\\{}", c),
+            c if (' '..='~').contains(&c) => c.to_string(),
+            c if u32::from(c) >= 0xA0 && u32::from(c) <= 0xFF => format!("\\{:03o}", u32::from(c)),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+fn pdf_document(width: f64, height: f64, content: &str) -> Vec<u8> {
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents 4 0 R \
+             /Resources << /Font << /F1 5 0 R /F2 6 0 R >> >> >>",
+            width, height
+        ),
+        format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string(),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).as_bytes(),
+    );
+    pdf
+}
+
+// Where QR codes link when a template sets no link, with {id} and {lot}
+// placeholders
+pub fn link_url_from_env() -> String {
+    std::env::var("LABEL_LINK_URL").unwrap_or_else(|_| "http://localhost:8080/api/batches/{id}".to_string())
+}
+
+pub fn batch_link(url: &str, batch_id: i32, lot_code: &str) -> String {
+    url.replace("{id}", &batch_id.to_string()).replace("{lot}", &super::epcis::percent_encode(lot_code))
+}
@@ -0,0 +1,130 @@
+use chrono::{Datelike, NaiveDate};
+
+// Error types
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum LotCodeError {
+    #[error("Unknown placeholder {{{0}}} in lot code template")]
+    UnknownPlaceholder(String),
+
+    #[error("Lot code template has an unclosed placeholder")]
+    UnclosedPlaceholder,
+
+    #[error("Lot code template needs a {{seq}} placeholder")]
+    MissingSequence,
+
+    #[error("Lot code template cannot contain {0:?}")]
+    InvalidCharacter(char),
+
+    #[error("Lot code template uses {{line}}; a production line is required")]
+    MissingLine,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Julian,
+    Line,
+    // Zero-padded to the width
+    Sequence(usize),
+}
+
+// Default zero padding of {seq}
+const SEQUENCE_WIDTH: usize = 3;
+
+// A lot code scheme such as "{YY}{julian}{line}-{seq}". Placeholders:
+// {YYYY}, {YY}, {MM}, {DD}, {julian} (day of the year, 001-366), {line} (the
+// production line, letters and digits only) and {seq} or {seq:N}, the day's
+// running number padded to N digits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotCodeTemplate {
+    segments: Vec<Segment>,
+}
+
+impl LotCodeTemplate {
+    pub fn parse(template: &str) -> Result<Self, LotCodeError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.trim().chars();
+        while let Some(c) = chars.next() {
+            if c != '{' {
+                // Printable ASCII without spaces, so codes fit GS1 barcodes
+                if !c.is_ascii_graphic() || c == '}' {
+                    return Err(LotCodeError::InvalidCharacter(c));
+                }
+                literal.push(c);
+                continue;
+            }
+
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err(LotCodeError::UnclosedPlaceholder),
+                }
+            }
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(placeholder(&name)?);
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        // The running number is what keeps codes of the same day apart
+        if !segments.iter().any(|segment| matches!(segment, Segment::Sequence(_))) {
+            return Err(LotCodeError::MissingSequence);
+        }
+        Ok(LotCodeTemplate { segments })
+    }
+
+    // The lot code for a production date, line and running number
+    pub fn render(&self, date: NaiveDate, line: Option<&str>, sequence: i32) -> Result<String, LotCodeError> {
+        let mut code = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => code.push_str(text),
+                Segment::Year => code.push_str(&format!("{:04}", date.year())),
+                Segment::ShortYear => code.push_str(&format!("{:02}", date.year() % 100)),
+                Segment::Month => code.push_str(&format!("{:02}", date.month())),
+                Segment::Day => code.push_str(&format!("{:02}", date.day())),
+                Segment::Julian => code.push_str(&format!("{:03}", date.ordinal())),
+                Segment::Line => {
+                    let line: String = line.unwrap_or_default()
+                        .chars()
+                        .filter(char::is_ascii_alphanumeric)
+                        .map(|c| c.to_ascii_uppercase())
+                        .collect();
+                    if line.is_empty() {
+                        return Err(LotCodeError::MissingLine);
+                    }
+                    code.push_str(&line);
+                }
+                Segment::Sequence(width) => code.push_str(&format!("{:0width$}", sequence, width = *width)),
+            }
+        }
+        Ok(code)
+    }
+}
+
+fn placeholder(name: &str) -> Result<Segment, LotCodeError> {
+    let segment = match name {
+        "YYYY" => Segment::Year,
+        "YY" => Segment::ShortYear,
+        "MM" => Segment::Month,
+        "DD" => Segment::Day,
+        "julian" => Segment::Julian,
+        "line" => Segment::Line,
+        "seq" => Segment::Sequence(SEQUENCE_WIDTH),
+        _ => match name.strip_prefix("seq:").and_then(|width| width.parse::<usize>().ok()) {
+            Some(width @ 1..=9) => Segment::Sequence(width),
+            _ => return Err(LotCodeError::UnknownPlaceholder(name.to_string())),
+        },
+    };
+    Ok(segment)
+}
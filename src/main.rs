@@ -2,11 +2,38 @@ use actix_web::{web, HttpRequest, App, HttpResponse, HttpServer, Responder, midd
 use actix_cors::Cors;
 use dotenv::dotenv;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres, types::chrono::NaiveDate, Row};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use sqlx::{postgres::PgPoolOptions, PgConnection, Pool, Postgres, types::chrono::NaiveDate, Row};
+use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
+use uuid::Uuid;
 
 // Import auth module
+pub mod allergens;
 mod auth;
+pub mod capa;
+pub mod complaints;
+mod documents;
+mod epcis;
+pub mod gs1;
+pub mod haccp;
+pub mod labels;
+pub mod lotcodes;
+pub mod problemlogs;
+pub mod traceability;
+pub mod units;
+
+use allergens::{AllergenDeclaration, Presence, VerificationMethod, VerificationResult};
+use capa::{ActionType, CapaAction, CapaDetails, CapaError, CapaStatus};
+use complaints::{ComplaintError, ComplaintFact, ComplaintSubmission, ProductionFact, TrendGrouping};
+use problemlogs::{ActivityType, EscalationSettings, EscalationTarget, ProblemStatus, SlaBreach, WorkflowError};
+use haccp::{
+    CriticalControlPoint, CriticalLimit, Hazard, HazardType, MeasuredValue, MonitoringProcedure, ProcessStep,
+    VerificationActivity,
+};
+use traceability::{TraceabilityRecord, TrackingEvent};
+use units::{Amount, Quantity, Temperature, TemperatureRecord, TemperatureUnit, Unit, UnitError};
 
 // Organization entity
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,12 +46,17 @@ pub struct Organization {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProblemLog {
     pub id: Option<i32>,
+    // Missing on logs recorded before problem logs carried an organization
+    #[serde(alias = "orgId")]
+    pub org_id: Option<i32>,
     #[serde(alias = "isOpen")]
     pub is_open: bool,
     #[serde(alias = "dateOpened")]
     pub date_opened: String,
     #[serde(alias = "customerName")]
     pub customer_name: String,
+    #[serde(alias = "customerId")]
+    pub customer_id: Option<i32>,
     #[serde(alias = "problemType")]
     pub problem_type: String,
     #[serde(alias = "assignedTo")]
@@ -34,16 +66,43 @@ pub struct ProblemLog {
     pub recall: bool,
     #[serde(alias = "dateResolved")]
     pub date_resolved: Option<String>,
+    // Changed through the workflow; is_open follows it
+    #[serde(default)]
+    pub status: ProblemStatus,
+    #[serde(alias = "resolutionNotes")]
+    pub resolution_notes: Option<String>,
+    // The batch the problem concerns
+    #[serde(alias = "batchId")]
+    pub batch_id: Option<i32>,
+    #[serde(alias = "correctiveAction")]
+    pub corrective_action: Option<String>,
+    // Set for problems such as CCP deviations that cannot close without a corrective action
+    #[serde(alias = "correctiveActionRequired", default)]
+    pub corrective_action_required: bool,
+    // One of the organization's complaint categories
+    #[serde(alias = "categoryId")]
+    pub category_id: Option<i32>,
+    // Set from the organization's SLA for the problem type
+    #[serde(alias = "responseDueAt")]
+    pub response_due_at: Option<String>,
+    #[serde(alias = "resolutionDueAt")]
+    pub resolution_due_at: Option<String>,
+    #[serde(alias = "respondedAt")]
+    pub responded_at: Option<String>,
 }
 
+// The status, is_open and date_resolved are changed through transitions
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProblemLogInput {
-    #[serde(alias = "isOpen")]
-    pub is_open: bool,
+    #[serde(alias = "orgId")]
+    pub org_id: Option<i32>,
     #[serde(alias = "dateOpened")]
     pub date_opened: String,
-    #[serde(alias = "customerName")]
+    // Defaults to the customer's name when a customer is given
+    #[serde(alias = "customerName", default)]
     pub customer_name: String,
+    #[serde(alias = "customerId")]
+    pub customer_id: Option<i32>,
     #[serde(alias = "problemType")]
     pub problem_type: String,
     #[serde(alias = "assignedTo")]
@@ -51,8 +110,186 @@ pub struct ProblemLogInput {
     #[serde(alias = "problemDescription")]
     pub problem_description: String,
     pub recall: bool,
-    #[serde(alias = "dateResolved")]
-    pub date_resolved: Option<String>,
+    #[serde(alias = "batchId")]
+    pub batch_id: Option<i32>,
+    #[serde(alias = "correctiveAction")]
+    pub corrective_action: Option<String>,
+    #[serde(alias = "categoryId")]
+    pub category_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PublicSlugInput {
+    // None turns public complaint intake off
+    pub public_slug: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PublicSlug {
+    pub org_id: i32,
+    pub public_slug: Option<String>,
+}
+
+// What a member of the public gets back: the problem log number to quote
+#[derive(Serialize, Deserialize, Debug)]
+struct ComplaintReceipt {
+    pub reference: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Complaint {
+    pub id: i32,
+    pub org_id: i32,
+    pub problem_log_id: i32,
+    pub product: String,
+    pub lot_code: Option<String>,
+    pub batch_id: Option<i32>,
+    pub purchase_location: Option<String>,
+    pub purchase_date: Option<String>,
+    pub description: String,
+    pub contact_name: String,
+    pub contact_email: Option<String>,
+    pub contact_phone: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ComplaintQuery {
+    pub batch_id: Option<i32>,
+}
+
+fn default_category_active() -> bool {
+    true
+}
+
+fn default_alert_window_days() -> i32 {
+    30
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ComplaintCategoryInput {
+    pub org_id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    // Retired categories keep their history but take no new problem logs
+    #[serde(default = "default_category_active")]
+    pub active: bool,
+    // Alert once more than this many complaints arrive within alert_window_days
+    pub alert_threshold: Option<i32>,
+    #[serde(default = "default_alert_window_days")]
+    pub alert_window_days: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ComplaintCategory {
+    pub id: i32,
+    pub org_id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub active: bool,
+    pub alert_threshold: Option<i32>,
+    pub alert_window_days: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ComplaintTrendQuery {
+    #[serde(default)]
+    pub group_by: TrendGrouping,
+    pub from: Option<String>, // YYYY-MM-DD
+    pub to: Option<String>,   // YYYY-MM-DD
+}
+
+// A category with more complaints in its alert window than its threshold
+#[derive(Serialize, Deserialize, Debug)]
+struct ComplaintAlert {
+    pub category_id: i32,
+    pub category: String,
+    pub complaints: i64,
+    pub alert_threshold: i32,
+    pub alert_window_days: i32,
+    pub since: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ProblemLogTransitionInput {
+    pub status: ProblemStatus,
+    // Required to resolve or close
+    pub resolution_notes: Option<String>,
+    // Required to wait on the customer or reopen
+    pub comment: Option<String>,
+    pub employee_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ProblemLogCommentInput {
+    pub employee_id: Option<i32>,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ProblemLogActivity {
+    pub id: i32,
+    pub problem_log_id: i32,
+    pub activity_type: ActivityType,
+    pub from_status: Option<ProblemStatus>,
+    pub to_status: Option<ProblemStatus>,
+    pub body: Option<String>,
+    pub employee_id: Option<i32>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ProblemLogSlaInput {
+    pub org_id: i32,
+    // Matched to problem logs ignoring case
+    pub problem_type: String,
+    // Hours from opening to the first comment or status change
+    pub response_hours: i32,
+    // Hours from opening to resolution
+    pub resolution_hours: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ProblemLogSla {
+    pub id: i32,
+    pub org_id: i32,
+    pub problem_type: String,
+    pub response_hours: i32,
+    pub resolution_hours: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OverdueProblemLog {
+    pub id: i32,
+    pub problem_type: String,
+    pub status: ProblemStatus,
+    pub customer_name: String,
+    pub date_opened: String,
+    pub assigned_to: Vec<i32>,
+    pub breach: SlaBreach,
+    pub response_due_at: Option<String>,
+    pub resolution_due_at: Option<String>,
+    pub responded_at: Option<String>,
+    // 0 not escalated, 1 assigned employees notified, 2 QA notified
+    pub escalation_level: i16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Notification {
+    pub id: i32,
+    pub org_id: i32,
+    pub employee_id: i32,
+    pub problem_log_id: Option<i32>,
+    pub message: String,
+    pub created_at: String,
+    pub read_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct NotificationQuery {
+    pub employee_id: Option<i32>,
+    #[serde(default)]
+    pub unread: bool,
 }
 
 
@@ -61,12 +298,26 @@ struct BatchInput {
     pub org_id: i32,
     pub employee: String,
     pub recipe_lotcode: String,
+    // Recipe version the batch follows; defaults to the recipe's current version
+    #[serde(alias = "recipeVersion")]
+    pub recipe_version: Option<i32>,
+    // Generated from the lot code scheme when left out
     #[serde(alias = "batchLotCode")]
-    pub batch_lot_code: String,
+    pub batch_lot_code: Option<String>,
+    // Defaults to the organization's default lot code scheme
+    pub lot_code_scheme_id: Option<i32>,
     pub ingredients: Vec<i32>,
-    pub amount_ingredients: Vec<i32>,
+    pub amount_ingredients: Vec<Decimal>,
+    // Unit for each entry in `amount_ingredients`, in the same order. Missing
+    // units default to the lot's inventory unit.
+    #[serde(default)]
+    pub ingredient_units: Vec<Option<Unit>>,
     pub date_made: String,
-    pub amount_made: String,
+    // Accepts "20 lbs", {"value": 20, "unit": "lb"} or a plain number counted
+    // in the recipe's yield unit
+    pub amount_made: Amount,
+    // Production line; allergen changeovers are enforced between batches on the same line
+    pub line: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -75,12 +326,65 @@ struct Batch {
     pub org_id: i32,
     pub employee: String,
     pub recipe_lotcode: String,
+    pub recipe_version: Option<i32>,
     #[serde(alias = "batchLotCode")]
     pub batch_lot_code: String,
     pub ingredients: Vec<i32>,
-    pub amount_ingredients: Vec<i32>,
+    pub amount_ingredients: Vec<Decimal>,
+    pub ingredient_units: Vec<Option<String>>,
     pub date_made: String,
-    pub amount_made: String,
+    pub amount_made: Option<Quantity>,
+    pub line: Option<String>,
+    // Release status: released, on_hold, quarantined, rejected or destroyed
+    pub status: String,
+    // Earliest expiry of the ingredient lots used
+    pub expiry_date: Option<String>,
+    // Allergen profile of the ingredients actually used
+    pub allergens: Vec<AllergenDeclaration>,
+    // Allergens the batch introduces that its recipe version does not declare
+    pub undeclared_allergens: Vec<AllergenDeclaration>,
+}
+
+// A created or updated batch
+#[derive(Serialize, Deserialize, Debug)]
+struct SavedBatch {
+    #[serde(flatten)]
+    pub batch: Batch,
+    // Ingredient lots used that were never stocked, so the inventory ledger
+    // does not track what the batch took from them
+    pub untracked_stock: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AllergenChangeoverInput {
+    pub org_id: i32,
+    // The allergen-containing batch the line was cleaned after
+    pub from_batch_id: i32,
+    pub cleaning_steps: Vec<String>,
+    pub verification_method: VerificationMethod,
+    pub result: VerificationResult,
+    pub employee_id: i32,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AllergenChangeover {
+    pub id: i32,
+    pub org_id: i32,
+    pub line: String,
+    pub from_batch_id: i32,
+    pub to_batch_id: Option<i32>,
+    pub cleaning_steps: Vec<String>,
+    pub verification_method: String,
+    pub result: String,
+    pub employee_id: i32,
+    pub performed_at: String,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AllergenChangeoverQuery {
+    pub line: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -91,6 +395,13 @@ struct Employee {
     pub org_id: i32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RecipeStep {
+    #[serde(alias = "stepNumber")]
+    pub step_number: i32,
+    pub instruction: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct RecipeInput {
     pub lotcode: String,
@@ -98,7 +409,20 @@ struct RecipeInput {
     pub date_made: String, // For user input as string
     pub org_id: i32,
     pub ingredients: Vec<i32>,
+    // Quantity and unit for each entry in `ingredients`, in the same order
+    #[serde(default)]
+    pub amount_ingredients: Vec<Decimal>,
+    #[serde(default)]
+    pub ingredient_units: Vec<Unit>,
+    #[serde(alias = "yieldQuantity")]
+    pub yield_quantity: Option<Decimal>,
+    #[serde(alias = "yieldUnit")]
+    pub yield_unit: Option<Unit>,
+    #[serde(default)]
+    pub steps: Vec<RecipeStep>,
     pub description: String,
+    // GS1 trade item number of the product, printed in label barcodes
+    pub gtin: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -109,7 +433,98 @@ struct Recipe {
     pub date_made: String,
     pub org_id: i32,
     pub ingredients: Vec<i32>,
+    pub amount_ingredients: Vec<Option<Decimal>>,
+    pub ingredient_units: Vec<Option<String>>,
+    pub yield_quantity: Option<Decimal>,
+    pub yield_unit: Option<String>,
+    pub steps: Vec<RecipeStep>,
     pub description: Option<String>,
+    pub version: i32,
+    pub gtin: Option<String>,
+    // Computed from the allergens declared on the recipe's ingredients
+    pub allergens: Vec<AllergenDeclaration>,
+}
+
+// A single ingredient line of a recipe formulation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RecipeIngredientLine {
+    pub ingredient_id: i32,
+    pub quantity: Option<Decimal>,
+    pub unit: Option<String>,
+}
+
+// An immutable snapshot of a recipe's formulation
+#[derive(Serialize, Deserialize, Debug)]
+struct RecipeVersion {
+    pub recipe_id: i32,
+    pub version: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub yield_quantity: Option<Decimal>,
+    pub yield_unit: Option<String>,
+    pub created_at: String,
+    pub ingredients: Vec<RecipeIngredientLine>,
+    pub steps: Vec<RecipeStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RecipeDiffQuery {
+    pub from: i32,
+    pub to: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FieldChange {
+    pub field: String,
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IngredientLineChange {
+    pub ingredient_id: i32,
+    pub from: RecipeIngredientLine,
+    pub to: RecipeIngredientLine,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StepChange {
+    pub step_number: i32,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RecipeDiff {
+    pub recipe_id: i32,
+    pub from_version: i32,
+    pub to_version: i32,
+    pub changed_fields: Vec<FieldChange>,
+    pub added_ingredients: Vec<RecipeIngredientLine>,
+    pub removed_ingredients: Vec<RecipeIngredientLine>,
+    pub changed_ingredients: Vec<IngredientLineChange>,
+    pub added_steps: Vec<RecipeStep>,
+    pub removed_steps: Vec<RecipeStep>,
+    pub changed_steps: Vec<StepChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ScaleRecipeRequest {
+    #[serde(alias = "targetYield")]
+    pub target_yield: Decimal,
+    pub unit: Option<Unit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ScaledRecipe {
+    pub recipe_id: i32,
+    pub name: String,
+    pub yield_quantity: Decimal,
+    pub yield_unit: Option<String>,
+    pub target_yield: Decimal,
+    pub scale_factor: Decimal,
+    pub ingredients: Vec<RecipeIngredientLine>,
+    pub steps: Vec<RecipeStep>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -117,8 +532,19 @@ struct IngredientInput {
     pub id: Option<i32>,
     pub lotcode: String,
     pub name: String,
-    pub date: String, // For user input as string
+    // Date the lot was received
+    #[serde(alias = "date")]
+    pub received_date: String, // For user input as string
     pub org_id: i32,
+    pub density: Option<Decimal>, // Grams per milliliter
+    #[serde(default)]
+    pub allergens: Vec<AllergenDeclaration>,
+    pub manufactured_date: Option<String>,
+    pub best_by_date: Option<String>,
+    // Defaults to the shelf life for the ingredient type when omitted
+    pub expiry_date: Option<String>,
+    // Used to look up the default shelf life; defaults to the ingredient name
+    pub ingredient_type: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,18 +552,179 @@ struct Ingredient {
     pub id: Option<i32>,
     pub lotcode: String,
     pub name: String,
-    pub date: String,
+    // Returned as `date`, the name existing clients read
+    #[serde(rename = "date", alias = "received_date")]
+    pub received_date: String,
+    pub org_id: i32,
+    pub density: Option<Decimal>,
+    pub allergens: Vec<AllergenDeclaration>,
+    // Release status: released, on_hold, quarantined, rejected or destroyed
+    pub status: String,
+    pub manufactured_date: Option<String>,
+    pub best_by_date: Option<String>,
+    pub expiry_date: Option<String>,
+    pub ingredient_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ShelfLifeDefaultInput {
+    pub org_id: i32,
+    pub ingredient_type: String,
+    pub shelf_life_days: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ShelfLifeDefault {
+    pub id: i32,
+    pub org_id: i32,
+    pub ingredient_type: String,
+    pub shelf_life_days: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ExpiringLotsQuery {
+    pub days: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ExpiringLot {
+    pub ingredient_id: i32,
+    pub lotcode: String,
+    pub name: String,
+    pub status: String,
+    pub best_by_date: Option<String>,
+    pub expiry_date: String,
+    // Negative once the lot has expired
+    pub days_until_expiry: i32,
+    pub expired: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum LotStatus {
+    Released,
+    OnHold,
+    Quarantined,
+    Rejected,
+    Destroyed,
+}
+
+impl LotStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            LotStatus::Released => "released",
+            LotStatus::OnHold => "on_hold",
+            LotStatus::Quarantined => "quarantined",
+            LotStatus::Rejected => "rejected",
+            LotStatus::Destroyed => "destroyed",
+        }
+    }
+
+    fn parse(value: &str) -> Option<LotStatus> {
+        match value {
+            "released" => Some(LotStatus::Released),
+            "on_hold" => Some(LotStatus::OnHold),
+            "quarantined" => Some(LotStatus::Quarantined),
+            "rejected" => Some(LotStatus::Rejected),
+            "destroyed" => Some(LotStatus::Destroyed),
+            _ => None,
+        }
+    }
+
+    // Held and quarantined lots can be released or dispositioned; rejected
+    // lots can only be destroyed, and destroyed is final
+    fn can_change_to(self, next: LotStatus) -> bool {
+        match self {
+            LotStatus::Released | LotStatus::OnHold | LotStatus::Quarantined => self != next,
+            LotStatus::Rejected => next == LotStatus::Destroyed,
+            LotStatus::Destroyed => false,
+        }
+    }
+}
+
+// An ingredient lot or a batch
+#[derive(Debug, Clone, Copy)]
+enum LotRef {
+    Ingredient(i32),
+    Batch(i32),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LotStatusInput {
     pub org_id: i32,
+    pub status: LotStatus,
+    pub reason: String,
+    // Who placed or lifted the hold
+    pub employee_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LotStatusChange {
+    pub id: i32,
+    pub ingredient_id: Option<i32>,
+    pub batch_id: Option<i32>,
+    pub from_status: String,
+    pub status: String,
+    pub reason: String,
+    pub employee_id: Option<i32>,
+    pub employee_name: Option<String>,
+    pub changed_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HoldListQuery {
+    pub status: Option<LotStatus>,
+}
+
+// An ingredient lot or batch that is not released for use
+#[derive(Serialize, Deserialize, Debug)]
+struct HeldLot {
+    // "ingredient" or "batch"
+    pub kind: String,
+    pub id: i32,
+    pub lotcode: String,
+    pub name: String,
+    pub status: String,
+    pub reason: Option<String>,
+    pub employee_id: Option<i32>,
+    pub employee_name: Option<String>,
+    pub since: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ReceivingLogInput {
     pub lotcode: String,
+    // Defaults to the supplier's name when a supplier is given
+    #[serde(default)]
     pub company_name: String,
     pub item_name: String,
-    pub temperature: String,
+    // A reading such as "4C"; older free-text values are kept as written
+    pub temperature: TemperatureRecord,
     pub date: String, // For user input as string
     pub org_id: i32,
+    pub quantity: Option<Quantity>,
+    // Ingredient lot being received. When omitted the lot is matched by lot
+    // code, or created from the lot code and item name.
+    pub ingredient_id: Option<i32>,
+    pub supplier_id: Option<i32>,
+    // Accept a delivery from an unapproved, expired or unregistered supplier;
+    // `override_reason` records why
+    #[serde(default)]
+    pub override_supplier_check: bool,
+    pub override_reason: Option<String>,
+    // Expiry printed on the delivered lot, YYYY-MM-DD
+    pub expiry_date: Option<String>,
+    // Checklist to inspect against; defaults to the organization's default checklist
+    pub checklist_id: Option<i32>,
+    // Answers to the checklist's yes/no items
+    #[serde(default)]
+    pub checks: Vec<ReceivingCheckInput>,
+    // Defaults to accept when every check passes and hold otherwise
+    pub disposition: Option<Disposition>,
+    pub disposition_reason: Option<String>,
+    // GS1 trade item number and case serial from a scanned label
+    pub gtin: Option<String>,
+    pub serial_number: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -146,219 +733,8735 @@ struct ReceivingLog {
     pub lotcode: String,
     pub company_name: String,
     pub item_name: String,
-    pub temperature: String,
+    pub temperature: Option<Temperature>,
     pub date: String,
     pub org_id: i32,
+    pub quantity: Option<Quantity>,
+    pub ingredient_id: Option<i32>,
+    pub supplier_id: Option<i32>,
+    pub supplier_warning: Option<String>,
+    pub supplier_override_reason: Option<String>,
+    pub checklist_id: Option<i32>,
+    pub checks: Vec<ReceivingCheck>,
+    pub disposition: String,
+    pub disposition_reason: Option<String>,
+    // Problem log opened when the delivery was rejected
+    pub problem_log_id: Option<i32>,
+    pub gtin: Option<String>,
+    pub serial_number: Option<String>,
 }
 
-// Application state
-pub struct AppState {
-    db_pool: Pool<Postgres>,
+// A scanned case label plus whatever the receiver has filled in. Missing
+// fields are pre-filled from the scan and the last delivery of the same GTIN.
+#[derive(Serialize, Deserialize, Debug)]
+struct ReceivingScanInput {
+    pub org_id: i32,
+    // Raw GS1-128 or GS1 DataMatrix scan, or its human-readable form
+    pub scan: String,
+    // Record the delivery instead of only returning the pre-filled record
+    #[serde(default)]
+    pub create: bool,
+    pub lotcode: Option<String>,
+    pub company_name: Option<String>,
+    pub item_name: Option<String>,
+    pub temperature: Option<Temperature>,
+    pub date: Option<String>,
+    pub quantity: Option<Quantity>,
+    pub supplier_id: Option<i32>,
+    #[serde(default)]
+    pub override_supplier_check: bool,
+    pub override_reason: Option<String>,
+    pub expiry_date: Option<String>,
+    pub checklist_id: Option<i32>,
+    #[serde(default)]
+    pub checks: Vec<ReceivingCheckInput>,
+    pub disposition: Option<Disposition>,
+    pub disposition_reason: Option<String>,
 }
 
-// Health check endpoint
-async fn health_check() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({"status": "healthy"}))
+#[derive(Serialize, Deserialize, Debug)]
+struct ReceivingScan {
+    pub scan: gs1::Gs1Scan,
+    // The receiving record as pre-filled so far
+    pub receiving_log: serde_json::Value,
+    // Required fields still to be filled in before the record can be created
+    pub missing: Vec<String>,
 }
 
-// Secure endpoint pattern - use this as a guide when updating all endpoints
-// This is a demo function showing the secure pattern - commented out for now to fix compilation
-/*
-async fn secure_endpoint_pattern(
-    data: web::Data<AppState>,
-    org_id: OrgId, // Extract authenticated org ID
-    path_id: web::Path<i32>, // Example path parameter
-) -> impl Responder {
-    let resource_id = path_id.into_inner();
-    
-    // Example security check - would use actual table name instead of "resources"
-    // First check if the requested resource belongs to the authenticated organization
-    match sqlx::query!(
-        "SELECT org_id FROM ingredients WHERE id = $1", // Just an example, use the actual table
-        resource_id
-    )
-    .fetch_optional(&data.db_pool)
-    .await
-    {
-        Ok(Some(record)) => {
-            // Verify org_id matches the authenticated user's organization
-            if record.org_id != org_id.0 {
-                return HttpResponse::Forbidden().json(serde_json::json!({
-                    "error": "You don't have permission to access this resource"
-                }));
-            }
-            
-            // If authorized, proceed with the actual operation
-            // ... actual resource handling code here
-            
-            HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
-        },
-        Ok(None) => {
-            HttpResponse::NotFound().json(serde_json::json!({
-                "error": "Resource not found"
-            }))
-        },
-        Err(e) => {
-            eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Internal server error"
-            }))
+// Outcome of a delivery inspection
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Disposition {
+    Accept,
+    Reject,
+    Hold,
+}
+
+impl Disposition {
+    fn as_str(self) -> &'static str {
+        match self {
+            Disposition::Accept => "accept",
+            Disposition::Reject => "reject",
+            Disposition::Hold => "hold",
         }
     }
 }
-*/
 
-// Organization endpoints
-async fn create_organization(
-    org: web::Json<Organization>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    match sqlx::query!(
-        "INSERT INTO organizations (name, email) VALUES ($1, $2) RETURNING id",
-        org.name,
-        org.email
-    )
-    .fetch_one(&data.db_pool)
-    .await
-    {
-        Ok(record) => {
-            let created_org = Organization {
-                id: Some(record.id),
-                name: org.name.clone(),
-                email: org.email.clone(),
-            };
-            HttpResponse::Created().json(created_org)
-        }
-        Err(e) => {
-            eprintln!("Failed to create organization: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create organization"}))
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ChecklistItemKind {
+    // Checked against the delivery temperature
+    Temperature,
+    // Answered by the receiver, e.g. "Packaging intact"
+    YesNo,
+}
+
+impl ChecklistItemKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChecklistItemKind::Temperature => "temperature",
+            ChecklistItemKind::YesNo => "yes_no",
         }
     }
 }
 
-async fn get_organization(
-    path: web::Path<i32>,
-    data: web::Data<AppState>,
-) -> impl Responder {
+#[derive(Serialize, Deserialize, Debug)]
+struct ChecklistItemInput {
+    pub label: String,
+    pub kind: ChecklistItemKind,
+    // Acceptable range for temperature items; either bound may be omitted
+    pub min_temperature: Option<Decimal>,
+    pub max_temperature: Option<Decimal>,
+    pub temperature_unit: Option<TemperatureUnit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ReceivingChecklistInput {
+    pub org_id: i32,
+    pub name: String,
+    // Applied to deliveries that do not name a checklist
+    #[serde(default)]
+    pub is_default: bool,
+    pub items: Vec<ChecklistItemInput>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ChecklistItem {
+    pub id: i32,
+    pub label: String,
+    pub kind: String,
+    pub min_temperature: Option<Decimal>,
+    pub max_temperature: Option<Decimal>,
+    pub temperature_unit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ReceivingChecklist {
+    pub id: i32,
+    pub org_id: i32,
+    pub name: String,
+    pub is_default: bool,
+    pub items: Vec<ChecklistItem>,
+}
+
+// The receiver's answer to a yes/no checklist item
+#[derive(Serialize, Deserialize, Debug)]
+struct ReceivingCheckInput {
+    pub item_id: i32,
+    pub passed: bool,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ReceivingCheck {
+    pub item_id: Option<i32>,
+    pub label: String,
+    pub passed: bool,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SupplierStatus {
+    Pending,
+    Approved,
+    Suspended,
+}
+
+impl SupplierStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            SupplierStatus::Pending => "pending",
+            SupplierStatus::Approved => "approved",
+            SupplierStatus::Suspended => "suspended",
+        }
+    }
+}
+
+fn default_supplier_status() -> SupplierStatus {
+    SupplierStatus::Pending
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SupplierInput {
+    pub org_id: i32,
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub contact_email: Option<String>,
+    pub contact_phone: Option<String>,
+    pub address: Option<String>,
+    #[serde(default = "default_supplier_status")]
+    pub status: SupplierStatus,
+    pub approval_date: Option<String>, // YYYY-MM-DD
+    pub audit_expiry: Option<String>,  // YYYY-MM-DD
+    // Products supplied
+    #[serde(default)]
+    pub products: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Supplier {
+    pub id: i32,
+    pub org_id: i32,
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub contact_email: Option<String>,
+    pub contact_phone: Option<String>,
+    pub address: Option<String>,
+    pub status: String,
+    pub approval_date: Option<String>,
+    pub audit_expiry: Option<String>,
+    pub products: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CustomerInput {
+    pub org_id: i32,
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub contact_email: Option<String>,
+    pub contact_phone: Option<String>,
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Customer {
+    pub id: i32,
+    pub org_id: i32,
+    pub name: String,
+    pub contact_name: Option<String>,
+    pub contact_email: Option<String>,
+    pub contact_phone: Option<String>,
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ShipmentLineInput {
+    pub batch_id: i32,
+    pub quantity: Quantity,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ShipmentInput {
+    pub org_id: i32,
+    pub customer_id: i32,
+    pub ship_date: String, // YYYY-MM-DD
+    // Purchase order, bill of lading or invoice number
+    pub reference: Option<String>,
+    pub notes: Option<String>,
+    pub lines: Vec<ShipmentLineInput>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ShipmentLine {
+    pub batch_id: i32,
+    pub batch_lot_code: String,
+    pub quantity: Option<Quantity>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Shipment {
+    pub id: i32,
+    pub org_id: i32,
+    pub customer_id: i32,
+    pub customer_name: String,
+    pub ship_date: String,
+    pub reference: Option<String>,
+    pub notes: Option<String>,
+    pub lines: Vec<ShipmentLine>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ShipmentQuery {
+    pub customer_id: Option<i32>,
+}
+
+// One shipment of a batch, for forward traceability
+#[derive(Serialize, Deserialize, Debug)]
+struct BatchShipment {
+    pub shipment_id: i32,
+    pub customer_id: i32,
+    pub customer_name: String,
+    pub ship_date: String,
+    pub reference: Option<String>,
+    pub quantity: Option<Quantity>,
+}
+
+// Scope of a traceability export: a date range, a lot code, or both
+#[derive(Serialize, Deserialize, Debug)]
+struct TraceabilityExportQuery {
+    pub from: Option<String>, // YYYY-MM-DD
+    pub to: Option<String>,   // YYYY-MM-DD
+    pub lot: Option<String>,
+    // csv (default) or json
+    pub format: Option<String>,
+}
+
+// Supplier shipping events to record as deliveries
+#[derive(Serialize, Deserialize, Debug)]
+struct EpcisImportInput {
+    pub org_id: i32,
+    // Supplier the deliveries come from; otherwise the event's source party
+    pub supplier_id: Option<i32>,
+    // Date received, YYYY-MM-DD, for events without an eventTime; defaults to today
+    pub date: Option<String>,
+    // Temperature on arrival, for events that carry no sensor reading
+    pub temperature: Option<Temperature>,
+    pub document: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct EpcisImportResult {
+    pub created: Vec<ReceivingLog>,
+    // Events imported before, by eventID and lot
+    pub skipped: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LabelTemplateInput {
+    pub org_id: i32,
+    pub name: String,
+    #[serde(default)]
+    pub is_default: bool,
+    // Defaults to a 4 x 3 inch label at 203 dpi
+    pub width_mm: Option<Decimal>,
+    pub height_mm: Option<Decimal>,
+    pub dpi: Option<i32>,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    pub show_allergens: Option<bool>,
+    pub show_barcode: Option<bool>,
+    pub show_qr: Option<bool>,
+    pub best_by_days: Option<i32>,
+    pub link_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LabelTemplate {
+    pub id: i32,
+    pub org_id: i32,
+    pub name: String,
+    pub is_default: bool,
+    pub width_mm: Decimal,
+    pub height_mm: Decimal,
+    pub dpi: i32,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    pub show_allergens: bool,
+    pub show_barcode: bool,
+    pub show_qr: bool,
+    pub best_by_days: Option<i32>,
+    pub link_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LabelQuery {
+    // zpl (default) or pdf
+    pub format: Option<String>,
+    // Defaults to the organization's default template
+    pub template_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LotCodeSchemeInput {
+    pub org_id: i32,
+    pub name: String,
+    // e.g. "{YY}{julian}{line}-{seq}"
+    pub template: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LotCodeScheme {
+    pub id: i32,
+    pub org_id: i32,
+    pub name: String,
+    pub template: String,
+    pub is_default: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HaccpPlanInput {
+    pub org_id: i32,
+    pub name: String,
+    // The recipe the plan covers
+    pub recipe_id: Option<i32>,
+    pub product_description: Option<String>,
+    // Why this version was made
+    pub change_summary: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<ProcessStep>,
+    #[serde(default)]
+    pub hazards: Vec<Hazard>,
+    #[serde(default)]
+    pub ccps: Vec<CriticalControlPoint>,
+}
+
+// An immutable snapshot of a HACCP plan
+#[derive(Serialize, Deserialize, Debug)]
+struct HaccpPlanVersion {
+    pub plan_id: i32,
+    pub version: i32,
+    pub name: String,
+    pub product_description: Option<String>,
+    pub change_summary: Option<String>,
+    pub created_at: String,
+    pub steps: Vec<ProcessStep>,
+    pub hazards: Vec<Hazard>,
+    pub ccps: Vec<CriticalControlPoint>,
+}
+
+// A HACCP plan as of its current version
+#[derive(Serialize, Deserialize, Debug)]
+struct HaccpPlan {
+    pub id: i32,
+    pub org_id: i32,
+    pub recipe_id: Option<i32>,
+    #[serde(flatten)]
+    pub current: HaccpPlanVersion,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HaccpPlanQuery {
+    pub recipe_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CcpReadingInput {
+    pub limit_id: i32,
+    // A number, text with a unit ("72C") or true/false for pass/fail checks
+    pub value: MeasuredValue,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CcpMonitoringInput {
+    pub org_id: i32,
+    pub ccp_id: i32,
+    pub batch_id: Option<i32>,
+    // Who made the check
+    pub employee_id: Option<i32>,
+    // RFC 3339; defaults to now
+    pub monitored_at: Option<String>,
+    // One reading per critical limit of the CCP
+    pub readings: Vec<CcpReadingInput>,
+    // Immediate action taken when a reading is out of limits
+    pub corrective_action: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CcpReading {
+    pub limit_id: i32,
+    pub parameter: String,
+    // As entered
+    pub measured: String,
+    // In the limit's unit
+    pub value: Decimal,
+    pub unit: Option<String>,
+    pub min_value: Option<Decimal>,
+    pub max_value: Option<Decimal>,
+    pub within_limit: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CcpMonitoringRecord {
+    pub id: i32,
+    pub org_id: i32,
+    pub plan_id: i32,
+    pub ccp_id: i32,
+    pub ccp_number: String,
+    pub batch_id: Option<i32>,
+    pub batch_lot_code: Option<String>,
+    pub employee_id: Option<i32>,
+    pub monitored_at: String,
+    pub within_limits: bool,
+    pub corrective_action: Option<String>,
+    pub notes: Option<String>,
+    // The problem log opened for a deviation
+    pub problem_log_id: Option<i32>,
+    pub readings: Vec<CcpReading>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CcpMonitoringQuery {
+    pub plan_id: Option<i32>,
+    pub ccp_id: Option<i32>,
+    pub batch_id: Option<i32>,
+    // Only records with a reading out of limits
+    pub deviations: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CapaInput {
+    pub org_id: i32,
+    pub problem_log_id: i32,
+    // Defaults to the current status, or open for a new CAPA
+    pub status: Option<CapaStatus>,
+    #[serde(flatten)]
+    pub details: CapaDetails,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Capa {
+    pub id: i32,
+    pub org_id: i32,
+    pub problem_log_id: i32,
+    pub status: CapaStatus,
+    #[serde(flatten)]
+    pub details: CapaDetails,
+    // An action is past its due date and not completed
+    pub overdue: bool,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CapaQuery {
+    pub problem_log_id: Option<i32>,
+    pub status: Option<CapaStatus>,
+    // CAPAs with an action owned by this employee
+    pub owner_id: Option<i32>,
+    pub overdue: Option<bool>,
+}
+
+// The record a document is attached to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DocumentEntity {
+    ReceivingLog,
+    Ingredient,
+    Supplier,
+}
+
+impl DocumentEntity {
+    fn as_str(self) -> &'static str {
+        match self {
+            DocumentEntity::ReceivingLog => "receiving_log",
+            DocumentEntity::Ingredient => "ingredient",
+            DocumentEntity::Supplier => "supplier",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DocumentType {
+    Coa,
+    SpecSheet,
+    LetterOfGuarantee,
+    Other,
+}
+
+impl DocumentType {
+    fn as_str(self) -> &'static str {
+        match self {
+            DocumentType::Coa => "coa",
+            DocumentType::SpecSheet => "spec_sheet",
+            DocumentType::LetterOfGuarantee => "letter_of_guarantee",
+            DocumentType::Other => "other",
+        }
+    }
+}
+
+fn default_document_type() -> DocumentType {
+    DocumentType::Other
+}
+
+// Upload parameters; the request body is the file itself
+#[derive(Serialize, Deserialize, Debug)]
+struct DocumentUploadQuery {
+    pub entity_type: DocumentEntity,
+    pub entity_id: i32,
+    #[serde(default = "default_document_type")]
+    pub document_type: DocumentType,
+    pub file_name: String,
+    pub expires_on: Option<String>, // YYYY-MM-DD
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DocumentListQuery {
+    pub entity_type: Option<DocumentEntity>,
+    pub entity_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ExpiringDocumentsQuery {
+    pub days: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Document {
+    pub id: i32,
+    pub org_id: i32,
+    pub entity_type: String,
+    pub entity_id: i32,
+    pub document_type: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    pub expires_on: Option<String>,
+    pub uploaded_at: String,
+    // "expired", or "expiring_soon" within 30 days of the expiry date
+    pub expiry_status: Option<String>,
+}
+
+// A manual stock correction or write-off for an ingredient lot
+#[derive(Serialize, Deserialize, Debug)]
+struct InventoryAdjustmentInput {
+    pub org_id: i32,
+    pub ingredient_id: i32,
+    pub kind: AdjustmentKind,
+    // Signed for adjustments; for waste, the positive amount written off
+    pub quantity: Decimal,
+    pub unit: Unit,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum AdjustmentKind {
+    Adjustment,
+    Waste,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct InventoryTransaction {
+    pub id: i32,
+    pub ingredient_id: i32,
+    pub kind: String,
+    pub quantity: Decimal,
+    pub unit: String,
+    pub reason: Option<String>,
+    pub batch_id: Option<i32>,
+    pub receiving_log_id: Option<i32>,
+    pub created_at: String,
+}
+
+// On-hand stock of one ingredient lot; `on_hand` is null for untracked lots
+#[derive(Serialize, Deserialize, Debug)]
+struct LotOnHand {
+    pub ingredient_id: i32,
+    pub lotcode: String,
+    pub name: String,
+    pub on_hand: Option<Quantity>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LotInventory {
+    #[serde(flatten)]
+    pub lot: LotOnHand,
+    pub transactions: Vec<InventoryTransaction>,
+}
+
+// On-hand stock of an ingredient summed across its lots
+#[derive(Serialize, Deserialize, Debug)]
+struct IngredientOnHand {
+    pub name: String,
+    pub on_hand: Quantity,
+    pub lots: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ConvertQuantityRequest {
+    pub quantity: Quantity,
+    pub to: Unit,
+    // Use this ingredient's density for mass/volume conversion
+    pub ingredient_id: Option<i32>,
+}
+
+// Application state
+pub struct AppState {
+    db_pool: Pool<Postgres>,
+    // None when DOCUMENT_STORAGE is misconfigured; main refuses to start then
+    document_storage: Option<Arc<dyn documents::DocumentStorage>>,
+    complaint_limiter: Arc<complaints::RateLimiter>,
+}
+
+// Health check endpoint
+async fn health_check() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({"status": "healthy"}))
+}
+
+// Secure endpoint pattern - use this as a guide when updating all endpoints
+// This is a demo function showing the secure pattern - commented out for now to fix compilation
+/*
+async fn secure_endpoint_pattern(
+    data: web::Data<AppState>,
+    org_id: OrgId, // Extract authenticated org ID
+    path_id: web::Path<i32>, // Example path parameter
+) -> impl Responder {
+    let resource_id = path_id.into_inner();
+    
+    // Example security check - would use actual table name instead of "resources"
+    // First check if the requested resource belongs to the authenticated organization
+    match sqlx::query!(
+        "SELECT org_id FROM ingredients WHERE id = $1", // Just an example, use the actual table
+        resource_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(record)) => {
+            // Verify org_id matches the authenticated user's organization
+            if record.org_id != org_id.0 {
+                return HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "You don't have permission to access this resource"
+                }));
+            }
+            
+            // If authorized, proceed with the actual operation
+            // ... actual resource handling code here
+            
+            HttpResponse::Ok().json(serde_json::json!({"status": "success"}))
+        },
+        Ok(None) => {
+            HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Resource not found"
+            }))
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Internal server error"
+            }))
+        }
+    }
+}
+*/
+
+// Organization endpoints
+async fn create_organization(
+    org: web::Json<Organization>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match sqlx::query!(
+        "INSERT INTO organizations (name, email) VALUES ($1, $2) RETURNING id",
+        org.name,
+        org.email
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(record) => {
+            let created_org = Organization {
+                id: Some(record.id),
+                name: org.name.clone(),
+                email: org.email.clone(),
+            };
+            HttpResponse::Created().json(created_org)
+        }
+        Err(e) => {
+            eprintln!("Failed to create organization: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create organization"}))
+        }
+    }
+}
+
+async fn get_organization(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query_as!(
+        Organization,
+        "SELECT id, name, email FROM organizations WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(org)) => HttpResponse::Ok().json(org),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// async fn get_all_organizations(
+//     data: web::Data<AppState>,
+// ) -> impl Responder {
+//     match sqlx::query_as!(
+//         Organization,
+//         "SELECT id, name, email FROM organizations"
+//     )
+//     .fetch_all(&data.db_pool)
+//     .await
+//     {
+//         Ok(orgs) => HttpResponse::Ok().json(orgs),
+//         Err(e) => {
+//             eprintln!("Database error: {}", e);
+//             HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+//         }
+//     }
+// }
+
+async fn update_organization(
+    path: web::Path<i32>,
+    org: web::Json<Organization>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query!(
+        "UPDATE organizations SET name = $1, email = $2 WHERE id = $3 RETURNING id",
+        org.name,
+        org.email,
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(_)) => {
+            let updated_org = Organization {
+                id: Some(id),
+                name: org.name.clone(),
+                email: org.email.clone(),
+            };
+            HttpResponse::Ok().json(updated_org)
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_organization(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query!("DELETE FROM organizations WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => {
+            // Attached documents went with it
+            purge_deleted_documents(&data).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_public_slug(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query_as!(PublicSlug, "SELECT id as org_id, public_slug FROM organizations WHERE id = $1", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(slug)) => HttpResponse::Ok().json(slug),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Choose the slug of the public complaint form, /api/public/{slug}/complaints
+async fn update_public_slug(
+    path: web::Path<i32>,
+    slug: web::Json<PublicSlugInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let public_slug = match slug.public_slug.as_deref().map(complaints::normalize_slug).transpose() {
+        Ok(public_slug) => public_slug,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query!(
+        "UPDATE organizations SET public_slug = $1 WHERE id = $2 RETURNING id",
+        public_slug,
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(_)) => HttpResponse::Ok().json(PublicSlug { org_id: id, public_slug }),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Err(e) => {
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({"error": "That public slug is already taken"}));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Employee endpoints
+async fn create_employee(
+    employee: web::Json<Employee>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match sqlx::query!(
+        "INSERT INTO employees (name, role, org_id) VALUES ($1, $2, $3) RETURNING id",
+        employee.name,
+        employee.role,
+        employee.org_id
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(record) => {
+            let created_employee = Employee {
+                id: Some(record.id),
+                name: employee.name.clone(),
+                role: employee.role.clone(),
+                org_id: employee.org_id,
+            };
+            HttpResponse::Created().json(created_employee)
+        }
+        Err(e) => {
+            eprintln!("Failed to create employee: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create employee"}))
+        }
+    }
+}
+
+async fn get_employee(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Extract auth token from request
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            match header.to_str() {
+                Ok(header_str) => {
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
+                    } else {
+                        return HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Invalid authorization header format"
+                        }));
+                    }
+                },
+                Err(_) => {
+                    return HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid authorization header"
+                    }));
+                }
+            }
+        },
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authorization header is missing"
+            }));
+        }
+    };
+    
+    // Verify token and get organization ID
+    let claims = match auth::verify_token(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid auth token"
+            }));
+        }
+    };
+    
+    let auth_org_id = claims.org_id;
+    
+    match sqlx::query!(
+        "SELECT id, name, role, org_id FROM employees WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(record)) => {
+            // Security check: Verify the authenticated user belongs to the same organization
+            // as the requested resource
+            if record.org_id.unwrap_or(0) != auth_org_id {
+                return HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "You don't have permission to access this employee"
+                }));
+            }
+            
+            let employee = Employee {
+                id: Some(record.id),
+                name: record.name,
+                role: record.role,
+                org_id: record.org_id.unwrap_or(0),
+            };
+            HttpResponse::Ok().json(employee)
+        },
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Employee not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_employees(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // Extract auth token from request
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            match header.to_str() {
+                Ok(header_str) => {
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
+                    } else {
+                        return HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Invalid authorization header format"
+                        }));
+                    }
+                },
+                Err(_) => {
+                    return HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid authorization header"
+                    }));
+                }
+            }
+        },
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authorization header is missing"
+            }));
+        }
+    };
+    
+    // Verify token and get organization ID
+    let claims = match auth::verify_token(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid auth token"
+            }));
+        }
+    };
+    
+    let org_id = claims.org_id;
+
+    match sqlx::query!(
+        "SELECT id, name, role, org_id FROM employees WHERE org_id = $1",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => {
+            let employees: Vec<Employee> = records.into_iter().map(|record| {
+                Employee {
+                    id: Some(record.id),
+                    name: record.name,
+                    role: record.role,
+                    org_id: record.org_id.unwrap_or(0),
+                }
+            }).collect();
+            HttpResponse::Ok().json(employees)
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn update_employee(
+    path: web::Path<i32>,
+    employee: web::Json<Employee>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query!(
+        "UPDATE employees SET name = $1, role = $2, org_id = $3 WHERE id = $4 RETURNING id",
+        employee.name,
+        employee.role,
+        employee.org_id,
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(_)) => {
+            let updated_employee = Employee {
+                id: Some(id),
+                name: employee.name.clone(),
+                role: employee.role.clone(),
+                org_id: employee.org_id,
+            };
+            HttpResponse::Ok().json(updated_employee)
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Employee not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_employee(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query!("DELETE FROM employees WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Employee not found"})),
+        Err(e) => {
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Employee owns CAPA actions and cannot be deleted"
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Replace the allergen declarations of an ingredient, returning the normalized profile
+async fn save_ingredient_allergens(
+    conn: &mut PgConnection,
+    ingredient_id: i32,
+    declarations: &[AllergenDeclaration],
+) -> Result<Vec<AllergenDeclaration>, sqlx::Error> {
+    let profile = allergens::merge_profile(
+        declarations.iter().map(|declaration| AllergenDeclaration::new(&declaration.allergen, declaration.presence))
+    );
+
+    sqlx::query!("DELETE FROM ingredient_allergens WHERE ingredient_id = $1", ingredient_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for declaration in &profile {
+        sqlx::query!(
+            "INSERT INTO ingredient_allergens (ingredient_id, allergen, presence) VALUES ($1, $2, $3)",
+            ingredient_id,
+            declaration.allergen,
+            declaration.presence.as_str()
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(profile)
+}
+
+// Fetch the allergen declarations of several ingredients, keyed by ingredient id
+async fn fetch_ingredient_allergens(
+    pool: &Pool<Postgres>,
+    ingredient_ids: &[i32],
+) -> Result<HashMap<i32, Vec<AllergenDeclaration>>, sqlx::Error> {
+    let records = sqlx::query!(
+        "SELECT ingredient_id as \"ingredient_id!\", allergen, presence
+         FROM ingredient_allergens WHERE ingredient_id = ANY($1) ORDER BY allergen",
+        ingredient_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut allergens_by_ingredient: HashMap<i32, Vec<AllergenDeclaration>> = HashMap::new();
+    for record in records {
+        allergens_by_ingredient
+            .entry(record.ingredient_id)
+            .or_default()
+            .push(AllergenDeclaration::new(&record.allergen, Presence::parse(&record.presence)));
+    }
+    Ok(allergens_by_ingredient)
+}
+
+fn validate_allergens(declarations: &[AllergenDeclaration]) -> Result<(), String> {
+    if declarations.iter().any(|declaration| declaration.allergen.trim().is_empty()) {
+        return Err("Allergen names cannot be empty".to_string());
+    }
+    Ok(())
+}
+
+// Dates recorded for an ingredient lot besides the received date
+struct LotDates {
+    manufactured: Option<NaiveDate>,
+    best_by: Option<NaiveDate>,
+    expiry: Option<NaiveDate>,
+}
+
+fn parse_lot_dates(ingredient: &IngredientInput) -> Result<LotDates, HttpResponse> {
+    Ok(LotDates {
+        manufactured: parse_optional_date(&ingredient.manufactured_date, "manufactured_date")?,
+        best_by: parse_optional_date(&ingredient.best_by_date, "best_by_date")?,
+        expiry: parse_optional_date(&ingredient.expiry_date, "expiry_date")?,
+    })
+}
+
+// Longest shelf life an organization can set, about a hundred years
+const MAX_SHELF_LIFE_DAYS: i32 = 36500;
+
+// Expiry from the organization's default shelf life for an ingredient type,
+// counted from the manufactured date when known and the received date otherwise
+async fn default_expiry(
+    conn: &mut PgConnection,
+    org_id: i32,
+    ingredient_type: &str,
+    received: NaiveDate,
+    manufactured: Option<NaiveDate>,
+) -> Result<Option<NaiveDate>, HttpResponse> {
+    let shelf_life_days = sqlx::query_scalar!(
+        "SELECT shelf_life_days FROM shelf_life_defaults WHERE org_id = $1 AND LOWER(ingredient_type) = LOWER($2)",
+        org_id,
+        ingredient_type.trim()
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    })?;
+
+    let Some(days) = shelf_life_days else {
+        return Ok(None);
+    };
+    manufactured
+        .unwrap_or(received)
+        .checked_add_days(chrono::Days::new(days.max(0) as u64))
+        .map(Some)
+        .ok_or_else(|| {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "The default shelf life for this ingredient type gives an expiry date out of range"
+            }))
+        })
+}
+
+// Shelf life endpoints
+async fn create_shelf_life_default(
+    shelf_life: web::Json<ShelfLifeDefaultInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if shelf_life.ingredient_type.trim().is_empty() || shelf_life.shelf_life_days <= 0 {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "An ingredient_type and a positive shelf_life_days are required"
+        }));
+    }
+    if shelf_life.shelf_life_days > MAX_SHELF_LIFE_DAYS {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("shelf_life_days cannot be more than {}", MAX_SHELF_LIFE_DAYS)
+        }));
+    }
+
+    // Setting the shelf life of a type again replaces it
+    match sqlx::query_as!(
+        ShelfLifeDefault,
+        "INSERT INTO shelf_life_defaults (org_id, ingredient_type, shelf_life_days) VALUES ($1, $2, $3)
+         ON CONFLICT (org_id, LOWER(ingredient_type))
+         DO UPDATE SET ingredient_type = EXCLUDED.ingredient_type, shelf_life_days = EXCLUDED.shelf_life_days
+         RETURNING id, org_id, ingredient_type, shelf_life_days",
+        shelf_life.org_id,
+        shelf_life.ingredient_type.trim(),
+        shelf_life.shelf_life_days
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(created) => HttpResponse::Created().json(created),
+        Err(e) => {
+            eprintln!("Failed to save shelf life: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to save shelf life"}))
+        }
+    }
+}
+
+async fn get_shelf_life_defaults(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        ShelfLifeDefault,
+        "SELECT id, org_id, ingredient_type, shelf_life_days FROM shelf_life_defaults
+         WHERE org_id = $1 ORDER BY LOWER(ingredient_type)",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(defaults) => HttpResponse::Ok().json(defaults),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch shelf lives"}))
+        }
+    }
+}
+
+async fn delete_shelf_life_default(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM shelf_life_defaults WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Shelf life not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Ingredient lots that have expired or expire within `days` (default 30),
+// soonest first. Rejected and destroyed lots are left out.
+async fn get_expiring_ingredients(
+    req: HttpRequest,
+    query: web::Query<ExpiringLotsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        ExpiringLot,
+        "SELECT id as ingredient_id, lotcode, name, status, best_by_date::text, expiry_date::text as \"expiry_date!\",
+         (expiry_date - CURRENT_DATE) as \"days_until_expiry!\", (expiry_date < CURRENT_DATE) as \"expired!\"
+         FROM ingredients
+         WHERE org_id = $1 AND expiry_date <= CURRENT_DATE + $2::int AND status NOT IN ('rejected', 'destroyed')
+         ORDER BY expiry_date, id",
+        org_id,
+        query.days.unwrap_or(30)
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(lots) => HttpResponse::Ok().json(lots),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch expiring lots"}))
+        }
+    }
+}
+
+// Ingredient endpoints
+async fn create_ingredient(
+    ingredient: web::Json<IngredientInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // Parse the date string to NaiveDate
+    let date = match NaiveDate::parse_from_str(&ingredient.received_date, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid date format. Use YYYY-MM-DD"
+            }));
+        }
+    };
+
+    if ingredient.density.is_some_and(|density| density <= Decimal::ZERO) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Density must be greater than zero"
+        }));
+    }
+
+    if let Err(message) = validate_allergens(&ingredient.allergens) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let lot_dates = match parse_lot_dates(&ingredient) {
+        Ok(lot_dates) => lot_dates,
+        Err(response) => return response,
+    };
+
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+
+    let expiry_date = match lot_dates.expiry {
+        Some(expiry_date) => Some(expiry_date),
+        None => {
+            let ingredient_type = ingredient.ingredient_type.as_deref().unwrap_or(&ingredient.name);
+            match default_expiry(&mut tx, ingredient.org_id, ingredient_type, date, lot_dates.manufactured).await {
+                Ok(expiry_date) => expiry_date,
+                Err(response) => {
+                    let _ = tx.rollback().await;
+                    return response;
+                }
+            }
+        }
+    };
+
+    let ingredient_id = match sqlx::query!(
+        "INSERT INTO ingredients (lotcode, name, received_date, org_id, density, manufactured_date, best_by_date, expiry_date,
+         ingredient_type)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+        ingredient.lotcode,
+        ingredient.name,
+        date,
+        ingredient.org_id,
+        ingredient.density,
+        lot_dates.manufactured,
+        lot_dates.best_by,
+        expiry_date,
+        ingredient.ingredient_type
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(record) => record.id,
+        Err(e) => {
+            let _ = tx.rollback().await;
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("Lot {} of {} already exists", ingredient.lotcode, ingredient.name)
+                }));
+            }
+            eprintln!("Failed to create ingredient: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create ingredient"}));
+        }
+    };
+
+    let allergens = match save_ingredient_allergens(&mut tx, ingredient_id, &ingredient.allergens).await {
+        Ok(allergens) => allergens,
+        Err(e) => {
+            eprintln!("Failed to save ingredient allergens: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create ingredient"}));
+        }
+    };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    let created_ingredient = Ingredient {
+        id: Some(ingredient_id),
+        lotcode: ingredient.lotcode.clone(),
+        name: ingredient.name.clone(),
+        received_date: ingredient.received_date.clone(),
+        org_id: ingredient.org_id,
+        density: ingredient.density,
+        allergens,
+        status: LotStatus::Released.as_str().to_string(),
+        manufactured_date: lot_dates.manufactured.map(|date| date.to_string()),
+        best_by_date: lot_dates.best_by.map(|date| date.to_string()),
+        expiry_date: expiry_date.map(|date| date.to_string()),
+        ingredient_type: ingredient.ingredient_type.clone(),
+    };
+    HttpResponse::Created().json(created_ingredient)
+}
+
+async fn get_ingredient(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Extract auth token from request
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            match header.to_str() {
+                Ok(header_str) => {
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
+                    } else {
+                        return HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Invalid authorization header format"
+                        }));
+                    }
+                },
+                Err(_) => {
+                    return HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid authorization header"
+                    }));
+                }
+            }
+        },
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authorization header is missing"
+            }));
+        }
+    };
+    
+    // Verify token and get organization ID
+    let claims = match auth::verify_token(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid auth token"
+            }));
+        }
+    };
+    
+    let auth_org_id = claims.org_id;
+    
+    match sqlx::query!(
+        "SELECT id, lotcode, name, received_date::text as received_date, org_id, density, status, manufactured_date::text,
+         best_by_date::text, expiry_date::text, ingredient_type FROM ingredients WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(record)) => {
+            // Security check: Verify the authenticated user belongs to the same organization
+            // as the requested resource
+            if record.org_id.unwrap_or(0) != auth_org_id {
+                return HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "You don't have permission to access this ingredient"
+                }));
+            }
+            
+            let allergens = match fetch_ingredient_allergens(&data.db_pool, &[record.id]).await {
+                Ok(mut allergens_by_ingredient) => allergens_by_ingredient.remove(&record.id).unwrap_or_default(),
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+
+            let ingredient = Ingredient {
+                id: Some(record.id),
+                lotcode: record.lotcode,
+                name: record.name,
+                received_date: record.received_date.unwrap_or_default(),
+                org_id: record.org_id.unwrap_or(0),
+                density: record.density,
+                allergens,
+                status: record.status,
+                manufactured_date: record.manufactured_date,
+                best_by_date: record.best_by_date,
+                expiry_date: record.expiry_date,
+                ingredient_type: record.ingredient_type,
+            };
+            HttpResponse::Ok().json(ingredient)
+        },
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_ingredients(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // Extract auth token from request
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            match header.to_str() {
+                Ok(header_str) => {
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
+                    } else {
+                        return HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Invalid authorization header format"
+                        }));
+                    }
+                },
+                Err(_) => {
+                    return HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid authorization header"
+                    }));
+                }
+            }
+        },
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authorization header is missing"
+            }));
+        }
+    };
+    
+    // Verify token and get organization ID
+    let claims = match auth::verify_token(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid auth token"
+            }));
+        }
+    };
+    
+    let org_id = claims.org_id;
+
+    match sqlx::query!(
+        "SELECT id, lotcode, name, received_date::text as received_date, org_id, density, status, manufactured_date::text,
+         best_by_date::text, expiry_date::text, ingredient_type FROM ingredients WHERE org_id = $1",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => {
+            let ingredient_ids: Vec<i32> = records.iter().map(|record| record.id).collect();
+            let mut allergens_by_ingredient = match fetch_ingredient_allergens(&data.db_pool, &ingredient_ids).await {
+                Ok(allergens_by_ingredient) => allergens_by_ingredient,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch ingredients"}));
+                }
+            };
+
+            let ingredients: Vec<Ingredient> = records.into_iter().map(|record| {
+                Ingredient {
+                    id: Some(record.id),
+                    lotcode: record.lotcode,
+                    name: record.name,
+                    received_date: record.received_date.unwrap_or_default(),
+                    org_id: record.org_id.unwrap_or(0),
+                    density: record.density,
+                    allergens: allergens_by_ingredient.remove(&record.id).unwrap_or_default(),
+                    status: record.status,
+                    manufactured_date: record.manufactured_date,
+                    best_by_date: record.best_by_date,
+                    expiry_date: record.expiry_date,
+                    ingredient_type: record.ingredient_type,
+                }
+            }).collect();
+            HttpResponse::Ok().json(ingredients)
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch ingredients"}))
+        }
+    }
+}
+
+
+async fn update_ingredient(
+    path: web::Path<i32>,
+    ingredient: web::Json<IngredientInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    // Parse the date string to NaiveDate
+    let date = match NaiveDate::parse_from_str(&ingredient.received_date, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid date format. Use YYYY-MM-DD"
+            }));
+        }
+    };
+    
+    if ingredient.density.is_some_and(|density| density <= Decimal::ZERO) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Density must be greater than zero"
+        }));
+    }
+
+    if let Err(message) = validate_allergens(&ingredient.allergens) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let lot_dates = match parse_lot_dates(&ingredient) {
+        Ok(lot_dates) => lot_dates,
+        Err(response) => return response,
+    };
+
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let expiry_date = match lot_dates.expiry {
+        Some(expiry_date) => Some(expiry_date),
+        None => {
+            let ingredient_type = ingredient.ingredient_type.as_deref().unwrap_or(&ingredient.name);
+            match default_expiry(&mut tx, ingredient.org_id, ingredient_type, date, lot_dates.manufactured).await {
+                Ok(expiry_date) => expiry_date,
+                Err(response) => {
+                    let _ = tx.rollback().await;
+                    return response;
+                }
+            }
+        }
+    };
+
+    match sqlx::query!(
+        "UPDATE ingredients SET lotcode = $1, name = $2, received_date = $3, org_id = $4, density = $5, manufactured_date = $6,
+         best_by_date = $7, expiry_date = $8, ingredient_type = $9
+         WHERE id = $10 RETURNING status",
+        ingredient.lotcode,
+        ingredient.name,
+        date,
+        ingredient.org_id,
+        ingredient.density,
+        lot_dates.manufactured,
+        lot_dates.best_by,
+        expiry_date,
+        ingredient.ingredient_type,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(updated)) => {
+            let allergens = match save_ingredient_allergens(&mut tx, id, &ingredient.allergens).await {
+                Ok(allergens) => allergens,
+                Err(e) => {
+                    eprintln!("Failed to save ingredient allergens: {}", e);
+                    let _ = tx.rollback().await;
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+
+            // Commit the transaction
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+
+            let updated_ingredient = Ingredient {
+                id: Some(id),
+                lotcode: ingredient.lotcode.clone(),
+                name: ingredient.name.clone(),
+                received_date: ingredient.received_date.clone(),
+                org_id: ingredient.org_id,
+                density: ingredient.density,
+                allergens,
+                status: updated.status,
+                manufactured_date: lot_dates.manufactured.map(|date| date.to_string()),
+                best_by_date: lot_dates.best_by.map(|date| date.to_string()),
+                expiry_date: expiry_date.map(|date| date.to_string()),
+                ingredient_type: ingredient.ingredient_type.clone(),
+            };
+            HttpResponse::Ok().json(updated_ingredient)
+        }
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"}))
+        },
+        Err(e) => {
+            let _ = tx.rollback().await;
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("Lot {} of {} already exists", ingredient.lotcode, ingredient.name)
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_ingredient(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query!("DELETE FROM ingredients WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => {
+            // Attached documents went with it
+            purge_deleted_documents(&data).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Parse an optional YYYY-MM-DD date field
+fn parse_optional_date(value: &Option<String>, field: &str) -> Result<Option<NaiveDate>, HttpResponse> {
+    match value {
+        Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Some).map_err(|_| {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid {} format. Use YYYY-MM-DD", field)
+            }))
+        }),
+        None => Ok(None),
+    }
+}
+
+// Why deliveries from a supplier should not be accepted on `received`, if at all
+fn supplier_problem(name: &str, status: &str, audit_expiry: Option<NaiveDate>, received: NaiveDate) -> Option<String> {
+    if status != SupplierStatus::Approved.as_str() {
+        return Some(format!("Supplier {} is not approved (status: {})", name, status));
+    }
+    match audit_expiry {
+        Some(expiry) if expiry < received => Some(format!("Supplier {}'s audit expired on {}", name, expiry)),
+        _ => None,
+    }
+}
+
+// Outcome of the approved supplier rule for a receiving record
+struct SupplierCheck {
+    company_name: String,
+    supplier_id: Option<i32>,
+    // Kept with overridden deliveries, with the receiver's reason
+    warning: Option<String>,
+    override_reason: Option<String>,
+}
+
+// Apply the approved supplier rule to a receiving record. A free-text company
+// name is looked up in the supplier registry; companies that are not
+// registered count as unapproved.
+async fn check_supplier(
+    conn: &mut PgConnection,
+    receiving_log: &ReceivingLogInput,
+    date: NaiveDate,
+) -> Result<SupplierCheck, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let company_name = receiving_log.company_name.trim();
+    let supplier = match receiving_log.supplier_id {
+        Some(supplier_id) => match sqlx::query!(
+            "SELECT id, name, status, audit_expiry FROM suppliers WHERE id = $1 AND org_id = $2",
+            supplier_id,
+            receiving_log.org_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?
+        {
+            Some(supplier) => Some((supplier.id, supplier.name, supplier.status, supplier.audit_expiry)),
+            None => return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "Supplier not found"}))),
+        },
+        None if company_name.is_empty() => {
+            return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Either company_name or supplier_id is required"
+            })));
+        }
+        None => sqlx::query!(
+            "SELECT id, name, status, audit_expiry FROM suppliers
+             WHERE org_id = $1 AND LOWER(name) = LOWER($2) ORDER BY id LIMIT 1",
+            receiving_log.org_id,
+            company_name
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?
+        .map(|supplier| (supplier.id, supplier.name, supplier.status, supplier.audit_expiry)),
+    };
+
+    let (supplier_id, company_name, problem) = match supplier {
+        Some((supplier_id, name, status, audit_expiry)) => {
+            let problem = supplier_problem(&name, &status, audit_expiry, date);
+            let company_name = if company_name.is_empty() { name } else { receiving_log.company_name.clone() };
+            (Some(supplier_id), company_name, problem)
+        }
+        None => (
+            None,
+            receiving_log.company_name.clone(),
+            Some(format!("{} is not a registered supplier", company_name)),
+        ),
+    };
+
+    let override_reason = receiving_log.override_reason.as_deref().map(str::trim).filter(|reason| !reason.is_empty());
+    match problem {
+        Some(problem) if !receiving_log.override_supplier_check => Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": problem,
+            "supplier_id": supplier_id
+        }))),
+        Some(_) if override_reason.is_none() => Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "An override_reason is required to accept this delivery"
+        }))),
+        warning => Ok(SupplierCheck {
+            company_name,
+            supplier_id,
+            override_reason: warning.as_ref().and(override_reason).map(str::to_string),
+            warning,
+        }),
+    }
+}
+
+fn validate_supplier_input(supplier: &SupplierInput) -> Result<(Option<NaiveDate>, Option<NaiveDate>), HttpResponse> {
+    if supplier.name.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "Supplier name is required"})));
+    }
+
+    let approval_date = parse_optional_date(&supplier.approval_date, "approval_date")?;
+    let audit_expiry = parse_optional_date(&supplier.audit_expiry, "audit_expiry")?;
+
+    if supplier.status == SupplierStatus::Approved && approval_date.is_none() {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Approved suppliers need an approval_date"
+        })));
+    }
+
+    Ok((approval_date, audit_expiry))
+}
+
+// Supplier endpoints
+async fn create_supplier(
+    supplier: web::Json<SupplierInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let (approval_date, audit_expiry) = match validate_supplier_input(&supplier) {
+        Ok(dates) => dates,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as!(
+        Supplier,
+        "INSERT INTO suppliers (org_id, name, contact_name, contact_email, contact_phone, address, status,
+         approval_date, audit_expiry, products)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+         RETURNING id, org_id as \"org_id!\", name, contact_name, contact_email, contact_phone, address, status,
+         approval_date::text, audit_expiry::text, products",
+        supplier.org_id,
+        supplier.name,
+        supplier.contact_name,
+        supplier.contact_email,
+        supplier.contact_phone,
+        supplier.address,
+        supplier.status.as_str(),
+        approval_date,
+        audit_expiry,
+        &supplier.products
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(created) => HttpResponse::Created().json(created),
+        Err(e) => {
+            eprintln!("Failed to create supplier: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create supplier"}))
+        }
+    }
+}
+
+async fn get_supplier(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Supplier,
+        "SELECT id, org_id as \"org_id!\", name, contact_name, contact_email, contact_phone, address, status,
+         approval_date::text, audit_expiry::text, products
+         FROM suppliers WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(supplier)) if supplier.org_id == auth_org_id => HttpResponse::Ok().json(supplier),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this supplier"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Supplier not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_suppliers(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Supplier,
+        "SELECT id, org_id as \"org_id!\", name, contact_name, contact_email, contact_phone, address, status,
+         approval_date::text, audit_expiry::text, products
+         FROM suppliers WHERE org_id = $1 ORDER BY name, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(suppliers) => HttpResponse::Ok().json(suppliers),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch suppliers"}))
+        }
+    }
+}
+
+async fn update_supplier(
+    path: web::Path<i32>,
+    supplier: web::Json<SupplierInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let (approval_date, audit_expiry) = match validate_supplier_input(&supplier) {
+        Ok(dates) => dates,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as!(
+        Supplier,
+        "UPDATE suppliers SET org_id = $1, name = $2, contact_name = $3, contact_email = $4, contact_phone = $5,
+         address = $6, status = $7, approval_date = $8, audit_expiry = $9, products = $10
+         WHERE id = $11
+         RETURNING id, org_id as \"org_id!\", name, contact_name, contact_email, contact_phone, address, status,
+         approval_date::text, audit_expiry::text, products",
+        supplier.org_id,
+        supplier.name,
+        supplier.contact_name,
+        supplier.contact_email,
+        supplier.contact_phone,
+        supplier.address,
+        supplier.status.as_str(),
+        approval_date,
+        audit_expiry,
+        &supplier.products,
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Supplier not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_supplier(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    match sqlx::query!("DELETE FROM suppliers WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => {
+            // Attached documents went with it
+            purge_deleted_documents(&data).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Supplier not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Set a problem log's due dates from its organization's SLA for its problem
+// type, counted from when the log was recorded. Logs without an SLA have none.
+async fn set_problem_log_due_dates(
+    conn: &mut PgConnection,
+    problem_log_id: i32,
+) -> Result<(Option<String>, Option<String>), sqlx::Error> {
+    let record = sqlx::query!(
+        "UPDATE problem_logs pl SET response_due_at = pl.opened_at + make_interval(hours => s.response_hours),
+         resolution_due_at = pl.opened_at + make_interval(hours => s.resolution_hours)
+         FROM problem_logs p
+         LEFT JOIN problem_log_slas s ON s.org_id = p.org_id AND LOWER(s.problem_type) = LOWER(p.problem_type)
+         WHERE p.id = $1 AND pl.id = p.id
+         RETURNING pl.response_due_at::text, pl.resolution_due_at::text",
+        problem_log_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok((record.response_due_at, record.resolution_due_at))
+}
+
+// Check a problem log's complaint category belongs to its organization.
+// Retired categories are only kept on logs that already had them.
+async fn check_problem_log_category(
+    conn: &mut PgConnection,
+    problem_log: &ProblemLogInput,
+    current_category_id: Option<i32>,
+) -> Result<(), HttpResponse> {
+    let category_id = match problem_log.category_id {
+        Some(category_id) => category_id,
+        None => return Ok(()),
+    };
+
+    match sqlx::query!("SELECT org_id, name, active FROM complaint_categories WHERE id = $1", category_id)
+        .fetch_optional(&mut *conn)
+        .await
+    {
+        Ok(Some(category)) if problem_log.org_id.is_some_and(|org_id| org_id != category.org_id) => {
+            Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Complaint category {} belongs to another organization", category_id)
+            })))
+        }
+        Ok(Some(category)) if !category.active && current_category_id != Some(category_id) => {
+            Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Complaint category {} has been retired", category.name)
+            })))
+        }
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Complaint category {} not found", category_id)
+        }))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+// The customer name recorded on a problem log: the given name, or the name
+// of the referenced customer when no name is given
+async fn problem_log_customer_name(
+    conn: &mut PgConnection,
+    problem_log: &ProblemLogInput,
+) -> Result<String, HttpResponse> {
+    let customer_id = match problem_log.customer_id {
+        Some(customer_id) => customer_id,
+        None => return Ok(problem_log.customer_name.clone()),
+    };
+
+    match sqlx::query_scalar!(
+        "SELECT name FROM customers WHERE id = $1 AND org_id IS NOT DISTINCT FROM $2",
+        customer_id,
+        problem_log.org_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    {
+        Ok(Some(name)) if problem_log.customer_name.trim().is_empty() => Ok(name),
+        Ok(Some(_)) => Ok(problem_log.customer_name.clone()),
+        Ok(None) => Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Customer {} not found", customer_id)
+        }))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+// Customer endpoints
+async fn create_customer(
+    customer: web::Json<CustomerInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if customer.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "Customer name is required"}));
+    }
+
+    match sqlx::query_as!(
+        Customer,
+        "INSERT INTO customers (org_id, name, contact_name, contact_email, contact_phone, address)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, org_id, name, contact_name, contact_email, contact_phone, address",
+        customer.org_id,
+        customer.name.trim(),
+        customer.contact_name,
+        customer.contact_email,
+        customer.contact_phone,
+        customer.address
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(created) => HttpResponse::Created().json(created),
+        Err(e) => {
+            eprintln!("Failed to create customer: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create customer"}))
+        }
+    }
+}
+
+async fn get_customer(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Customer,
+        "SELECT id, org_id, name, contact_name, contact_email, contact_phone, address FROM customers WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(customer)) if customer.org_id == auth_org_id => HttpResponse::Ok().json(customer),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this customer"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Customer not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_customers(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Customer,
+        "SELECT id, org_id, name, contact_name, contact_email, contact_phone, address
+         FROM customers WHERE org_id = $1 ORDER BY name, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(customers) => HttpResponse::Ok().json(customers),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch customers"}))
+        }
+    }
+}
+
+async fn update_customer(
+    path: web::Path<i32>,
+    customer: web::Json<CustomerInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    if customer.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "Customer name is required"}));
+    }
+
+    match sqlx::query_as!(
+        Customer,
+        "UPDATE customers SET name = $1, contact_name = $2, contact_email = $3, contact_phone = $4, address = $5
+         WHERE id = $6 AND org_id = $7
+         RETURNING id, org_id, name, contact_name, contact_email, contact_phone, address",
+        customer.name.trim(),
+        customer.contact_name,
+        customer.contact_email,
+        customer.contact_phone,
+        customer.address,
+        id,
+        customer.org_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Customer not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_customer(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM customers WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Customer not found"})),
+        Err(e) => {
+            // Customers with shipments must stay traceable
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Customer has shipments and cannot be deleted"
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Check a shipment's customer and batches belong to the organization, that
+// only released batches are shipped and that no batch ships more than was
+// made. `shipment_id` is the shipment being updated, whose lines are replaced.
+async fn validate_shipment(
+    conn: &mut PgConnection,
+    shipment: &ShipmentInput,
+    shipment_id: Option<i32>,
+) -> Result<NaiveDate, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let ship_date = NaiveDate::parse_from_str(&shipment.ship_date, "%Y-%m-%d").map_err(|_| {
+        HttpResponse::BadRequest().json(serde_json::json!({"error": "Invalid ship_date format. Use YYYY-MM-DD"}))
+    })?;
+
+    if shipment.lines.is_empty() {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "A shipment needs at least one batch"})));
+    }
+
+    let customer_org_id = sqlx::query_scalar!("SELECT org_id FROM customers WHERE id = $1", shipment.customer_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    if customer_org_id != Some(shipment.org_id) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Customer {} not found", shipment.customer_id)
+        })));
+    }
+
+    let unit_error = |e: UnitError| HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    for line in &shipment.lines {
+        // Locked so concurrent shipments of the batch are counted in turn
+        let batch = sqlx::query!(
+            "SELECT org_id, batch_lot_code, status, amount_made, amount_made_unit FROM batches WHERE id = $1 FOR UPDATE",
+            line.batch_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+        let batch = match batch {
+            Some(batch) if batch.org_id == Some(shipment.org_id) => batch,
+            _ => {
+                return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Batch {} not found", line.batch_id)
+                })));
+            }
+        };
+        if batch.status != LotStatus::Released.as_str() {
+            return Err(HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("Batch {} is {} and cannot be shipped", batch.batch_lot_code, batch.status)
+            })));
+        }
+
+        // Batches recorded without an amount made cannot be checked
+        let Some(amount_made) = Quantity::from_parts(batch.amount_made, batch.amount_made_unit.as_deref()) else {
+            continue;
+        };
+        let shipped_elsewhere = sqlx::query!(
+            "SELECT quantity, unit FROM shipment_lines WHERE batch_id = $1 AND ($2::int IS NULL OR shipment_id <> $2)",
+            line.batch_id,
+            shipment_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+        let mut shipped = Decimal::ZERO;
+        for other in shipped_elsewhere {
+            if let Some(quantity) = Quantity::from_parts(Some(other.quantity), Some(&other.unit)) {
+                shipped += quantity.convert(amount_made.unit, None).map_err(unit_error)?.value;
+            }
+        }
+        for same_batch in shipment.lines.iter().filter(|other| other.batch_id == line.batch_id) {
+            shipped += same_batch.quantity.convert(amount_made.unit, None).map_err(unit_error)?.value;
+        }
+        if shipped > amount_made.value {
+            return Err(HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!(
+                    "Batch {} would ship {} but only {} was made",
+                    batch.batch_lot_code,
+                    Quantity::new(shipped, amount_made.unit),
+                    amount_made
+                )
+            })));
+        }
+    }
+
+    Ok(ship_date)
+}
+
+async fn save_shipment_lines(
+    conn: &mut PgConnection,
+    shipment_id: i32,
+    lines: &[ShipmentLineInput],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM shipment_lines WHERE shipment_id = $1", shipment_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for line in lines {
+        sqlx::query!(
+            "INSERT INTO shipment_lines (shipment_id, batch_id, quantity, unit) VALUES ($1, $2, $3, $4)",
+            shipment_id,
+            line.batch_id,
+            line.quantity.value,
+            line.quantity.unit.symbol()
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn fetch_shipment(pool: &Pool<Postgres>, id: i32) -> Result<Option<Shipment>, sqlx::Error> {
+    let shipment = match sqlx::query!(
+        "SELECT s.id, s.org_id, s.customer_id, c.name as customer_name, s.ship_date::text as \"ship_date!\", s.reference, s.notes
+         FROM shipments s JOIN customers c ON c.id = s.customer_id
+         WHERE s.id = $1",
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(shipment) => shipment,
+        None => return Ok(None),
+    };
+
+    let lines = sqlx::query!(
+        "SELECT sl.batch_id, b.batch_lot_code, sl.quantity, sl.unit
+         FROM shipment_lines sl JOIN batches b ON b.id = sl.batch_id
+         WHERE sl.shipment_id = $1 ORDER BY sl.id",
+        id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|line| ShipmentLine {
+        batch_id: line.batch_id,
+        batch_lot_code: line.batch_lot_code,
+        quantity: Quantity::from_parts(Some(line.quantity), Some(&line.unit)),
+    })
+    .collect();
+
+    Ok(Some(Shipment {
+        id: shipment.id,
+        org_id: shipment.org_id,
+        customer_id: shipment.customer_id,
+        customer_name: shipment.customer_name,
+        ship_date: shipment.ship_date,
+        reference: shipment.reference,
+        notes: shipment.notes,
+        lines,
+    }))
+}
+
+// Shipment endpoints
+async fn create_shipment(
+    shipment: web::Json<ShipmentInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let ship_date = match validate_shipment(&mut tx, &shipment, None).await {
+        Ok(ship_date) => ship_date,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    let shipment_id = match sqlx::query_scalar!(
+        "INSERT INTO shipments (org_id, customer_id, ship_date, reference, notes) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        shipment.org_id,
+        shipment.customer_id,
+        ship_date,
+        shipment.reference,
+        shipment.notes
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(shipment_id) => shipment_id,
+        Err(e) => {
+            eprintln!("Failed to create shipment: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create shipment"}));
+        }
+    };
+
+    if let Err(e) = save_shipment_lines(&mut tx, shipment_id, &shipment.lines).await {
+        eprintln!("Failed to save shipment lines: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create shipment"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    match fetch_shipment(&data.db_pool, shipment_id).await {
+        Ok(Some(created)) => HttpResponse::Created().json(created),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Shipment not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_shipment(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match fetch_shipment(&data.db_pool, id).await {
+        Ok(Some(shipment)) if shipment.org_id == auth_org_id => HttpResponse::Ok().json(shipment),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this shipment"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Shipment not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// The organization's shipments, newest first, optionally for one customer
+async fn get_all_shipments(
+    req: HttpRequest,
+    query: web::Query<ShipmentQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let ids = match sqlx::query_scalar!(
+        "SELECT id FROM shipments WHERE org_id = $1 AND ($2::int IS NULL OR customer_id = $2)
+         ORDER BY ship_date DESC, id DESC",
+        org_id,
+        query.customer_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch shipments"}));
+        }
+    };
+
+    let mut shipments = Vec::new();
+    for id in ids {
+        match fetch_shipment(&data.db_pool, id).await {
+            Ok(Some(shipment)) => shipments.push(shipment),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch shipments"}));
+            }
+        }
+    }
+    HttpResponse::Ok().json(shipments)
+}
+
+async fn update_shipment(
+    path: web::Path<i32>,
+    shipment: web::Json<ShipmentInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let ship_date = match validate_shipment(&mut tx, &shipment, Some(id)).await {
+        Ok(ship_date) => ship_date,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    match sqlx::query!(
+        "UPDATE shipments SET customer_id = $1, ship_date = $2, reference = $3, notes = $4
+         WHERE id = $5 AND org_id = $6 RETURNING id",
+        shipment.customer_id,
+        ship_date,
+        shipment.reference,
+        shipment.notes,
+        id,
+        shipment.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Shipment not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    if let Err(e) = save_shipment_lines(&mut tx, id, &shipment.lines).await {
+        eprintln!("Failed to save shipment lines: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update shipment"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    match fetch_shipment(&data.db_pool, id).await {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Shipment not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_shipment(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    // Shipments that have gone out are the forward trace and must stay on record
+    match sqlx::query_scalar!(
+        "WITH target AS (SELECT id, ship_date <= CURRENT_DATE as shipped FROM shipments WHERE id = $1),
+         deleted AS (DELETE FROM shipments WHERE id IN (SELECT id FROM target WHERE NOT shipped) RETURNING id)
+         SELECT shipped as \"shipped!\" FROM target",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(false)) => HttpResponse::NoContent().finish(),
+        Ok(Some(true)) => HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Shipment has already shipped and cannot be deleted"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Shipment not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Forward trace: every shipment of a batch and the customer it went to
+async fn get_batch_shipments(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_scalar!("SELECT org_id FROM batches WHERE id = $1", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(Some(org_id))) if org_id == auth_org_id => {}
+        Ok(Some(_)) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "You don't have permission to access this batch"
+            }));
+        }
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    match sqlx::query!(
+        "SELECT s.id, s.customer_id, c.name as customer_name, s.ship_date::text as \"ship_date!\", s.reference, sl.quantity, sl.unit
+         FROM shipment_lines sl
+         JOIN shipments s ON s.id = sl.shipment_id
+         JOIN customers c ON c.id = s.customer_id
+         WHERE sl.batch_id = $1
+         ORDER BY s.ship_date, s.id, sl.id",
+        id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => {
+            let shipments: Vec<BatchShipment> = records.into_iter().map(|record| BatchShipment {
+                shipment_id: record.id,
+                customer_id: record.customer_id,
+                customer_name: record.customer_name,
+                ship_date: record.ship_date,
+                reference: record.reference,
+                quantity: Quantity::from_parts(Some(record.quantity), Some(&record.unit)),
+            }).collect();
+            HttpResponse::Ok().json(shipments)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch shipments"}))
+        }
+    }
+}
+
+// Traceability endpoints
+
+// Quantities in the export are written without trailing zeros
+fn export_quantity(value: Option<Decimal>, unit: Option<String>) -> (Option<String>, Option<String>) {
+    match value {
+        Some(value) => (Some(value.normalize().to_string()), unit),
+        None => (None, None),
+    }
+}
+
+// The lot codes linked to a lot through any number of batches: upstream, the
+// lot and the lots it was made from; downstream, the lot and the batches made
+// from it. A batch used in another batch is linked through the ingredient lot
+// carrying its batch lot code.
+async fn trace_lot_codes(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    lot: &str,
+) -> Result<(Vec<String>, Vec<String>), sqlx::Error> {
+    let upstream = sqlx::query_scalar!(
+        "WITH RECURSIVE upstream(code) AS (
+             SELECT $2::text
+             UNION
+             SELECT i.lotcode FROM upstream u
+             JOIN batches b ON b.org_id = $1 AND b.batch_lot_code = u.code
+             JOIN batch_ingredients bi ON bi.batch_id = b.id
+             JOIN ingredients i ON i.id = bi.ingredient_id
+         )
+         SELECT code as \"code!\" FROM upstream",
+        org_id,
+        lot
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let downstream = sqlx::query_scalar!(
+        "WITH RECURSIVE downstream(code) AS (
+             SELECT $2::text
+             UNION
+             SELECT b.batch_lot_code FROM downstream d
+             JOIN ingredients i ON i.org_id = $1 AND i.lotcode = d.code
+             JOIN batch_ingredients bi ON bi.ingredient_id = i.id
+             JOIN batches b ON b.id = bi.batch_id AND b.org_id = $1
+         )
+         SELECT code as \"code!\" FROM downstream",
+        org_id,
+        lot
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((upstream, downstream))
+}
+
+// Receiving, transformation and shipping events mapped to FSMA 204 Key Data
+// Elements. Filtering by lot follows the lot through every level of batches:
+// back to the received lots it was made from and forward to the batches made
+// from it and their shipments.
+async fn fsma204_records(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    lot: Option<&str>,
+) -> Result<Vec<TraceabilityRecord>, sqlx::Error> {
+    let org_name = sqlx::query_scalar!("SELECT name FROM organizations WHERE id = $1", org_id)
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or_default();
+    let mut records = Vec::new();
+
+    let (traced, shipped) = match lot {
+        Some(lot) => {
+            let (upstream, downstream) = trace_lot_codes(pool, org_id, lot).await?;
+            let mut traced = upstream;
+            traced.extend(downstream.iter().cloned());
+            (Some(traced), Some(downstream))
+        }
+        None => (None, None),
+    };
+
+    let receipts = sqlx::query!(
+        "SELECT r.id, r.lotcode, r.item_name, r.company_name, r.date::text as \"date!\", r.quantity, r.quantity_unit,
+         s.name as \"supplier_name?\", s.address as supplier_address
+         FROM receiving_log r
+         LEFT JOIN suppliers s ON s.id = r.supplier_id
+         WHERE r.org_id = $1 AND r.disposition <> 'reject'
+           AND ($2::date IS NULL OR r.date >= $2) AND ($3::date IS NULL OR r.date <= $3)
+           AND ($4::text[] IS NULL OR r.lotcode = ANY($4))",
+        org_id,
+        from,
+        to,
+        traced.as_deref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for receipt in receipts {
+        let source = match receipt.supplier_name {
+            Some(name) => traceability::location_description(&name, receipt.supplier_address.as_deref()),
+            None => receipt.company_name,
+        };
+        let (quantity, unit_of_measure) = export_quantity(receipt.quantity, receipt.quantity_unit);
+        records.push(TraceabilityRecord {
+            event_type: TrackingEvent::Receiving,
+            traceability_lot_code: receipt.lotcode,
+            product_description: receipt.item_name,
+            quantity,
+            unit_of_measure,
+            event_date: receipt.date,
+            location_description: org_name.clone(),
+            immediate_previous_source: Some(source.clone()),
+            ship_from_location: Some(source.clone()),
+            ship_to_location: Some(org_name.clone()),
+            traceability_lot_code_source: source,
+            input_traceability_lot_code: None,
+            input_product_description: None,
+            input_quantity: None,
+            input_unit_of_measure: None,
+            reference_document_type: "Receiving Record".to_string(),
+            reference_document_number: receipt.id.to_string(),
+        });
+    }
+
+    let transformations = sqlx::query!(
+        "SELECT b.batch_lot_code, b.date_made::text as \"date_made!\", b.amount_made, b.amount_made_unit,
+         COALESCE((SELECT r.name FROM recipes r WHERE r.org_id = b.org_id AND r.lotcode = b.recipe_lotcode
+                   ORDER BY r.id DESC LIMIT 1), b.recipe_lotcode) as \"product!\",
+         i.lotcode as \"input_lotcode?\", i.name as \"input_name?\", bi.amount as \"input_amount?\", bi.unit as input_unit
+         FROM batches b
+         LEFT JOIN batch_ingredients bi ON bi.batch_id = b.id
+         LEFT JOIN ingredients i ON i.id = bi.ingredient_id
+         WHERE b.org_id = $1
+           AND ($2::date IS NULL OR b.date_made >= $2) AND ($3::date IS NULL OR b.date_made <= $3)
+           AND ($4::text[] IS NULL OR b.batch_lot_code = ANY($4))",
+        org_id,
+        from,
+        to,
+        traced.as_deref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for transformation in transformations {
+        let (quantity, unit_of_measure) = export_quantity(transformation.amount_made, transformation.amount_made_unit);
+        let (input_quantity, input_unit_of_measure) = export_quantity(transformation.input_amount, transformation.input_unit);
+        records.push(TraceabilityRecord {
+            event_type: TrackingEvent::Transformation,
+            traceability_lot_code: transformation.batch_lot_code.clone(),
+            product_description: transformation.product,
+            quantity,
+            unit_of_measure,
+            event_date: transformation.date_made,
+            location_description: org_name.clone(),
+            immediate_previous_source: None,
+            ship_from_location: None,
+            ship_to_location: None,
+            traceability_lot_code_source: org_name.clone(),
+            input_traceability_lot_code: transformation.input_lotcode,
+            input_product_description: transformation.input_name,
+            input_quantity,
+            input_unit_of_measure,
+            reference_document_type: "Batch Record".to_string(),
+            reference_document_number: transformation.batch_lot_code,
+        });
+    }
+
+    let shipments = sqlx::query!(
+        "SELECT s.id, s.ship_date::text as \"ship_date!\", s.reference, c.name as customer_name, c.address as customer_address,
+         b.batch_lot_code, sl.quantity, sl.unit,
+         COALESCE((SELECT r.name FROM recipes r WHERE r.org_id = b.org_id AND r.lotcode = b.recipe_lotcode
+                   ORDER BY r.id DESC LIMIT 1), b.recipe_lotcode) as \"product!\"
+         FROM shipment_lines sl
+         JOIN shipments s ON s.id = sl.shipment_id
+         JOIN customers c ON c.id = s.customer_id
+         JOIN batches b ON b.id = sl.batch_id
+         WHERE s.org_id = $1
+           AND ($2::date IS NULL OR s.ship_date >= $2) AND ($3::date IS NULL OR s.ship_date <= $3)
+           AND ($4::text[] IS NULL OR b.batch_lot_code = ANY($4))",
+        org_id,
+        from,
+        to,
+        shipped.as_deref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for shipment in shipments {
+        let (quantity, unit_of_measure) = export_quantity(Some(shipment.quantity), Some(shipment.unit));
+        let (reference_document_type, reference_document_number) = match shipment.reference {
+            Some(reference) => ("Shipment Reference", reference),
+            None => ("Shipment Record", shipment.id.to_string()),
+        };
+        records.push(TraceabilityRecord {
+            event_type: TrackingEvent::Shipping,
+            traceability_lot_code: shipment.batch_lot_code,
+            product_description: shipment.product,
+            quantity,
+            unit_of_measure,
+            event_date: shipment.ship_date,
+            location_description: org_name.clone(),
+            immediate_previous_source: None,
+            ship_from_location: Some(org_name.clone()),
+            ship_to_location: Some(traceability::location_description(
+                &shipment.customer_name,
+                shipment.customer_address.as_deref(),
+            )),
+            traceability_lot_code_source: org_name.clone(),
+            input_traceability_lot_code: None,
+            input_product_description: None,
+            input_quantity: None,
+            input_unit_of_measure: None,
+            reference_document_type: reference_document_type.to_string(),
+            reference_document_number,
+        });
+    }
+
+    traceability::sort_records(&mut records);
+    Ok(records)
+}
+
+// The date range and lot of an export
+struct TraceabilityScope<'a> {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    lot: Option<&'a str>,
+}
+
+// One of a lot or a full date range is needed
+fn traceability_scope(query: &TraceabilityExportQuery) -> Result<TraceabilityScope<'_>, HttpResponse> {
+    let parse_date = |value: &Option<String>, field: &str| match value {
+        Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Some).map_err(|_| {
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid {} format. Use YYYY-MM-DD", field)
+            }))
+        }),
+        None => Ok(None),
+    };
+    let from = parse_date(&query.from, "from")?;
+    let to = parse_date(&query.to, "to")?;
+    let lot = query.lot.as_deref().map(str::trim).filter(|lot| !lot.is_empty());
+
+    if lot.is_none() && (from.is_none() || to.is_none()) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Give a lot, or a date range with from and to"
+        })));
+    }
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "from must not be after to"})));
+        }
+    }
+    Ok(TraceabilityScope { from, to, lot })
+}
+
+// FDA electronic sortable spreadsheet for a date range and/or lot
+async fn export_fsma204(
+    req: HttpRequest,
+    query: web::Query<TraceabilityExportQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let scope = match traceability_scope(&query) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let records = match fsma204_records(&data.db_pool, org_id, scope.from, scope.to, scope.lot).await {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    match query.format.as_deref() {
+        None | Some("csv") => {
+            let file_scope = match (scope.lot, scope.from, scope.to) {
+                (Some(lot), _, _) => lot.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_"),
+                (None, Some(from), Some(to)) => format!("{}_{}", from, to),
+                _ => "all".to_string(),
+            };
+            HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .insert_header(("Content-Disposition", format!("attachment; filename=\"fsma204_{}.csv\"", file_scope)))
+                .body(traceability::to_csv(&records))
+        }
+        Some("json") => HttpResponse::Ok().json(records),
+        Some(other) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unsupported format: {}. Use csv or json", other)
+        })),
+    }
+}
+
+// EPCIS endpoints
+
+fn record_quantity(value: &Option<String>, unit: &Option<String>) -> Option<Quantity> {
+    let value = value.as_deref().and_then(|value| value.parse::<Decimal>().ok());
+    Quantity::from_parts(value, unit.as_deref())
+}
+
+// Traceability records as EPCIS 2.0 events: receipts and shipments as object
+// events, batches as transformation events with their input lots
+fn epcis_events(org_id: i32, records: &[TraceabilityRecord]) -> Vec<serde_json::Value> {
+    let org = epcis::identifier("org", &org_id.to_string());
+    let party = |name: &Option<String>| {
+        let name = name.as_deref().unwrap_or_default();
+        serde_json::json!({"type": "owning_party", "source": epcis::identifier("party", name)})
+    };
+    let mut events: Vec<serde_json::Value> = Vec::new();
+    let mut last_transformation: Option<(&str, &str)> = None;
+
+    for record in records {
+        let epc_class = epcis::lot_class(org_id, &record.traceability_lot_code);
+        let output = epcis::quantity_element(&epc_class, record_quantity(&record.quantity, &record.unit_of_measure));
+        let mut event = match record.event_type {
+            TrackingEvent::Receiving => serde_json::json!({
+                "type": "ObjectEvent",
+                "eventID": epcis::identifier("event", &format!("receiving-{}", record.reference_document_number)),
+                "action": "OBSERVE",
+                "bizStep": "receiving",
+                "disposition": "in_progress",
+                "epcList": [],
+                "quantityList": [output],
+                "sourceList": [party(&record.immediate_previous_source)],
+                "destinationList": [{"type": "owning_party", "destination": org}],
+                "bizTransactionList": [{
+                    "bizTransaction": epcis::identifier("receiving", &record.reference_document_number)
+                }]
+            }),
+            TrackingEvent::Transformation => {
+                let key = (record.traceability_lot_code.as_str(), record.event_date.as_str());
+                let input = record.input_traceability_lot_code.as_ref().map(|input_lot| {
+                    epcis::quantity_element(
+                        &epcis::lot_class(org_id, input_lot),
+                        record_quantity(&record.input_quantity, &record.input_unit_of_measure),
+                    )
+                });
+                // Rows for the same batch add inputs to one event
+                if last_transformation == Some(key) {
+                    if let (Some(input), Some(event)) = (input, events.last_mut()) {
+                        if let Some(inputs) = event["inputQuantityList"].as_array_mut() {
+                            inputs.push(input);
+                        }
+                    }
+                    continue;
+                }
+                last_transformation = Some(key);
+                serde_json::json!({
+                    "type": "TransformationEvent",
+                    "eventID": epcis::identifier("event", &format!("transformation-{}", record.traceability_lot_code)),
+                    "bizStep": "transforming",
+                    "disposition": "active",
+                    "inputQuantityList": input.into_iter().collect::<Vec<_>>(),
+                    "outputQuantityList": [output]
+                })
+            }
+            TrackingEvent::Shipping => {
+                let mut source = party(&Some(record.location_description.clone()));
+                source["source"] = serde_json::json!(org);
+                serde_json::json!({
+                    "type": "ObjectEvent",
+                    "eventID": epcis::identifier(
+                        "event",
+                        &format!("shipping-{}-{}", record.reference_document_number, record.traceability_lot_code)
+                    ),
+                    "action": "OBSERVE",
+                    "bizStep": "shipping",
+                    "disposition": "in_transit",
+                    "epcList": [],
+                    "quantityList": [output],
+                    "sourceList": [source],
+                    "destinationList": [{
+                        "type": "owning_party",
+                        "destination": epcis::identifier("party", record.ship_to_location.as_deref().unwrap_or_default())
+                    }],
+                    "bizTransactionList": [{
+                        "bizTransaction": epcis::identifier("shipment", &record.reference_document_number)
+                    }]
+                })
+            }
+        };
+
+        let fields = event.as_object_mut().expect("events are JSON objects");
+        fields.insert("eventTime".to_string(), serde_json::json!(epcis::event_time(&record.event_date)));
+        fields.insert("eventTimeZoneOffset".to_string(), serde_json::json!("+00:00"));
+        fields.insert("bizLocation".to_string(), serde_json::json!({"id": org}));
+        fields.extend(epcis::extensions([
+            ("productName", serde_json::json!(record.product_description)),
+            ("lotCode", serde_json::json!(record.traceability_lot_code)),
+        ]));
+        events.push(event);
+    }
+    events
+}
+
+// Receiving, transformation and shipping events as an EPCIS 2.0 document
+async fn export_epcis(
+    req: HttpRequest,
+    query: web::Query<TraceabilityExportQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let scope = match traceability_scope(&query) {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    match fsma204_records(&data.db_pool, org_id, scope.from, scope.to, scope.lot).await {
+        Ok(records) => {
+            let creation_date = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+            HttpResponse::Ok()
+                .content_type("application/ld+json")
+                .json(epcis::document(epcis_events(org_id, &records), &creation_date))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Record the lots of a supplier's EPCIS shipping events as deliveries.
+// Either every delivery is recorded or none is.
+async fn import_epcis(
+    import: web::Json<EpcisImportInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let lots = match epcis::shipped_lots(&import.document) {
+        Ok(lots) if lots.is_empty() => {
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": "The document has no shipping events"}));
+        }
+        Ok(lots) => lots,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": e})),
+    };
+    let today = chrono::Local::now().date_naive().to_string();
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Only shipments to this organization are received here
+    let org_name = match sqlx::query_scalar!("SELECT name FROM organizations WHERE id = $1", import.org_id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(org_name)) => org_name,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": "Organization not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+    if let Some(lot) = lots.iter().find(|lot| {
+        lot.destination.as_deref().is_some_and(|destination| !epcis::is_own_destination(destination, import.org_id, &org_name))
+    }) {
+        let _ = tx.rollback().await;
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!(
+                "Lot {} was shipped to {}, not to this organization",
+                lot.lot,
+                lot.destination.as_deref().unwrap_or_default()
+            )
+        }));
+    }
+
+    let mut result = EpcisImportResult { created: Vec::new(), skipped: Vec::new() };
+    for lot in lots {
+        if let Some(event_id) = &lot.event_id {
+            match sqlx::query_scalar!(
+                "INSERT INTO epcis_imports (org_id, event_id, lotcode) VALUES ($1, $2, $3)
+                 ON CONFLICT (org_id, event_id, lotcode) DO NOTHING RETURNING id",
+                import.org_id,
+                event_id,
+                lot.lot
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    result.skipped.push(serde_json::json!({"event_id": event_id, "lotcode": lot.lot}));
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    let _ = tx.rollback().await;
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            }
+        }
+
+        let temperature = match lot.temperature.or(import.temperature) {
+            Some(temperature) => temperature,
+            None => {
+                let _ = tx.rollback().await;
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Lot {} has no temperature reading; give the temperature on arrival", lot.lot)
+                }));
+            }
+        };
+        let receiving_log = ReceivingLogInput {
+            lotcode: lot.lot.clone(),
+            company_name: if import.supplier_id.is_some() { String::new() } else { lot.source.clone().unwrap_or_default() },
+            item_name: lot.item_name.clone(),
+            temperature: temperature.into(),
+            // The day the supplier shipped, unless the event has no time
+            date: lot.event_date.clone().or_else(|| import.date.clone()).unwrap_or_else(|| today.clone()),
+            org_id: import.org_id,
+            quantity: lot.quantity,
+            ingredient_id: None,
+            supplier_id: import.supplier_id,
+            override_supplier_check: false,
+            override_reason: None,
+            expiry_date: lot.expiry_date.clone(),
+            checklist_id: None,
+            checks: Vec::new(),
+            disposition: None,
+            disposition_reason: None,
+            gtin: None,
+            serial_number: None,
+        };
+
+        let created = match insert_receiving_log(&mut tx, &receiving_log).await {
+            Ok(created) => created,
+            Err(response) => {
+                let _ = tx.rollback().await;
+                return response;
+            }
+        };
+        if let Some(event_id) = &lot.event_id {
+            if let Err(e) = sqlx::query!(
+                "UPDATE epcis_imports SET receiving_log_id = $1 WHERE org_id = $2 AND event_id = $3 AND lotcode = $4",
+                created.id,
+                import.org_id,
+                event_id,
+                lot.lot
+            )
+            .execute(&mut *tx)
+            .await
+            {
+                eprintln!("Database error: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+        }
+        result.created.push(created);
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(result)
+}
+
+// Label endpoints
+fn validate_label_template(template: &LabelTemplateInput) -> Result<(), String> {
+    if template.name.trim().is_empty() {
+        return Err("Label template name is required".to_string());
+    }
+    if template.width_mm.is_some_and(|width| width <= Decimal::ZERO)
+        || template.height_mm.is_some_and(|height| height <= Decimal::ZERO)
+    {
+        return Err("Label width and height must be greater than zero".to_string());
+    }
+    if template.dpi.is_some_and(|dpi| ![152, 203, 300, 600].contains(&dpi)) {
+        return Err("dpi must be 152, 203, 300 or 600".to_string());
+    }
+    if template.best_by_days.is_some_and(|days| days <= 0) {
+        return Err("best_by_days must be greater than zero".to_string());
+    }
+    if template.best_by_days.is_some_and(|days| days > MAX_SHELF_LIFE_DAYS) {
+        return Err(format!("best_by_days cannot be more than {}", MAX_SHELF_LIFE_DAYS));
+    }
+    Ok(())
+}
+
+// Clear the organization's default before another template becomes it
+async fn clear_default_label_template(
+    conn: &mut PgConnection,
+    template: &LabelTemplateInput,
+    id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    if template.is_default {
+        sqlx::query!(
+            "UPDATE label_templates SET is_default = FALSE WHERE org_id = $1 AND id IS DISTINCT FROM $2 AND is_default",
+            template.org_id,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn create_label_template(
+    template: web::Json<LabelTemplateInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(message) = validate_label_template(&template) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(e) = clear_default_label_template(&mut tx, &template, None).await {
+        eprintln!("Failed to create label template: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create label template"}));
+    }
+
+    let created = match sqlx::query_as!(
+        LabelTemplate,
+        "INSERT INTO label_templates (org_id, name, is_default, width_mm, height_mm, dpi, header, footer,
+         show_allergens, show_barcode, show_qr, best_by_days, link_url)
+         VALUES ($1, $2, $3, COALESCE($4, 101.6), COALESCE($5, 76.2), COALESCE($6, 203), $7, $8,
+         COALESCE($9, TRUE), COALESCE($10, TRUE), COALESCE($11, TRUE), $12, $13)
+         RETURNING id, org_id, name, is_default, width_mm, height_mm, dpi, header, footer,
+         show_allergens, show_barcode, show_qr, best_by_days, link_url",
+        template.org_id,
+        template.name.trim(),
+        template.is_default,
+        template.width_mm,
+        template.height_mm,
+        template.dpi,
+        template.header,
+        template.footer,
+        template.show_allergens,
+        template.show_barcode,
+        template.show_qr,
+        template.best_by_days,
+        template.link_url
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(created) => created,
+        Err(e) => {
+            eprintln!("Failed to create label template: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create label template"}));
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(created)
+}
+
+async fn get_label_template(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        LabelTemplate,
+        "SELECT id, org_id, name, is_default, width_mm, height_mm, dpi, header, footer,
+         show_allergens, show_barcode, show_qr, best_by_days, link_url
+         FROM label_templates WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(template)) if template.org_id == auth_org_id => HttpResponse::Ok().json(template),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this label template"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Label template not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_label_templates(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        LabelTemplate,
+        "SELECT id, org_id, name, is_default, width_mm, height_mm, dpi, header, footer,
+         show_allergens, show_barcode, show_qr, best_by_days, link_url
+         FROM label_templates WHERE org_id = $1 ORDER BY is_default DESC, name, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(templates) => HttpResponse::Ok().json(templates),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch label templates"}))
+        }
+    }
+}
+
+async fn update_label_template(
+    path: web::Path<i32>,
+    template: web::Json<LabelTemplateInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    if let Err(message) = validate_label_template(&template) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(e) = clear_default_label_template(&mut tx, &template, Some(id)).await {
+        eprintln!("Failed to update label template: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update label template"}));
+    }
+
+    let updated = match sqlx::query_as!(
+        LabelTemplate,
+        "UPDATE label_templates SET name = $1, is_default = $2, width_mm = COALESCE($3, 101.6),
+         height_mm = COALESCE($4, 76.2), dpi = COALESCE($5, 203), header = $6, footer = $7,
+         show_allergens = COALESCE($8, TRUE), show_barcode = COALESCE($9, TRUE), show_qr = COALESCE($10, TRUE),
+         best_by_days = $11, link_url = $12
+         WHERE id = $13 AND org_id = $14
+         RETURNING id, org_id, name, is_default, width_mm, height_mm, dpi, header, footer,
+         show_allergens, show_barcode, show_qr, best_by_days, link_url",
+        template.name.trim(),
+        template.is_default,
+        template.width_mm,
+        template.height_mm,
+        template.dpi,
+        template.header,
+        template.footer,
+        template.show_allergens,
+        template.show_barcode,
+        template.show_qr,
+        template.best_by_days,
+        template.link_url,
+        id,
+        template.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(updated)) => updated,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Label template not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Ok().json(updated)
+}
+
+async fn delete_label_template(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM label_templates WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Label template not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Render a batch label as ZPL or PDF with the named or default template
+async fn get_batch_label(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<LabelQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let format = query.format.as_deref().unwrap_or("zpl");
+    if format != "zpl" && format != "pdf" {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unsupported format: {}. Use zpl or pdf", format)
+        }));
+    }
+
+    // The product is the recipe version made, or the recipe with the batch's recipe lot code
+    let batch = match sqlx::query!(
+        "SELECT b.org_id, b.batch_lot_code, b.date_made, b.expiry_date,
+         COALESCE(rv.name, r.name, b.recipe_lotcode) as \"product!\", r.gtin as \"gtin?\"
+         FROM batches b
+         LEFT JOIN recipe_versions rv ON rv.id = b.recipe_version_id
+         LEFT JOIN recipes r ON r.id = COALESCE(rv.recipe_id, (
+             SELECT id FROM recipes WHERE org_id = b.org_id AND lotcode = b.recipe_lotcode ORDER BY id DESC LIMIT 1))
+         WHERE b.id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(batch)) if batch.org_id == Some(auth_org_id) => batch,
+        Ok(Some(_)) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "You don't have permission to access this batch"
+            }));
+        }
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let template = match sqlx::query_as!(
+        LabelTemplate,
+        "SELECT id, org_id, name, is_default, width_mm, height_mm, dpi, header, footer,
+         show_allergens, show_barcode, show_qr, best_by_days, link_url
+         FROM label_templates
+         WHERE org_id = $1 AND (id = $2 OR ($2::int IS NULL AND is_default))",
+        auth_org_id,
+        query.template_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(None) if query.template_id.is_some() => {
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Label template not found"}));
+        }
+        Ok(template) => template,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let (allergen_profile, _) = match fetch_batch_allergens(&data.db_pool, id, false).await {
+        Ok(allergens) => allergens,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Best-by from the template's shelf life, never later than the batch expiry
+    let best_by = match template.as_ref().and_then(|template| template.best_by_days) {
+        Some(days) => {
+            let Some(best_by) = batch.date_made.checked_add_days(chrono::Days::new(days.max(0) as u64)) else {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "The template's best_by_days gives a best-by date out of range"
+                }));
+            };
+            Some(batch.expiry_date.map_or(best_by, |expiry| best_by.min(expiry)))
+        }
+        None => batch.expiry_date,
+    };
+    let link_url = template.as_ref()
+        .and_then(|template| template.link_url.clone())
+        .unwrap_or_else(labels::link_url_from_env);
+
+    let content = labels::LabelContent {
+        product: batch.product,
+        lot_code: batch.batch_lot_code.clone(),
+        date_made: batch.date_made,
+        best_by,
+        contains: allergen_profile.iter()
+            .filter(|declaration| declaration.presence == Presence::Contains)
+            .map(|declaration| declaration.allergen.clone())
+            .collect(),
+        may_contain: allergen_profile.iter()
+            .filter(|declaration| declaration.presence == Presence::MayContain)
+            .map(|declaration| declaration.allergen.clone())
+            .collect(),
+        gtin: batch.gtin,
+        link: labels::batch_link(&link_url, id, &batch.batch_lot_code),
+    };
+    let layout = match &template {
+        Some(template) => labels::LabelLayout {
+            width_mm: template.width_mm.to_f64().unwrap_or(101.6),
+            height_mm: template.height_mm.to_f64().unwrap_or(76.2),
+            dpi: template.dpi,
+            header: template.header.clone(),
+            footer: template.footer.clone(),
+            show_allergens: template.show_allergens,
+            show_barcode: template.show_barcode,
+            show_qr: template.show_qr,
+        },
+        None => labels::LabelLayout {
+            width_mm: 101.6,
+            height_mm: 76.2,
+            dpi: 203,
+            header: None,
+            footer: None,
+            show_allergens: true,
+            show_barcode: true,
+            show_qr: true,
+        },
+    };
+
+    let file_name = batch.batch_lot_code.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+    let rendered = if format == "pdf" {
+        labels::to_pdf(&content, &layout).map(|pdf| ("application/pdf", pdf))
+    } else {
+        labels::to_zpl(&content, &layout).map(|zpl| ("text/plain; charset=utf-8", zpl.into_bytes()))
+    };
+    match rendered {
+        Ok((content_type, body)) => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(("Content-Disposition", format!("inline; filename=\"label_{}.{}\"", file_name, format)))
+            .body(body),
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+// Lot code endpoints
+fn validate_lot_code_scheme(scheme: &LotCodeSchemeInput) -> Result<(), String> {
+    if scheme.name.trim().is_empty() {
+        return Err("Lot code scheme name is required".to_string());
+    }
+    lotcodes::LotCodeTemplate::parse(&scheme.template).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Clear the organization's default before another scheme becomes it
+async fn clear_default_lot_code_scheme(
+    conn: &mut PgConnection,
+    scheme: &LotCodeSchemeInput,
+    id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    if scheme.is_default {
+        sqlx::query!(
+            "UPDATE lot_code_schemes SET is_default = FALSE WHERE org_id = $1 AND id IS DISTINCT FROM $2 AND is_default",
+            scheme.org_id,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// The lot code given for a batch, if any
+fn batch_lot_code(batch: &BatchInput) -> Option<&str> {
+    batch.batch_lot_code.as_deref().map(str::trim).filter(|lot_code| !lot_code.is_empty())
+}
+
+// The next free lot code of the batch's lot code scheme, or of the
+// organization's default scheme
+async fn generate_batch_lot_code(
+    conn: &mut PgConnection,
+    batch: &BatchInput,
+    date_made: NaiveDate,
+) -> Result<String, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let template = sqlx::query_scalar!(
+        "SELECT template FROM lot_code_schemes
+         WHERE org_id = $1 AND (id = $2 OR ($2::int IS NULL AND is_default))",
+        batch.org_id,
+        batch.lot_code_scheme_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+    let template = match template {
+        Some(template) => template,
+        None if batch.lot_code_scheme_id.is_some() => {
+            return Err(HttpResponse::NotFound().json(serde_json::json!({"error": "Lot code scheme not found"})));
+        }
+        None => {
+            return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "batch_lot_code is required when the organization has no default lot code scheme"
+            })));
+        }
+    };
+    let bad_request = |e: lotcodes::LotCodeError| HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    let template = lotcodes::LotCodeTemplate::parse(&template).map_err(bad_request)?;
+
+    loop {
+        // Taking the next number locks the day's counter until the batch is saved
+        let sequence = sqlx::query_scalar!(
+            "INSERT INTO lot_code_sequences (org_id, sequence_date, last_value) VALUES ($1, $2, 1)
+             ON CONFLICT (org_id, sequence_date) DO UPDATE SET last_value = lot_code_sequences.last_value + 1
+             RETURNING last_value",
+            batch.org_id,
+            date_made
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+        let lot_code = template.render(date_made, batch.line.as_deref(), sequence).map_err(bad_request)?;
+
+        // Skip numbers already taken by lot codes entered by hand
+        let taken = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM batches WHERE org_id = $1 AND batch_lot_code = $2) as \"taken!\"",
+            batch.org_id,
+            lot_code
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+        if !taken {
+            return Ok(lot_code);
+        }
+    }
+}
+
+async fn create_lot_code_scheme(
+    scheme: web::Json<LotCodeSchemeInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(message) = validate_lot_code_scheme(&scheme) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(e) = clear_default_lot_code_scheme(&mut tx, &scheme, None).await {
+        eprintln!("Failed to create lot code scheme: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create lot code scheme"}));
+    }
+
+    let created = match sqlx::query_as!(
+        LotCodeScheme,
+        "INSERT INTO lot_code_schemes (org_id, name, template, is_default) VALUES ($1, $2, $3, $4)
+         RETURNING id, org_id, name, template, is_default",
+        scheme.org_id,
+        scheme.name.trim(),
+        scheme.template.trim(),
+        scheme.is_default
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(created) => created,
+        Err(e) => {
+            eprintln!("Failed to create lot code scheme: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create lot code scheme"}));
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(created)
+}
+
+async fn get_lot_code_scheme(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        LotCodeScheme,
+        "SELECT id, org_id, name, template, is_default FROM lot_code_schemes WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(scheme)) if scheme.org_id == auth_org_id => HttpResponse::Ok().json(scheme),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this lot code scheme"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Lot code scheme not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_lot_code_schemes(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        LotCodeScheme,
+        "SELECT id, org_id, name, template, is_default FROM lot_code_schemes
+         WHERE org_id = $1 ORDER BY is_default DESC, name, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(schemes) => HttpResponse::Ok().json(schemes),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch lot code schemes"}))
+        }
+    }
+}
+
+async fn update_lot_code_scheme(
+    path: web::Path<i32>,
+    scheme: web::Json<LotCodeSchemeInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    if let Err(message) = validate_lot_code_scheme(&scheme) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(e) = clear_default_lot_code_scheme(&mut tx, &scheme, Some(id)).await {
+        eprintln!("Failed to update lot code scheme: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update lot code scheme"}));
+    }
+
+    let updated = match sqlx::query_as!(
+        LotCodeScheme,
+        "UPDATE lot_code_schemes SET name = $1, template = $2, is_default = $3
+         WHERE id = $4 AND org_id = $5
+         RETURNING id, org_id, name, template, is_default",
+        scheme.name.trim(),
+        scheme.template.trim(),
+        scheme.is_default,
+        id,
+        scheme.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(updated)) => updated,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Lot code scheme not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Ok().json(updated)
+}
+
+async fn delete_lot_code_scheme(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM lot_code_schemes WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Lot code scheme not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// HACCP plan endpoints
+async fn validate_haccp_plan(
+    conn: &mut PgConnection,
+    plan: &HaccpPlanInput,
+) -> Result<(), HttpResponse> {
+    if plan.name.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "HACCP plan name is required"})));
+    }
+    if let Err(message) = haccp::validate_plan(&plan.steps, &plan.hazards, &plan.ccps) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": message})));
+    }
+
+    if let Some(recipe_id) = plan.recipe_id {
+        match sqlx::query!("SELECT id FROM recipes WHERE id = $1 AND org_id = $2", recipe_id, plan.org_id)
+            .fetch_optional(&mut *conn)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "Recipe not found"}))),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Record `plan` as the next immutable version of a HACCP plan
+async fn snapshot_haccp_plan_version(
+    conn: &mut PgConnection,
+    plan_id: i32,
+    plan: &HaccpPlanInput,
+) -> Result<i32, sqlx::Error> {
+    let version = sqlx::query!(
+        "UPDATE haccp_plans SET current_version = current_version + 1 WHERE id = $1 RETURNING current_version",
+        plan_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .current_version;
+
+    let version_id = sqlx::query!(
+        "INSERT INTO haccp_plan_versions (plan_id, version, name, product_description, change_summary)
+         VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        plan_id,
+        version,
+        plan.name.trim(),
+        plan.product_description,
+        plan.change_summary
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .id;
+
+    for step in &plan.steps {
+        sqlx::query!(
+            "INSERT INTO haccp_steps (version_id, step_number, name, description) VALUES ($1, $2, $3, $4)",
+            version_id,
+            step.step_number,
+            step.name.trim(),
+            step.description
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for hazard in &plan.hazards {
+        sqlx::query!(
+            "INSERT INTO haccp_hazards (version_id, step_number, hazard_type, description, likelihood, severity,
+             control_measure, justification)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            version_id,
+            hazard.step_number,
+            hazard.hazard_type.as_str(),
+            hazard.description,
+            hazard.likelihood,
+            hazard.severity,
+            hazard.control_measure,
+            hazard.justification
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for ccp in &plan.ccps {
+        let corrective_actions: Vec<String> = ccp.corrective_actions.iter()
+            .map(|action| action.trim().to_string())
+            .filter(|action| !action.is_empty())
+            .collect();
+        let ccp_id = sqlx::query!(
+            "INSERT INTO haccp_ccps (version_id, ccp_number, step_number, hazard, monitoring_what, monitoring_how,
+             monitoring_frequency, monitoring_responsible, corrective_actions)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+            version_id,
+            ccp.ccp_number.trim(),
+            ccp.step_number,
+            ccp.hazard,
+            ccp.monitoring.what,
+            ccp.monitoring.how,
+            ccp.monitoring.frequency,
+            ccp.monitoring.responsible,
+            &corrective_actions
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .id;
+
+        for limit in &ccp.critical_limits {
+            sqlx::query!(
+                "INSERT INTO haccp_critical_limits (ccp_id, parameter, min_value, max_value, unit) VALUES ($1, $2, $3, $4, $5)",
+                ccp_id,
+                limit.parameter.trim(),
+                limit.min_value,
+                limit.max_value,
+                limit.unit
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        for verification in &ccp.verification_activities {
+            sqlx::query!(
+                "INSERT INTO haccp_verification_activities (ccp_id, activity, frequency, responsible) VALUES ($1, $2, $3, $4)",
+                ccp_id,
+                verification.activity,
+                verification.frequency,
+                verification.responsible
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    Ok(version)
+}
+
+// Fetch a single version of a HACCP plan with its steps, hazards and CCPs
+async fn fetch_haccp_plan_version(
+    pool: &Pool<Postgres>,
+    plan_id: i32,
+    version: i32,
+) -> Result<Option<HaccpPlanVersion>, sqlx::Error> {
+    let record = match sqlx::query!(
+        "SELECT id, plan_id, version, name, product_description, change_summary, created_at::text as created_at
+         FROM haccp_plan_versions WHERE plan_id = $1 AND version = $2",
+        plan_id,
+        version
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let steps = sqlx::query_as!(
+        ProcessStep,
+        "SELECT step_number, name, description FROM haccp_steps WHERE version_id = $1 ORDER BY step_number",
+        record.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let hazards = sqlx::query!(
+        "SELECT step_number, hazard_type, description, likelihood, severity, control_measure, justification
+         FROM haccp_hazards WHERE version_id = $1 ORDER BY step_number, id",
+        record.id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|hazard| Hazard {
+        step_number: hazard.step_number,
+        // The column's check constraint only allows the three types
+        hazard_type: HazardType::parse(&hazard.hazard_type).unwrap_or(HazardType::Biological),
+        description: hazard.description,
+        likelihood: hazard.likelihood,
+        severity: hazard.severity,
+        risk_score: 0,
+        control_measure: hazard.control_measure,
+        justification: hazard.justification,
+    }.with_risk_score())
+    .collect();
+
+    let ccp_records = sqlx::query!(
+        "SELECT id, ccp_number, step_number, hazard, monitoring_what, monitoring_how, monitoring_frequency,
+         monitoring_responsible, corrective_actions
+         FROM haccp_ccps WHERE version_id = $1 ORDER BY step_number, ccp_number",
+        record.id
+    )
+    .fetch_all(pool)
+    .await?;
+    let ccp_ids: Vec<i32> = ccp_records.iter().map(|ccp| ccp.id).collect();
+
+    let mut limits: HashMap<i32, Vec<CriticalLimit>> = HashMap::new();
+    for limit in sqlx::query!(
+        "SELECT id, ccp_id, parameter, min_value, max_value, unit
+         FROM haccp_critical_limits WHERE ccp_id = ANY($1) ORDER BY id",
+        &ccp_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        limits.entry(limit.ccp_id).or_default().push(CriticalLimit {
+            id: Some(limit.id),
+            parameter: limit.parameter,
+            min_value: limit.min_value,
+            max_value: limit.max_value,
+            unit: limit.unit,
+        });
+    }
+
+    let mut verifications: HashMap<i32, Vec<VerificationActivity>> = HashMap::new();
+    for verification in sqlx::query!(
+        "SELECT ccp_id, activity, frequency, responsible
+         FROM haccp_verification_activities WHERE ccp_id = ANY($1) ORDER BY id",
+        &ccp_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        verifications.entry(verification.ccp_id).or_default().push(VerificationActivity {
+            activity: verification.activity,
+            frequency: verification.frequency,
+            responsible: verification.responsible,
+        });
+    }
+
+    let ccps = ccp_records.into_iter()
+        .map(|ccp| CriticalControlPoint {
+            id: Some(ccp.id),
+            ccp_number: ccp.ccp_number,
+            step_number: ccp.step_number,
+            hazard: ccp.hazard,
+            critical_limits: limits.remove(&ccp.id).unwrap_or_default(),
+            monitoring: MonitoringProcedure {
+                what: ccp.monitoring_what,
+                how: ccp.monitoring_how,
+                frequency: ccp.monitoring_frequency,
+                responsible: ccp.monitoring_responsible,
+            },
+            corrective_actions: ccp.corrective_actions,
+            verification_activities: verifications.remove(&ccp.id).unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(Some(HaccpPlanVersion {
+        plan_id: record.plan_id,
+        version: record.version,
+        name: record.name,
+        product_description: record.product_description,
+        change_summary: record.change_summary,
+        created_at: record.created_at.unwrap_or_default(),
+        steps,
+        hazards,
+        ccps,
+    }))
+}
+
+// A HACCP plan as of its current version
+async fn fetch_haccp_plan(
+    pool: &Pool<Postgres>,
+    id: i32,
+) -> Result<Option<HaccpPlan>, sqlx::Error> {
+    let record = match sqlx::query!(
+        "SELECT id, org_id, recipe_id, current_version FROM haccp_plans WHERE id = $1",
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    Ok(fetch_haccp_plan_version(pool, id, record.current_version).await?.map(|current| HaccpPlan {
+        id: record.id,
+        org_id: record.org_id,
+        recipe_id: record.recipe_id,
+        current,
+    }))
+}
+
+async fn haccp_plan_org_check(
+    pool: &Pool<Postgres>,
+    plan_id: i32,
+    auth_org_id: i32,
+) -> Result<(), HttpResponse> {
+    match sqlx::query!("SELECT org_id FROM haccp_plans WHERE id = $1", plan_id)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(Some(record)) if record.org_id == auth_org_id => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this HACCP plan"
+        }))),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan not found"}))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+async fn create_haccp_plan(
+    plan: web::Json<HaccpPlanInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(response) = validate_haccp_plan(&mut tx, &plan).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    let plan_id = match sqlx::query!(
+        "INSERT INTO haccp_plans (org_id, recipe_id) VALUES ($1, $2) RETURNING id",
+        plan.org_id,
+        plan.recipe_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(record) => record.id,
+        Err(e) => {
+            eprintln!("Failed to create HACCP plan: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create HACCP plan"}));
+        }
+    };
+
+    if let Err(e) = snapshot_haccp_plan_version(&mut tx, plan_id, &plan).await {
+        eprintln!("Failed to create HACCP plan: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create HACCP plan"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    match fetch_haccp_plan(&data.db_pool, plan_id).await {
+        Ok(Some(created)) => HttpResponse::Created().json(created),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_haccp_plan(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if let Err(response) = haccp_plan_org_check(&data.db_pool, id, auth_org_id).await {
+        return response;
+    }
+
+    match fetch_haccp_plan(&data.db_pool, id).await {
+        Ok(Some(plan)) => HttpResponse::Ok().json(plan),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_haccp_plans(
+    req: HttpRequest,
+    query: web::Query<HaccpPlanQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let plan_ids = match sqlx::query_scalar!(
+        "SELECT id FROM haccp_plans WHERE org_id = $1 AND ($2::int IS NULL OR recipe_id = $2) ORDER BY id",
+        org_id,
+        query.recipe_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(plan_ids) => plan_ids,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch HACCP plans"}));
+        }
+    };
+
+    let mut plans = Vec::new();
+    for plan_id in plan_ids {
+        match fetch_haccp_plan(&data.db_pool, plan_id).await {
+            Ok(Some(plan)) => plans.push(plan),
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Database error when fetching HACCP plan {}: {}", plan_id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch HACCP plans"}));
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(plans)
+}
+
+// Every update records a new version; earlier versions are kept
+async fn update_haccp_plan(
+    path: web::Path<i32>,
+    plan: web::Json<HaccpPlanInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if let Err(response) = validate_haccp_plan(&mut tx, &plan).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    match sqlx::query!(
+        "UPDATE haccp_plans SET recipe_id = $1 WHERE id = $2 AND org_id = $3 RETURNING id",
+        plan.recipe_id,
+        id,
+        plan.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    if let Err(e) = snapshot_haccp_plan_version(&mut tx, id, &plan).await {
+        eprintln!("Failed to update HACCP plan: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update HACCP plan"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    match fetch_haccp_plan(&data.db_pool, id).await {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_haccp_plan(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM haccp_plans WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan not found"})),
+        Err(e) => {
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "HACCP plan has monitoring records and cannot be deleted"
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// List every version of a HACCP plan, oldest first
+async fn get_haccp_plan_versions(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if let Err(response) = haccp_plan_org_check(&data.db_pool, id, auth_org_id).await {
+        return response;
+    }
+
+    let version_numbers = match sqlx::query_scalar!(
+        "SELECT version FROM haccp_plan_versions WHERE plan_id = $1 ORDER BY version",
+        id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(version_numbers) => version_numbers,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let mut versions = Vec::new();
+    for version in version_numbers {
+        match fetch_haccp_plan_version(&data.db_pool, id, version).await {
+            Ok(Some(plan_version)) => versions.push(plan_version),
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Database error when fetching version {} of HACCP plan {}: {}", version, id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(versions)
+}
+
+async fn get_haccp_plan_version(
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let (id, version) = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if let Err(response) = haccp_plan_org_check(&data.db_pool, id, auth_org_id).await {
+        return response;
+    }
+
+    match fetch_haccp_plan_version(&data.db_pool, id, version).await {
+        Ok(Some(plan_version)) => HttpResponse::Ok().json(plan_version),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "HACCP plan version not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// CCP monitoring endpoints
+
+// Check each critical limit of a CCP has exactly one reading and compare the
+// readings with their limits
+fn ccp_readings(limits: &[CriticalLimit], readings: &[CcpReadingInput]) -> Result<Vec<CcpReading>, String> {
+    let mut checked = Vec::new();
+    for reading in readings {
+        let limit = limits.iter()
+            .find(|limit| limit.id == Some(reading.limit_id))
+            .ok_or_else(|| format!("Limit {} is not a critical limit of this CCP", reading.limit_id))?;
+        if checked.iter().any(|checked: &CcpReading| checked.limit_id == reading.limit_id) {
+            return Err(format!("{} has more than one reading", limit.parameter));
+        }
+        let value = haccp::value_in_limit_unit(limit, &reading.value)?;
+        checked.push(CcpReading {
+            limit_id: reading.limit_id,
+            parameter: limit.parameter.clone(),
+            measured: reading.value.to_string(),
+            value,
+            unit: limit.unit.clone(),
+            min_value: limit.min_value,
+            max_value: limit.max_value,
+            within_limit: haccp::within_limit(limit, value),
+        });
+    }
+
+    let missing: Vec<&str> = limits.iter()
+        .filter(|limit| !checked.iter().any(|reading| Some(reading.limit_id) == limit.id))
+        .map(|limit| limit.parameter.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing readings for: {}", missing.join(", ")));
+    }
+    Ok(checked)
+}
+
+// Open a problem log for a CCP deviation. It cannot be closed until a
+// corrective action is recorded.
+async fn open_ccp_deviation_problem_log(
+    conn: &mut PgConnection,
+    input: &CcpMonitoringInput,
+    ccp_number: &str,
+    hazard: &str,
+    readings: &[CcpReading],
+    batch_lot_code: Option<&str>,
+    date: NaiveDate,
+) -> Result<i32, sqlx::Error> {
+    let org_name = sqlx::query_scalar!("SELECT name FROM organizations WHERE id = $1", input.org_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let deviations: Vec<String> = readings.iter()
+        .filter(|reading| !reading.within_limit)
+        .map(|reading| {
+            let unit = reading.unit.as_deref().map(|unit| format!(" {}", unit)).unwrap_or_default();
+            let bounds: Vec<String> = [("min", reading.min_value), ("max", reading.max_value)].iter()
+                .filter_map(|(bound, value)| value.map(|value| format!("{} {}{}", bound, value.normalize(), unit)))
+                .collect();
+            format!("{} {}{} (limit: {})", reading.parameter, reading.value.normalize(), unit, bounds.join(", "))
+        })
+        .collect();
+    let mut description = format!("{} ({}) out of critical limits: {}.", ccp_number, hazard, deviations.join("; "));
+    if let Some(lot_code) = batch_lot_code {
+        description.push_str(&format!(" Batch {}.", lot_code));
+    }
+    if let Some(notes) = input.notes.as_deref().filter(|notes| !notes.trim().is_empty()) {
+        description.push_str(&format!(" Notes: {}", notes.trim()));
+    }
+    let corrective_action = input.corrective_action.as_deref()
+        .map(str::trim)
+        .filter(|action| !action.is_empty());
+
+    let problem_log_id = sqlx::query_scalar!(
+        "INSERT INTO problem_logs (is_open, date_opened, customer_name, problem_type, problem_description, recall,
+         batch_id, corrective_action, corrective_action_required, org_id)
+         VALUES (TRUE, $1, $2, 'CCP deviation', $3, FALSE, $4, $5, TRUE, $6) RETURNING id",
+        date,
+        org_name,
+        description,
+        input.batch_id,
+        corrective_action,
+        input.org_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    set_problem_log_due_dates(conn, problem_log_id).await?;
+
+    record_problem_log_activity(
+        conn, problem_log_id, ActivityType::Created, None, Some(ProblemStatus::New), None, input.employee_id,
+    ).await?;
+    Ok(problem_log_id)
+}
+
+// Monitoring records of an organization, newest first, with their readings
+async fn fetch_ccp_monitoring_records(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    id: Option<i32>,
+    query: &CcpMonitoringQuery,
+) -> Result<Vec<CcpMonitoringRecord>, sqlx::Error> {
+    let records = sqlx::query!(
+        "SELECT m.id, m.org_id, v.plan_id, m.ccp_id, c.ccp_number, m.batch_id, b.batch_lot_code as \"batch_lot_code?\",
+         m.employee_id, m.monitored_at::text as \"monitored_at!\", m.within_limits, m.corrective_action, m.notes,
+         m.problem_log_id
+         FROM ccp_monitoring_records m
+         JOIN haccp_ccps c ON c.id = m.ccp_id
+         JOIN haccp_plan_versions v ON v.id = c.version_id
+         LEFT JOIN batches b ON b.id = m.batch_id
+         WHERE m.org_id = $1
+         AND ($2::int IS NULL OR m.id = $2)
+         AND ($3::int IS NULL OR v.plan_id = $3)
+         AND ($4::int IS NULL OR m.ccp_id = $4)
+         AND ($5::int IS NULL OR m.batch_id = $5)
+         AND (NOT $6 OR NOT m.within_limits)
+         ORDER BY m.monitored_at DESC, m.id DESC",
+        org_id,
+        id,
+        query.plan_id,
+        query.ccp_id,
+        query.batch_id,
+        query.deviations.unwrap_or(false)
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let record_ids: Vec<i32> = records.iter().map(|record| record.id).collect();
+    let mut readings: HashMap<i32, Vec<CcpReading>> = HashMap::new();
+    for reading in sqlx::query!(
+        "SELECT r.record_id, r.limit_id, l.parameter, r.measured, r.value, l.unit, l.min_value, l.max_value, r.within_limit
+         FROM ccp_monitoring_readings r
+         JOIN haccp_critical_limits l ON l.id = r.limit_id
+         WHERE r.record_id = ANY($1)
+         ORDER BY l.id",
+        &record_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        readings.entry(reading.record_id).or_default().push(CcpReading {
+            limit_id: reading.limit_id,
+            parameter: reading.parameter,
+            measured: reading.measured,
+            value: reading.value,
+            unit: reading.unit,
+            min_value: reading.min_value,
+            max_value: reading.max_value,
+            within_limit: reading.within_limit,
+        });
+    }
+
+    Ok(records.into_iter()
+        .map(|record| CcpMonitoringRecord {
+            id: record.id,
+            org_id: record.org_id,
+            plan_id: record.plan_id,
+            ccp_id: record.ccp_id,
+            ccp_number: record.ccp_number,
+            batch_id: record.batch_id,
+            batch_lot_code: record.batch_lot_code,
+            employee_id: record.employee_id,
+            monitored_at: record.monitored_at,
+            within_limits: record.within_limits,
+            corrective_action: record.corrective_action,
+            notes: record.notes,
+            problem_log_id: record.problem_log_id,
+            readings: readings.remove(&record.id).unwrap_or_default(),
+        })
+        .collect())
+}
+
+// Log a CCP check. Readings outside a critical limit open a problem log for
+// the batch and put the batch on hold.
+async fn create_ccp_monitoring_record(
+    record: web::Json<CcpMonitoringInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let monitored_at = match record.monitored_at.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+        Some(Ok(monitored_at)) => monitored_at.with_timezone(&chrono::Utc),
+        Some(Err(_)) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid monitored_at format. Use RFC 3339, e.g. 2025-04-01T14:30:00Z"
+            }));
+        }
+        None => chrono::Utc::now(),
+    };
+    if monitored_at > chrono::Utc::now() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "monitored_at cannot be in the future"}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Checks are made against the plan version in force
+    let ccp = match sqlx::query!(
+        "SELECT c.ccp_number, c.hazard, p.current_version = v.version as \"current!\"
+         FROM haccp_ccps c
+         JOIN haccp_plan_versions v ON v.id = c.version_id
+         JOIN haccp_plans p ON p.id = v.plan_id
+         WHERE c.id = $1 AND p.org_id = $2",
+        record.ccp_id,
+        record.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(ccp)) if ccp.current => ccp,
+        Ok(Some(ccp)) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("{} belongs to a superseded version of the HACCP plan", ccp.ccp_number)
+            }));
+        }
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "CCP not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let limits = match sqlx::query!(
+        "SELECT id, parameter, min_value, max_value, unit FROM haccp_critical_limits WHERE ccp_id = $1 ORDER BY id",
+        record.ccp_id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    {
+        Ok(limits) => limits.into_iter()
+            .map(|limit| CriticalLimit {
+                id: Some(limit.id),
+                parameter: limit.parameter,
+                min_value: limit.min_value,
+                max_value: limit.max_value,
+                unit: limit.unit,
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let readings = match ccp_readings(&limits, &record.readings) {
+        Ok(readings) => readings,
+        Err(message) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+        }
+    };
+    let within_limits = readings.iter().all(|reading| reading.within_limit);
+
+    let batch = match record.batch_id {
+        Some(batch_id) => match sqlx::query!(
+            "SELECT batch_lot_code, status FROM batches WHERE id = $1 AND org_id = $2 FOR UPDATE",
+            batch_id,
+            record.org_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        {
+            Ok(Some(batch)) => Some(batch),
+            Ok(None) => {
+                let _ = tx.rollback().await;
+                return HttpResponse::BadRequest().json(serde_json::json!({"error": "Batch not found"}));
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+        },
+        None => None,
+    };
+
+    if let Some(employee_id) = record.employee_id {
+        match sqlx::query!("SELECT id FROM employees WHERE id = $1 AND org_id = $2", employee_id, record.org_id)
+            .fetch_optional(&mut *tx)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let _ = tx.rollback().await;
+                return HttpResponse::BadRequest().json(serde_json::json!({"error": "Employee not found"}));
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+        }
+    }
+
+    let record_id = match sqlx::query_scalar!(
+        "INSERT INTO ccp_monitoring_records (org_id, ccp_id, batch_id, employee_id, monitored_at, within_limits,
+         corrective_action, notes)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        record.org_id,
+        record.ccp_id,
+        record.batch_id,
+        record.employee_id,
+        monitored_at,
+        within_limits,
+        record.corrective_action,
+        record.notes
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(record_id) => record_id,
+        Err(e) => {
+            eprintln!("Failed to create CCP monitoring record: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create CCP monitoring record"}));
+        }
+    };
+
+    for reading in &readings {
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO ccp_monitoring_readings (record_id, limit_id, value, measured, within_limit) VALUES ($1, $2, $3, $4, $5)",
+            record_id,
+            reading.limit_id,
+            reading.value,
+            reading.measured,
+            reading.within_limit
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            eprintln!("Failed to save CCP reading: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create CCP monitoring record"}));
+        }
+    }
+
+    if !within_limits {
+        let batch_lot_code = batch.as_ref().map(|batch| batch.batch_lot_code.as_str());
+        let problem_log_id = match open_ccp_deviation_problem_log(
+            &mut tx,
+            &record,
+            &ccp.ccp_number,
+            &ccp.hazard,
+            &readings,
+            batch_lot_code,
+            monitored_at.date_naive(),
+        ).await {
+            Ok(problem_log_id) => problem_log_id,
+            Err(e) => {
+                eprintln!("Failed to open problem log for CCP deviation: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create CCP monitoring record"}));
+            }
+        };
+
+        if let Err(e) = sqlx::query!(
+            "UPDATE ccp_monitoring_records SET problem_log_id = $1 WHERE id = $2",
+            problem_log_id,
+            record_id
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            eprintln!("Failed to link problem log: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create CCP monitoring record"}));
+        }
+
+        // Product made through a deviation is held until it is dispositioned
+        if let (Some(batch_id), Some(batch)) = (record.batch_id, &batch) {
+            if LotStatus::parse(&batch.status) == Some(LotStatus::Released) {
+                let reason = format!("{} deviation (problem log {})", ccp.ccp_number, problem_log_id);
+                if let Err(response) = set_lot_status(
+                    &mut tx, record.org_id, LotRef::Batch(batch_id), LotStatus::OnHold, &reason, record.employee_id,
+                ).await {
+                    let _ = tx.rollback().await;
+                    return response;
+                }
+            }
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    let query = CcpMonitoringQuery { plan_id: None, ccp_id: None, batch_id: None, deviations: None };
+    match fetch_ccp_monitoring_records(&data.db_pool, record.org_id, Some(record_id), &query).await {
+        Ok(mut records) if !records.is_empty() => HttpResponse::Created().json(records.remove(0)),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({"error": "CCP monitoring record not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_ccp_monitoring_record(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let query = CcpMonitoringQuery { plan_id: None, ccp_id: None, batch_id: None, deviations: None };
+    match fetch_ccp_monitoring_records(&data.db_pool, auth_org_id, Some(id), &query).await {
+        Ok(mut records) if !records.is_empty() => HttpResponse::Ok().json(records.remove(0)),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({"error": "CCP monitoring record not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_ccp_monitoring_records(
+    req: HttpRequest,
+    query: web::Query<CcpMonitoringQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match fetch_ccp_monitoring_records(&data.db_pool, org_id, None, &query).await {
+        Ok(records) => HttpResponse::Ok().json(records),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch CCP monitoring records"}))
+        }
+    }
+}
+
+// CAPA endpoints
+
+// Check a CAPA may move to its requested status and that its owners and
+// verifier work for the organization, returning the status to save
+async fn validate_capa(
+    conn: &mut PgConnection,
+    capa: &CapaInput,
+    from: Option<CapaStatus>,
+) -> Result<CapaStatus, HttpResponse> {
+    let to = capa.status.or(from).unwrap_or(CapaStatus::Open);
+    if let Err(e) = capa::check_transition(from, to, &capa.details) {
+        return Err(match e {
+            CapaError::InvalidTransition { .. } | CapaError::Closed(_) => {
+                HttpResponse::Conflict().json(serde_json::json!({"error": e.to_string()}))
+            }
+            _ => HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+        });
+    }
+
+    let mut employee_ids: Vec<i32> = capa.details.actions.iter().map(|action| action.owner_id).collect();
+    employee_ids.extend(capa.details.verified_by);
+    let known = match sqlx::query_scalar!(
+        "SELECT id FROM employees WHERE id = ANY($1) AND org_id = $2",
+        &employee_ids,
+        capa.org_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    {
+        Ok(known) => known,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+        }
+    };
+    if let Some(missing) = employee_ids.iter().find(|id| !known.contains(id)) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Employee {} not found", missing)
+        })));
+    }
+    Ok(to)
+}
+
+// Replace the actions of a CAPA
+async fn save_capa_actions(
+    conn: &mut PgConnection,
+    capa_id: i32,
+    actions: &[CapaAction],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM capa_actions WHERE capa_id = $1", capa_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for action in actions {
+        sqlx::query!(
+            "INSERT INTO capa_actions (capa_id, action_type, description, owner_id, due_date, completed_on)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            capa_id,
+            action.action_type.as_str(),
+            action.description.trim(),
+            action.owner_id,
+            action.due_date,
+            action.completed_on
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// CAPAs of an organization, oldest first, with their actions
+async fn fetch_capas(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    id: Option<i32>,
+    query: &CapaQuery,
+) -> Result<Vec<Capa>, sqlx::Error> {
+    let today = chrono::Local::now().date_naive();
+    let records = sqlx::query!(
+        "SELECT id, org_id, problem_log_id, status, root_cause, effectiveness_check, effective, verified_by,
+         verified_on, created_at::text as \"created_at!\"
+         FROM capas c
+         WHERE org_id = $1
+         AND ($2::int IS NULL OR id = $2)
+         AND ($3::int IS NULL OR problem_log_id = $3)
+         AND ($4::text IS NULL OR status = $4)
+         AND ($5::int IS NULL OR EXISTS (SELECT 1 FROM capa_actions a WHERE a.capa_id = c.id AND a.owner_id = $5))
+         AND (NOT $6 OR EXISTS (
+             SELECT 1 FROM capa_actions a WHERE a.capa_id = c.id AND a.completed_on IS NULL AND a.due_date < $7
+         ))
+         ORDER BY id",
+        org_id,
+        id,
+        query.problem_log_id,
+        query.status.map(CapaStatus::as_str),
+        query.owner_id,
+        query.overdue.unwrap_or(false),
+        today
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let capa_ids: Vec<i32> = records.iter().map(|record| record.id).collect();
+    let mut actions: HashMap<i32, Vec<CapaAction>> = HashMap::new();
+    for action in sqlx::query!(
+        "SELECT id, capa_id, action_type, description, owner_id, due_date, completed_on
+         FROM capa_actions WHERE capa_id = ANY($1) ORDER BY id",
+        &capa_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        let action_type = ActionType::parse(&action.action_type).unwrap_or(ActionType::Corrective);
+        actions.entry(action.capa_id).or_default().push(CapaAction {
+            id: Some(action.id),
+            action_type,
+            description: action.description,
+            owner_id: action.owner_id,
+            due_date: action.due_date,
+            completed_on: action.completed_on,
+        });
+    }
+
+    Ok(records.into_iter()
+        .map(|record| {
+            let actions = actions.remove(&record.id).unwrap_or_default();
+            Capa {
+                id: record.id,
+                org_id: record.org_id,
+                problem_log_id: record.problem_log_id,
+                status: CapaStatus::parse(&record.status).unwrap_or(CapaStatus::Open),
+                overdue: actions.iter().any(|action| action.is_overdue(today)),
+                details: CapaDetails {
+                    root_cause: record.root_cause,
+                    actions,
+                    effectiveness_check: record.effectiveness_check,
+                    effective: record.effective,
+                    verified_by: record.verified_by,
+                    verified_on: record.verified_on,
+                },
+                created_at: record.created_at,
+            }
+        })
+        .collect())
+}
+
+async fn fetch_capa(pool: &Pool<Postgres>, org_id: i32, id: i32) -> HttpResponse {
+    let query = CapaQuery { problem_log_id: None, status: None, owner_id: None, overdue: None };
+    match fetch_capas(pool, org_id, Some(id), &query).await {
+        Ok(mut capas) if !capas.is_empty() => HttpResponse::Ok().json(capas.remove(0)),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({"error": "CAPA not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Raise a CAPA on an open problem log. CAPAs start open.
+async fn create_capa(
+    capa: web::Json<CapaInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    match sqlx::query_scalar!(
+        "SELECT is_open FROM problem_logs WHERE id = $1 AND (org_id = $2 OR org_id IS NULL) FOR UPDATE",
+        capa.problem_log_id,
+        capa.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(true)) => {}
+        Ok(Some(false)) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::Conflict().json(serde_json::json!({"error": "Problem log is closed"}));
+        }
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": "Problem log not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    let status = match validate_capa(&mut tx, &capa, None).await {
+        Ok(status) => status,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    let details = &capa.details;
+    let capa_id = match sqlx::query_scalar!(
+        "INSERT INTO capas (org_id, problem_log_id, status, root_cause, effectiveness_check, effective, verified_by,
+         verified_on)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        capa.org_id,
+        capa.problem_log_id,
+        status.as_str(),
+        details.root_cause,
+        details.effectiveness_check,
+        details.effective,
+        details.verified_by,
+        details.verified_on
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(capa_id) => capa_id,
+        Err(e) => {
+            eprintln!("Failed to create CAPA: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create CAPA"}));
+        }
+    };
+
+    if let Err(e) = save_capa_actions(&mut tx, capa_id, &details.actions).await {
+        eprintln!("Failed to save CAPA actions: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create CAPA"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    let query = CapaQuery { problem_log_id: None, status: None, owner_id: None, overdue: None };
+    match fetch_capas(&data.db_pool, capa.org_id, Some(capa_id), &query).await {
+        Ok(mut capas) if !capas.is_empty() => HttpResponse::Created().json(capas.remove(0)),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({"error": "CAPA not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_capa(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    fetch_capa(&data.db_pool, auth_org_id, id).await
+}
+
+async fn get_all_capas(
+    req: HttpRequest,
+    query: web::Query<CapaQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match fetch_capas(&data.db_pool, org_id, None, &query).await {
+        Ok(capas) => HttpResponse::Ok().json(capas),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch CAPAs"}))
+        }
+    }
+}
+
+// Update a CAPA and move it through its workflow. Verified and cancelled
+// CAPAs are final.
+async fn update_capa(
+    path: web::Path<i32>,
+    capa: web::Json<CapaInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let current = match sqlx::query!(
+        "SELECT status, problem_log_id FROM capas WHERE id = $1 AND org_id = $2 FOR UPDATE",
+        id,
+        capa.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(current)) => current,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "CAPA not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    if current.problem_log_id != capa.problem_log_id {
+        let _ = tx.rollback().await;
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "A CAPA cannot be moved to another problem log"
+        }));
+    }
+
+    let status = match validate_capa(&mut tx, &capa, CapaStatus::parse(&current.status)).await {
+        Ok(status) => status,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    let details = &capa.details;
+    let verified_on = match status {
+        CapaStatus::Verified => Some(details.verified_on.unwrap_or_else(|| chrono::Local::now().date_naive())),
+        _ => details.verified_on,
+    };
+    if let Err(e) = sqlx::query!(
+        "UPDATE capas SET status = $1, root_cause = $2, effectiveness_check = $3, effective = $4, verified_by = $5,
+         verified_on = $6
+         WHERE id = $7",
+        status.as_str(),
+        details.root_cause,
+        details.effectiveness_check,
+        details.effective,
+        details.verified_by,
+        verified_on,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        eprintln!("Failed to update CAPA: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update CAPA"}));
+    }
+
+    if let Err(e) = save_capa_actions(&mut tx, id, &details.actions).await {
+        eprintln!("Failed to save CAPA actions: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update CAPA"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    fetch_capa(&data.db_pool, capa.org_id, id).await
+}
+
+async fn delete_capa(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM capas WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "CAPA not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Complaint endpoints
+
+// Turn a public complaint into the text of its problem log
+fn complaint_problem_description(complaint: &ComplaintSubmission, purchase_date: Option<NaiveDate>) -> String {
+    let mut details = vec![format!("Product: {}", complaint.product.trim())];
+    if let Some(lot_code) = complaint.lot_code.as_deref().map(str::trim).filter(|lot_code| !lot_code.is_empty()) {
+        details.push(format!("Lot code: {}", lot_code));
+    }
+    if let Some(location) = complaint.purchase_location.as_deref().map(str::trim).filter(|location| !location.is_empty()) {
+        details.push(format!("Purchased at: {}", location));
+    }
+    if let Some(date) = purchase_date {
+        details.push(format!("Purchased on: {}", date));
+    }
+    let contact: Vec<&str> = [&complaint.contact_email, &complaint.contact_phone].iter()
+        .filter_map(|contact| contact.as_deref().map(str::trim).filter(|contact| !contact.is_empty()))
+        .collect();
+    details.push(format!("Contact: {} ({})", complaint.contact_name.trim(), contact.join(", ")));
+    format!("{}\n\n{}", complaint.description.trim(), details.join("\n"))
+}
+
+// Public complaint intake. Needs no authentication; the organization is
+// found by its public slug and submissions are rate limited per client.
+async fn submit_complaint(
+    req: HttpRequest,
+    path: web::Path<String>,
+    complaint: web::Json<ComplaintSubmission>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let slug = path.into_inner().to_ascii_lowercase();
+
+    let org = match sqlx::query!("SELECT id FROM organizations WHERE public_slug = $1", slug)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(org)) => org,
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Complaint form not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let client_address = req.peer_addr().map(|addr| addr.ip().to_string());
+    let limit_key = format!("{}:{}", org.id, client_address.as_deref().unwrap_or("unknown"));
+    if !data.complaint_limiter.allow(&limit_key, std::time::Instant::now()) {
+        return HttpResponse::TooManyRequests().json(serde_json::json!({
+            "error": "Too many complaints from this address. Please try again later"
+        }));
+    }
+
+    if let Err(e) = complaints::check_submission(&complaint) {
+        if e == ComplaintError::Rejected {
+            eprintln!("Rejected complaint for {} from {:?} as spam", slug, client_address);
+        }
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    }
+
+    let trimmed = |value: &Option<String>| value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_string);
+    // Forms send blank fields as empty strings
+    let purchase_date = match parse_optional_date(&trimmed(&complaint.purchase_date), "purchase_date") {
+        Ok(purchase_date) => purchase_date,
+        Err(response) => return response,
+    };
+    if purchase_date.is_some_and(|date| date > chrono::Local::now().date_naive()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "purchase_date cannot be in the future"}));
+    }
+    let lot_code = trimmed(&complaint.lot_code);
+    let contact_email = trimmed(&complaint.contact_email);
+    let contact_phone = trimmed(&complaint.contact_phone);
+    let description = complaint.description.trim();
+
+    // A resubmitted form gets the reference of the first submission
+    match sqlx::query_scalar!(
+        "SELECT problem_log_id FROM complaints
+         WHERE org_id = $1 AND description = $2
+         AND (contact_email IS NOT DISTINCT FROM $3 AND contact_phone IS NOT DISTINCT FROM $4)
+         AND created_at > CURRENT_TIMESTAMP - INTERVAL '1 day'
+         ORDER BY id LIMIT 1",
+        org.id,
+        description,
+        contact_email,
+        contact_phone
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(reference)) => return HttpResponse::Ok().json(ComplaintReceipt { reference }),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Lot codes are printed in upper case but often typed in lower case
+    let batch_id = match &lot_code {
+        Some(lot_code) => match sqlx::query_scalar!(
+            "SELECT id FROM batches WHERE org_id = $1 AND UPPER(batch_lot_code) = UPPER($2)
+             ORDER BY batch_lot_code = $2 DESC, id LIMIT 1",
+            org.id,
+            lot_code
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        {
+            Ok(batch_id) => batch_id,
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+        },
+        None => None,
+    };
+
+    let problem_log_id = match sqlx::query_scalar!(
+        "INSERT INTO problem_logs (is_open, date_opened, customer_name, problem_type, problem_description, recall,
+         batch_id, org_id)
+         VALUES (TRUE, $1, $2, 'Customer complaint', $3, FALSE, $4, $5) RETURNING id",
+        chrono::Local::now().date_naive(),
+        complaint.contact_name.trim(),
+        complaint_problem_description(&complaint, purchase_date),
+        batch_id,
+        org.id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(problem_log_id) => problem_log_id,
+        Err(e) => {
+            eprintln!("Failed to create problem log for complaint: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to submit complaint"}));
+        }
+    };
+
+    if let Err(e) = set_problem_log_due_dates(&mut tx, problem_log_id).await {
+        eprintln!("Failed to set problem log due dates: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to submit complaint"}));
+    }
+
+    if let Err(e) = record_problem_log_activity(
+        &mut tx, problem_log_id, ActivityType::Created, None, Some(ProblemStatus::New),
+        Some("Received through the public complaint form"), None,
+    ).await {
+        eprintln!("Failed to record problem log activity: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to submit complaint"}));
+    }
+
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO complaints (org_id, problem_log_id, product, lot_code, batch_id, purchase_location, purchase_date,
+         description, contact_name, contact_email, contact_phone, client_address)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+        org.id,
+        problem_log_id,
+        complaint.product.trim(),
+        lot_code,
+        batch_id,
+        trimmed(&complaint.purchase_location),
+        purchase_date,
+        description,
+        complaint.contact_name.trim(),
+        contact_email,
+        contact_phone,
+        client_address
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        eprintln!("Failed to save complaint: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to submit complaint"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(ComplaintReceipt { reference: problem_log_id })
+}
+
+async fn fetch_complaints(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    id: Option<i32>,
+    batch_id: Option<i32>,
+) -> Result<Vec<Complaint>, sqlx::Error> {
+    sqlx::query_as!(
+        Complaint,
+        "SELECT id, org_id, problem_log_id, product, lot_code, batch_id, purchase_location,
+         purchase_date::text as purchase_date, description, contact_name, contact_email, contact_phone,
+         created_at::text as \"created_at!\"
+         FROM complaints
+         WHERE org_id = $1 AND ($2::int IS NULL OR id = $2) AND ($3::int IS NULL OR batch_id = $3)
+         ORDER BY id DESC",
+        org_id,
+        id,
+        batch_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn get_complaint(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match fetch_complaints(&data.db_pool, auth_org_id, Some(id), None).await {
+        Ok(mut complaints) if !complaints.is_empty() => HttpResponse::Ok().json(complaints.remove(0)),
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({"error": "Complaint not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_complaints(
+    req: HttpRequest,
+    query: web::Query<ComplaintQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match fetch_complaints(&data.db_pool, org_id, None, query.batch_id).await {
+        Ok(complaints) => HttpResponse::Ok().json(complaints),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch complaints"}))
+        }
+    }
+}
+
+// Complaint category endpoints
+async fn create_complaint_category(
+    req: HttpRequest,
+    category: web::Json<ComplaintCategoryInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if category.org_id != auth_org_id {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to manage this organization's complaint categories"
+        }));
+    }
+
+    if let Err(e) = complaints::check_category(&category.name, category.alert_threshold, category.alert_window_days) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    }
+
+    match sqlx::query_as!(
+        ComplaintCategory,
+        "INSERT INTO complaint_categories (org_id, name, description, active, alert_threshold, alert_window_days)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, org_id, name, description, active, alert_threshold, alert_window_days",
+        category.org_id,
+        category.name.trim(),
+        category.description,
+        category.active,
+        category.alert_threshold,
+        category.alert_window_days
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(created) => HttpResponse::Created().json(created),
+        Err(e) => {
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("A complaint category named {} already exists", category.name.trim())
+                }));
+            }
+            eprintln!("Failed to create complaint category: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create complaint category"}))
+        }
+    }
+}
+
+async fn get_complaint_category(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        ComplaintCategory,
+        "SELECT id, org_id, name, description, active, alert_threshold, alert_window_days
+         FROM complaint_categories WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(category)) if category.org_id == auth_org_id => HttpResponse::Ok().json(category),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this complaint category"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Complaint category not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_complaint_categories(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        ComplaintCategory,
+        "SELECT id, org_id, name, description, active, alert_threshold, alert_window_days
+         FROM complaint_categories WHERE org_id = $1 ORDER BY active DESC, name, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(categories) => HttpResponse::Ok().json(categories),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch complaint categories"}))
+        }
+    }
+}
+
+async fn update_complaint_category(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    category: web::Json<ComplaintCategoryInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if category.org_id != auth_org_id {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to manage this organization's complaint categories"
+        }));
+    }
+
+    if let Err(e) = complaints::check_category(&category.name, category.alert_threshold, category.alert_window_days) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    }
+
+    match sqlx::query_as!(
+        ComplaintCategory,
+        "UPDATE complaint_categories SET name = $1, description = $2, active = $3, alert_threshold = $4,
+         alert_window_days = $5
+         WHERE id = $6 AND org_id = $7
+         RETURNING id, org_id, name, description, active, alert_threshold, alert_window_days",
+        category.name.trim(),
+        category.description,
+        category.active,
+        category.alert_threshold,
+        category.alert_window_days,
+        id,
+        auth_org_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Complaint category not found"})),
+        Err(e) => {
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("A complaint category named {} already exists", category.name.trim())
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_complaint_category(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query!("DELETE FROM complaint_categories WHERE id = $1 AND org_id = $2 RETURNING id", id, auth_org_id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Complaint category not found"})),
+        Err(e) => {
+            // Categorized problem logs keep their category for trend history
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Complaint category is in use. Set active to false to retire it"
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Complaints per category, product, batch or month between `from` and `to`,
+// with the complaint rate per million units produced. Complaints are the
+// organization's categorized problem logs and those from the public form.
+async fn get_complaint_trends(
+    req: HttpRequest,
+    query: web::Query<ComplaintTrendQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let from = match parse_optional_date(&query.from, "from") {
+        Ok(from) => from,
+        Err(response) => return response,
+    };
+    let to = match parse_optional_date(&query.to, "to") {
+        Ok(to) => to,
+        Err(response) => return response,
+    };
+
+    let complaint_facts: Vec<ComplaintFact> = match sqlx::query!(
+        "SELECT pl.category_id, cc.name as \"category?\", pl.batch_id, c.product as \"product?\", pl.date_opened
+         FROM problem_logs pl
+         LEFT JOIN complaint_categories cc ON cc.id = pl.category_id
+         LEFT JOIN complaints c ON c.problem_log_id = pl.id
+         WHERE pl.org_id = $1 AND (pl.category_id IS NOT NULL OR pl.problem_type = 'Customer complaint')
+         AND ($2::date IS NULL OR pl.date_opened >= $2) AND ($3::date IS NULL OR pl.date_opened <= $3)",
+        org_id,
+        from,
+        to
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => records.into_iter().map(|record| ComplaintFact {
+            category_id: record.category_id,
+            category: record.category,
+            batch_id: record.batch_id,
+            product: record.product,
+            date_opened: record.date_opened,
+        }).collect(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch complaint trends"}));
+        }
+    };
+
+    let complained_batches: Vec<i32> = complaint_facts.iter().filter_map(|complaint| complaint.batch_id).collect();
+    let production: Vec<ProductionFact> = match sqlx::query!(
+        "SELECT b.id, b.batch_lot_code, b.date_made, b.amount_made, b.amount_made_unit,
+         COALESCE((SELECT r.name FROM recipes r WHERE r.org_id = b.org_id AND r.lotcode = b.recipe_lotcode
+                   ORDER BY r.id DESC LIMIT 1), b.recipe_lotcode) as \"product!\",
+         (($2::date IS NULL OR b.date_made >= $2) AND ($3::date IS NULL OR b.date_made <= $3)) as \"in_period!\"
+         FROM batches b
+         WHERE b.org_id = $1
+         AND ((($2::date IS NULL OR b.date_made >= $2) AND ($3::date IS NULL OR b.date_made <= $3)) OR b.id = ANY($4))",
+        org_id,
+        from,
+        to,
+        &complained_batches
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => records.into_iter().map(|record| ProductionFact {
+            batch_id: record.id,
+            batch_lot_code: record.batch_lot_code,
+            product: record.product,
+            date_made: record.date_made,
+            amount: Quantity::from_parts(record.amount_made, record.amount_made_unit.as_deref()),
+            in_period: record.in_period,
+        }).collect(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch complaint trends"}));
+        }
+    };
+
+    HttpResponse::Ok().json(complaints::complaint_trends(query.group_by, &complaint_facts, &production))
+}
+
+// Active categories with more complaints in their alert window than their threshold
+async fn get_complaint_alerts(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        ComplaintAlert,
+        "SELECT cc.id as category_id, cc.name as category, COUNT(pl.id) as \"complaints!\",
+         cc.alert_threshold as \"alert_threshold!\", cc.alert_window_days,
+         (CURRENT_DATE - cc.alert_window_days + 1)::text as \"since!\"
+         FROM complaint_categories cc
+         JOIN problem_logs pl ON pl.category_id = cc.id
+         AND pl.date_opened > CURRENT_DATE - cc.alert_window_days
+         WHERE cc.org_id = $1 AND cc.active AND cc.alert_threshold IS NOT NULL
+         GROUP BY cc.id
+         HAVING COUNT(pl.id) > cc.alert_threshold
+         ORDER BY COUNT(pl.id)::float / cc.alert_threshold DESC, cc.name",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(alerts) => HttpResponse::Ok().json(alerts),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch complaint alerts"}))
+        }
+    }
+}
+
+// Document endpoints
+async fn upload_document(
+    req: HttpRequest,
+    query: web::Query<DocumentUploadQuery>,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if query.file_name.trim().is_empty() || body.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "A file name and file contents are required"}));
+    }
+
+    let expires_on = match parse_optional_date(&query.expires_on, "expires_on") {
+        Ok(expires_on) => expires_on,
+        Err(response) => return response,
+    };
+
+    // Ignore parameters such as "; charset=utf-8"
+    let content_type = req.headers().get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_lowercase())
+        .unwrap_or_default();
+    if let Err(message) = documents::check_content(&content_type, &body) {
+        return HttpResponse::UnsupportedMediaType().json(serde_json::json!({"error": message}));
+    }
+
+    // The document must be attached to a record of the same organization
+    let owner = match query.entity_type {
+        DocumentEntity::ReceivingLog => sqlx::query_scalar!("SELECT org_id FROM receiving_log WHERE id = $1", query.entity_id)
+            .fetch_optional(&data.db_pool)
+            .await,
+        DocumentEntity::Ingredient => sqlx::query_scalar!("SELECT org_id FROM ingredients WHERE id = $1", query.entity_id)
+            .fetch_optional(&data.db_pool)
+            .await,
+        DocumentEntity::Supplier => sqlx::query_scalar!("SELECT org_id FROM suppliers WHERE id = $1", query.entity_id)
+            .fetch_optional(&data.db_pool)
+            .await,
+    };
+    match owner {
+        Ok(Some(Some(owner_org_id))) if owner_org_id == org_id => {}
+        Ok(_) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("No {} with id {}", query.entity_type.as_str(), query.entity_id)
+            }));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    let checksum = documents::sha256_hex(&body);
+    let storage_key = format!("{}/{}", org_id, Uuid::new_v4());
+
+    let storage = match document_storage(&data) {
+        Ok(storage) => storage,
+        Err(response) => return response,
+    };
+    if let Err(e) = documents::put(storage, storage_key.clone(), body.clone()).await {
+        eprintln!("Failed to store document: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to store document"}));
+    }
+
+    match sqlx::query_as!(
+        Document,
+        "INSERT INTO documents (org_id, entity_type, entity_id, document_type, file_name, content_type, size_bytes,
+         sha256, storage_key, expires_on)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+         RETURNING id, org_id as \"org_id!\", entity_type, entity_id, document_type, file_name, content_type, size_bytes,
+         sha256, expires_on::text, uploaded_at::text as \"uploaded_at!\",
+         CASE WHEN expires_on < CURRENT_DATE THEN 'expired'
+              WHEN expires_on <= CURRENT_DATE + 30 THEN 'expiring_soon' END as expiry_status",
+        org_id,
+        query.entity_type.as_str(),
+        query.entity_id,
+        query.document_type.as_str(),
+        query.file_name.trim(),
+        content_type,
+        body.len() as i64,
+        checksum,
+        storage_key,
+        expires_on
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(document) => HttpResponse::Created().json(document),
+        Err(e) => {
+            eprintln!("Failed to record document: {}", e);
+            if let Ok(storage) = document_storage(&data) {
+                let _ = documents::delete(storage, storage_key).await;
+            }
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to upload document"}))
+        }
+    }
+}
+
+// The configured document storage; main refuses to start without one
+fn document_storage(data: &AppState) -> Result<Arc<dyn documents::DocumentStorage>, HttpResponse> {
+    data.document_storage.clone().ok_or_else(|| {
+        HttpResponse::ServiceUnavailable().json(serde_json::json!({"error": "Document storage is not configured"}))
+    })
+}
+
+// Remove the stored contents of deleted documents. Deleting a document, or the
+// record it is attached to, queues its storage key; contents that cannot be
+// removed stay queued for the next purge.
+async fn purge_deleted_documents(data: &AppState) {
+    let storage = match data.document_storage.clone() {
+        Some(storage) => storage,
+        None => return,
+    };
+
+    let keys = match sqlx::query_scalar!("SELECT storage_key FROM document_purges ORDER BY deleted_at")
+        .fetch_all(&data.db_pool)
+        .await
+    {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return;
+        }
+    };
+
+    for key in keys {
+        match documents::delete(storage.clone(), key.clone()).await {
+            Ok(()) => {
+                if let Err(e) = sqlx::query!("DELETE FROM document_purges WHERE storage_key = $1", key)
+                    .execute(&data.db_pool)
+                    .await
+                {
+                    eprintln!("Database error: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to delete document contents: {}", e),
+        }
+    }
+}
+
+// List the organization's documents, optionally for one record
+async fn get_documents(
+    req: HttpRequest,
+    query: web::Query<DocumentListQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Document,
+        "SELECT id, org_id as \"org_id!\", entity_type, entity_id, document_type, file_name, content_type, size_bytes,
+         sha256, expires_on::text, uploaded_at::text as \"uploaded_at!\",
+         CASE WHEN expires_on < CURRENT_DATE THEN 'expired'
+              WHEN expires_on <= CURRENT_DATE + 30 THEN 'expiring_soon' END as expiry_status
+         FROM documents
+         WHERE org_id = $1 AND ($2::varchar IS NULL OR entity_type = $2) AND ($3::int IS NULL OR entity_id = $3)
+         ORDER BY uploaded_at DESC, id DESC",
+        org_id,
+        query.entity_type.map(|entity_type| entity_type.as_str()),
+        query.entity_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(documents) => HttpResponse::Ok().json(documents),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch documents"}))
+        }
+    }
+}
+
+// Documents that have expired or expire within `days` (default 30)
+async fn get_expiring_documents(
+    req: HttpRequest,
+    query: web::Query<ExpiringDocumentsQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Document,
+        "SELECT id, org_id as \"org_id!\", entity_type, entity_id, document_type, file_name, content_type, size_bytes,
+         sha256, expires_on::text, uploaded_at::text as \"uploaded_at!\",
+         CASE WHEN expires_on < CURRENT_DATE THEN 'expired'
+              WHEN expires_on <= CURRENT_DATE + 30 THEN 'expiring_soon' END as expiry_status
+         FROM documents
+         WHERE org_id = $1 AND expires_on <= CURRENT_DATE + $2::int
+         ORDER BY expires_on, id",
+        org_id,
+        query.days.unwrap_or(30)
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(documents) => HttpResponse::Ok().json(documents),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch documents"}))
+        }
+    }
+}
+
+async fn get_document(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Document,
+        "SELECT id, org_id as \"org_id!\", entity_type, entity_id, document_type, file_name, content_type, size_bytes,
+         sha256, expires_on::text, uploaded_at::text as \"uploaded_at!\",
+         CASE WHEN expires_on < CURRENT_DATE THEN 'expired'
+              WHEN expires_on <= CURRENT_DATE + 30 THEN 'expiring_soon' END as expiry_status
+         FROM documents WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(document)) if document.org_id == auth_org_id => HttpResponse::Ok().json(document),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this document"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Document not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Download a document's contents, verifying them against the stored checksum
+async fn get_document_content(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let document = match sqlx::query!(
+        "SELECT org_id, file_name, content_type, sha256, storage_key FROM documents WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(document)) if document.org_id == Some(auth_org_id) => document,
+        Ok(Some(_)) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "You don't have permission to access this document"
+            }));
+        }
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Document not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let storage = match document_storage(&data) {
+        Ok(storage) => storage,
+        Err(response) => return response,
+    };
+    let contents = match documents::get(storage, document.storage_key.clone()).await {
+        Ok(contents) => contents,
+        Err(documents::StorageError::NotFound(key)) => {
+            eprintln!("Document contents missing from storage: {}", key);
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Document contents not found"}));
+        }
+        Err(e) => {
+            eprintln!("Failed to read document: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to read document"}));
+        }
+    };
+
+    if documents::sha256_hex(&contents) != document.sha256 {
+        eprintln!("Checksum mismatch for document {}", id);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Document failed checksum verification"}));
+    }
+
+    HttpResponse::Ok()
+        .content_type(document.content_type)
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", document.file_name.replace('"', ""))))
+        .insert_header(("X-Checksum-SHA256", document.sha256))
+        .body(contents)
+}
+
+async fn delete_document(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query!(
+        "DELETE FROM documents WHERE id = $1 AND org_id = $2 RETURNING id",
+        id,
+        auth_org_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(_)) => {
+            purge_deleted_documents(&data).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Document not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Find the ingredient lot a receiving record brings in. An explicit
+// `ingredient_id` must belong to the organization; otherwise the lot is matched
+// by lot code and item name or created.
+async fn resolve_receiving_lot(
+    conn: &mut PgConnection,
+    receiving_log: &ReceivingLogInput,
+    date: NaiveDate,
+) -> Result<i32, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let expiry_date = parse_optional_date(&receiving_log.expiry_date, "expiry_date")?;
+
+    let existing = match receiving_log.ingredient_id {
+        Some(ingredient_id) => match sqlx::query!(
+            "SELECT id FROM ingredients WHERE id = $1 AND org_id = $2",
+            ingredient_id,
+            receiving_log.org_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?
+        {
+            Some(record) => Some(record.id),
+            None => return Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "Ingredient lot not found"}))),
+        },
+        // Supplier lot codes can repeat across items, so the item must match too
+        None => sqlx::query!(
+            "SELECT id FROM ingredients WHERE org_id = $1 AND lotcode = $2 AND LOWER(name) = LOWER($3)
+             ORDER BY id LIMIT 1",
+            receiving_log.org_id,
+            receiving_log.lotcode,
+            receiving_log.item_name
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?
+        .map(|record| record.id),
+    };
+
+    match existing {
+        Some(ingredient_id) => {
+            // Fill in the lot's expiry if it was not known yet
+            if expiry_date.is_some() {
+                sqlx::query!(
+                    "UPDATE ingredients SET expiry_date = COALESCE(expiry_date, $1) WHERE id = $2",
+                    expiry_date,
+                    ingredient_id
+                )
+                .execute(&mut *conn)
+                .await
+                .map_err(internal_error)?;
+            }
+            Ok(ingredient_id)
+        }
+        None => {
+            let expiry_date = match expiry_date {
+                Some(expiry_date) => Some(expiry_date),
+                None => default_expiry(&mut *conn, receiving_log.org_id, &receiving_log.item_name, date, None).await?,
+            };
+            sqlx::query!(
+                "INSERT INTO ingredients (lotcode, name, received_date, org_id, expiry_date) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+                receiving_log.lotcode,
+                receiving_log.item_name,
+                date,
+                receiving_log.org_id,
+                expiry_date
+            )
+            .fetch_one(&mut *conn)
+            .await
+            .map(|record| record.id)
+            .map_err(internal_error)
+        }
+    }
+}
+
+// Receiving history of one ingredient lot, newest first
+async fn get_ingredient_receiving_history(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query!("SELECT org_id FROM ingredients WHERE id = $1", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(record)) if record.org_id == Some(auth_org_id) => {}
+        Ok(Some(_)) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "You don't have permission to access this ingredient"
+            }));
+        }
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    match sqlx::query!(
+        "SELECT id, lotcode, company_name, item_name, date::text as date, org_id, quantity, quantity_unit, ingredient_id,
+         supplier_id, supplier_warning, supplier_override_reason, temperature_value, temperature_unit, checklist_id,
+         disposition, disposition_reason, problem_log_id, gtin, serial_number
+         FROM receiving_log WHERE ingredient_id = $1 ORDER BY date DESC, id DESC",
+        id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => {
+            let ids: Vec<i32> = records.iter().map(|record| record.id).collect();
+            let mut checks = match fetch_receiving_checks(&data.db_pool, &ids).await {
+                Ok(checks) => checks,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+            let logs: Vec<ReceivingLog> = records.into_iter().map(|record| {
+                ReceivingLog {
+                    id: Some(record.id),
+                    lotcode: record.lotcode,
+                    company_name: record.company_name,
+                    item_name: record.item_name,
+                    temperature: Temperature::from_parts(record.temperature_value, record.temperature_unit.as_deref()),
+                    date: record.date.unwrap_or_default(),
+                    org_id: record.org_id.unwrap_or(0),
+                    quantity: Quantity::from_parts(record.quantity, record.quantity_unit.as_deref()),
+                    ingredient_id: record.ingredient_id,
+                    supplier_id: record.supplier_id,
+                    supplier_warning: record.supplier_warning,
+                    supplier_override_reason: record.supplier_override_reason,
+                    checklist_id: record.checklist_id,
+                    checks: checks.remove(&record.id).unwrap_or_default(),
+                    disposition: record.disposition,
+                    disposition_reason: record.disposition_reason,
+                    problem_log_id: record.problem_log_id,
+                    gtin: record.gtin,
+                    serial_number: record.serial_number,
+                }
+            }).collect();
+            HttpResponse::Ok().json(logs)
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch receiving logs"}))
+        }
+    }
+}
+
+// Result of inspecting a delivery against its checklist
+struct Inspection {
+    checklist_id: Option<i32>,
+    checks: Vec<ReceivingCheck>,
+    disposition: Disposition,
+}
+
+// Inspect a delivery against the checklist it names, or the organization's
+// default checklist. Temperature items are checked against the delivery
+// temperature and every yes/no item must be answered. Without an explicit
+// disposition a delivery is accepted when all checks pass and held otherwise.
+async fn inspect_delivery(
+    conn: &mut PgConnection,
+    input: &ReceivingLogInput,
+) -> Result<Inspection, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let checklist_id = match input.checklist_id {
+        Some(checklist_id) => {
+            let owner = sqlx::query_scalar!("SELECT org_id FROM receiving_checklists WHERE id = $1", checklist_id)
+                .fetch_optional(&mut *conn)
+                .await
+                .map_err(internal_error)?;
+            if owner != Some(input.org_id) {
+                return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Receiving checklist {} not found", checklist_id)
+                })));
+            }
+            Some(checklist_id)
+        }
+        None => sqlx::query_scalar!(
+            "SELECT id FROM receiving_checklists WHERE org_id = $1 AND is_default",
+            input.org_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?,
+    };
+
+    let items = match checklist_id {
+        Some(checklist_id) => sqlx::query_as!(
+            ChecklistItem,
+            "SELECT id, label, kind, min_temperature, max_temperature, temperature_unit
+             FROM receiving_checklist_items WHERE checklist_id = $1 ORDER BY position, id",
+            checklist_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(internal_error)?,
+        None => Vec::new(),
+    };
+
+    if let Some(answer) = input.checks.iter().find(|answer| !items.iter().any(|item| item.id == answer.item_id)) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Checklist item {} is not on the delivery's checklist", answer.item_id)
+        })));
+    }
+
+    let mut checks = Vec::new();
+    for item in &items {
+        if item.kind == ChecklistItemKind::Temperature.as_str() {
+            // Free text without a unit cannot be checked against the range
+            let Some(temperature) = input.temperature.reading() else {
+                checks.push(ReceivingCheck {
+                    item_id: Some(item.id),
+                    label: item.label.clone(),
+                    passed: false,
+                    notes: Some(format!("No temperature reading ('{}')", input.temperature)),
+                });
+                continue;
+            };
+            let unit = item.temperature_unit.as_deref()
+                .and_then(|unit| TemperatureUnit::parse(unit).ok())
+                .unwrap_or(temperature.unit);
+            let reading = temperature.convert(unit).map_err(|e| {
+                HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}))
+            })?;
+            let passed = item.min_temperature.is_none_or(|min| reading.value >= min)
+                && item.max_temperature.is_none_or(|max| reading.value <= max);
+            checks.push(ReceivingCheck {
+                item_id: Some(item.id),
+                label: item.label.clone(),
+                passed,
+                notes: Some(format!("Received at {}", reading)),
+            });
+        } else {
+            let answer = input.checks.iter().find(|answer| answer.item_id == item.id).ok_or_else(|| {
+                HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Checklist item '{}' needs an answer", item.label)
+                }))
+            })?;
+            checks.push(ReceivingCheck {
+                item_id: Some(item.id),
+                label: item.label.clone(),
+                passed: answer.passed,
+                notes: answer.notes.clone(),
+            });
+        }
+    }
+
+    let failed: Vec<&str> = checks.iter().filter(|check| !check.passed).map(|check| check.label.as_str()).collect();
+    let has_reason = input.disposition_reason.as_deref().is_some_and(|reason| !reason.trim().is_empty());
+    let disposition = match input.disposition {
+        Some(Disposition::Accept) if !failed.is_empty() && !has_reason => {
+            return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "A disposition_reason is required to accept a delivery that failed inspection",
+                "failed_checks": failed
+            })));
+        }
+        Some(Disposition::Reject) if failed.is_empty() && !has_reason => {
+            return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "A disposition_reason is required to reject a delivery that passed inspection"
+            })));
+        }
+        Some(disposition) => disposition,
+        None if failed.is_empty() => Disposition::Accept,
+        None => Disposition::Hold,
+    };
+
+    Ok(Inspection { checklist_id, checks, disposition })
+}
+
+// Replace the checklist results recorded for a delivery
+async fn save_receiving_checks(
+    conn: &mut PgConnection,
+    receiving_log_id: i32,
+    checks: &[ReceivingCheck],
+) -> Result<(), HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    sqlx::query!("DELETE FROM receiving_checks WHERE receiving_log_id = $1", receiving_log_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+
+    for check in checks {
+        sqlx::query!(
+            "INSERT INTO receiving_checks (receiving_log_id, checklist_item_id, label, passed, notes)
+             VALUES ($1, $2, $3, $4, $5)",
+            receiving_log_id,
+            check.item_id,
+            check.label,
+            check.passed,
+            check.notes
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    }
+
+    Ok(())
+}
+
+async fn fetch_receiving_checks(
+    pool: &Pool<Postgres>,
+    receiving_log_ids: &[i32],
+) -> Result<HashMap<i32, Vec<ReceivingCheck>>, sqlx::Error> {
+    let records = sqlx::query!(
+        "SELECT receiving_log_id, checklist_item_id, label, passed, notes
+         FROM receiving_checks WHERE receiving_log_id = ANY($1) ORDER BY id",
+        receiving_log_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut checks_by_log: HashMap<i32, Vec<ReceivingCheck>> = HashMap::new();
+    for record in records {
+        checks_by_log.entry(record.receiving_log_id).or_default().push(ReceivingCheck {
+            item_id: record.checklist_item_id,
+            label: record.label,
+            passed: record.passed,
+            notes: record.notes,
+        });
+    }
+    Ok(checks_by_log)
+}
+
+// Put the lot of a held or rejected delivery on hold or reject it, unless
+// it is already out of use. A rejected delivery only rejects a lot it alone
+// stocked; a lot with other deliveries or stock keeps its status and the
+// rejected quantity simply never enters stock.
+async fn hold_received_lot(
+    conn: &mut PgConnection,
+    receiving_log_id: i32,
+    input: &ReceivingLogInput,
+    ingredient_id: i32,
+    disposition: Disposition,
+) -> Result<(), HttpResponse> {
+    let (status, reason) = match disposition {
+        Disposition::Accept => return Ok(()),
+        Disposition::Hold => (LotStatus::OnHold, format!("Delivery held on receiving (receiving log {})", receiving_log_id)),
+        Disposition::Reject => (LotStatus::Rejected, format!("Delivery rejected on receiving (receiving log {})", receiving_log_id)),
+    };
+
+    let current = match sqlx::query!(
+        "SELECT status,
+         EXISTS(SELECT 1 FROM receiving_log WHERE ingredient_id = $1 AND id <> $2)
+         OR EXISTS(SELECT 1 FROM inventory_transactions WHERE ingredient_id = $1 AND receiving_log_id IS DISTINCT FROM $2)
+         as \"shared!\"
+         FROM ingredients WHERE id = $1",
+        ingredient_id,
+        receiving_log_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    {
+        Ok(current) => current,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+        }
+    };
+    if status == LotStatus::Rejected && current.shared {
+        return Ok(());
+    }
+    let reason = match input.disposition_reason.as_deref().filter(|reason| !reason.trim().is_empty()) {
+        Some(detail) => format!("{}: {}", reason, detail.trim()),
+        None => reason,
+    };
+
+    let applies = LotStatus::parse(&current.status).is_some_and(|current| {
+        (current == LotStatus::Released || status == LotStatus::Rejected) && current.can_change_to(status)
+    });
+    if applies {
+        set_lot_status(conn, input.org_id, LotRef::Ingredient(ingredient_id), status, &reason, None).await?;
+    }
+    Ok(())
+}
+
+// Open a problem log for a rejected delivery and link it to the receiving log
+async fn open_rejection_problem_log(
+    conn: &mut PgConnection,
+    receiving_log_id: i32,
+    input: &ReceivingLogInput,
+    company_name: &str,
+    inspection: &Inspection,
+    date: NaiveDate,
+) -> Result<i32, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let mut description = format!(
+        "Delivery of {} (lot {}) from {} was rejected on receiving.",
+        input.item_name, input.lotcode, company_name
+    );
+    let failed: Vec<&str> = inspection.checks.iter()
+        .filter(|check| !check.passed)
+        .map(|check| check.label.as_str())
+        .collect();
+    if !failed.is_empty() {
+        description.push_str(&format!(" Failed checks: {}.", failed.join(", ")));
+    }
+    if let Some(reason) = input.disposition_reason.as_deref().filter(|reason| !reason.trim().is_empty()) {
+        description.push_str(&format!(" Reason: {}", reason.trim()));
+    }
+
+    let problem_log_id = sqlx::query_scalar!(
+        "INSERT INTO problem_logs (is_open, date_opened, customer_name, problem_type, problem_description, recall, org_id)
+         VALUES (TRUE, $1, $2, 'Rejected delivery', $3, FALSE, $4) RETURNING id",
+        date,
+        company_name,
+        description,
+        input.org_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+    set_problem_log_due_dates(conn, problem_log_id).await.map_err(internal_error)?;
+
+    record_problem_log_activity(
+        conn, problem_log_id, ActivityType::Created, None, Some(ProblemStatus::New), None, None,
+    ).await.map_err(internal_error)?;
+
+    sqlx::query!(
+        "UPDATE receiving_log SET problem_log_id = $1 WHERE id = $2",
+        problem_log_id,
+        receiving_log_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(problem_log_id)
+}
+
+// Withdraw the problem log of a delivery that is no longer rejected. The log
+// is unlinked from the receiving log and closed, unless CAPAs are still open
+// against it, in which case the change is noted on its thread.
+async fn withdraw_rejection_problem_log(
+    conn: &mut PgConnection,
+    receiving_log_id: i32,
+    problem_log_id: i32,
+    disposition: Disposition,
+) -> Result<(), HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    sqlx::query!("UPDATE receiving_log SET problem_log_id = NULL WHERE id = $1", receiving_log_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+
+    let current = sqlx::query!(
+        "SELECT status, corrective_action_required,
+         (SELECT COUNT(*) FROM capas WHERE problem_log_id = p.id AND status NOT IN ('verified', 'cancelled')) as \"open_capas!\"
+         FROM problem_logs p WHERE id = $1 FOR UPDATE",
+        problem_log_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+    let Some(current) = current else {
+        return Ok(());
+    };
+    let from = ProblemStatus::parse(&current.status).unwrap_or_default();
+    if !from.is_open() {
+        return Ok(());
+    }
+
+    let note = format!(
+        "Delivery changed to {} on receiving log {}",
+        disposition.as_str(),
+        receiving_log_id
+    );
+    if current.open_capas > 0 || current.corrective_action_required {
+        record_problem_log_activity(conn, problem_log_id, ActivityType::Comment, None, None, Some(&note), None)
+            .await
+            .map_err(internal_error)?;
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "UPDATE problem_logs SET status = $1, is_open = FALSE, resolution_notes = $2,
+         date_resolved = COALESCE(date_resolved, CURRENT_DATE) WHERE id = $3",
+        ProblemStatus::Closed.as_str(),
+        note,
+        problem_log_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+    record_problem_log_activity(
+        conn, problem_log_id, ActivityType::StatusChange, Some(from), Some(ProblemStatus::Closed), Some(&note), None,
+    ).await.map_err(internal_error)?;
+    Ok(())
+}
+
+async fn fetch_receiving_checklist(
+    pool: &Pool<Postgres>,
+    id: i32,
+) -> Result<Option<ReceivingChecklist>, sqlx::Error> {
+    let checklist = match sqlx::query!(
+        "SELECT id, org_id, name, is_default FROM receiving_checklists WHERE id = $1",
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(checklist) => checklist,
+        None => return Ok(None),
+    };
+
+    let items = sqlx::query_as!(
+        ChecklistItem,
+        "SELECT id, label, kind, min_temperature, max_temperature, temperature_unit
+         FROM receiving_checklist_items WHERE checklist_id = $1 ORDER BY position, id",
+        id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(ReceivingChecklist {
+        id: checklist.id,
+        org_id: checklist.org_id,
+        name: checklist.name,
+        is_default: checklist.is_default,
+        items,
+    }))
+}
+
+fn validate_checklist_input(checklist: &ReceivingChecklistInput) -> Result<(), String> {
+    if checklist.name.trim().is_empty() {
+        return Err("Checklist name is required".to_string());
+    }
+    for item in &checklist.items {
+        if item.label.trim().is_empty() {
+            return Err("Checklist item labels cannot be empty".to_string());
+        }
+        if let (Some(min), Some(max)) = (item.min_temperature, item.max_temperature) {
+            if min > max {
+                return Err(format!("'{}': min_temperature is above max_temperature", item.label));
+            }
+        }
+        if item.kind == ChecklistItemKind::Temperature && item.min_temperature.is_none() && item.max_temperature.is_none() {
+            return Err(format!("'{}': temperature items need a min_temperature or max_temperature", item.label));
+        }
+    }
+    Ok(())
+}
+
+// Save a checklist's items in order, replacing any existing items. Making a
+// checklist the default clears the organization's previous default.
+async fn save_checklist_items(
+    conn: &mut PgConnection,
+    checklist_id: i32,
+    checklist: &ReceivingChecklistInput,
+) -> Result<(), sqlx::Error> {
+    if checklist.is_default {
+        sqlx::query!(
+            "UPDATE receiving_checklists SET is_default = FALSE WHERE org_id = $1 AND id <> $2 AND is_default",
+            checklist.org_id,
+            checklist_id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query!("DELETE FROM receiving_checklist_items WHERE checklist_id = $1", checklist_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for (position, item) in checklist.items.iter().enumerate() {
+        let temperature_unit = match item.kind {
+            ChecklistItemKind::Temperature => Some(item.temperature_unit.unwrap_or(TemperatureUnit::Celsius).symbol()),
+            ChecklistItemKind::YesNo => None,
+        };
+        sqlx::query!(
+            "INSERT INTO receiving_checklist_items (checklist_id, position, label, kind, min_temperature, max_temperature, temperature_unit)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            checklist_id,
+            position as i32,
+            item.label.trim(),
+            item.kind.as_str(),
+            item.min_temperature,
+            item.max_temperature,
+            temperature_unit
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Receiving Checklist endpoints
+async fn create_receiving_checklist(
+    checklist: web::Json<ReceivingChecklistInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(message) = validate_checklist_input(&checklist) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // The default flag is set once the previous default has been cleared
+    let checklist_id = match sqlx::query_scalar!(
+        "INSERT INTO receiving_checklists (org_id, name) VALUES ($1, $2) RETURNING id",
+        checklist.org_id,
+        checklist.name.trim()
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(checklist_id) => checklist_id,
+        Err(e) => {
+            eprintln!("Failed to create receiving checklist: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create receiving checklist"}));
+        }
+    };
+
+    if let Err(e) = save_checklist_items(&mut tx, checklist_id, &checklist).await {
+        eprintln!("Failed to save checklist items: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create receiving checklist"}));
+    }
+
+    if let Err(e) = sqlx::query!(
+        "UPDATE receiving_checklists SET is_default = $1 WHERE id = $2",
+        checklist.is_default,
+        checklist_id
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        eprintln!("Failed to create receiving checklist: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create receiving checklist"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    match fetch_receiving_checklist(&data.db_pool, checklist_id).await {
+        Ok(Some(created)) => HttpResponse::Created().json(created),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving checklist not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_receiving_checklist(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match fetch_receiving_checklist(&data.db_pool, id).await {
+        Ok(Some(checklist)) if checklist.org_id == auth_org_id => HttpResponse::Ok().json(checklist),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this receiving checklist"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving checklist not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_receiving_checklists(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let ids = match sqlx::query_scalar!(
+        "SELECT id FROM receiving_checklists WHERE org_id = $1 ORDER BY is_default DESC, name, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch receiving checklists"}));
+        }
+    };
+
+    let mut checklists = Vec::new();
+    for id in ids {
+        match fetch_receiving_checklist(&data.db_pool, id).await {
+            Ok(Some(checklist)) => checklists.push(checklist),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch receiving checklists"}));
+            }
+        }
+    }
+    HttpResponse::Ok().json(checklists)
+}
+
+async fn update_receiving_checklist(
+    path: web::Path<i32>,
+    checklist: web::Json<ReceivingChecklistInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    if let Err(message) = validate_checklist_input(&checklist) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    match sqlx::query!(
+        "UPDATE receiving_checklists SET name = $1, is_default = FALSE WHERE id = $2 AND org_id = $3 RETURNING id",
+        checklist.name.trim(),
+        id,
+        checklist.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving checklist not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    if let Err(e) = save_checklist_items(&mut tx, id, &checklist).await {
+        eprintln!("Failed to save checklist items: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update receiving checklist"}));
+    }
+
+    if let Err(e) = sqlx::query!(
+        "UPDATE receiving_checklists SET is_default = $1 WHERE id = $2",
+        checklist.is_default,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        eprintln!("Failed to update receiving checklist: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update receiving checklist"}));
+    }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    match fetch_receiving_checklist(&data.db_pool, id).await {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving checklist not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_receiving_checklist(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    match sqlx::query!("DELETE FROM receiving_checklists WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::NoContent().finish(),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving checklist not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Receiving Log endpoints
+// Record a delivery: check the supplier, inspect it, resolve its lot and add
+// accepted quantities to stock
+async fn insert_receiving_log(
+    conn: &mut PgConnection,
+    receiving_log: &ReceivingLogInput,
+) -> Result<ReceivingLog, HttpResponse> {
+    // Parse the date string to NaiveDate
+    let date = NaiveDate::parse_from_str(&receiving_log.date, "%Y-%m-%d").map_err(|_| {
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid date format. Use YYYY-MM-DD"
+        }))
+    })?;
+
+    let supplier = check_supplier(&mut *conn, receiving_log, date).await?;
+    let company_name = supplier.company_name;
+    let inspection = inspect_delivery(&mut *conn, receiving_log).await?;
+    let ingredient_id = resolve_receiving_lot(&mut *conn, receiving_log, date).await?;
+
+    let receiving_log_id = match sqlx::query!(
+        "INSERT INTO receiving_log (lotcode, company_name, item_name, temperature, temperature_value, temperature_unit, date, org_id,
+         quantity, quantity_unit, ingredient_id, supplier_id, supplier_warning, supplier_override_reason, checklist_id, disposition,
+         disposition_reason, gtin, serial_number) 
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) RETURNING id",
+        receiving_log.lotcode,
+        company_name,
+        receiving_log.item_name,
+        receiving_log.temperature.to_string(),
+        receiving_log.temperature.reading().map(|t| t.value),
+        receiving_log.temperature.reading().map(|t| t.unit.symbol()),
+        date,
+        receiving_log.org_id,
+        receiving_log.quantity.map(|quantity| quantity.value),
+        receiving_log.quantity.map(|quantity| quantity.unit.symbol()),
+        ingredient_id,
+        supplier.supplier_id,
+        supplier.warning,
+        supplier.override_reason,
+        inspection.checklist_id,
+        inspection.disposition.as_str(),
+        receiving_log.disposition_reason,
+        receiving_log.gtin,
+        receiving_log.serial_number
+    )
+    .fetch_one(&mut *conn)
+    .await
+    {
+        Ok(record) => record.id,
+        Err(e) => {
+            eprintln!("Failed to create receiving log: {}", e);
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create receiving log"})));
+        }
+    };
+
+    save_receiving_checks(&mut *conn, receiving_log_id, &inspection.checks).await?;
+
+    // Rejected deliveries open a problem log. Only accepted deliveries enter
+    // stock; held ones are booked when the lot is released.
+    let mut problem_log_id = None;
+    if inspection.disposition == Disposition::Reject {
+        problem_log_id = Some(
+            open_rejection_problem_log(&mut *conn, receiving_log_id, receiving_log, &company_name, &inspection, date).await?
+        );
+    }
+    let received = receiving_log.quantity.filter(|_| inspection.disposition == Disposition::Accept);
+
+    hold_received_lot(&mut *conn, receiving_log_id, receiving_log, ingredient_id, inspection.disposition).await?;
+
+    // Add the received quantity to the lot's stock
+    record_receipt(&mut *conn, receiving_log_id, receiving_log.org_id, ingredient_id, received).await?;
+
+    Ok(ReceivingLog {
+        id: Some(receiving_log_id),
+        lotcode: receiving_log.lotcode.clone(),
+        company_name,
+        item_name: receiving_log.item_name.clone(),
+        temperature: receiving_log.temperature.reading(),
+        date: receiving_log.date.clone(),
+        org_id: receiving_log.org_id,
+        quantity: receiving_log.quantity,
+        ingredient_id: Some(ingredient_id),
+        supplier_id: supplier.supplier_id,
+        supplier_warning: supplier.warning,
+        supplier_override_reason: supplier.override_reason,
+        checklist_id: inspection.checklist_id,
+        checks: inspection.checks,
+        disposition: inspection.disposition.as_str().to_string(),
+        disposition_reason: receiving_log.disposition_reason.clone(),
+        problem_log_id,
+        gtin: receiving_log.gtin.clone(),
+        serial_number: receiving_log.serial_number.clone(),
+    })
+}
+
+async fn create_receiving_log(
+    receiving_log: web::Json<ReceivingLogInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let created_log = match insert_receiving_log(&mut tx, &receiving_log).await {
+        Ok(created_log) => created_log,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(created_log)
+}
+
+// Pre-fill a receiving record from a scanned case label, or create it when
+// `create` is set and nothing required is missing
+async fn scan_receiving_label(
+    scan: web::Json<ReceivingScanInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let scan = scan.into_inner();
+    let parsed = match gs1::parse(&scan.scan) {
+        Ok(parsed) => parsed,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    // The last delivery of the same trade item supplies the item and supplier
+    let previous = match &parsed.gtin {
+        Some(gtin) => match sqlx::query!(
+            "SELECT item_name, company_name, supplier_id FROM receiving_log
+             WHERE org_id = $1 AND gtin = $2 ORDER BY date DESC, id DESC LIMIT 1",
+            scan.org_id,
+            gtin
+        )
+        .fetch_optional(&data.db_pool)
+        .await
+        {
+            Ok(previous) => previous,
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+        },
+        None => None,
+    };
+
+    let lotcode = scan.lotcode.clone().or_else(|| parsed.lot.clone());
+    let item_name = scan.item_name.clone().or_else(|| previous.as_ref().map(|previous| previous.item_name.clone()));
+    let supplier_id = scan.supplier_id.or_else(|| previous.as_ref().and_then(|previous| previous.supplier_id));
+    let company_name = scan.company_name.clone()
+        .or_else(|| previous.as_ref().map(|previous| previous.company_name.clone()).filter(|_| supplier_id.is_none()));
+    let expiry_date = scan.expiry_date.clone().or_else(|| parsed.expiry_date.map(|date| date.to_string()));
+    let quantity = scan.quantity.or(parsed.net_weight);
+    let date = scan.date.clone().unwrap_or_else(|| chrono::Local::now().date_naive().to_string());
+
+    let mut missing = Vec::new();
+    if lotcode.is_none() {
+        missing.push("lotcode".to_string());
+    }
+    if item_name.is_none() {
+        missing.push("item_name".to_string());
+    }
+    if company_name.is_none() && supplier_id.is_none() {
+        missing.push("company_name or supplier_id".to_string());
+    }
+    if scan.temperature.is_none() {
+        missing.push("temperature".to_string());
+    }
+
+    let prefilled = serde_json::json!({
+        "lotcode": lotcode,
+        "company_name": company_name,
+        "item_name": item_name,
+        "temperature": scan.temperature,
+        "date": date,
+        "org_id": scan.org_id,
+        "quantity": quantity,
+        "supplier_id": supplier_id,
+        "expiry_date": expiry_date,
+        "gtin": parsed.gtin,
+        "serial_number": parsed.serial
+    });
+
+    if !scan.create {
+        return HttpResponse::Ok().json(ReceivingScan { scan: parsed, receiving_log: prefilled, missing });
+    }
+    let (lotcode, item_name, temperature) = match (lotcode, item_name, scan.temperature) {
+        (Some(lotcode), Some(item_name), Some(temperature)) if missing.is_empty() => (lotcode, item_name, temperature),
+        _ => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Cannot create the receiving record; missing {}", missing.join(", ")),
+                "missing": missing,
+                "receiving_log": prefilled
+            }));
+        }
+    };
+
+    let receiving_log = ReceivingLogInput {
+        lotcode,
+        company_name: company_name.unwrap_or_default(),
+        item_name,
+        temperature: temperature.into(),
+        date,
+        org_id: scan.org_id,
+        quantity,
+        ingredient_id: None,
+        supplier_id,
+        override_supplier_check: scan.override_supplier_check,
+        override_reason: scan.override_reason,
+        expiry_date,
+        checklist_id: scan.checklist_id,
+        checks: scan.checks,
+        disposition: scan.disposition,
+        disposition_reason: scan.disposition_reason,
+        gtin: parsed.gtin,
+        serial_number: parsed.serial,
+    };
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let created_log = match insert_receiving_log(&mut tx, &receiving_log).await {
+        Ok(created_log) => created_log,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(created_log)
+}
+
+async fn get_receiving_log(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Extract auth token from request
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            match header.to_str() {
+                Ok(header_str) => {
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
+                    } else {
+                        return HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Invalid authorization header format"
+                        }));
+                    }
+                },
+                Err(_) => {
+                    return HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid authorization header"
+                    }));
+                }
+            }
+        },
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authorization header is missing"
+            }));
+        }
+    };
+    
+    // Verify token and get organization ID
+    let claims = match auth::verify_token(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid auth token"
+            }));
+        }
+    };
+    
+    let auth_org_id = claims.org_id;
+    
+    match sqlx::query!(
+        "SELECT id, lotcode, company_name, item_name, date::text as date, org_id, quantity, quantity_unit, ingredient_id,
+         supplier_id, supplier_warning, supplier_override_reason, temperature_value, temperature_unit, checklist_id,
+         disposition, disposition_reason, problem_log_id, gtin, serial_number
+         FROM receiving_log WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(record)) => {
+            // Security check: Verify the authenticated user belongs to the same organization
+            // as the requested resource
+            if record.org_id.unwrap_or(0) != auth_org_id {
+                return HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "You don't have permission to access this receiving log"
+                }));
+            }
+
+            let mut checks = match fetch_receiving_checks(&data.db_pool, &[record.id]).await {
+                Ok(checks) => checks,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+            
+            let receiving_log = ReceivingLog {
+                id: Some(record.id),
+                lotcode: record.lotcode,
+                company_name: record.company_name,
+                item_name: record.item_name,
+                temperature: Temperature::from_parts(record.temperature_value, record.temperature_unit.as_deref()),
+                date: record.date.unwrap_or_default(),
+                org_id: record.org_id.unwrap_or(0),
+                quantity: Quantity::from_parts(record.quantity, record.quantity_unit.as_deref()),
+                ingredient_id: record.ingredient_id,
+                supplier_id: record.supplier_id,
+                supplier_warning: record.supplier_warning,
+                supplier_override_reason: record.supplier_override_reason,
+                checklist_id: record.checklist_id,
+                checks: checks.remove(&record.id).unwrap_or_default(),
+                disposition: record.disposition,
+                disposition_reason: record.disposition_reason,
+                problem_log_id: record.problem_log_id,
+                gtin: record.gtin,
+                serial_number: record.serial_number,
+            };
+            HttpResponse::Ok().json(receiving_log)
+        },
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving log not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn get_all_receiving_logs(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // Extract auth token from request
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            match header.to_str() {
+                Ok(header_str) => {
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
+                    } else {
+                        return HttpResponse::Unauthorized().json(serde_json::json!({
+                            "error": "Invalid authorization header format"
+                        }));
+                    }
+                },
+                Err(_) => {
+                    return HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid authorization header"
+                    }));
+                }
+            }
+        },
+        None => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Authorization header is missing"
+            }));
+        }
+    };
+    
+    // Verify token and get organization ID
+    let claims = match auth::verify_token(token) {
+        Ok(claims) => claims,
+        Err(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid auth token"
+            }));
+        }
+    };
+    
+    let org_id = claims.org_id;
+
+    match sqlx::query!(
+        "SELECT id, lotcode, company_name, item_name, date::text as date, org_id, quantity, quantity_unit, ingredient_id,
+         supplier_id, supplier_warning, supplier_override_reason, temperature_value, temperature_unit, checklist_id,
+         disposition, disposition_reason, problem_log_id, gtin, serial_number
+         FROM receiving_log WHERE org_id = $1 ORDER BY date DESC",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => {
+            let ids: Vec<i32> = records.iter().map(|record| record.id).collect();
+            let mut checks = match fetch_receiving_checks(&data.db_pool, &ids).await {
+                Ok(checks) => checks,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+            let logs: Vec<ReceivingLog> = records.into_iter().map(|record| {
+                ReceivingLog {
+                    id: Some(record.id),
+                    lotcode: record.lotcode,
+                    company_name: record.company_name,
+                    item_name: record.item_name,
+                    temperature: Temperature::from_parts(record.temperature_value, record.temperature_unit.as_deref()),
+                    date: record.date.unwrap_or_default(),
+                    org_id: record.org_id.unwrap_or(0),
+                    quantity: Quantity::from_parts(record.quantity, record.quantity_unit.as_deref()),
+                    ingredient_id: record.ingredient_id,
+                    supplier_id: record.supplier_id,
+                    supplier_warning: record.supplier_warning,
+                    supplier_override_reason: record.supplier_override_reason,
+                    checklist_id: record.checklist_id,
+                    checks: checks.remove(&record.id).unwrap_or_default(),
+                    disposition: record.disposition,
+                    disposition_reason: record.disposition_reason,
+                    problem_log_id: record.problem_log_id,
+                    gtin: record.gtin,
+                    serial_number: record.serial_number,
+                }
+            }).collect();
+            HttpResponse::Ok().json(logs)
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn update_receiving_log(
+    path: web::Path<i32>,
+    receiving_log: web::Json<ReceivingLogInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
     let id = path.into_inner();
     
-    match sqlx::query_as!(
-        Organization,
-        "SELECT id, name, email FROM organizations WHERE id = $1",
+    // Parse the date string to NaiveDate
+    let date = match NaiveDate::parse_from_str(&receiving_log.date, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid date format. Use YYYY-MM-DD"
+            }));
+        }
+    };
+    
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+    
+    let supplier = match check_supplier(&mut tx, &receiving_log, date).await {
+        Ok(supplier) => supplier,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+    
+    let inspection = match inspect_delivery(&mut tx, &receiving_log).await {
+        Ok(inspection) => inspection,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+    
+    let ingredient_id = match resolve_receiving_lot(&mut tx, &receiving_log, date).await {
+        Ok(ingredient_id) => ingredient_id,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+    
+    match sqlx::query!(
+        "UPDATE receiving_log SET lotcode = $1, company_name = $2, item_name = $3, temperature = $4, temperature_value = $5,
+         temperature_unit = $6, date = $7, org_id = $8, quantity = $9, quantity_unit = $10, ingredient_id = $11, supplier_id = $12,
+         supplier_warning = $13, supplier_override_reason = $14, checklist_id = $15, disposition = $16,
+         disposition_reason = $17, gtin = $18, serial_number = $19
+         WHERE id = $20 RETURNING problem_log_id",
+        receiving_log.lotcode,
+        supplier.company_name,
+        receiving_log.item_name,
+        receiving_log.temperature.to_string(),
+        receiving_log.temperature.reading().map(|t| t.value),
+        receiving_log.temperature.reading().map(|t| t.unit.symbol()),
+        date,
+        receiving_log.org_id,
+        receiving_log.quantity.map(|quantity| quantity.value),
+        receiving_log.quantity.map(|quantity| quantity.unit.symbol()),
+        ingredient_id,
+        supplier.supplier_id,
+        supplier.warning,
+        supplier.override_reason,
+        inspection.checklist_id,
+        inspection.disposition.as_str(),
+        receiving_log.disposition_reason,
+        receiving_log.gtin,
+        receiving_log.serial_number,
         id
     )
-    .fetch_optional(&data.db_pool)
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(record)) => {
+            if let Err(response) = save_receiving_checks(&mut tx, id, &inspection.checks).await {
+                let _ = tx.rollback().await;
+                return response;
+            }
+
+            // Open a problem log the first time the delivery is rejected
+            let mut problem_log_id = record.problem_log_id;
+            if inspection.disposition == Disposition::Reject && problem_log_id.is_none() {
+                problem_log_id = match open_rejection_problem_log(&mut tx, id, &receiving_log, &supplier.company_name, &inspection, date).await {
+                    Ok(problem_log_id) => Some(problem_log_id),
+                    Err(response) => {
+                        let _ = tx.rollback().await;
+                        return response;
+                    }
+                };
+            }
+            // A delivery that is no longer rejected drops its problem log
+            if let (Some(rejection_log_id), false) = (problem_log_id, inspection.disposition == Disposition::Reject) {
+                if let Err(response) = withdraw_rejection_problem_log(&mut tx, id, rejection_log_id, inspection.disposition).await {
+                    let _ = tx.rollback().await;
+                    return response;
+                }
+                problem_log_id = None;
+            }
+            // Only accepted deliveries are booked into stock; held ones wait for the lot's release
+            let received = receiving_log.quantity.filter(|_| inspection.disposition == Disposition::Accept);
+
+            if let Err(response) = hold_received_lot(&mut tx, id, &receiving_log, ingredient_id, inspection.disposition).await {
+                let _ = tx.rollback().await;
+                return response;
+            }
+
+            // Rebook the receipt in the inventory ledger
+            if let Err(response) = record_receipt(&mut tx, id, receiving_log.org_id, ingredient_id, received).await {
+                let _ = tx.rollback().await;
+                return response;
+            }
+
+            // Commit the transaction
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+
+            let updated_log = ReceivingLog {
+                id: Some(id),
+                lotcode: receiving_log.lotcode.clone(),
+                company_name: supplier.company_name,
+                item_name: receiving_log.item_name.clone(),
+                temperature: receiving_log.temperature.reading(),
+                date: receiving_log.date.clone(),
+                org_id: receiving_log.org_id,
+                quantity: receiving_log.quantity,
+                ingredient_id: Some(ingredient_id),
+                supplier_id: supplier.supplier_id,
+                supplier_warning: supplier.warning,
+                supplier_override_reason: supplier.override_reason,
+                checklist_id: inspection.checklist_id,
+                checks: inspection.checks,
+                disposition: inspection.disposition.as_str().to_string(),
+                disposition_reason: receiving_log.disposition_reason.clone(),
+                problem_log_id,
+                gtin: receiving_log.gtin.clone(),
+                serial_number: receiving_log.serial_number.clone(),
+            };
+            HttpResponse::Ok().json(updated_log)
+        }
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving log not found"}))
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_receiving_log(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+    
+    // The receipt leaves the ledger with the log; stock already used stays covered
+    let received_lots = match remove_receipt(&mut tx, id).await {
+        Ok(received_lots) => received_lots,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+    
+    match sqlx::query!("DELETE FROM receiving_log WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(_)) => {
+            if let Err(response) = check_lots_on_hand(&mut tx, &received_lots).await {
+                let _ = tx.rollback().await;
+                return response;
+            }
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+            // Attached documents went with it
+            purge_deleted_documents(&data).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving log not found"}))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// A single change to the stock of an ingredient lot
+struct InventoryMovement<'a> {
+    org_id: i32,
+    ingredient_id: i32,
+    kind: &'a str,
+    // Signed: receipts are positive, consumption and waste negative
+    change: Quantity,
+    reason: Option<&'a str>,
+    batch_id: Option<i32>,
+    receiving_log_id: Option<i32>,
+}
+
+// Record a movement in the inventory ledger, converted to the lot's inventory
+// unit, and return the lot's new on-hand. The lot row is locked so concurrent
+// movements see each other. A lot's first receipt or adjustment sets its
+// inventory unit; consumption of lots that were never stocked is not tracked
+// and returns `None`. Movements that would take stock below zero are refused.
+async fn record_inventory_movement(
+    conn: &mut PgConnection,
+    movement: InventoryMovement<'_>,
+) -> Result<Option<Quantity>, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let lot = sqlx::query!(
+        "SELECT inventory_unit, density FROM ingredients WHERE id = $1 AND org_id = $2 FOR UPDATE",
+        movement.ingredient_id,
+        movement.org_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(internal_error)?
+    .ok_or_else(|| HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Ingredient lot {} not found", movement.ingredient_id)
+    })))?;
+
+    let inventory_unit = match lot.inventory_unit.as_deref().and_then(|unit| Unit::parse(unit).ok()) {
+        Some(unit) => unit,
+        None if movement.kind == "consumption" => return Ok(None),
+        None => {
+            sqlx::query!(
+                "UPDATE ingredients SET inventory_unit = $1 WHERE id = $2",
+                movement.change.unit.symbol(),
+                movement.ingredient_id
+            )
+            .execute(&mut *conn)
+            .await
+            .map_err(internal_error)?;
+            movement.change.unit
+        }
+    };
+
+    let change = movement.change.convert(inventory_unit, lot.density).map_err(|e| {
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Ingredient lot {}: {}", movement.ingredient_id, e)
+        }))
+    })?;
+
+    let on_hand = sqlx::query!(
+        "SELECT COALESCE(SUM(quantity), 0) as \"on_hand!\" FROM inventory_transactions WHERE ingredient_id = $1",
+        movement.ingredient_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(internal_error)?
+    .on_hand;
+
+    let new_on_hand = on_hand + change.value;
+    if change.value.is_sign_negative() && new_on_hand < Decimal::ZERO {
+        return Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Insufficient inventory for ingredient lot",
+            "ingredient_id": movement.ingredient_id,
+            "on_hand": Quantity::new(on_hand, inventory_unit),
+            "requested": Quantity::new(-change.value, inventory_unit)
+        })));
+    }
+
+    sqlx::query!(
+        "INSERT INTO inventory_transactions (org_id, ingredient_id, kind, quantity, unit, reason, batch_id, receiving_log_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        movement.org_id,
+        movement.ingredient_id,
+        movement.kind,
+        change.value,
+        inventory_unit.symbol(),
+        movement.reason,
+        movement.batch_id,
+        movement.receiving_log_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    Ok(Some(Quantity::new(new_on_hand, inventory_unit)))
+}
+
+// Take a receiving log's receipts out of the ledger and return the lots they
+// stocked. The lots are locked first so concurrent consumption waits.
+async fn remove_receipt(conn: &mut PgConnection, receiving_log_id: i32) -> Result<Vec<i32>, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let ingredient_ids = sqlx::query_scalar!(
+        "SELECT id FROM ingredients
+         WHERE id IN (SELECT ingredient_id FROM inventory_transactions WHERE receiving_log_id = $1)
+         ORDER BY id FOR UPDATE",
+        receiving_log_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    sqlx::query!("DELETE FROM inventory_transactions WHERE receiving_log_id = $1", receiving_log_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(ingredient_ids)
+}
+
+// Refuse when stock already used from these lots would no longer be covered
+async fn check_lots_on_hand(conn: &mut PgConnection, ingredient_ids: &[i32]) -> Result<(), HttpResponse> {
+    match sqlx::query!(
+        "SELECT t.ingredient_id, i.inventory_unit, SUM(t.quantity) as \"on_hand!\"
+         FROM inventory_transactions t JOIN ingredients i ON i.id = t.ingredient_id
+         WHERE t.ingredient_id = ANY($1)
+         GROUP BY t.ingredient_id, i.inventory_unit HAVING SUM(t.quantity) < 0
+         ORDER BY t.ingredient_id LIMIT 1",
+        ingredient_ids
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    {
+        Ok(None) => Ok(()),
+        Ok(Some(record)) => Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Stock from this receipt has already been used",
+            "ingredient_id": record.ingredient_id,
+            "on_hand": Quantity::from_parts(Some(record.on_hand), record.inventory_unit.as_deref())
+        }))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+// Replace the ledger receipt for a receiving log with its received quantity.
+// Reducing or removing a receipt must not leave its lot with negative stock.
+async fn record_receipt(
+    conn: &mut PgConnection,
+    receiving_log_id: i32,
+    org_id: i32,
+    ingredient_id: i32,
+    quantity: Option<Quantity>,
+) -> Result<(), HttpResponse> {
+    let previous_lots = remove_receipt(conn, receiving_log_id).await?;
+
+    if let Some(quantity) = quantity {
+        record_inventory_movement(conn, InventoryMovement {
+            org_id,
+            ingredient_id,
+            kind: "receipt",
+            change: quantity,
+            reason: None,
+            batch_id: None,
+            receiving_log_id: Some(receiving_log_id),
+        })
+        .await?;
+    }
+
+    check_lots_on_hand(conn, &previous_lots).await
+}
+
+// Accept the held deliveries of a released ingredient lot and book them into stock
+async fn release_held_receipts(conn: &mut PgConnection, ingredient_id: i32) -> Result<(), HttpResponse> {
+    let held = match sqlx::query!(
+        "UPDATE receiving_log SET disposition = 'accept'
+         WHERE ingredient_id = $1 AND disposition = 'hold'
+         RETURNING id, org_id, quantity, quantity_unit",
+        ingredient_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    {
+        Ok(held) => held,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+        }
+    };
+
+    for receipt in held {
+        let Some(org_id) = receipt.org_id else { continue };
+        let quantity = Quantity::from_parts(receipt.quantity, receipt.quantity_unit.as_deref());
+        record_receipt(conn, receipt.id, org_id, ingredient_id, quantity).await?;
+    }
+    Ok(())
+}
+
+// Replace the ledger consumption for a batch with its current ingredient amounts
+// and return the lots that were used without ever being stocked
+async fn record_batch_consumption(
+    conn: &mut PgConnection,
+    batch_id: i32,
+    batch: &BatchInput,
+    ingredient_units: &[Unit],
+) -> Result<Vec<i32>, HttpResponse> {
+    if let Err(e) = sqlx::query!("DELETE FROM inventory_transactions WHERE batch_id = $1", batch_id)
+        .execute(&mut *conn)
+        .await
+    {
+        eprintln!("Database error: {}", e);
+        return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+    }
+
+    let mut untracked = Vec::new();
+    for (index, ingredient_id) in batch.ingredients.iter().enumerate() {
+        let on_hand = record_inventory_movement(conn, InventoryMovement {
+            org_id: batch.org_id,
+            ingredient_id: *ingredient_id,
+            kind: "consumption",
+            change: Quantity::new(-batch.amount_ingredients[index], ingredient_units[index]),
+            reason: None,
+            batch_id: Some(batch_id),
+            receiving_log_id: None,
+        })
+        .await?;
+        if on_hand.is_none() {
+            untracked.push(*ingredient_id);
+        }
+    }
+
+    Ok(untracked)
+}
+
+// Inventory endpoints
+async fn create_inventory_adjustment(
+    adjustment: web::Json<InventoryAdjustmentInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if adjustment.reason.trim().is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "A reason is required"}));
+    }
+
+    let change = match adjustment.kind {
+        AdjustmentKind::Adjustment if !adjustment.quantity.is_zero() => adjustment.quantity,
+        AdjustmentKind::Waste if adjustment.quantity > Decimal::ZERO => -adjustment.quantity,
+        AdjustmentKind::Adjustment => {
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": "Adjustment quantity cannot be zero"}));
+        }
+        AdjustmentKind::Waste => {
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": "Waste quantity must be greater than zero"}));
+        }
+    };
+
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let lot = match sqlx::query!(
+        "SELECT lotcode, name FROM ingredients WHERE id = $1 AND org_id = $2",
+        adjustment.ingredient_id,
+        adjustment.org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(lot)) => lot,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let kind = match adjustment.kind {
+        AdjustmentKind::Adjustment => "adjustment",
+        AdjustmentKind::Waste => "waste",
+    };
+    let on_hand = match record_inventory_movement(&mut tx, InventoryMovement {
+        org_id: adjustment.org_id,
+        ingredient_id: adjustment.ingredient_id,
+        kind,
+        change: Quantity::new(change, adjustment.unit),
+        reason: Some(adjustment.reason.trim()),
+        batch_id: None,
+        receiving_log_id: None,
+    })
+    .await
+    {
+        Ok(on_hand) => on_hand,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(LotOnHand {
+        ingredient_id: adjustment.ingredient_id,
+        lotcode: lot.lotcode,
+        name: lot.name,
+        on_hand,
+    })
+}
+
+// On-hand stock of every ingredient lot in the organization
+async fn get_lot_inventory(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query!(
+        "SELECT i.id, i.lotcode, i.name, i.inventory_unit, COALESCE(SUM(t.quantity), 0) as \"on_hand!\"
+         FROM ingredients i LEFT JOIN inventory_transactions t ON t.ingredient_id = i.id
+         WHERE i.org_id = $1
+         GROUP BY i.id ORDER BY i.name, i.id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
     .await
     {
-        Ok(Some(org)) => HttpResponse::Ok().json(org),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Ok(records) => {
+            let lots: Vec<LotOnHand> = records.into_iter().map(|record| LotOnHand {
+                ingredient_id: record.id,
+                lotcode: record.lotcode,
+                name: record.name,
+                on_hand: Quantity::from_parts(Some(record.on_hand), record.inventory_unit.as_deref()),
+            }).collect();
+            HttpResponse::Ok().json(lots)
+        }
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch inventory"}))
         }
     }
 }
 
-// async fn get_all_organizations(
-//     data: web::Data<AppState>,
-// ) -> impl Responder {
-//     match sqlx::query_as!(
-//         Organization,
-//         "SELECT id, name, email FROM organizations"
-//     )
-//     .fetch_all(&data.db_pool)
-//     .await
-//     {
-//         Ok(orgs) => HttpResponse::Ok().json(orgs),
-//         Err(e) => {
-//             eprintln!("Database error: {}", e);
-//             HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
-//         }
-//     }
-// }
-
-async fn update_organization(
+// On-hand stock and ledger history of one ingredient lot
+async fn get_lot_inventory_detail(
+    req: HttpRequest,
     path: web::Path<i32>,
-    org: web::Json<Organization>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
-    
-    match sqlx::query!(
-        "UPDATE organizations SET name = $1, email = $2 WHERE id = $3 RETURNING id",
-        org.name,
-        org.email,
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let lot = match sqlx::query!(
+        "SELECT i.id, i.org_id, i.lotcode, i.name, i.inventory_unit, COALESCE(SUM(t.quantity), 0) as \"on_hand!\"
+         FROM ingredients i LEFT JOIN inventory_transactions t ON t.ingredient_id = i.id
+         WHERE i.id = $1
+         GROUP BY i.id",
         id
     )
     .fetch_optional(&data.db_pool)
     .await
     {
+        Ok(Some(record)) if record.org_id == Some(auth_org_id) => record,
         Ok(Some(_)) => {
-            let updated_org = Organization {
-                id: Some(id),
-                name: org.name.clone(),
-                email: org.email.clone(),
-            };
-            HttpResponse::Ok().json(updated_org)
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "You don't have permission to access this ingredient"
+            }));
         }
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
-    }
+    };
+
+    let transactions = match sqlx::query_as!(
+        InventoryTransaction,
+        "SELECT id, ingredient_id, kind, quantity, unit, reason, batch_id, receiving_log_id,
+         created_at::text as \"created_at!\"
+         FROM inventory_transactions WHERE ingredient_id = $1 ORDER BY created_at, id",
+        id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(transactions) => transactions,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    HttpResponse::Ok().json(LotInventory {
+        lot: LotOnHand {
+            ingredient_id: lot.id,
+            lotcode: lot.lotcode,
+            name: lot.name,
+            on_hand: Quantity::from_parts(Some(lot.on_hand), lot.inventory_unit.as_deref()),
+        },
+        transactions,
+    })
 }
 
-async fn delete_organization(
-    path: web::Path<i32>,
+// On-hand stock per ingredient, summed across stock-tracked lots. Lots kept in
+// different units are reported separately.
+async fn get_ingredient_inventory(
+    req: HttpRequest,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    let id = path.into_inner();
-    
-    match sqlx::query!("DELETE FROM organizations WHERE id = $1 RETURNING id", id)
-        .fetch_optional(&data.db_pool)
-        .await
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query!(
+        "SELECT i.name, i.inventory_unit as \"unit!\", COALESCE(SUM(t.quantity), 0) as \"on_hand!\",
+         COUNT(DISTINCT i.id) as \"lots!\"
+         FROM ingredients i LEFT JOIN inventory_transactions t ON t.ingredient_id = i.id
+         WHERE i.org_id = $1 AND i.inventory_unit IS NOT NULL
+         GROUP BY i.name, i.inventory_unit ORDER BY i.name, i.inventory_unit",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
     {
-        Ok(Some(_)) => HttpResponse::NoContent().finish(),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Organization not found"})),
+        Ok(records) => {
+            let ingredients: Vec<IngredientOnHand> = records.into_iter()
+                .filter_map(|record| {
+                    Quantity::from_parts(Some(record.on_hand), Some(&record.unit)).map(|on_hand| IngredientOnHand {
+                        name: record.name,
+                        on_hand,
+                        lots: record.lots,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(ingredients)
+        }
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch inventory"}))
         }
     }
 }
 
-// Employee endpoints
-async fn create_employee(
-    employee: web::Json<Employee>,
+// Validate the formulation parts of a recipe payload
+fn validate_recipe_input(recipe: &RecipeInput) -> Result<(), String> {
+    if !recipe.amount_ingredients.is_empty() && recipe.amount_ingredients.len() != recipe.ingredients.len() {
+        return Err("Ingredients and amounts must have the same length".to_string());
+    }
+    if !recipe.amount_ingredients.is_empty() && recipe.ingredient_units.len() != recipe.ingredients.len() {
+        return Err("Each ingredient amount needs a unit".to_string());
+    }
+    if recipe.amount_ingredients.iter().any(|amount| amount.is_sign_negative()) {
+        return Err("Ingredient amounts cannot be negative".to_string());
+    }
+    let mut ingredient_ids = recipe.ingredients.clone();
+    ingredient_ids.sort_unstable();
+    ingredient_ids.dedup();
+    if ingredient_ids.len() != recipe.ingredients.len() {
+        return Err("Each ingredient can only be listed once".to_string());
+    }
+    if let Some(yield_quantity) = recipe.yield_quantity {
+        if yield_quantity <= Decimal::ZERO {
+            return Err("Yield quantity must be greater than zero".to_string());
+        }
+        if recipe.yield_unit.is_none() {
+            return Err("Yield quantity needs a unit".to_string());
+        }
+    }
+
+    let mut step_numbers: Vec<i32> = recipe.steps.iter().map(|step| step.step_number).collect();
+    if step_numbers.iter().any(|number| *number < 1) {
+        return Err("Step numbers must start at 1".to_string());
+    }
+    step_numbers.sort_unstable();
+    step_numbers.dedup();
+    if step_numbers.len() != recipe.steps.len() {
+        return Err("Step numbers must be unique".to_string());
+    }
+
+    if let Some(gtin) = &recipe.gtin {
+        gs1::normalize_gtin(gtin).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+// The recipe's GTIN as 14 digits, once validated
+fn recipe_gtin(recipe: &RecipeInput) -> Option<String> {
+    recipe.gtin.as_deref().and_then(|gtin| gs1::normalize_gtin(gtin).ok())
+}
+
+// Insert the ingredient lines and process steps of a recipe
+async fn insert_recipe_formulation(
+    conn: &mut PgConnection,
+    recipe_id: i32,
+    recipe: &RecipeInput,
+) -> Result<(), sqlx::Error> {
+    for (index, ingredient_id) in recipe.ingredients.iter().enumerate() {
+        sqlx::query!(
+            "INSERT INTO recipe_ingredients (recipe_id, ingredient_id, quantity, unit, position)
+             VALUES ($1, $2, $3, $4, $5)",
+            recipe_id,
+            ingredient_id,
+            recipe.amount_ingredients.get(index).copied(),
+            recipe.ingredient_units.get(index).map(|unit| unit.symbol()),
+            index as i32
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for step in &recipe.steps {
+        sqlx::query!(
+            "INSERT INTO recipe_steps (recipe_id, step_number, instruction) VALUES ($1, $2, $3)",
+            recipe_id,
+            step.step_number,
+            step.instruction
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Fetch the ingredient lines and ordered process steps of a recipe
+async fn fetch_recipe_formulation(
+    pool: &Pool<Postgres>,
+    recipe_id: i32,
+) -> Result<(Vec<RecipeIngredientLine>, Vec<RecipeStep>), sqlx::Error> {
+    let lines = sqlx::query_as!(
+        RecipeIngredientLine,
+        "SELECT ingredient_id as \"ingredient_id!\", quantity, unit
+         FROM recipe_ingredients WHERE recipe_id = $1 ORDER BY position, ingredient_id",
+        recipe_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let steps = sqlx::query_as!(
+        RecipeStep,
+        "SELECT step_number, instruction FROM recipe_steps WHERE recipe_id = $1 ORDER BY step_number",
+        recipe_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((lines, steps))
+}
+
+// Allergen profile of a recipe's current ingredient lines
+async fn fetch_recipe_allergens(
+    pool: &Pool<Postgres>,
+    recipe_id: i32,
+) -> Result<Vec<AllergenDeclaration>, sqlx::Error> {
+    let records = sqlx::query!(
+        "SELECT ia.allergen, ia.presence FROM recipe_ingredients ri
+         JOIN ingredient_allergens ia ON ia.ingredient_id = ri.ingredient_id
+         WHERE ri.recipe_id = $1",
+        recipe_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(allergens::merge_profile(records.into_iter().map(|record| {
+        AllergenDeclaration::new(&record.allergen, Presence::parse(&record.presence))
+    })))
+}
+
+// Record the formulation in `recipe` as the next immutable version of a recipe
+async fn snapshot_recipe_version(
+    conn: &mut PgConnection,
+    recipe_id: i32,
+    recipe: &RecipeInput,
+) -> Result<i32, sqlx::Error> {
+    let version = sqlx::query!(
+        "UPDATE recipes SET current_version = current_version + 1 WHERE id = $1 RETURNING current_version",
+        recipe_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .current_version;
+
+    let version_id = sqlx::query!(
+        "INSERT INTO recipe_versions (recipe_id, version, name, description, yield_quantity, yield_unit)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        recipe_id,
+        version,
+        recipe.name,
+        recipe.description,
+        recipe.yield_quantity,
+        recipe.yield_unit.map(|unit| unit.symbol())
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .id;
+
+    for (index, ingredient_id) in recipe.ingredients.iter().enumerate() {
+        sqlx::query!(
+            "INSERT INTO recipe_version_ingredients (version_id, ingredient_id, quantity, unit, position)
+             VALUES ($1, $2, $3, $4, $5)",
+            version_id,
+            ingredient_id,
+            recipe.amount_ingredients.get(index).copied(),
+            recipe.ingredient_units.get(index).map(|unit| unit.symbol()),
+            index as i32
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for step in &recipe.steps {
+        sqlx::query!(
+            "INSERT INTO recipe_version_steps (version_id, step_number, instruction) VALUES ($1, $2, $3)",
+            version_id,
+            step.step_number,
+            step.instruction
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(version)
+}
+
+// Fetch a single version of a recipe with its ingredient lines and steps
+async fn fetch_recipe_version(
+    pool: &Pool<Postgres>,
+    recipe_id: i32,
+    version: i32,
+) -> Result<Option<RecipeVersion>, sqlx::Error> {
+    let record = match sqlx::query!(
+        "SELECT id, recipe_id, version, name, description, yield_quantity, yield_unit, created_at::text as created_at
+         FROM recipe_versions WHERE recipe_id = $1 AND version = $2",
+        recipe_id,
+        version
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let ingredients = sqlx::query_as!(
+        RecipeIngredientLine,
+        "SELECT ingredient_id, quantity, unit
+         FROM recipe_version_ingredients WHERE version_id = $1 ORDER BY position, ingredient_id",
+        record.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let steps = sqlx::query_as!(
+        RecipeStep,
+        "SELECT step_number, instruction FROM recipe_version_steps WHERE version_id = $1 ORDER BY step_number",
+        record.id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(RecipeVersion {
+        recipe_id: record.recipe_id,
+        version: record.version,
+        name: record.name,
+        description: record.description,
+        yield_quantity: record.yield_quantity,
+        yield_unit: record.yield_unit,
+        created_at: record.created_at.unwrap_or_default(),
+        ingredients,
+        steps,
+    }))
+}
+
+// Compare two versions of the same recipe
+fn diff_recipe_versions(from: &RecipeVersion, to: &RecipeVersion) -> RecipeDiff {
+    let mut changed_fields = Vec::new();
+    let fields = [
+        ("name", serde_json::json!(from.name), serde_json::json!(to.name)),
+        ("description", serde_json::json!(from.description), serde_json::json!(to.description)),
+        ("yield_quantity", serde_json::json!(from.yield_quantity), serde_json::json!(to.yield_quantity)),
+        ("yield_unit", serde_json::json!(from.yield_unit), serde_json::json!(to.yield_unit)),
+    ];
+    for (field, old, new) in fields {
+        if old != new {
+            changed_fields.push(FieldChange { field: field.to_string(), from: old, to: new });
+        }
+    }
+
+    let mut added_ingredients = Vec::new();
+    let mut changed_ingredients = Vec::new();
+    for line in &to.ingredients {
+        match from.ingredients.iter().find(|old| old.ingredient_id == line.ingredient_id) {
+            None => added_ingredients.push(line.clone()),
+            Some(old) if old != line => changed_ingredients.push(IngredientLineChange {
+                ingredient_id: line.ingredient_id,
+                from: old.clone(),
+                to: line.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    let removed_ingredients = from.ingredients.iter()
+        .filter(|old| !to.ingredients.iter().any(|line| line.ingredient_id == old.ingredient_id))
+        .cloned()
+        .collect();
+
+    let mut added_steps = Vec::new();
+    let mut changed_steps = Vec::new();
+    for step in &to.steps {
+        match from.steps.iter().find(|old| old.step_number == step.step_number) {
+            None => added_steps.push(step.clone()),
+            Some(old) if old.instruction != step.instruction => changed_steps.push(StepChange {
+                step_number: step.step_number,
+                from: old.instruction.clone(),
+                to: step.instruction.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    let removed_steps = from.steps.iter()
+        .filter(|old| !to.steps.iter().any(|step| step.step_number == old.step_number))
+        .cloned()
+        .collect();
+
+    RecipeDiff {
+        recipe_id: to.recipe_id,
+        from_version: from.version,
+        to_version: to.version,
+        changed_fields,
+        added_ingredients,
+        removed_ingredients,
+        changed_ingredients,
+        added_steps,
+        removed_steps,
+        changed_steps,
+    }
+}
+
+// Recipe endpoints
+async fn create_recipe(
+    recipe: web::Json<RecipeInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    match sqlx::query!(
-        "INSERT INTO employees (name, role, org_id) VALUES ($1, $2, $3) RETURNING id",
-        employee.name,
-        employee.role,
-        employee.org_id
+    // Parse the date string to NaiveDate
+    let date_made = match NaiveDate::parse_from_str(&recipe.date_made, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid date format. Use YYYY-MM-DD"
+            }));
+        }
+    };
+
+    if let Err(message) = validate_recipe_input(&recipe) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Insert the recipe
+    let recipe_id = match sqlx::query!(
+        "INSERT INTO recipes (lotcode, name, date_made, org_id, description, yield_quantity, yield_unit, gtin)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        recipe.lotcode,
+        recipe.name,
+        date_made,
+        recipe.org_id,
+        recipe.description,
+        recipe.yield_quantity,
+        recipe.yield_unit.map(|unit| unit.symbol()),
+        recipe_gtin(&recipe)
     )
-    .fetch_one(&data.db_pool)
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(record) => {
-            let created_employee = Employee {
-                id: Some(record.id),
-                name: employee.name.clone(),
-                role: employee.role.clone(),
-                org_id: employee.org_id,
-            };
-            HttpResponse::Created().json(created_employee)
+        Ok(record) => record.id,
+        Err(e) => {
+            eprintln!("Failed to create recipe: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create recipe"}));
         }
+    };
+
+    // Insert recipe-ingredient relationships and process steps
+    if let Err(e) = insert_recipe_formulation(&mut tx, recipe_id, &recipe).await {
+        eprintln!("Failed to store recipe formulation: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create recipe"}));
+    }
+
+    // Record the formulation as version 1
+    let version = match snapshot_recipe_version(&mut tx, recipe_id, &recipe).await {
+        Ok(version) => version,
         Err(e) => {
-            eprintln!("Failed to create employee: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create employee"}))
+            eprintln!("Failed to record recipe version: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create recipe"}));
         }
+    };
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
     }
+
+    let allergens = match fetch_recipe_allergens(&data.db_pool, recipe_id).await {
+        Ok(allergens) => allergens,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Return the created recipe
+    let created_recipe = Recipe {
+        id: Some(recipe_id),
+        lotcode: recipe.lotcode.clone(),
+        name: recipe.name.clone(),
+        date_made: recipe.date_made.clone(),
+        org_id: recipe.org_id,
+        ingredients: recipe.ingredients.clone(),
+        amount_ingredients: recipe.ingredients.iter().enumerate()
+            .map(|(index, _)| recipe.amount_ingredients.get(index).copied())
+            .collect(),
+        ingredient_units: recipe.ingredients.iter().enumerate()
+            .map(|(index, _)| recipe.ingredient_units.get(index).map(|unit| unit.to_string()))
+            .collect(),
+        yield_quantity: recipe.yield_quantity,
+        yield_unit: recipe.yield_unit.map(String::from),
+        steps: recipe.steps.clone(),
+        description: Some(recipe.description.clone()),
+        version,
+        gtin: recipe_gtin(&recipe),
+        allergens,
+    };
+
+    HttpResponse::Created().json(created_recipe)
 }
 
-async fn get_employee(
+async fn get_recipe(
     req: HttpRequest,
     path: web::Path<i32>,
     data: web::Data<AppState>,
@@ -370,8 +9473,8 @@ async fn get_employee(
         Some(header) => {
             match header.to_str() {
                 Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
                     } else {
                         return HttpResponse::Unauthorized().json(serde_json::json!({
                             "error": "Invalid authorization header format"
@@ -404,8 +9507,11 @@ async fn get_employee(
     
     let auth_org_id = claims.org_id;
     
-    match sqlx::query!(
-        "SELECT id, name, role, org_id FROM employees WHERE id = $1",
+    // Get the recipe
+    let record = match sqlx::query!(
+        "SELECT id, lotcode, name, date_made::text as date_made, org_id, description, yield_quantity, yield_unit,
+         current_version, gtin
+         FROM recipes WHERE id = $1",
         id
     )
     .fetch_optional(&data.db_pool)
@@ -416,27 +9522,56 @@ async fn get_employee(
             // as the requested resource
             if record.org_id.unwrap_or(0) != auth_org_id {
                 return HttpResponse::Forbidden().json(serde_json::json!({
-                    "error": "You don't have permission to access this employee"
+                    "error": "You don't have permission to access this recipe"
                 }));
             }
-            
-            let employee = Employee {
-                id: Some(record.id),
-                name: record.name,
-                role: record.role,
-                org_id: record.org_id.unwrap_or(0),
-            };
-            HttpResponse::Ok().json(employee)
+            record
         },
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Employee not found"})),
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
-    }
+    };
+
+    // Get the ingredient lines and process steps
+    let (lines, steps) = match fetch_recipe_formulation(&data.db_pool, id).await {
+        Ok(formulation) => formulation,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let allergens = match fetch_recipe_allergens(&data.db_pool, id).await {
+        Ok(allergens) => allergens,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let recipe = Recipe {
+        id: Some(record.id),
+        lotcode: record.lotcode,
+        name: record.name,
+        date_made: record.date_made.unwrap_or_default(),
+        org_id: record.org_id.unwrap_or(0),
+        ingredients: lines.iter().map(|line| line.ingredient_id).collect(),
+        amount_ingredients: lines.iter().map(|line| line.quantity).collect(),
+        ingredient_units: lines.into_iter().map(|line| line.unit).collect(),
+        yield_quantity: record.yield_quantity,
+        yield_unit: record.yield_unit,
+        steps,
+        description: record.description,
+        version: record.current_version,
+        gtin: record.gtin,
+        allergens,
+    };
+    HttpResponse::Ok().json(recipe)
 }
 
-async fn get_all_employees(
+async fn get_all_recipes(
     req: HttpRequest,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -445,8 +9580,8 @@ async fn get_all_employees(
         Some(header) => {
             match header.to_str() {
                 Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
                     } else {
                         return HttpResponse::Unauthorized().json(serde_json::json!({
                             "error": "Invalid authorization header format"
@@ -479,77 +9614,343 @@ async fn get_all_employees(
     
     let org_id = claims.org_id;
 
-    match sqlx::query!(
-        "SELECT id, name, role, org_id FROM employees WHERE org_id = $1",
+    let recipe_records = match sqlx::query!(
+        "SELECT r.id, r.lotcode, r.name, r.date_made::text as date_made, r.org_id, r.description,
+         r.yield_quantity, r.yield_unit, r.current_version, r.gtin
+         FROM recipes r WHERE r.org_id = $1",
         org_id
     )
     .fetch_all(&data.db_pool)
     .await
     {
-        Ok(records) => {
-            let employees: Vec<Employee> = records.into_iter().map(|record| {
-                Employee {
-                    id: Some(record.id),
-                    name: record.name,
-                    role: record.role,
-                    org_id: record.org_id.unwrap_or(0),
-                }
-            }).collect();
-            HttpResponse::Ok().json(employees)
-        },
+        Ok(records) => records,
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
+    };
+
+    // For each recipe, get its ingredient lines and process steps
+    let mut recipes = Vec::new();
+    for record in recipe_records {
+        let (lines, steps) = match fetch_recipe_formulation(&data.db_pool, record.id).await {
+            Ok(formulation) => formulation,
+            Err(e) => {
+                eprintln!("Database error when fetching formulation for recipe {}: {}", record.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch recipes"}));
+            }
+        };
+
+        let allergens = match fetch_recipe_allergens(&data.db_pool, record.id).await {
+            Ok(allergens) => allergens,
+            Err(e) => {
+                eprintln!("Database error when fetching allergens for recipe {}: {}", record.id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch recipes"}));
+            }
+        };
+
+        recipes.push(Recipe {
+            id: Some(record.id),
+            lotcode: record.lotcode,
+            name: record.name,
+            date_made: record.date_made.unwrap_or_default(),
+            org_id: record.org_id.unwrap_or(0),
+            ingredients: lines.iter().map(|line| line.ingredient_id).collect(),
+            amount_ingredients: lines.iter().map(|line| line.quantity).collect(),
+            ingredient_units: lines.into_iter().map(|line| line.unit).collect(),
+            yield_quantity: record.yield_quantity,
+            yield_unit: record.yield_unit,
+            steps,
+            description: record.description,
+            version: record.current_version,
+            gtin: record.gtin,
+            allergens,
+        });
     }
+
+    HttpResponse::Ok().json(recipes)
 }
 
-async fn update_employee(
+async fn update_recipe(
     path: web::Path<i32>,
-    employee: web::Json<Employee>,
+    recipe: web::Json<RecipeInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
     
-    match sqlx::query!(
-        "UPDATE employees SET name = $1, role = $2, org_id = $3 WHERE id = $4 RETURNING id",
-        employee.name,
-        employee.role,
-        employee.org_id,
+    // Parse the date string to NaiveDate
+    let date_made = match NaiveDate::parse_from_str(&recipe.date_made, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid date format. Use YYYY-MM-DD"
+            }));
+        }
+    };
+
+    if let Err(message) = validate_recipe_input(&recipe) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": message}));
+    }
+    
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Update the recipe
+    let update_result = sqlx::query!(
+        "UPDATE recipes SET lotcode = $1, name = $2, date_made = $3, org_id = $4, description = $5,
+         yield_quantity = $6, yield_unit = $7, gtin = $8
+         WHERE id = $9 RETURNING id",
+        recipe.lotcode,
+        recipe.name,
+        date_made,
+        recipe.org_id,
+        recipe.description,
+        recipe.yield_quantity,
+        recipe.yield_unit.map(|unit| unit.symbol()),
+        recipe_gtin(&recipe),
         id
     )
-    .fetch_optional(&data.db_pool)
-    .await
-    {
+    .fetch_optional(&mut *tx)
+    .await;
+
+    match update_result {
         Ok(Some(_)) => {
-            let updated_employee = Employee {
+            // Delete existing recipe-ingredient relationships
+            if let Err(e) = sqlx::query!("DELETE FROM recipe_ingredients WHERE recipe_id = $1", id)
+                .execute(&mut *tx)
+                .await
+            {
+                eprintln!("Failed to delete ingredient relations: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update recipe"}));
+            }
+
+            // Delete existing process steps
+            if let Err(e) = sqlx::query!("DELETE FROM recipe_steps WHERE recipe_id = $1", id)
+                .execute(&mut *tx)
+                .await
+            {
+                eprintln!("Failed to delete recipe steps: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update recipe"}));
+            }
+
+            // Insert new recipe-ingredient relationships and process steps
+            if let Err(e) = insert_recipe_formulation(&mut tx, id, &recipe).await {
+                eprintln!("Failed to store recipe formulation: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update recipe"}));
+            }
+
+            // Every edit becomes a new version; earlier versions are left untouched
+            let version = match snapshot_recipe_version(&mut tx, id, &recipe).await {
+                Ok(version) => version,
+                Err(e) => {
+                    eprintln!("Failed to record recipe version: {}", e);
+                    let _ = tx.rollback().await;
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update recipe"}));
+                }
+            };
+
+            // Commit the transaction
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+
+            let allergens = match fetch_recipe_allergens(&data.db_pool, id).await {
+                Ok(allergens) => allergens,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+
+            // Return the updated recipe
+            let updated_recipe = Recipe {
                 id: Some(id),
-                name: employee.name.clone(),
-                role: employee.role.clone(),
-                org_id: employee.org_id,
+                lotcode: recipe.lotcode.clone(),
+                name: recipe.name.clone(),
+                date_made: recipe.date_made.clone(),
+                org_id: recipe.org_id,
+                ingredients: recipe.ingredients.clone(),
+                amount_ingredients: recipe.ingredients.iter().enumerate()
+                    .map(|(index, _)| recipe.amount_ingredients.get(index).copied())
+                    .collect(),
+                ingredient_units: recipe.ingredients.iter().enumerate()
+                    .map(|(index, _)| recipe.ingredient_units.get(index).map(|unit| unit.to_string()))
+                    .collect(),
+                yield_quantity: recipe.yield_quantity,
+                yield_unit: recipe.yield_unit.map(String::from),
+                steps: recipe.steps.clone(),
+                description: Some(recipe.description.clone()),
+                version,
+                gtin: recipe_gtin(&recipe),
+                allergens,
             };
-            HttpResponse::Ok().json(updated_employee)
+            HttpResponse::Ok().json(updated_recipe)
+        },
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"}))
+        },
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
         }
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Employee not found"})),
+    }
+}
+
+async fn delete_recipe(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Delete recipe-ingredient relationships first
+    if let Err(e) = sqlx::query!("DELETE FROM recipe_ingredients WHERE recipe_id = $1", id)
+        .execute(&mut *tx)
+        .await
+    {
+        eprintln!("Failed to delete ingredient relations: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete recipe"}));
+    }
+
+    // Delete the recipe
+    match sqlx::query!("DELETE FROM recipes WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(_)) => {
+            // Commit the transaction
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+            HttpResponse::NoContent().finish()
+        },
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"}))
+        },
+        Err(e) => {
+            let _ = tx.rollback().await;
+            // Versions pinned by batches must stay readable
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Recipe has versions used by batches and cannot be deleted"
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+// Look up the organization owning a recipe, mapping failures to responses
+async fn recipe_org_check(
+    pool: &Pool<Postgres>,
+    recipe_id: i32,
+    auth_org_id: i32,
+) -> Result<(), HttpResponse> {
+    match sqlx::query!("SELECT org_id FROM recipes WHERE id = $1", recipe_id)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(Some(record)) if record.org_id.unwrap_or(0) == auth_org_id => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this recipe"
+        }))),
+        Ok(None) => Err(HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"}))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+// List every version of a recipe, oldest first
+async fn get_recipe_versions(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if let Err(response) = recipe_org_check(&data.db_pool, id, auth_org_id).await {
+        return response;
+    }
+
+    let version_numbers = match sqlx::query!(
+        "SELECT version FROM recipe_versions WHERE recipe_id = $1 ORDER BY version",
+        id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => records.into_iter().map(|r| r.version).collect::<Vec<i32>>(),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let mut versions = Vec::new();
+    for version in version_numbers {
+        match fetch_recipe_version(&data.db_pool, id, version).await {
+            Ok(Some(recipe_version)) => versions.push(recipe_version),
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Database error when fetching version {} of recipe {}: {}", version, id, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
         }
     }
+
+    HttpResponse::Ok().json(versions)
 }
 
-async fn delete_employee(
-    path: web::Path<i32>,
+async fn get_recipe_version(
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    let id = path.into_inner();
-    
-    match sqlx::query!("DELETE FROM employees WHERE id = $1 RETURNING id", id)
-        .fetch_optional(&data.db_pool)
-        .await
-    {
-        Ok(Some(_)) => HttpResponse::NoContent().finish(),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Employee not found"})),
+    let (id, version) = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    if let Err(response) = recipe_org_check(&data.db_pool, id, auth_org_id).await {
+        return response;
+    }
+
+    match fetch_recipe_version(&data.db_pool, id, version).await {
+        Ok(Some(recipe_version)) => HttpResponse::Ok().json(recipe_version),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe version not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
             HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
@@ -557,206 +9958,483 @@ async fn delete_employee(
     }
 }
 
-// Ingredient endpoints
-async fn create_ingredient(
-    ingredient: web::Json<IngredientInput>,
+// Show what changed between two versions of a recipe
+async fn diff_recipe(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<RecipeDiffQuery>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    // Parse the date string to NaiveDate
-    let date = match NaiveDate::parse_from_str(&ingredient.date, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
-            }));
-        }
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
 
-    match sqlx::query!(
-        "INSERT INTO ingredients (lotcode, name, date, org_id) VALUES ($1, $2, $3, $4) RETURNING id",
-        ingredient.lotcode,
-        ingredient.name,
-        date,
-        ingredient.org_id
-    )
-    .fetch_one(&data.db_pool)
-    .await
-    {
-        Ok(record) => {
-            let created_ingredient = Ingredient {
-                id: Some(record.id),
-                lotcode: ingredient.lotcode.clone(),
-                name: ingredient.name.clone(),
-                date: ingredient.date.clone(),
-                org_id: ingredient.org_id,
-            };
-            HttpResponse::Created().json(created_ingredient)
-        }
-        Err(e) => {
-            eprintln!("Failed to create ingredient: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create ingredient"}))
+    if let Err(response) = recipe_org_check(&data.db_pool, id, auth_org_id).await {
+        return response;
+    }
+
+    let mut versions = Vec::new();
+    for version in [query.from, query.to] {
+        match fetch_recipe_version(&data.db_pool, id, version).await {
+            Ok(Some(recipe_version)) => versions.push(recipe_version),
+            Ok(None) => {
+                return HttpResponse::NotFound().json(serde_json::json!({
+                    "error": format!("Recipe version {} not found", version)
+                }));
+            },
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
         }
     }
+
+    HttpResponse::Ok().json(diff_recipe_versions(&versions[0], &versions[1]))
 }
 
-async fn get_ingredient(
+// Scale a recipe to a target yield and return the required ingredient amounts
+async fn scale_recipe(
     req: HttpRequest,
     path: web::Path<i32>,
+    scale: web::Json<ScaleRecipeRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
-    
-    // Extract auth token from request
-    let token = match req.headers().get("Authorization") {
-        Some(header) => {
-            match header.to_str() {
-                Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
-                    } else {
-                        return HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid authorization header format"
-                        }));
-                    }
-                },
-                Err(_) => {
-                    return HttpResponse::Unauthorized().json(serde_json::json!({
-                        "error": "Invalid authorization header"
-                    }));
-                }
-            }
-        },
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authorization header is missing"
-            }));
-        }
-    };
-    
-    // Verify token and get organization ID
-    let claims = match auth::verify_token(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid auth token"
-            }));
-        }
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
-    
-    let auth_org_id = claims.org_id;
-    
-    match sqlx::query!(
-        "SELECT id, lotcode, name, date::text as date, org_id FROM ingredients WHERE id = $1",
+
+    if scale.target_yield <= Decimal::ZERO {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Target yield must be greater than zero"
+        }));
+    }
+
+    let record = match sqlx::query!(
+        "SELECT id, name, org_id, yield_quantity, yield_unit FROM recipes WHERE id = $1",
         id
     )
     .fetch_optional(&data.db_pool)
     .await
     {
         Ok(Some(record)) => {
-            // Security check: Verify the authenticated user belongs to the same organization
-            // as the requested resource
             if record.org_id.unwrap_or(0) != auth_org_id {
                 return HttpResponse::Forbidden().json(serde_json::json!({
-                    "error": "You don't have permission to access this ingredient"
+                    "error": "You don't have permission to access this recipe"
                 }));
             }
-            
-            let ingredient = Ingredient {
-                id: Some(record.id),
-                lotcode: record.lotcode,
-                name: record.name,
-                date: record.date.unwrap_or_default(),
-                org_id: record.org_id.unwrap_or(0),
-            };
-            HttpResponse::Ok().json(ingredient)
+            record
         },
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let yield_quantity = match record.yield_quantity {
+        Some(quantity) if quantity > Decimal::ZERO => quantity,
+        _ => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Recipe has no declared yield"
+            }));
+        }
+    };
+
+    // Express the target in the recipe's yield unit before scaling
+    let target_yield = match (scale.unit, record.yield_unit.as_deref().map(Unit::parse)) {
+        (Some(target_unit), Some(Ok(yield_unit))) => {
+            match Quantity::new(scale.target_yield, target_unit).convert(yield_unit, None) {
+                Ok(converted) => converted.value,
+                Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+            }
+        },
+        (Some(_), _) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Recipe yield has no known unit to convert the target yield to"
+            }));
+        },
+        (None, _) => scale.target_yield,
+    };
+
+    let (lines, steps) = match fetch_recipe_formulation(&data.db_pool, id).await {
+        Ok(formulation) => formulation,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
+    };
+
+    let missing: Vec<i32> = lines.iter()
+        .filter(|line| line.quantity.is_none())
+        .map(|line| line.ingredient_id)
+        .collect();
+    if !missing.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Recipe has ingredients without quantities",
+            "ingredients": missing
+        }));
     }
+
+    let too_large = || HttpResponse::BadRequest().json(serde_json::json!({"error": "Target yield is too large"}));
+    let scale_factor = match target_yield.checked_div(yield_quantity) {
+        Some(scale_factor) => scale_factor,
+        None => return too_large(),
+    };
+    let ingredients: Option<Vec<RecipeIngredientLine>> = lines.into_iter().map(|line| {
+        let quantity = match line.quantity {
+            Some(quantity) => Some(quantity.checked_mul(scale_factor)?.round_dp(4)),
+            None => None,
+        };
+        Some(RecipeIngredientLine {
+            ingredient_id: line.ingredient_id,
+            quantity,
+            unit: line.unit,
+        })
+    }).collect();
+    let ingredients = match ingredients {
+        Some(ingredients) => ingredients,
+        None => return too_large(),
+    };
+
+    HttpResponse::Ok().json(ScaledRecipe {
+        recipe_id: record.id,
+        name: record.name,
+        yield_quantity,
+        yield_unit: record.yield_unit,
+        target_yield: scale.target_yield,
+        scale_factor: scale_factor.round_dp(6),
+        ingredients,
+        steps,
+    })
 }
 
-async fn get_all_ingredients(
+// Unit conversion endpoint
+async fn convert_quantity(
     req: HttpRequest,
+    conversion: web::Json<ConvertQuantityRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    // Extract auth token from request
-    let token = match req.headers().get("Authorization") {
-        Some(header) => {
-            match header.to_str() {
-                Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
-                    } else {
-                        return HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid authorization header format"
+    // Look up the ingredient density when converting on behalf of an ingredient
+    let density = match conversion.ingredient_id {
+        Some(ingredient_id) => {
+            let auth_org_id = match auth::org_id_from_request(&req) {
+                Ok(org_id) => org_id,
+                Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+            };
+
+            match sqlx::query!("SELECT org_id, density FROM ingredients WHERE id = $1", ingredient_id)
+                .fetch_optional(&data.db_pool)
+                .await
+            {
+                Ok(Some(record)) => {
+                    if record.org_id.unwrap_or(0) != auth_org_id {
+                        return HttpResponse::Forbidden().json(serde_json::json!({
+                            "error": "You don't have permission to access this ingredient"
                         }));
                     }
+                    record.density
                 },
-                Err(_) => {
-                    return HttpResponse::Unauthorized().json(serde_json::json!({
-                        "error": "Invalid authorization header"
-                    }));
+                Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
                 }
             }
         },
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authorization header is missing"
-            }));
-        }
-    };
-    
-    // Verify token and get organization ID
-    let claims = match auth::verify_token(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid auth token"
-            }));
-        }
+        None => None,
     };
-    
-    let org_id = claims.org_id;
 
+    match conversion.quantity.convert(conversion.to, density) {
+        Ok(converted) => HttpResponse::Ok().json(serde_json::json!({
+            "quantity": conversion.quantity,
+            "converted": converted
+        })),
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+// Find the recipe version a batch should pin, as (version id, version number).
+// Without an explicit version the recipe's current version is used; batches
+// for recipes that are not on file stay unpinned.
+async fn resolve_recipe_version(
+    conn: &mut PgConnection,
+    org_id: i32,
+    recipe_lotcode: &str,
+    version: Option<i32>,
+) -> Result<Option<(i32, i32)>, HttpResponse> {
     match sqlx::query!(
-        "SELECT id, lotcode, name, date::text as date, org_id FROM ingredients WHERE org_id = $1",
-        org_id
+        "SELECT rv.id, rv.version FROM recipes r
+         JOIN recipe_versions rv ON rv.recipe_id = r.id
+         WHERE r.org_id = $1 AND r.lotcode = $2 AND rv.version = COALESCE($3, r.current_version)
+         ORDER BY r.id DESC LIMIT 1",
+        org_id,
+        recipe_lotcode,
+        version
     )
-    .fetch_all(&data.db_pool)
+    .fetch_optional(&mut *conn)
     .await
     {
-        Ok(records) => {
-            let ingredients: Vec<Ingredient> = records.into_iter().map(|record| {
-                Ingredient {
-                    id: Some(record.id),
-                    lotcode: record.lotcode,
-                    name: record.name,
-                    date: record.date.unwrap_or_default(),
-                    org_id: record.org_id.unwrap_or(0),
-                }
-            }).collect();
-            HttpResponse::Ok().json(ingredients)
-        },
+        Ok(Some(record)) => Ok(Some((record.id, record.version))),
+        Ok(None) if version.is_some() => Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Recipe version not found"
+        }))),
+        Ok(None) => Ok(None),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch ingredients"}))
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+// Units for a batch's ingredient amounts and amount made. Ingredient amounts
+// without a unit are taken in the lot's inventory unit; a plain amount made is
+// counted in the recipe version's yield unit, or as each.
+async fn resolve_batch_units(
+    conn: &mut PgConnection,
+    batch: &BatchInput,
+    recipe_version_id: Option<i32>,
+) -> Result<(Vec<Unit>, Quantity), HttpResponse> {
+    let mut ingredient_units = Vec::with_capacity(batch.ingredients.len());
+    for (index, ingredient_id) in batch.ingredients.iter().enumerate() {
+        if let Some(unit) = batch.ingredient_units.get(index).copied().flatten() {
+            ingredient_units.push(unit);
+            continue;
+        }
+
+        let inventory_unit = match sqlx::query_scalar!(
+            "SELECT inventory_unit FROM ingredients WHERE id = $1 AND org_id = $2",
+            ingredient_id,
+            batch.org_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        {
+            Ok(inventory_unit) => inventory_unit.flatten(),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+            }
+        };
+        match inventory_unit.as_deref().and_then(|unit| Unit::parse(unit).ok()) {
+            Some(unit) => ingredient_units.push(unit),
+            None => {
+                return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("The amount of ingredient {} needs a unit", ingredient_id)
+                })));
+            }
+        }
+    }
+
+    let yield_unit = match recipe_version_id {
+        Some(version_id) => match sqlx::query_scalar!("SELECT yield_unit FROM recipe_versions WHERE id = $1", version_id)
+            .fetch_one(&mut *conn)
+            .await
+        {
+            Ok(yield_unit) => yield_unit,
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
+            }
+        },
+        None => None,
+    };
+    let default_unit = yield_unit.as_deref().and_then(|unit| Unit::parse(unit).ok()).unwrap_or(Unit::Each);
+    let amount_made = batch.amount_made.with_default_unit(default_unit).map_err(|e| {
+        HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}))
+    })?;
+
+    Ok((ingredient_units, amount_made))
+}
+
+// Allergen profile of a batch and the allergens it introduces beyond those of
+// its pinned recipe version. Unpinned batches have no recipe to compare with.
+async fn fetch_batch_allergens(
+    pool: &Pool<Postgres>,
+    batch_id: i32,
+    pinned: bool,
+) -> Result<(Vec<AllergenDeclaration>, Vec<AllergenDeclaration>), sqlx::Error> {
+    let records = sqlx::query!(
+        "SELECT 'batch' as \"source!\", ia.allergen as \"allergen!\", ia.presence as \"presence!\"
+         FROM batch_ingredients bi
+         JOIN ingredient_allergens ia ON ia.ingredient_id = bi.ingredient_id
+         WHERE bi.batch_id = $1
+         UNION ALL
+         SELECT 'recipe', ia.allergen, ia.presence
+         FROM batches b
+         JOIN recipe_version_ingredients rvi ON rvi.version_id = b.recipe_version_id
+         JOIN ingredient_allergens ia ON ia.ingredient_id = rvi.ingredient_id
+         WHERE b.id = $1",
+        batch_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut batch_declarations = Vec::new();
+    let mut recipe_declarations = Vec::new();
+    for record in records {
+        let declaration = AllergenDeclaration::new(&record.allergen, Presence::parse(&record.presence));
+        if record.source == "batch" {
+            batch_declarations.push(declaration);
+        } else {
+            recipe_declarations.push(declaration);
         }
     }
+
+    let batch_profile = allergens::merge_profile(batch_declarations);
+    let undeclared = if pinned {
+        allergens::undeclared_allergens(&batch_profile, &allergens::merge_profile(recipe_declarations))
+    } else {
+        Vec::new()
+    };
+    Ok((batch_profile, undeclared))
+}
+
+// Find the passing changeover that allows a new batch on `line`. A changeover
+// is required when the previous batch on the line contained allergens that
+// the new batch's ingredients do not.
+async fn required_changeover(
+    conn: &mut PgConnection,
+    org_id: i32,
+    line: Option<&str>,
+    ingredient_ids: &[i32],
+    batch_id: Option<i32>,
+) -> Result<Option<i32>, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let line = match line {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    // The batch started before this one on the line, in the order batches were recorded
+    let previous_batch_id = match sqlx::query!(
+        "SELECT id FROM batches WHERE org_id = $1 AND line = $2 AND ($3::int IS NULL OR id < $3)
+         ORDER BY id DESC LIMIT 1",
+        org_id,
+        line,
+        batch_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(internal_error)?
+    {
+        Some(record) => record.id,
+        None => return Ok(None),
+    };
+
+    let previous_profile = sqlx::query!(
+        "SELECT ia.allergen, ia.presence FROM batch_ingredients bi
+         JOIN ingredient_allergens ia ON ia.ingredient_id = bi.ingredient_id
+         WHERE bi.batch_id = $1",
+        previous_batch_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    let next_profile = sqlx::query!(
+        "SELECT allergen, presence FROM ingredient_allergens WHERE ingredient_id = ANY($1)",
+        ingredient_ids
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    let carried_over = allergens::changeover_allergens(
+        &allergens::merge_profile(previous_profile.into_iter().map(|record| {
+            AllergenDeclaration::new(&record.allergen, Presence::parse(&record.presence))
+        })),
+        &allergens::merge_profile(next_profile.into_iter().map(|record| {
+            AllergenDeclaration::new(&record.allergen, Presence::parse(&record.presence))
+        })),
+    );
+    if carried_over.is_empty() {
+        return Ok(None);
+    }
+
+    // A changeover already used by this batch still counts when it is updated
+    match sqlx::query!(
+        "SELECT id FROM allergen_changeovers
+         WHERE from_batch_id = $1 AND result = 'pass' AND (to_batch_id IS NULL OR to_batch_id = $2)
+         ORDER BY to_batch_id IS NOT NULL DESC, performed_at DESC LIMIT 1
+         FOR UPDATE",
+        previous_batch_id,
+        batch_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(internal_error)?
+    {
+        Some(record) => Ok(Some(record.id)),
+        None => Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "A passing allergen changeover must be recorded before the next batch on this line",
+            "line": line,
+            "previous_batch_id": previous_batch_id,
+            "allergens": carried_over
+        }))),
+    }
 }
 
+// Link the changeover to the batch that uses it, releasing any other changeover
+// the batch held. A changeover claimed by a concurrent batch is refused.
+async fn claim_changeover(
+    conn: &mut PgConnection,
+    batch_id: i32,
+    changeover_id: Option<i32>,
+) -> Result<(), HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
 
-async fn update_ingredient(
-    path: web::Path<i32>,
-    ingredient: web::Json<IngredientInput>,
+    sqlx::query!(
+        "UPDATE allergen_changeovers SET to_batch_id = NULL
+         WHERE to_batch_id = $1 AND id IS DISTINCT FROM $2",
+        batch_id,
+        changeover_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    let changeover_id = match changeover_id {
+        Some(changeover_id) => changeover_id,
+        None => return Ok(()),
+    };
+    let claimed = sqlx::query!(
+        "UPDATE allergen_changeovers SET to_batch_id = $1
+         WHERE id = $2 AND (to_batch_id IS NULL OR to_batch_id = $1) RETURNING id",
+        batch_id,
+        changeover_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(internal_error)?;
+
+    match claimed {
+        Some(_) => Ok(()),
+        None => Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "The allergen changeover has already been used by another batch"
+        }))),
+    }
+}
+
+// Batch endpoints
+async fn create_batch(
+    batch: web::Json<BatchInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    let id = path.into_inner();
-
     // Parse the date string to NaiveDate
-    let date = match NaiveDate::parse_from_str(&ingredient.date, "%Y-%m-%d") {
+    let date_made = match NaiveDate::parse_from_str(&batch.date_made, "%Y-%m-%d") {
         Ok(date) => date,
         Err(_) => {
             return HttpResponse::BadRequest().json(serde_json::json!({
@@ -764,103 +10442,199 @@ async fn update_ingredient(
             }));
         }
     };
-    
-    match sqlx::query!(
-        "UPDATE ingredients SET lotcode = $1, name = $2, date = $3, org_id = $4 WHERE id = $5 RETURNING id",
-        ingredient.lotcode,
-        ingredient.name,
-        date,
-        ingredient.org_id,
-        id
-    )
-    .fetch_optional(&data.db_pool)
-    .await
-    {
-        Ok(Some(_)) => {
-            let updated_ingredient = Ingredient {
-                id: Some(id),
-                lotcode: ingredient.lotcode.clone(),
-                name: ingredient.name.clone(),
-                date: ingredient.date.clone(),
-                org_id: ingredient.org_id,
-            };
-            HttpResponse::Ok().json(updated_ingredient)
-        }
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
+
+    // Validate ingredients and amounts have the same length
+    if batch.ingredients.len() != batch.amount_ingredients.len() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Ingredients and amounts must have the same length"
+        }));
+    }
+
+    if batch.ingredient_units.len() > batch.ingredients.len() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "There are more units than ingredient amounts"
+        }));
+    }
+
+    if batch.amount_ingredients.iter().any(|amount| amount.is_sign_negative()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Ingredient amounts cannot be negative"
+        }));
+    }
+
+    if batch.ingredients.iter().enumerate().any(|(index, id)| batch.ingredients[..index].contains(id)) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Each ingredient lot can only be listed once"
+        }));
+    }
+
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
         Err(e) => {
-            eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Pin the recipe version the batch follows
+    let recipe_version = match resolve_recipe_version(&mut tx, batch.org_id, &batch.recipe_lotcode, batch.recipe_version).await {
+        Ok(recipe_version) => recipe_version,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
         }
+    };
+
+    // Other organizations' lots and held, quarantined, rejected or expired lots cannot be used
+    if let Err(response) = check_lots_released(&mut tx, batch.org_id, &batch.ingredients).await {
+        let _ = tx.rollback().await;
+        return response;
     }
-}
 
-async fn delete_ingredient(
-    path: web::Path<i32>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    let id = path.into_inner();
-    
-    match sqlx::query!("DELETE FROM ingredients WHERE id = $1 RETURNING id", id)
-        .fetch_optional(&data.db_pool)
-        .await
-    {
-        Ok(Some(_)) => HttpResponse::NoContent().finish(),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Ingredient not found"})),
-        Err(e) => {
-            eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    let (ingredient_units, amount_made) = match resolve_batch_units(&mut tx, &batch, recipe_version.map(|(version_id, _)| version_id)).await {
+        Ok(units) => units,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
         }
+    };
+
+    if let Err(response) = check_lots_unexpired(&mut tx, &batch.ingredients, date_made, &batch.ingredients).await {
+        let _ = tx.rollback().await;
+        return response;
     }
-}
 
-// Receiving Log endpoints
-async fn create_receiving_log(
-    receiving_log: web::Json<ReceivingLogInput>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    // Parse the date string to NaiveDate
-    let date = match NaiveDate::parse_from_str(&receiving_log.date, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
-            }));
+    // Moving off an allergen on the same line needs a verified changeover
+    let changeover_id = match required_changeover(&mut tx, batch.org_id, batch.line.as_deref(), &batch.ingredients, None).await {
+        Ok(changeover_id) => changeover_id,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
         }
     };
 
-    match sqlx::query!(
-        "INSERT INTO receiving_log (lotcode, company_name, item_name, temperature, date, org_id) 
-         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
-        receiving_log.lotcode,
-        receiving_log.company_name,
-        receiving_log.item_name,
-        receiving_log.temperature,
-        date,
-        receiving_log.org_id
+    let batch_lot_code = match batch_lot_code(&batch) {
+        Some(lot_code) => lot_code.to_string(),
+        None => match generate_batch_lot_code(&mut tx, &batch, date_made).await {
+            Ok(lot_code) => lot_code,
+            Err(response) => {
+                let _ = tx.rollback().await;
+                return response;
+            }
+        },
+    };
+
+    // Insert the batch
+    let batch_id = match sqlx::query!(
+        "INSERT INTO batches (org_id, employee, recipe_lotcode, batch_lot_code, date_made, amount_made, amount_made_unit, recipe_version_id, line) 
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+        batch.org_id,
+        batch.employee,
+        batch.recipe_lotcode,
+        batch_lot_code,
+        date_made,
+        amount_made.value,
+        amount_made.unit.symbol(),
+        recipe_version.map(|(version_id, _)| version_id),
+        batch.line
     )
-    .fetch_one(&data.db_pool)
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(record) => {
-            let created_log = ReceivingLog {
-                id: Some(record.id),
-                lotcode: receiving_log.lotcode.clone(),
-                company_name: receiving_log.company_name.clone(),
-                item_name: receiving_log.item_name.clone(),
-                temperature: receiving_log.temperature.clone(),
-                date: receiving_log.date.clone(),
-                org_id: receiving_log.org_id,
-            };
-            HttpResponse::Created().json(created_log)
-        }
+        Ok(record) => record.id,
         Err(e) => {
-            eprintln!("Failed to create receiving log: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create receiving log"}))
+            let _ = tx.rollback().await;
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("Lot code {} is already used by another batch", batch_lot_code)
+                }));
+            }
+            eprintln!("Failed to create batch: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create batch"}));
+        }
+    };
+
+    // Insert batch-ingredient relationships with amounts
+    for (index, ingredient_id) in batch.ingredients.iter().enumerate() {
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO batch_ingredients (batch_id, ingredient_id, amount, unit) VALUES ($1, $2, $3, $4)",
+            batch_id,
+            ingredient_id,
+            batch.amount_ingredients[index],
+            ingredient_units[index].symbol()
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            eprintln!("Failed to link ingredient to batch: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create batch"}));
+        }
+    }
+
+    // Deduct the ingredients used from stock
+    let untracked_stock = match record_batch_consumption(&mut tx, batch_id, &batch, &ingredient_units).await {
+        Ok(untracked_stock) => untracked_stock,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    let expiry_date = match update_batch_expiry(&mut tx, batch_id).await {
+        Ok(expiry_date) => expiry_date,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
         }
+    };
+
+    // The changeover is used up by this batch
+    if let Err(response) = claim_changeover(&mut tx, batch_id, changeover_id).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
     }
+
+    // Flag allergens the recipe version does not account for
+    let (allergens, undeclared_allergens) = match fetch_batch_allergens(&data.db_pool, batch_id, recipe_version.is_some()).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Return the created batch
+    let created_batch = Batch {
+        id: Some(batch_id),
+        org_id: batch.org_id,
+        employee: batch.employee.clone(),
+        recipe_lotcode: batch.recipe_lotcode.clone(),
+        recipe_version: recipe_version.map(|(_, version)| version),
+        batch_lot_code,
+        ingredients: batch.ingredients.clone(),
+        amount_ingredients: batch.amount_ingredients.clone(),
+        ingredient_units: ingredient_units.iter().map(|unit| Some(unit.to_string())).collect(),
+        date_made: batch.date_made.clone(),
+        amount_made: Some(amount_made),
+        line: batch.line.clone(),
+        status: LotStatus::Released.as_str().to_string(),
+        expiry_date,
+        allergens,
+        undeclared_allergens,
+    };
+
+    HttpResponse::Created().json(SavedBatch { batch: created_batch, untracked_stock })
 }
 
-async fn get_receiving_log(
+async fn get_batch(
     req: HttpRequest,
     path: web::Path<i32>,
     data: web::Data<AppState>,
@@ -872,8 +10646,8 @@ async fn get_receiving_log(
         Some(header) => {
             match header.to_str() {
                 Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
                     } else {
                         return HttpResponse::Unauthorized().json(serde_json::json!({
                             "error": "Invalid authorization header format"
@@ -906,9 +10680,12 @@ async fn get_receiving_log(
     
     let auth_org_id = claims.org_id;
     
-    match sqlx::query!(
-        "SELECT id, lotcode, company_name, item_name, temperature, date::text as date, org_id 
-         FROM receiving_log WHERE id = $1",
+    // Get the basic batch information
+    let batch_record = match sqlx::query!(
+        "SELECT b.id, b.org_id, b.employee, b.recipe_lotcode, b.batch_lot_code, b.date_made::text as date_made,
+         b.amount_made, b.amount_made_unit, b.line, b.status, b.expiry_date::text, rv.version as \"recipe_version?\"
+         FROM batches b LEFT JOIN recipe_versions rv ON rv.id = b.recipe_version_id
+         WHERE b.id = $1",
         id
     )
     .fetch_optional(&data.db_pool)
@@ -919,30 +10696,72 @@ async fn get_receiving_log(
             // as the requested resource
             if record.org_id.unwrap_or(0) != auth_org_id {
                 return HttpResponse::Forbidden().json(serde_json::json!({
-                    "error": "You don't have permission to access this receiving log"
+                    "error": "You don't have permission to access this batch"
                 }));
             }
-            
-            let receiving_log = ReceivingLog {
-                id: Some(record.id),
-                lotcode: record.lotcode,
-                company_name: record.company_name,
-                item_name: record.item_name,
-                temperature: record.temperature,
-                date: record.date.unwrap_or_default(),
-                org_id: record.org_id.unwrap_or(0),
-            };
-            HttpResponse::Ok().json(receiving_log)
+            record
         },
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving log not found"})),
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
-    }
+    };
+
+    // Get the batch ingredients with amounts
+    let batch_ingredients = match sqlx::query!(
+        "SELECT bi.ingredient_id, bi.amount, bi.unit 
+         FROM batch_ingredients bi 
+         WHERE bi.batch_id = $1",
+        id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Extract ingredient ids and amounts
+    let ingredients: Vec<i32> = batch_ingredients.iter().map(|r| r.ingredient_id).collect();
+    let amount_ingredients: Vec<Decimal> = batch_ingredients.iter().map(|r| r.amount).collect();
+    let ingredient_units: Vec<Option<String>> = batch_ingredients.into_iter().map(|r| r.unit).collect();
+
+    let (allergens, undeclared_allergens) = match fetch_batch_allergens(&data.db_pool, id, batch_record.recipe_version.is_some()).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Create the complete batch object
+    let batch = Batch {
+        id: Some(batch_record.id),
+        org_id: batch_record.org_id.unwrap_or(0),
+        employee: batch_record.employee,
+        recipe_lotcode: batch_record.recipe_lotcode,
+        recipe_version: batch_record.recipe_version,
+        batch_lot_code: batch_record.batch_lot_code,
+        ingredients,
+        amount_ingredients,
+        ingredient_units,
+        date_made: batch_record.date_made.unwrap_or_default(),
+        amount_made: Quantity::from_parts(batch_record.amount_made, batch_record.amount_made_unit.as_deref()),
+        line: batch_record.line,
+        status: batch_record.status,
+        expiry_date: batch_record.expiry_date,
+        allergens,
+        undeclared_allergens,
+    };
+
+    HttpResponse::Ok().json(batch)
 }
 
-async fn get_all_receiving_logs(
+async fn get_all_batches(
     req: HttpRequest,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -951,8 +10770,8 @@ async fn get_all_receiving_logs(
         Some(header) => {
             match header.to_str() {
                 Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
                     } else {
                         return HttpResponse::Unauthorized().json(serde_json::json!({
                             "error": "Invalid authorization header format"
@@ -985,121 +10804,593 @@ async fn get_all_receiving_logs(
     
     let org_id = claims.org_id;
 
-    match sqlx::query!(
-        "SELECT id, lotcode, company_name, item_name, temperature, date::text as date, org_id 
-         FROM receiving_log WHERE org_id = $1 ORDER BY date DESC",
+    // Get all batches for this organization
+    let batch_records = match sqlx::query!(
+        "SELECT b.id, b.org_id, b.employee, b.recipe_lotcode, b.batch_lot_code, b.date_made::text as date_made,
+         b.amount_made, b.amount_made_unit, b.line, b.status, b.expiry_date::text, rv.version as \"recipe_version?\"
+         FROM batches b LEFT JOIN recipe_versions rv ON rv.id = b.recipe_version_id
+         WHERE b.org_id = $1 ORDER BY b.id DESC",
         org_id
     )
     .fetch_all(&data.db_pool)
     .await
     {
-        Ok(records) => {
-            let logs: Vec<ReceivingLog> = records.into_iter().map(|record| {
-                ReceivingLog {
-                    id: Some(record.id),
-                    lotcode: record.lotcode,
-                    company_name: record.company_name,
-                    item_name: record.item_name,
-                    temperature: record.temperature,
-                    date: record.date.unwrap_or_default(),
-                    org_id: record.org_id.unwrap_or(0),
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Create a vector to hold all batches
+    let mut batches = Vec::new();
+
+    // For each batch, get its ingredients and amounts
+    for record in batch_records {
+        let batch_id = record.id;
+
+        // Get the batch ingredients with amounts
+        let batch_ingredients = match sqlx::query!(
+            "SELECT bi.ingredient_id, bi.amount, bi.unit 
+             FROM batch_ingredients bi 
+             WHERE bi.batch_id = $1",
+            batch_id
+        )
+        .fetch_all(&data.db_pool)
+        .await
+        {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("Database error when fetching ingredients for batch {}: {}", batch_id, e);
+                continue; // Skip this batch if we can't get its ingredients
+            }
+        };
+
+        // Extract ingredient ids and amounts
+        let ingredients: Vec<i32> = batch_ingredients.iter().map(|r| r.ingredient_id).collect();
+        let amount_ingredients: Vec<Decimal> = batch_ingredients.iter().map(|r| r.amount).collect();
+        let ingredient_units: Vec<Option<String>> = batch_ingredients.into_iter().map(|r| r.unit).collect();
+
+        let (allergens, undeclared_allergens) = match fetch_batch_allergens(&data.db_pool, batch_id, record.recipe_version.is_some()).await {
+            Ok(profile) => profile,
+            Err(e) => {
+                eprintln!("Database error when fetching allergens for batch {}: {}", batch_id, e);
+                continue;
+            }
+        };
+
+        // Create the complete batch object
+        let batch = Batch {
+            id: Some(record.id),
+            org_id: record.org_id.unwrap_or(0),
+            employee: record.employee,
+            recipe_lotcode: record.recipe_lotcode,
+            recipe_version: record.recipe_version,
+            batch_lot_code: record.batch_lot_code,
+            ingredients,
+            amount_ingredients,
+            ingredient_units,
+            date_made: record.date_made.unwrap_or_default(),
+            amount_made: Quantity::from_parts(record.amount_made, record.amount_made_unit.as_deref()),
+            line: record.line,
+            status: record.status,
+            expiry_date: record.expiry_date,
+            allergens,
+            undeclared_allergens,
+        };
+
+        batches.push(batch);
+    }
+
+    HttpResponse::Ok().json(batches)
+}
+
+async fn update_batch(
+    path: web::Path<i32>,
+    batch: web::Json<BatchInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Parse the date string to NaiveDate
+    let date_made = match NaiveDate::parse_from_str(&batch.date_made, "%Y-%m-%d") {
+        Ok(date) => date,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid date format. Use YYYY-MM-DD"
+            }));
+        }
+    };
+
+    // Validate ingredients and amounts have the same length
+    if batch.ingredients.len() != batch.amount_ingredients.len() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Ingredients and amounts must have the same length"
+        }));
+    }
+
+    if batch.ingredient_units.len() > batch.ingredients.len() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "There are more units than ingredient amounts"
+        }));
+    }
+
+    if batch.amount_ingredients.iter().any(|amount| amount.is_sign_negative()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Ingredient amounts cannot be negative"
+        }));
+    }
+
+    if batch.ingredients.iter().enumerate().any(|(index, id)| batch.ingredients[..index].contains(id)) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Each ingredient lot can only be listed once"
+        }));
+    }
+
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let recipe_version = match resolve_recipe_version(&mut tx, batch.org_id, &batch.recipe_lotcode, batch.recipe_version).await {
+        Ok(recipe_version) => recipe_version,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    // Every lot on the batch must be the organization's and still released,
+    // including lots it already used
+    if let Err(response) = check_lots_released(&mut tx, batch.org_id, &batch.ingredients).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    let (ingredient_units, amount_made) = match resolve_batch_units(&mut tx, &batch, recipe_version.map(|(version_id, _)| version_id)).await {
+        Ok(units) => units,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    // Lots the batch already used may have expired since it was made; lots
+    // being added must not have. Moving date_made rechecks every lot.
+    let current = match sqlx::query!(
+        "SELECT date_made, ARRAY(SELECT ingredient_id FROM batch_ingredients WHERE batch_id = $1) as \"ingredient_ids!\"
+         FROM batches WHERE id = $1",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(current) => current,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+    let (current_date_made, current_ingredients) = match current {
+        Some(current) => (Some(current.date_made), current.ingredient_ids),
+        None => (None, Vec::new()),
+    };
+    let added_ingredients: Vec<i32> = batch.ingredients.iter()
+        .copied()
+        .filter(|ingredient_id| !current_ingredients.contains(ingredient_id))
+        .collect();
+    let checked_ingredients = if current_date_made == Some(date_made) { &added_ingredients } else { &batch.ingredients };
+    if let Err(response) = check_lots_unexpired(&mut tx, checked_ingredients, date_made, &added_ingredients).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    // Changing the lots or line can bring an allergen onto the line
+    let changeover_id = match required_changeover(&mut tx, batch.org_id, batch.line.as_deref(), &batch.ingredients, Some(id)).await {
+        Ok(changeover_id) => changeover_id,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    // Update the batch. The pinned recipe version only moves when a version is
+    // requested explicitly or the batch now follows a different recipe.
+    let update_result = sqlx::query!(
+        "UPDATE batches SET org_id = $1, employee = $2, recipe_lotcode = $3::varchar,
+         batch_lot_code = COALESCE($4, batch_lot_code), date_made = $5, amount_made = $6, amount_made_unit = $7,
+         recipe_version_id = CASE WHEN $8 OR recipe_lotcode <> $3::varchar THEN $9 ELSE recipe_version_id END,
+         line = $10
+         WHERE id = $11
+         RETURNING (SELECT version FROM recipe_versions WHERE id = batches.recipe_version_id) as recipe_version, status,
+         batch_lot_code",
+        batch.org_id,
+        batch.employee,
+        batch.recipe_lotcode,
+        batch_lot_code(&batch),
+        date_made,
+        amount_made.value,
+        amount_made.unit.symbol(),
+        batch.recipe_version.is_some(),
+        recipe_version.map(|(version_id, _)| version_id),
+        batch.line,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+
+    match update_result {
+        Ok(Some(updated)) => {
+            // Delete existing batch-ingredient relationships
+            if let Err(e) = sqlx::query!("DELETE FROM batch_ingredients WHERE batch_id = $1", id)
+                .execute(&mut *tx)
+                .await
+            {
+                eprintln!("Failed to delete ingredient relations: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update batch"}));
+            }
+
+            // Insert new batch-ingredient relationships with amounts
+            for (index, ingredient_id) in batch.ingredients.iter().enumerate() {
+                if let Err(e) = sqlx::query!(
+                    "INSERT INTO batch_ingredients (batch_id, ingredient_id, amount, unit) VALUES ($1, $2, $3, $4)",
+                    id,
+                    ingredient_id,
+                    batch.amount_ingredients[index],
+                    ingredient_units[index].symbol()
+                )
+                .execute(&mut *tx)
+                .await
+                {
+                    eprintln!("Failed to link ingredient to batch: {}", e);
+                    let _ = tx.rollback().await;
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update batch"}));
                 }
-            }).collect();
-            HttpResponse::Ok().json(logs)
+            }
+
+            // Return the previous consumption to stock and deduct the new amounts
+            let untracked_stock = match record_batch_consumption(&mut tx, id, &batch, &ingredient_units).await {
+                Ok(untracked_stock) => untracked_stock,
+                Err(response) => {
+                    let _ = tx.rollback().await;
+                    return response;
+                }
+            };
+
+            if let Err(response) = claim_changeover(&mut tx, id, changeover_id).await {
+                let _ = tx.rollback().await;
+                return response;
+            }
+
+            let expiry_date = match update_batch_expiry(&mut tx, id).await {
+                Ok(expiry_date) => expiry_date,
+                Err(response) => {
+                    let _ = tx.rollback().await;
+                    return response;
+                }
+            };
+
+            // Commit the transaction
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+
+            let (allergens, undeclared_allergens) = match fetch_batch_allergens(&data.db_pool, id, updated.recipe_version.is_some()).await {
+                Ok(profile) => profile,
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+                }
+            };
+
+            // Return the updated batch
+            let updated_batch = Batch {
+                id: Some(id),
+                org_id: batch.org_id,
+                employee: batch.employee.clone(),
+                recipe_lotcode: batch.recipe_lotcode.clone(),
+                recipe_version: updated.recipe_version,
+                batch_lot_code: updated.batch_lot_code,
+                ingredients: batch.ingredients.clone(),
+                amount_ingredients: batch.amount_ingredients.clone(),
+                ingredient_units: ingredient_units.iter().map(|unit| Some(unit.to_string())).collect(),
+                date_made: batch.date_made.clone(),
+                amount_made: Some(amount_made),
+                line: batch.line.clone(),
+                status: updated.status,
+                expiry_date,
+                allergens,
+                undeclared_allergens,
+            };
+            HttpResponse::Ok().json(SavedBatch { batch: updated_batch, untracked_stock })
+        },
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"}))
+        },
+        Err(e) => {
+            let _ = tx.rollback().await;
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({"error": "Lot code is already used by another batch"}));
+            }
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
+    }
+}
+
+async fn delete_batch(
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+    
+    // Start a transaction
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Delete batch-ingredient relationships first
+    if let Err(e) = sqlx::query!("DELETE FROM batch_ingredients WHERE batch_id = $1", id)
+        .execute(&mut *tx)
+        .await
+    {
+        eprintln!("Failed to delete ingredient relations: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete batch"}));
+    }
+
+    // Delete the batch
+    match sqlx::query!("DELETE FROM batches WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(_)) => {
+            // Commit the transaction
+            if let Err(e) = tx.commit().await {
+                eprintln!("Failed to commit transaction: {}", e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
+            HttpResponse::NoContent().finish()
+        },
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"}))
         },
         Err(e) => {
+            let _ = tx.rollback().await;
+            // Shipped batches must stay traceable
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": "Batch has been shipped and cannot be deleted"
+                }));
+            }
             eprintln!("Database error: {}", e);
             HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
         }
     }
 }
 
-async fn update_receiving_log(
-    path: web::Path<i32>,
-    receiving_log: web::Json<ReceivingLogInput>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    let id = path.into_inner();
-    
-    // Parse the date string to NaiveDate
-    let date = match NaiveDate::parse_from_str(&receiving_log.date, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
-            }));
-        }
-    };
-    
-    match sqlx::query!(
-        "UPDATE receiving_log SET lotcode = $1, company_name = $2, item_name = $3, temperature = $4, date = $5, org_id = $6 
-         WHERE id = $7 RETURNING id",
-        receiving_log.lotcode,
-        receiving_log.company_name,
-        receiving_log.item_name,
-        receiving_log.temperature,
-        date,
-        receiving_log.org_id,
-        id
+// Refuse ingredient lots that are not the organization's or not released for use
+async fn check_lots_released(
+    conn: &mut PgConnection,
+    org_id: i32,
+    ingredient_ids: &[i32],
+) -> Result<(), HttpResponse> {
+    // Lock the lots so they cannot be put on hold before the batch commits.
+    // The lock is FOR UPDATE rather than FOR SHARE because consuming the lots
+    // later in the same transaction locks them for update, and upgrading a
+    // shared lock deadlocks two batches drawing on the same lot.
+    let records = match sqlx::query!(
+        "SELECT id, lotcode, status FROM ingredients WHERE id = ANY($1) AND org_id = $2 ORDER BY id FOR UPDATE",
+        ingredient_ids,
+        org_id
     )
-    .fetch_optional(&data.db_pool)
+    .fetch_all(&mut *conn)
     .await
     {
-        Ok(Some(_)) => {
-            let updated_log = ReceivingLog {
-                id: Some(id),
-                lotcode: receiving_log.lotcode.clone(),
-                company_name: receiving_log.company_name.clone(),
-                item_name: receiving_log.item_name.clone(),
-                temperature: receiving_log.temperature.clone(),
-                date: receiving_log.date.clone(),
-                org_id: receiving_log.org_id,
-            };
-            HttpResponse::Ok().json(updated_log)
-        }
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving log not found"})),
+        Ok(records) => records,
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
         }
+    };
+
+    if let Some(missing) = ingredient_ids.iter().find(|id| !records.iter().any(|record| record.id == **id)) {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Ingredient lot {} not found", missing)
+        })));
     }
+
+    let unreleased: Vec<_> = records.into_iter().filter(|record| record.status != LotStatus::Released.as_str()).collect();
+
+    if unreleased.is_empty() {
+        return Ok(());
+    }
+
+    let lots: Vec<serde_json::Value> = unreleased.into_iter()
+        .map(|record| serde_json::json!({
+            "ingredient_id": record.id,
+            "lotcode": record.lotcode,
+            "status": record.status
+        }))
+        .collect();
+    Err(HttpResponse::Conflict().json(serde_json::json!({
+        "error": "Ingredient lots are not released for use",
+        "lots": lots
+    })))
 }
 
-async fn delete_receiving_log(
-    path: web::Path<i32>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    let id = path.into_inner();
-    
-    match sqlx::query!("DELETE FROM receiving_log WHERE id = $1 RETURNING id", id)
-        .fetch_optional(&data.db_pool)
-        .await
+// Refuse ingredient lots that expired before the batch was made. Lots being
+// drawn on now must also not have expired by today, whatever date_made says.
+async fn check_lots_unexpired(
+    conn: &mut PgConnection,
+    ingredient_ids: &[i32],
+    date_made: NaiveDate,
+    newly_used: &[i32],
+) -> Result<(), HttpResponse> {
+    let expired = match sqlx::query!(
+        "SELECT id, lotcode, expiry_date::text as \"expiry_date!\" FROM ingredients
+         WHERE id = ANY($1) AND (expiry_date < $2 OR (id = ANY($3) AND expiry_date < CURRENT_DATE)) ORDER BY id",
+        ingredient_ids,
+        date_made,
+        newly_used
+    )
+    .fetch_all(&mut *conn)
+    .await
     {
-        Ok(Some(_)) => HttpResponse::NoContent().finish(),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Receiving log not found"})),
+        Ok(records) => records,
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})));
         }
+    };
+
+    if expired.is_empty() {
+        return Ok(());
     }
+
+    let lots: Vec<serde_json::Value> = expired.into_iter()
+        .map(|record| serde_json::json!({
+            "ingredient_id": record.id,
+            "lotcode": record.lotcode,
+            "expiry_date": record.expiry_date
+        }))
+        .collect();
+    Err(HttpResponse::Conflict().json(serde_json::json!({
+        "error": "Ingredient lots are past their expiry date",
+        "lots": lots
+    })))
 }
 
-// Recipe endpoints
-async fn create_recipe(
-    recipe: web::Json<RecipeInput>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    // Parse the date string to NaiveDate
-    let date_made = match NaiveDate::parse_from_str(&recipe.date_made, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
-            }));
+// Set a batch's expiry to the earliest expiry of its ingredient lots
+async fn update_batch_expiry(conn: &mut PgConnection, batch_id: i32) -> Result<Option<String>, HttpResponse> {
+    sqlx::query_scalar!(
+        "UPDATE batches SET expiry_date = (
+             SELECT MIN(i.expiry_date) FROM batch_ingredients bi JOIN ingredients i ON i.id = bi.ingredient_id
+             WHERE bi.batch_id = $1
+         )
+         WHERE id = $1 RETURNING expiry_date::text",
+        batch_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    })
+}
+
+// Move an ingredient lot or batch to a new status and record the change.
+// `employee_id` is empty for changes made automatically.
+async fn set_lot_status(
+    conn: &mut PgConnection,
+    org_id: i32,
+    lot: LotRef,
+    status: LotStatus,
+    reason: &str,
+    employee_id: Option<i32>,
+) -> Result<LotStatusChange, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+    };
+
+    let current = match lot {
+        LotRef::Ingredient(id) => sqlx::query!("SELECT org_id, status FROM ingredients WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(internal_error)?
+            .map(|record| (record.org_id, record.status)),
+        LotRef::Batch(id) => sqlx::query!("SELECT org_id, status FROM batches WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(internal_error)?
+            .map(|record| (record.org_id, record.status)),
+    };
+
+    let from_status = match current {
+        Some((Some(lot_org_id), from_status)) if lot_org_id == org_id => from_status,
+        _ => {
+            let message = match lot {
+                LotRef::Ingredient(_) => "Ingredient not found",
+                LotRef::Batch(_) => "Batch not found",
+            };
+            return Err(HttpResponse::NotFound().json(serde_json::json!({"error": message})));
         }
     };
 
-    // Start a transaction
+    let allowed = LotStatus::parse(&from_status).is_some_and(|from| from.can_change_to(status));
+    if !allowed {
+        return Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Cannot change status from {} to {}", from_status, status.as_str())
+        })));
+    }
+
+    let (ingredient_id, batch_id) = match lot {
+        LotRef::Ingredient(id) => {
+            sqlx::query!("UPDATE ingredients SET status = $1 WHERE id = $2", status.as_str(), id)
+                .execute(&mut *conn)
+                .await
+                .map_err(internal_error)?;
+            if status == LotStatus::Released {
+                release_held_receipts(conn, id).await?;
+            }
+            (Some(id), None)
+        }
+        LotRef::Batch(id) => {
+            sqlx::query!("UPDATE batches SET status = $1 WHERE id = $2", status.as_str(), id)
+                .execute(&mut *conn)
+                .await
+                .map_err(internal_error)?;
+            (None, Some(id))
+        }
+    };
+
+    sqlx::query_as!(
+        LotStatusChange,
+        "WITH change AS (
+             INSERT INTO lot_status_changes (org_id, ingredient_id, batch_id, from_status, status, reason, employee_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, ingredient_id, batch_id, from_status, status, reason, employee_id, changed_at
+         )
+         SELECT c.id as \"id!\", c.ingredient_id, c.batch_id, c.from_status as \"from_status!\", c.status as \"status!\",
+         c.reason as \"reason!\", c.employee_id, e.name as \"employee_name?\", c.changed_at::text as \"changed_at!\"
+         FROM change c LEFT JOIN employees e ON e.id = c.employee_id",
+        org_id,
+        ingredient_id,
+        batch_id,
+        from_status,
+        status.as_str(),
+        reason,
+        employee_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(internal_error)
+}
+
+// Lot status endpoints
+async fn change_lot_status(
+    lot: LotRef,
+    input: &LotStatusInput,
+    data: &AppState,
+) -> HttpResponse {
+    if input.reason.trim().is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "A reason is required"}));
+    }
+
     let mut tx = match data.db_pool.begin().await {
         Ok(tx) => tx,
         Err(e) => {
@@ -1108,389 +11399,350 @@ async fn create_recipe(
         }
     };
 
-    // Insert the recipe
-    let recipe_id = match sqlx::query!(
-        "INSERT INTO recipes (lotcode, name, date_made, org_id, description) VALUES ($1, $2, $3, $4, $5) RETURNING id",
-        recipe.lotcode,
-        recipe.name,
-        date_made,
-        recipe.org_id,
-        recipe.description
-    )
-    .fetch_one(&mut *tx)
-    .await
+    match sqlx::query_scalar!("SELECT org_id FROM employees WHERE id = $1", input.employee_id)
+        .fetch_optional(&mut *tx)
+        .await
     {
-        Ok(record) => record.id,
+        Ok(Some(Some(org_id))) if org_id == input.org_id => {}
+        Ok(_) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Employee {} not found", input.employee_id)
+            }));
+        }
         Err(e) => {
-            eprintln!("Failed to create recipe: {}", e);
+            eprintln!("Database error: {}", e);
             let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create recipe"}));
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
-    };
+    }
 
-    // Insert recipe-ingredient relationships
-    for ingredient_id in &recipe.ingredients {
-        if let Err(e) = sqlx::query!(
-            "INSERT INTO recipe_ingredients (recipe_id, ingredient_id) VALUES ($1, $2)",
-            recipe_id,
-            ingredient_id
-        )
-        .execute(&mut *tx)
-        .await
-        {
-            eprintln!("Failed to link ingredient to recipe: {}", e);
+    let change = match set_lot_status(&mut tx, input.org_id, lot, input.status, input.reason.trim(), Some(input.employee_id)).await {
+        Ok(change) => change,
+        Err(response) => {
             let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create recipe"}));
+            return response;
         }
-    }
+    };
 
-    // Commit the transaction
     if let Err(e) = tx.commit().await {
         eprintln!("Failed to commit transaction: {}", e);
         return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
     }
 
-    // Return the created recipe
-    let created_recipe = Recipe {
-        id: Some(recipe_id),
-        lotcode: recipe.lotcode.clone(),
-        name: recipe.name.clone(),
-        date_made: recipe.date_made.clone(),
-        org_id: recipe.org_id,
-        ingredients: recipe.ingredients.clone(),
-        description: Some(recipe.description.clone()),
-    };
+    HttpResponse::Ok().json(change)
+}
 
-    HttpResponse::Created().json(created_recipe)
+async fn update_ingredient_status(
+    path: web::Path<i32>,
+    input: web::Json<LotStatusInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    change_lot_status(LotRef::Ingredient(path.into_inner()), &input, &data).await
 }
 
-async fn get_recipe(
-    req: HttpRequest,
+async fn update_batch_status(
     path: web::Path<i32>,
+    input: web::Json<LotStatusInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    let id = path.into_inner();
-    
-    // Extract auth token from request
-    let token = match req.headers().get("Authorization") {
-        Some(header) => {
-            match header.to_str() {
-                Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
-                    } else {
-                        return HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid authorization header format"
-                        }));
-                    }
-                },
-                Err(_) => {
-                    return HttpResponse::Unauthorized().json(serde_json::json!({
-                        "error": "Invalid authorization header"
-                    }));
-                }
-            }
-        },
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authorization header is missing"
-            }));
-        }
+    change_lot_status(LotRef::Batch(path.into_inner()), &input, &data).await
+}
+
+// Status history of an ingredient lot or batch, newest first
+async fn lot_status_history(
+    req: HttpRequest,
+    lot: LotRef,
+    data: &AppState,
+) -> HttpResponse {
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
-    
-    // Verify token and get organization ID
-    let claims = match auth::verify_token(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid auth token"
+
+    let (owner, ingredient_id, batch_id, name) = match lot {
+        LotRef::Ingredient(id) => (
+            sqlx::query_scalar!("SELECT org_id FROM ingredients WHERE id = $1", id).fetch_optional(&data.db_pool).await,
+            Some(id),
+            None,
+            "ingredient",
+        ),
+        LotRef::Batch(id) => (
+            sqlx::query_scalar!("SELECT org_id FROM batches WHERE id = $1", id).fetch_optional(&data.db_pool).await,
+            None,
+            Some(id),
+            "batch",
+        ),
+    };
+    match owner {
+        Ok(Some(Some(org_id))) if org_id == auth_org_id => {}
+        Ok(Some(_)) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": format!("You don't have permission to access this {}", name)
             }));
         }
-    };
-    
-    let auth_org_id = claims.org_id;
-    
-    // Get the recipe
-    match sqlx::query!(
-        "SELECT id, lotcode, name, date_made::text as date_made, org_id, description, 
-         ARRAY(SELECT ingredient_id FROM recipe_ingredients WHERE recipe_id = $1)::int[] as ingredients 
-         FROM recipes WHERE id = $1",
-        id
+        Ok(None) => {
+            let message = match lot {
+                LotRef::Ingredient(_) => "Ingredient not found",
+                LotRef::Batch(_) => "Batch not found",
+            };
+            return HttpResponse::NotFound().json(serde_json::json!({"error": message}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
+
+    match sqlx::query_as!(
+        LotStatusChange,
+        "SELECT c.id, c.ingredient_id, c.batch_id, c.from_status, c.status, c.reason, c.employee_id,
+         e.name as \"employee_name?\", c.changed_at::text as \"changed_at!\"
+         FROM lot_status_changes c LEFT JOIN employees e ON e.id = c.employee_id
+         WHERE c.ingredient_id IS NOT DISTINCT FROM $1 AND c.batch_id IS NOT DISTINCT FROM $2
+         ORDER BY c.changed_at DESC, c.id DESC",
+        ingredient_id,
+        batch_id
     )
-    .fetch_optional(&data.db_pool)
+    .fetch_all(&data.db_pool)
     .await
     {
-        Ok(Some(record)) => {
-            // Security check: Verify the authenticated user belongs to the same organization
-            // as the requested resource
-            if record.org_id.unwrap_or(0) != auth_org_id {
-                return HttpResponse::Forbidden().json(serde_json::json!({
-                    "error": "You don't have permission to access this recipe"
-                }));
-            }
-            
-            let recipe = Recipe {
-                id: Some(record.id),
-                lotcode: record.lotcode,
-                name: record.name,
-                date_made: record.date_made.unwrap_or_default(),
-                org_id: record.org_id.unwrap_or(0),
-                ingredients: record.ingredients.unwrap_or_default(),
-                description: record.description,
-            };
-            HttpResponse::Ok().json(recipe)
-        },
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"})),
+        Ok(changes) => HttpResponse::Ok().json(changes),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch status history"}))
         }
     }
 }
 
-async fn get_all_recipes(
+async fn get_ingredient_status_history(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    lot_status_history(req, LotRef::Ingredient(path.into_inner()), &data).await
+}
+
+async fn get_batch_status_history(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    lot_status_history(req, LotRef::Batch(path.into_inner()), &data).await
+}
+
+// Ingredient lots and batches that are on hold, quarantined or rejected (or
+// in the requested status), with the latest reason and who set it
+async fn get_hold_list(
     req: HttpRequest,
+    query: web::Query<HoldListQuery>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    // Extract auth token from request
-    let token = match req.headers().get("Authorization") {
-        Some(header) => {
-            match header.to_str() {
-                Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
-                    } else {
-                        return HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid authorization header format"
-                        }));
-                    }
-                },
-                Err(_) => {
-                    return HttpResponse::Unauthorized().json(serde_json::json!({
-                        "error": "Invalid authorization header"
-                    }));
-                }
-            }
-        },
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authorization header is missing"
-            }));
-        }
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
-    
-    // Verify token and get organization ID
-    let claims = match auth::verify_token(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid auth token"
-            }));
-        }
+
+    let statuses: Vec<String> = match query.status {
+        Some(status) => vec![status.as_str().to_string()],
+        None => [LotStatus::OnHold, LotStatus::Quarantined, LotStatus::Rejected]
+            .iter()
+            .map(|status| status.as_str().to_string())
+            .collect(),
     };
-    
-    let org_id = claims.org_id;
 
-    match sqlx::query!(
-        "SELECT r.id, r.lotcode, r.name, r.date_made::text as date_made, r.org_id, r.description,
-         ARRAY(SELECT ingredient_id FROM recipe_ingredients WHERE recipe_id = r.id)::int[] as ingredients
-         FROM recipes r WHERE r.org_id = $1",
-        org_id
+    match sqlx::query_as!(
+        HeldLot,
+        "SELECT l.kind as \"kind!\", l.id as \"id!\", l.lotcode as \"lotcode!\", l.name as \"name!\", l.status as \"status!\",
+         c.reason as \"reason?\", c.employee_id as \"employee_id?\", e.name as \"employee_name?\", c.changed_at::text as \"since?\"
+         FROM (
+             SELECT 'ingredient' as kind, id, lotcode, name, status FROM ingredients WHERE org_id = $1 AND status = ANY($2)
+             UNION ALL
+             SELECT 'batch', id, batch_lot_code, recipe_lotcode, status FROM batches WHERE org_id = $1 AND status = ANY($2)
+         ) l
+         LEFT JOIN LATERAL (
+             SELECT reason, employee_id, changed_at FROM lot_status_changes
+             WHERE (l.kind = 'ingredient' AND ingredient_id = l.id) OR (l.kind = 'batch' AND batch_id = l.id)
+             ORDER BY changed_at DESC, id DESC LIMIT 1
+         ) c ON TRUE
+         LEFT JOIN employees e ON e.id = c.employee_id
+         ORDER BY c.changed_at DESC NULLS LAST, l.kind, l.id",
+        org_id,
+        &statuses
     )
     .fetch_all(&data.db_pool)
     .await
     {
-        Ok(records) => {
-            let recipes: Vec<Recipe> = records.into_iter().map(|record| {
-                Recipe {
-                    id: Some(record.id),
-                    lotcode: record.lotcode,
-                    name: record.name,
-                    date_made: record.date_made.unwrap_or_default(),
-                    org_id: record.org_id.unwrap_or(0),
-                    ingredients: record.ingredients.unwrap_or_default(),
-                    description: record.description,
-                }
-            }).collect();
-            HttpResponse::Ok().json(recipes)
-        },
+        Ok(lots) => HttpResponse::Ok().json(lots),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch hold list"}))
         }
     }
 }
 
-async fn update_recipe(
-    path: web::Path<i32>,
-    recipe: web::Json<RecipeInput>,
+// Allergen changeover endpoints
+async fn create_allergen_changeover(
+    changeover: web::Json<AllergenChangeoverInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    let id = path.into_inner();
-    
-    // Parse the date string to NaiveDate
-    let date_made = match NaiveDate::parse_from_str(&recipe.date_made, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
-            }));
-        }
-    };
-    
-    // Start a transaction
-    let mut tx = match data.db_pool.begin().await {
-        Ok(tx) => tx,
+    if changeover.cleaning_steps.iter().all(|step| step.trim().is_empty()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "At least one cleaning step is required"
+        }));
+    }
+
+    // The changeover follows a batch on a known line
+    let line = match sqlx::query!(
+        "SELECT org_id, line FROM batches WHERE id = $1",
+        changeover.from_batch_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(record)) if record.org_id == Some(changeover.org_id) => match record.line {
+            Some(line) => line,
+            None => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "The batch was not made on a production line"
+                }));
+            }
+        },
+        Ok(_) => return HttpResponse::BadRequest().json(serde_json::json!({"error": "Batch not found"})),
         Err(e) => {
-            eprintln!("Failed to start transaction: {}", e);
+            eprintln!("Database error: {}", e);
             return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
     };
 
-    // Update the recipe
-    let update_result = sqlx::query!(
-        "UPDATE recipes SET lotcode = $1, name = $2, date_made = $3, org_id = $4, description = $5 
-         WHERE id = $6 RETURNING id",
-        recipe.lotcode,
-        recipe.name,
-        date_made,
-        recipe.org_id,
-        recipe.description,
-        id
+    match sqlx::query!(
+        "SELECT id FROM employees WHERE id = $1 AND org_id = $2",
+        changeover.employee_id,
+        changeover.org_id
     )
-    .fetch_optional(&mut *tx)
-    .await;
-
-    match update_result {
-        Ok(Some(_)) => {
-            // Delete existing recipe-ingredient relationships
-            if let Err(e) = sqlx::query!("DELETE FROM recipe_ingredients WHERE recipe_id = $1", id)
-                .execute(&mut *tx)
-                .await
-            {
-                eprintln!("Failed to delete ingredient relations: {}", e);
-                let _ = tx.rollback().await;
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update recipe"}));
-            }
-
-            // Insert new recipe-ingredient relationships
-            for ingredient_id in &recipe.ingredients {
-                if let Err(e) = sqlx::query!(
-                    "INSERT INTO recipe_ingredients (recipe_id, ingredient_id) VALUES ($1, $2)",
-                    id,
-                    ingredient_id
-                )
-                .execute(&mut *tx)
-                .await
-                {
-                    eprintln!("Failed to link ingredient to recipe: {}", e);
-                    let _ = tx.rollback().await;
-                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update recipe"}));
-                }
-            }
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::BadRequest().json(serde_json::json!({"error": "Employee not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
 
-            // Commit the transaction
-            if let Err(e) = tx.commit().await {
-                eprintln!("Failed to commit transaction: {}", e);
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
-            }
+    let cleaning_steps: Vec<String> = changeover.cleaning_steps.iter()
+        .map(|step| step.trim().to_string())
+        .filter(|step| !step.is_empty())
+        .collect();
 
-            // Return the updated recipe
-            let updated_recipe = Recipe {
-                id: Some(id),
-                lotcode: recipe.lotcode.clone(),
-                name: recipe.name.clone(),
-                date_made: recipe.date_made.clone(),
-                org_id: recipe.org_id,
-                ingredients: recipe.ingredients.clone(),
-                description: Some(recipe.description.clone()),
-            };
-            HttpResponse::Ok().json(updated_recipe)
-        },
-        Ok(None) => {
-            let _ = tx.rollback().await;
-            HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"}))
-        },
+    match sqlx::query_as!(
+        AllergenChangeover,
+        "INSERT INTO allergen_changeovers (org_id, line, from_batch_id, cleaning_steps, verification_method, result, employee_id, notes)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+         RETURNING id, org_id as \"org_id!\", line, from_batch_id, to_batch_id, cleaning_steps, verification_method,
+         result, employee_id, performed_at::text as \"performed_at!\", notes",
+        changeover.org_id,
+        line,
+        changeover.from_batch_id,
+        &cleaning_steps,
+        changeover.verification_method.as_str(),
+        changeover.result.as_str(),
+        changeover.employee_id,
+        changeover.notes
+    )
+    .fetch_one(&data.db_pool)
+    .await
+    {
+        Ok(created) => HttpResponse::Created().json(created),
         Err(e) => {
-            eprintln!("Database error: {}", e);
-            let _ = tx.rollback().await;
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            eprintln!("Failed to create allergen changeover: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create allergen changeover"}))
         }
     }
 }
 
-async fn delete_recipe(
+async fn get_allergen_changeover(
+    req: HttpRequest,
     path: web::Path<i32>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
-    
-    // Start a transaction
-    let mut tx = match data.db_pool.begin().await {
-        Ok(tx) => tx,
-        Err(e) => {
-            eprintln!("Failed to start transaction: {}", e);
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
-        }
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
 
-    // Delete recipe-ingredient relationships first
-    if let Err(e) = sqlx::query!("DELETE FROM recipe_ingredients WHERE recipe_id = $1", id)
-        .execute(&mut *tx)
-        .await
+    match sqlx::query_as!(
+        AllergenChangeover,
+        "SELECT id, org_id as \"org_id!\", line, from_batch_id, to_batch_id, cleaning_steps, verification_method,
+         result, employee_id, performed_at::text as \"performed_at!\", notes
+         FROM allergen_changeovers WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
     {
-        eprintln!("Failed to delete ingredient relations: {}", e);
-        let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete recipe"}));
+        Ok(Some(changeover)) if changeover.org_id == auth_org_id => HttpResponse::Ok().json(changeover),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this changeover"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Allergen changeover not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+        }
     }
+}
 
-    // Delete the recipe
-    match sqlx::query!("DELETE FROM recipes WHERE id = $1 RETURNING id", id)
-        .fetch_optional(&mut *tx)
-        .await
+// List changeovers for the organization, newest first, optionally for one line
+async fn get_all_allergen_changeovers(
+    req: HttpRequest,
+    query: web::Query<AllergenChangeoverQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        AllergenChangeover,
+        "SELECT id, org_id as \"org_id!\", line, from_batch_id, to_batch_id, cleaning_steps, verification_method,
+         result, employee_id, performed_at::text as \"performed_at!\", notes
+         FROM allergen_changeovers
+         WHERE org_id = $1 AND ($2::varchar IS NULL OR line = $2)
+         ORDER BY performed_at DESC, id DESC",
+        org_id,
+        query.line
+    )
+    .fetch_all(&data.db_pool)
+    .await
     {
-        Ok(Some(_)) => {
-            // Commit the transaction
-            if let Err(e) = tx.commit().await {
-                eprintln!("Failed to commit transaction: {}", e);
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
-            }
-            HttpResponse::NoContent().finish()
-        },
-        Ok(None) => {
-            let _ = tx.rollback().await;
-            HttpResponse::NotFound().json(serde_json::json!({"error": "Recipe not found"}))
-        },
+        Ok(changeovers) => HttpResponse::Ok().json(changeovers),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            let _ = tx.rollback().await;
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch allergen changeovers"}))
         }
     }
 }
 
-// Batch endpoints
-async fn create_batch(
-    batch: web::Json<BatchInput>,
+// Problem Log endpoints
+async fn create_problem_log(
+    problem_log: web::Json<ProblemLogInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    // Parse the date string to NaiveDate
-    let date_made = match NaiveDate::parse_from_str(&batch.date_made, "%Y-%m-%d") {
+    // Parse date_opened string to NaiveDate
+    let date_opened = match NaiveDate::parse_from_str(&problem_log.date_opened, "%Y-%m-%d") {
         Ok(date) => date,
         Err(_) => {
             return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
+                "error": "Invalid date_opened format. Use YYYY-MM-DD"
             }));
         }
     };
 
-    // Validate ingredients and amounts have the same length
-    if batch.ingredients.len() != batch.amount_ingredients.len() {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Ingredients and amounts must have the same length"
-        }));
-    }
-
     // Start a transaction
     let mut tx = match data.db_pool.begin().await {
         Ok(tx) => tx,
@@ -1500,43 +11752,77 @@ async fn create_batch(
         }
     };
 
-    // Insert the batch
-    let batch_id = match sqlx::query!(
-        "INSERT INTO batches (org_id, employee, recipe_lotcode, batch_lot_code, date_made, amount_made) 
-         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
-        batch.org_id,
-        batch.employee,
-        batch.recipe_lotcode,
-        batch.batch_lot_code,
-        date_made,
-        batch.amount_made
+    let customer_name = match problem_log_customer_name(&mut tx, &problem_log).await {
+        Ok(customer_name) => customer_name,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    if let Err(response) = check_problem_log_category(&mut tx, &problem_log, None).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    // Insert the problem log
+    let problem_log_id = match sqlx::query!(
+        "INSERT INTO problem_logs (is_open, date_opened, customer_name, problem_type, problem_description, recall,
+         customer_id, batch_id, corrective_action, org_id, category_id) 
+         VALUES (TRUE, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+        date_opened,
+        customer_name,
+        problem_log.problem_type,
+        problem_log.problem_description,
+        problem_log.recall,
+        problem_log.customer_id,
+        problem_log.batch_id,
+        problem_log.corrective_action,
+        problem_log.org_id,
+        problem_log.category_id
     )
     .fetch_one(&mut *tx)
     .await
     {
         Ok(record) => record.id,
         Err(e) => {
-            eprintln!("Failed to create batch: {}", e);
+            eprintln!("Failed to create problem log: {}", e);
             let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create batch"}));
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create problem log"}));
         }
     };
 
-    // Insert batch-ingredient relationships with amounts
-    for (index, ingredient_id) in batch.ingredients.iter().enumerate() {
+    // Insert problem log-employee relationships
+    for employee_id in &problem_log.assigned_to {
         if let Err(e) = sqlx::query!(
-            "INSERT INTO batch_ingredients (batch_id, ingredient_id, amount) VALUES ($1, $2, $3)",
-            batch_id,
-            ingredient_id,
-            batch.amount_ingredients[index]
+            "INSERT INTO problem_logs_employees (problem_log_id, employee_id) VALUES ($1, $2)",
+            problem_log_id,
+            employee_id
         )
         .execute(&mut *tx)
         .await
         {
-            eprintln!("Failed to link ingredient to batch: {}", e);
+            eprintln!("Failed to link employee to problem log: {}", e);
             let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create batch"}));
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create problem log"}));
+        }
+    }
+
+    let (response_due_at, resolution_due_at) = match set_problem_log_due_dates(&mut tx, problem_log_id).await {
+        Ok(due_dates) => due_dates,
+        Err(e) => {
+            eprintln!("Failed to set problem log due dates: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create problem log"}));
         }
+    };
+
+    if let Err(e) = record_problem_log_activity(
+        &mut tx, problem_log_id, ActivityType::Created, None, Some(ProblemStatus::New), None, None,
+    ).await {
+        eprintln!("Failed to record problem log activity: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create problem log"}));
     }
 
     // Commit the transaction
@@ -1545,23 +11831,34 @@ async fn create_batch(
         return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
     }
 
-    // Return the created batch
-    let created_batch = Batch {
-        id: Some(batch_id),
-        org_id: batch.org_id,
-        employee: batch.employee.clone(),
-        recipe_lotcode: batch.recipe_lotcode.clone(),
-        batch_lot_code: batch.batch_lot_code.clone(),
-        ingredients: batch.ingredients.clone(),
-        amount_ingredients: batch.amount_ingredients.clone(),
-        date_made: batch.date_made.clone(),
-        amount_made: batch.amount_made.clone(),
+    // Return the created problem log
+    let created_problem_log = ProblemLog {
+        id: Some(problem_log_id),
+        org_id: problem_log.org_id,
+        is_open: true,
+        date_opened: problem_log.date_opened.clone(),
+        customer_name,
+        customer_id: problem_log.customer_id,
+        problem_type: problem_log.problem_type.clone(),
+        assigned_to: problem_log.assigned_to.clone(),
+        problem_description: problem_log.problem_description.clone(),
+        recall: problem_log.recall,
+        date_resolved: None,
+        status: ProblemStatus::New,
+        resolution_notes: None,
+        batch_id: problem_log.batch_id,
+        corrective_action: problem_log.corrective_action.clone(),
+        corrective_action_required: false,
+        category_id: problem_log.category_id,
+        response_due_at,
+        resolution_due_at,
+        responded_at: None,
     };
 
-    HttpResponse::Created().json(created_batch)
+    HttpResponse::Created().json(created_problem_log)
 }
 
-async fn get_batch(
+async fn get_problem_log(
     req: HttpRequest,
     path: web::Path<i32>,
     data: web::Data<AppState>,
@@ -1573,8 +11870,8 @@ async fn get_batch(
         Some(header) => {
             match header.to_str() {
                 Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
                     } else {
                         return HttpResponse::Unauthorized().json(serde_json::json!({
                             "error": "Invalid authorization header format"
@@ -1607,70 +11904,106 @@ async fn get_batch(
     
     let auth_org_id = claims.org_id;
     
-    // Get the basic batch information
-    let batch_record = match sqlx::query!(
-        "SELECT id, org_id, employee, recipe_lotcode, batch_lot_code, date_made::text as date_made, amount_made 
-         FROM batches WHERE id = $1",
+    // First check if org_id column exists in problem_logs table
+    let column_check = sqlx::query!("
+        SELECT column_name 
+        FROM information_schema.columns 
+        WHERE table_name = 'problem_logs' AND column_name = 'org_id'
+    ")
+    .fetch_optional(&data.db_pool)
+    .await;
+
+    let has_org_id = matches!(column_check, Ok(Some(_)));
+
+    // Get the problem log without assuming org_id column exists
+    // Since SQLx validates queries at compile time, we need a version without the org_id column
+    // Get the problem log record
+    let problem_log_record = match sqlx::query!(
+        "SELECT id, is_open, date_opened::text as date_opened, customer_name, problem_type, 
+         problem_description, recall, date_resolved::text as date_resolved, status, resolution_notes, customer_id,
+         batch_id, corrective_action, corrective_action_required, org_id, category_id,
+         response_due_at::text, resolution_due_at::text, responded_at::text
+         FROM problem_logs WHERE id = $1",
         id
     )
     .fetch_optional(&data.db_pool)
     .await
     {
         Ok(Some(record)) => {
-            // Security check: Verify the authenticated user belongs to the same organization
-            // as the requested resource
-            if record.org_id.unwrap_or(0) != auth_org_id {
-                return HttpResponse::Forbidden().json(serde_json::json!({
-                    "error": "You don't have permission to access this batch"
-                }));
+            // If org_id column exists, do an additional check for authorization
+            if has_org_id {
+                // Use dynamic SQL for the org_id check
+                let sql = format!("SELECT org_id FROM problem_logs WHERE id = {}", id);
+                let org_result = sqlx::query(&sql)
+                    .fetch_optional(&data.db_pool)
+                    .await;
+                
+                // If we get a valid org_id, check permission
+                if let Ok(Some(org_record)) = org_result {
+                    if let Ok(org_id) = org_record.try_get::<i32, _>("org_id") {
+                        if org_id != auth_org_id {
+                            return HttpResponse::Forbidden().json(serde_json::json!({
+                                "error": "You don't have permission to access this problem log"
+                            }));
+                        }
+                    }
+                }
+            } else {
+                // Skip security check and log warning
+                eprintln!("Warning: org_id column not found in problem_logs table - cannot verify organization access");
             }
             record
         },
-        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"})),
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
             return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
     };
 
-    // Get the batch ingredients with amounts
-    let batch_ingredients = match sqlx::query!(
-        "SELECT bi.ingredient_id, bi.amount 
-         FROM batch_ingredients bi 
-         WHERE bi.batch_id = $1",
+    // Get the assigned employees
+    let assigned_employees = match sqlx::query!(
+        "SELECT employee_id FROM problem_logs_employees WHERE problem_log_id = $1",
         id
     )
     .fetch_all(&data.db_pool)
     .await
     {
-        Ok(records) => records,
+        Ok(records) => records.into_iter().map(|r| r.employee_id).collect(),
         Err(e) => {
-            eprintln!("Database error: {}", e);
+            eprintln!("Database error when fetching assigned employees: {}", e);
             return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
     };
 
-    // Extract ingredient ids and amounts
-    let ingredients: Vec<i32> = batch_ingredients.iter().map(|r| r.ingredient_id).collect();
-    let amount_ingredients: Vec<i32> = batch_ingredients.iter().map(|r| r.amount).collect();
-
-    // Create the complete batch object
-    let batch = Batch {
-        id: Some(batch_record.id),
-        org_id: batch_record.org_id.unwrap_or(0),
-        employee: batch_record.employee,
-        recipe_lotcode: batch_record.recipe_lotcode,
-        batch_lot_code: batch_record.batch_lot_code,
-        ingredients,
-        amount_ingredients,
-        date_made: batch_record.date_made.unwrap_or_default(),
-        amount_made: batch_record.amount_made,
+    // Create the complete problem log object
+    let problem_log = ProblemLog {
+        id: Some(problem_log_record.id),
+        org_id: problem_log_record.org_id,
+        is_open: problem_log_record.is_open,
+        date_opened: problem_log_record.date_opened.unwrap_or_default(),
+        customer_name: problem_log_record.customer_name,
+        customer_id: problem_log_record.customer_id,
+        problem_type: problem_log_record.problem_type,
+        assigned_to: assigned_employees,
+        problem_description: problem_log_record.problem_description,
+        recall: problem_log_record.recall,
+        date_resolved: problem_log_record.date_resolved,
+        status: ProblemStatus::parse(&problem_log_record.status).unwrap_or_default(),
+        resolution_notes: problem_log_record.resolution_notes,
+        batch_id: problem_log_record.batch_id,
+        corrective_action: problem_log_record.corrective_action,
+        corrective_action_required: problem_log_record.corrective_action_required,
+        category_id: problem_log_record.category_id,
+        response_due_at: problem_log_record.response_due_at,
+        resolution_due_at: problem_log_record.resolution_due_at,
+        responded_at: problem_log_record.responded_at,
     };
 
-    HttpResponse::Ok().json(batch)
+    HttpResponse::Ok().json(problem_log)
 }
 
-async fn get_all_batches(
+async fn get_all_problem_logs(
     req: HttpRequest,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -1679,8 +12012,8 @@ async fn get_all_batches(
         Some(header) => {
             match header.to_str() {
                 Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
+                    if let Some(token) = header_str.strip_prefix("Bearer ") {
+                        token
                     } else {
                         return HttpResponse::Unauthorized().json(serde_json::json!({
                             "error": "Invalid authorization header format"
@@ -1713,12 +12046,36 @@ async fn get_all_batches(
     
     let org_id = claims.org_id;
 
-    // Get all batches for this organization
-    let batch_records = match sqlx::query!(
-        "SELECT id, org_id, employee, recipe_lotcode, batch_lot_code, date_made::text as date_made, amount_made 
-         FROM batches WHERE org_id = $1 ORDER BY id DESC",
-        org_id
-    )
+    // First, check if org_id column exists in problem_logs table
+    let column_check = sqlx::query!("
+        SELECT column_name 
+        FROM information_schema.columns 
+        WHERE table_name = 'problem_logs' AND column_name = 'org_id'
+    ")
+    .fetch_optional(&data.db_pool)
+    .await;
+
+    // Build the right query based on whether org_id column exists
+    let sql_query = if let Ok(Some(_)) = column_check {
+        format!("SELECT id, is_open, date_opened::text as date_opened, customer_name, problem_type, problem_description, 
+                recall, date_resolved::text as date_resolved, status, resolution_notes, customer_id, batch_id, corrective_action,
+                corrective_action_required, org_id, category_id, response_due_at::text, resolution_due_at::text,
+                responded_at::text
+                FROM problem_logs 
+                WHERE org_id = {} OR org_id IS NULL
+                ORDER BY id DESC", org_id)
+    } else {
+        // Fall back to getting all problem logs if org_id column doesn't exist
+        eprintln!("Warning: org_id column not found in problem_logs table - cannot filter by organization");
+        "SELECT id, is_open, date_opened::text as date_opened, customer_name, problem_type, problem_description, 
+        recall, date_resolved::text as date_resolved, status, resolution_notes, customer_id, batch_id, corrective_action,
+        corrective_action_required
+        FROM problem_logs 
+        ORDER BY id DESC".to_string()
+    };
+    
+    // Use direct SQL query
+    let problem_log_records = match sqlx::query(&sql_query)
     .fetch_all(&data.db_pool)
     .await
     {
@@ -1729,77 +12086,94 @@ async fn get_all_batches(
         }
     };
 
-    // Create a vector to hold all batches
-    let mut batches = Vec::new();
+    // Create a vector to hold all problem logs
+    let mut problem_logs = Vec::new();
 
-    // For each batch, get its ingredients and amounts
-    for record in batch_records {
-        let batch_id = record.id;
+    // For each problem log, get its assigned employees
+    for record in problem_log_records {
+        // Get fields from the PgRow
+        let problem_log_id: i32 = record.try_get("id").unwrap_or_default();
+        let problem_log_org_id: Option<i32> = record.try_get("org_id").unwrap_or_default();
+        let is_open: bool = record.try_get("is_open").unwrap_or_default();
+        let date_opened: Option<String> = record.try_get("date_opened").unwrap_or_default();
+        let customer_name: String = record.try_get("customer_name").unwrap_or_default();
+        let customer_id: Option<i32> = record.try_get("customer_id").unwrap_or_default();
+        let problem_type: String = record.try_get("problem_type").unwrap_or_default();
+        let problem_description: String = record.try_get("problem_description").unwrap_or_default();
+        let recall: bool = record.try_get("recall").unwrap_or_default();
+        let date_resolved: Option<String> = record.try_get("date_resolved").unwrap_or_default();
+        let status: String = record.try_get("status").unwrap_or_default();
+        let resolution_notes: Option<String> = record.try_get("resolution_notes").unwrap_or_default();
+        let batch_id: Option<i32> = record.try_get("batch_id").unwrap_or_default();
+        let corrective_action: Option<String> = record.try_get("corrective_action").unwrap_or_default();
+        let corrective_action_required: bool = record.try_get("corrective_action_required").unwrap_or_default();
+        let category_id: Option<i32> = record.try_get("category_id").unwrap_or_default();
+        let response_due_at: Option<String> = record.try_get("response_due_at").unwrap_or_default();
+        let resolution_due_at: Option<String> = record.try_get("resolution_due_at").unwrap_or_default();
+        let responded_at: Option<String> = record.try_get("responded_at").unwrap_or_default();
 
-        // Get the batch ingredients with amounts
-        let batch_ingredients = match sqlx::query!(
-            "SELECT bi.ingredient_id, bi.amount 
-             FROM batch_ingredients bi 
-             WHERE bi.batch_id = $1",
-            batch_id
+        // Get the assigned employees
+        let assigned_employees = match sqlx::query!(
+            "SELECT employee_id FROM problem_logs_employees WHERE problem_log_id = $1",
+            problem_log_id
         )
         .fetch_all(&data.db_pool)
         .await
         {
-            Ok(records) => records,
+            Ok(records) => records.into_iter().map(|r| r.employee_id).collect(),
             Err(e) => {
-                eprintln!("Database error when fetching ingredients for batch {}: {}", batch_id, e);
-                continue; // Skip this batch if we can't get its ingredients
+                eprintln!("Database error when fetching assigned employees for problem log {}: {}", problem_log_id, e);
+                continue; // Skip this problem log if we can't get its assigned employees
             }
         };
 
-        // Extract ingredient ids and amounts
-        let ingredients: Vec<i32> = batch_ingredients.iter().map(|r| r.ingredient_id).collect();
-        let amount_ingredients: Vec<i32> = batch_ingredients.iter().map(|r| r.amount).collect();
-
-        // Create the complete batch object
-        let batch = Batch {
-            id: Some(record.id),
-            org_id: record.org_id.unwrap_or(0),
-            employee: record.employee,
-            recipe_lotcode: record.recipe_lotcode,
-            batch_lot_code: record.batch_lot_code,
-            ingredients,
-            amount_ingredients,
-            date_made: record.date_made.unwrap_or_default(),
-            amount_made: record.amount_made,
+        // Create the complete problem log object
+        let problem_log = ProblemLog {
+            id: Some(problem_log_id),
+            org_id: problem_log_org_id,
+            is_open,
+            date_opened: date_opened.unwrap_or_default(),
+            customer_name,
+            customer_id,
+            problem_type,
+            assigned_to: assigned_employees,
+            problem_description,
+            recall,
+            date_resolved,
+            status: ProblemStatus::parse(&status).unwrap_or_default(),
+            resolution_notes,
+            batch_id,
+            corrective_action,
+            corrective_action_required,
+            category_id,
+            response_due_at,
+            resolution_due_at,
+            responded_at,
         };
 
-        batches.push(batch);
+        problem_logs.push(problem_log);
     }
 
-    HttpResponse::Ok().json(batches)
+    HttpResponse::Ok().json(problem_logs)
 }
 
-async fn update_batch(
+async fn update_problem_log(
     path: web::Path<i32>,
-    batch: web::Json<BatchInput>,
+    problem_log: web::Json<ProblemLogInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
     
-    // Parse the date string to NaiveDate
-    let date_made = match NaiveDate::parse_from_str(&batch.date_made, "%Y-%m-%d") {
+    // Parse date_opened string to NaiveDate
+    let date_opened = match NaiveDate::parse_from_str(&problem_log.date_opened, "%Y-%m-%d") {
         Ok(date) => date,
         Err(_) => {
             return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date format. Use YYYY-MM-DD"
+                "error": "Invalid date_opened format. Use YYYY-MM-DD"
             }));
         }
     };
 
-    // Validate ingredients and amounts have the same length
-    if batch.ingredients.len() != batch.amount_ingredients.len() {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Ingredients and amounts must have the same length"
-        }));
-    }
-
     // Start a transaction
     let mut tx = match data.db_pool.begin().await {
         Ok(tx) => tx,
@@ -1809,73 +12183,123 @@ async fn update_batch(
         }
     };
 
-    // Update the batch
+    let customer_name = match problem_log_customer_name(&mut tx, &problem_log).await {
+        Ok(customer_name) => customer_name,
+        Err(response) => {
+            let _ = tx.rollback().await;
+            return response;
+        }
+    };
+
+    let current_category_id = match sqlx::query_scalar!("SELECT category_id FROM problem_logs WHERE id = $1", id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(current) => current.flatten(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+    if let Err(response) = check_problem_log_category(&mut tx, &problem_log, current_category_id).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    // Update the problem log
     let update_result = sqlx::query!(
-        "UPDATE batches SET org_id = $1, employee = $2, recipe_lotcode = $3, batch_lot_code = $4, 
-         date_made = $5, amount_made = $6 WHERE id = $7 RETURNING id",
-        batch.org_id,
-        batch.employee,
-        batch.recipe_lotcode,
-        batch.batch_lot_code,
-        date_made,
-        batch.amount_made,
+        "UPDATE problem_logs SET date_opened = $1, customer_name = $2, problem_type = $3, problem_description = $4,
+         recall = $5, customer_id = $6, batch_id = $7, corrective_action = $8, category_id = $9
+         WHERE id = $10
+         RETURNING org_id, is_open, status, date_resolved::text as date_resolved, resolution_notes,
+         corrective_action_required, responded_at::text",
+        date_opened,
+        customer_name,
+        problem_log.problem_type,
+        problem_log.problem_description,
+        problem_log.recall,
+        problem_log.customer_id,
+        problem_log.batch_id,
+        problem_log.corrective_action,
+        problem_log.category_id,
         id
     )
     .fetch_optional(&mut *tx)
     .await;
 
     match update_result {
-        Ok(Some(_)) => {
-            // Delete existing batch-ingredient relationships
-            if let Err(e) = sqlx::query!("DELETE FROM batch_ingredients WHERE batch_id = $1", id)
+        Ok(Some(updated)) => {
+            // Delete existing problem log-employee relationships
+            if let Err(e) = sqlx::query!("DELETE FROM problem_logs_employees WHERE problem_log_id = $1", id)
                 .execute(&mut *tx)
                 .await
             {
-                eprintln!("Failed to delete ingredient relations: {}", e);
+                eprintln!("Failed to delete employee relations: {}", e);
                 let _ = tx.rollback().await;
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update batch"}));
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
             }
 
-            // Insert new batch-ingredient relationships with amounts
-            for (index, ingredient_id) in batch.ingredients.iter().enumerate() {
+            // Insert new problem log-employee relationships
+            for employee_id in &problem_log.assigned_to {
                 if let Err(e) = sqlx::query!(
-                    "INSERT INTO batch_ingredients (batch_id, ingredient_id, amount) VALUES ($1, $2, $3)",
+                    "INSERT INTO problem_logs_employees (problem_log_id, employee_id) VALUES ($1, $2)",
                     id,
-                    ingredient_id,
-                    batch.amount_ingredients[index]
+                    employee_id
                 )
                 .execute(&mut *tx)
                 .await
                 {
-                    eprintln!("Failed to link ingredient to batch: {}", e);
+                    eprintln!("Failed to link employee to problem log: {}", e);
                     let _ = tx.rollback().await;
-                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update batch"}));
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
                 }
             }
 
+            // The problem type may have changed
+            let (response_due_at, resolution_due_at) = match set_problem_log_due_dates(&mut tx, id).await {
+                Ok(due_dates) => due_dates,
+                Err(e) => {
+                    eprintln!("Failed to set problem log due dates: {}", e);
+                    let _ = tx.rollback().await;
+                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
+                }
+            };
+
             // Commit the transaction
             if let Err(e) = tx.commit().await {
                 eprintln!("Failed to commit transaction: {}", e);
                 return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
             }
 
-            // Return the updated batch
-            let updated_batch = Batch {
+            // Return the updated problem log
+            let updated_problem_log = ProblemLog {
                 id: Some(id),
-                org_id: batch.org_id,
-                employee: batch.employee.clone(),
-                recipe_lotcode: batch.recipe_lotcode.clone(),
-                batch_lot_code: batch.batch_lot_code.clone(),
-                ingredients: batch.ingredients.clone(),
-                amount_ingredients: batch.amount_ingredients.clone(),
-                date_made: batch.date_made.clone(),
-                amount_made: batch.amount_made.clone(),
+                org_id: updated.org_id,
+                is_open: updated.is_open,
+                date_opened: problem_log.date_opened.clone(),
+                customer_name,
+                customer_id: problem_log.customer_id,
+                problem_type: problem_log.problem_type.clone(),
+                assigned_to: problem_log.assigned_to.clone(),
+                problem_description: problem_log.problem_description.clone(),
+                recall: problem_log.recall,
+                date_resolved: updated.date_resolved,
+                status: ProblemStatus::parse(&updated.status).unwrap_or_default(),
+                resolution_notes: updated.resolution_notes,
+                batch_id: problem_log.batch_id,
+                corrective_action: problem_log.corrective_action.clone(),
+                corrective_action_required: updated.corrective_action_required,
+                category_id: problem_log.category_id,
+                response_due_at,
+                resolution_due_at,
+                responded_at: updated.responded_at,
             };
-            HttpResponse::Ok().json(updated_batch)
+            HttpResponse::Ok().json(updated_problem_log)
         },
         Ok(None) => {
             let _ = tx.rollback().await;
-            HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"}))
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"}))
         },
         Err(e) => {
             eprintln!("Database error: {}", e);
@@ -1885,7 +12309,7 @@ async fn update_batch(
     }
 }
 
-async fn delete_batch(
+async fn delete_problem_log(
     path: web::Path<i32>,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -1900,18 +12324,18 @@ async fn delete_batch(
         }
     };
 
-    // Delete batch-ingredient relationships first
-    if let Err(e) = sqlx::query!("DELETE FROM batch_ingredients WHERE batch_id = $1", id)
+    // Delete problem log-employee relationships first
+    if let Err(e) = sqlx::query!("DELETE FROM problem_logs_employees WHERE problem_log_id = $1", id)
         .execute(&mut *tx)
         .await
     {
-        eprintln!("Failed to delete ingredient relations: {}", e);
+        eprintln!("Failed to delete employee relations: {}", e);
         let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete batch"}));
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete problem log"}));
     }
 
-    // Delete the batch
-    match sqlx::query!("DELETE FROM batches WHERE id = $1 RETURNING id", id)
+    // Delete the problem log
+    match sqlx::query!("DELETE FROM problem_logs WHERE id = $1 RETURNING id", id)
         .fetch_optional(&mut *tx)
         .await
     {
@@ -1925,7 +12349,7 @@ async fn delete_batch(
         },
         Ok(None) => {
             let _ = tx.rollback().await;
-            HttpResponse::NotFound().json(serde_json::json!({"error": "Batch not found"}))
+            HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"}))
         },
         Err(e) => {
             eprintln!("Database error: {}", e);
@@ -1935,37 +12359,91 @@ async fn delete_batch(
     }
 }
 
-// Problem Log endpoints
-async fn create_problem_log(
-    problem_log: web::Json<ProblemLogInput>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    // Parse date_opened string to NaiveDate
-    let date_opened = match NaiveDate::parse_from_str(&problem_log.date_opened, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date_opened format. Use YYYY-MM-DD"
-            }));
-        }
-    };
+// Append an entry to the activity thread of a problem log
+async fn record_problem_log_activity(
+    conn: &mut PgConnection,
+    problem_log_id: i32,
+    activity_type: ActivityType,
+    from_status: Option<ProblemStatus>,
+    to_status: Option<ProblemStatus>,
+    body: Option<&str>,
+    employee_id: Option<i32>,
+) -> Result<ProblemLogActivity, sqlx::Error> {
+    let record = sqlx::query!(
+        "INSERT INTO problem_log_activity (problem_log_id, activity_type, from_status, to_status, body, employee_id)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, created_at::text as \"created_at!\"",
+        problem_log_id,
+        activity_type.as_str(),
+        from_status.map(ProblemStatus::as_str),
+        to_status.map(ProblemStatus::as_str),
+        body,
+        employee_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // The first comment or status change is the response to a new log.
+    // Escalation then starts over for the resolution due date.
+    if matches!(activity_type, ActivityType::Comment | ActivityType::StatusChange) {
+        sqlx::query!(
+            "UPDATE problem_logs SET responded_at = CURRENT_TIMESTAMP, escalation_level = 0, escalated_at = NULL
+             WHERE id = $1 AND responded_at IS NULL",
+            problem_log_id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
 
-    // Parse date_resolved string to NaiveDate if it exists
-    let date_resolved = match &problem_log.date_resolved {
-        Some(date_str) => {
-            match NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
-                Ok(date) => Some(date),
-                Err(_) => {
-                    return HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Invalid date_resolved format. Use YYYY-MM-DD"
-                    }));
-                }
-            }
-        },
-        None => None
+    Ok(ProblemLogActivity {
+        id: record.id,
+        problem_log_id,
+        activity_type,
+        from_status,
+        to_status,
+        body: body.map(str::to_string),
+        employee_id,
+        created_at: record.created_at,
+    })
+}
+
+// The employee must work for the problem log's organization
+async fn problem_log_employee_check(
+    conn: &mut PgConnection,
+    problem_log_id: i32,
+    employee_id: Option<i32>,
+) -> Result<(), HttpResponse> {
+    let employee_id = match employee_id {
+        Some(employee_id) => employee_id,
+        None => return Ok(()),
     };
+    match sqlx::query!(
+        "SELECT e.id FROM employees e, problem_logs p
+         WHERE e.id = $1 AND p.id = $2 AND (p.org_id IS NULL OR e.org_id = p.org_id)",
+        employee_id,
+        problem_log_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(HttpResponse::BadRequest().json(serde_json::json!({"error": "Employee not found"}))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"})))
+        }
+    }
+}
+
+// Move a problem log through its workflow. Resolving sets date_resolved and
+// reopening clears it; closing also needs any required corrective action and
+// every CAPA verified or cancelled.
+async fn transition_problem_log(
+    path: web::Path<i32>,
+    transition: web::Json<ProblemLogTransitionInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
 
-    // Start a transaction
     let mut tx = match data.db_pool.begin().await {
         Ok(tx) => tx,
         Err(e) => {
@@ -1974,361 +12452,394 @@ async fn create_problem_log(
         }
     };
 
-    // Insert the problem log
-    let problem_log_id = match sqlx::query!(
-        "INSERT INTO problem_logs (is_open, date_opened, customer_name, problem_type, problem_description, recall, date_resolved) 
-         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
-        problem_log.is_open,
-        date_opened,
-        problem_log.customer_name,
-        problem_log.problem_type,
-        problem_log.problem_description,
-        problem_log.recall,
-        date_resolved
+    let current = match sqlx::query!(
+        "SELECT status, resolution_notes, corrective_action, corrective_action_required
+         FROM problem_logs WHERE id = $1 FOR UPDATE",
+        id
     )
-    .fetch_one(&mut *tx)
+    .fetch_optional(&mut *tx)
     .await
     {
-        Ok(record) => record.id,
+        Ok(Some(current)) => current,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"}));
+        }
         Err(e) => {
-            eprintln!("Failed to create problem log: {}", e);
+            eprintln!("Database error: {}", e);
             let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create problem log"}));
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
     };
 
-    // Insert problem log-employee relationships
-    for employee_id in &problem_log.assigned_to {
-        if let Err(e) = sqlx::query!(
-            "INSERT INTO problem_logs_employees (problem_log_id, employee_id) VALUES ($1, $2)",
-            problem_log_id,
-            employee_id
+    let from = ProblemStatus::parse(&current.status).unwrap_or_default();
+    let to = transition.status;
+    // Notes given when resolving carry over to closing
+    let resolution_notes = transition.resolution_notes.as_deref()
+        .map(str::trim)
+        .filter(|notes| !notes.is_empty())
+        .or_else(|| current.resolution_notes.as_deref().filter(|_| from == ProblemStatus::Resolved));
+    let comment = transition.comment.as_deref().map(str::trim).filter(|comment| !comment.is_empty());
+    if let Err(e) = problemlogs::check_transition(from, to, resolution_notes, comment) {
+        let _ = tx.rollback().await;
+        return match e {
+            WorkflowError::InvalidTransition { .. } => HttpResponse::Conflict().json(serde_json::json!({"error": e.to_string()})),
+            _ => HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()})),
+        };
+    }
+
+    if to == ProblemStatus::Closed {
+        let has_corrective_action = current.corrective_action.as_deref().is_some_and(|action| !action.trim().is_empty());
+        if current.corrective_action_required && !has_corrective_action {
+            let _ = tx.rollback().await;
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "A corrective action is required before this problem log can be closed"
+            }));
+        }
+
+        match sqlx::query_scalar!(
+            "SELECT COUNT(*) as \"count!\" FROM capas
+             WHERE problem_log_id = $1 AND status NOT IN ('verified', 'cancelled')",
+            id
         )
-        .execute(&mut *tx)
+        .fetch_one(&mut *tx)
         .await
         {
-            eprintln!("Failed to link employee to problem log: {}", e);
-            let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create problem log"}));
+            Ok(0) => {}
+            Ok(open_capas) => {
+                let _ = tx.rollback().await;
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("Problem log has {} open CAPA(s) and cannot be closed", open_capas)
+                }));
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            }
         }
     }
 
-    // Commit the transaction
-    if let Err(e) = tx.commit().await {
-        eprintln!("Failed to commit transaction: {}", e);
-        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    if let Err(response) = problem_log_employee_check(&mut tx, id, transition.employee_id).await {
+        let _ = tx.rollback().await;
+        return response;
     }
 
-    // Return the created problem log
-    let created_problem_log = ProblemLog {
-        id: Some(problem_log_id),
-        is_open: problem_log.is_open,
-        date_opened: problem_log.date_opened.clone(),
-        customer_name: problem_log.customer_name.clone(),
-        problem_type: problem_log.problem_type.clone(),
-        assigned_to: problem_log.assigned_to.clone(),
-        problem_description: problem_log.problem_description.clone(),
-        recall: problem_log.recall,
-        date_resolved: problem_log.date_resolved.clone(),
-    };
-
-    HttpResponse::Created().json(created_problem_log)
-}
-
-async fn get_problem_log(
-    req: HttpRequest,
-    path: web::Path<i32>,
-    data: web::Data<AppState>,
-) -> impl Responder {
-    let id = path.into_inner();
-    
-    // Extract auth token from request
-    let token = match req.headers().get("Authorization") {
-        Some(header) => {
-            match header.to_str() {
-                Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
-                    } else {
-                        return HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid authorization header format"
-                        }));
-                    }
-                },
-                Err(_) => {
-                    return HttpResponse::Unauthorized().json(serde_json::json!({
-                        "error": "Invalid authorization header"
-                    }));
-                }
-            }
-        },
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authorization header is missing"
-            }));
-        }
-    };
-    
-    // Verify token and get organization ID
-    let claims = match auth::verify_token(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid auth token"
-            }));
-        }
+    // A reopened log needs a new resolution
+    let (resolution_notes, date_resolved) = if to.is_resolved() {
+        (resolution_notes, Some(chrono::Local::now().date_naive()))
+    } else {
+        (None, None)
     };
-    
-    let auth_org_id = claims.org_id;
-    
-    // First check if org_id column exists in problem_logs table
-    let column_check = sqlx::query!("
-        SELECT column_name 
-        FROM information_schema.columns 
-        WHERE table_name = 'problem_logs' AND column_name = 'org_id'
-    ")
-    .fetch_optional(&data.db_pool)
-    .await;
-
-    let has_org_id = matches!(column_check, Ok(Some(_)));
-
-    // Get the problem log without assuming org_id column exists
-    // Since SQLx validates queries at compile time, we need a version without the org_id column
-    // Get the problem log record
-    let problem_log_record = match sqlx::query!(
-        "SELECT id, is_open, date_opened::text as date_opened, customer_name, problem_type, 
-         problem_description, recall, date_resolved::text as date_resolved
-         FROM problem_logs WHERE id = $1",
-        id
+    // Reopened logs are escalated afresh
+    if let Err(e) = sqlx::query!(
+        "UPDATE problem_logs SET status = $1, is_open = $2, resolution_notes = $3,
+         date_resolved = CASE WHEN $4::date IS NULL THEN NULL ELSE COALESCE(date_resolved, $4) END,
+         escalation_level = CASE WHEN $6 THEN 0 ELSE escalation_level END,
+         escalated_at = CASE WHEN $6 THEN NULL ELSE escalated_at END
+         WHERE id = $5",
+        to.as_str(),
+        to.is_open(),
+        resolution_notes,
+        date_resolved,
+        id,
+        to == ProblemStatus::Reopened
     )
-    .fetch_optional(&data.db_pool)
-    .await
-    {
-        Ok(Some(record)) => {
-            // If org_id column exists, do an additional check for authorization
-            if has_org_id {
-                // Use dynamic SQL for the org_id check
-                let sql = format!("SELECT org_id FROM problem_logs WHERE id = {}", id);
-                let org_result = sqlx::query(&sql)
-                    .fetch_optional(&data.db_pool)
-                    .await;
-                
-                // If we get a valid org_id, check permission
-                if let Ok(Some(org_record)) = org_result {
-                    if let Ok(org_id) = org_record.try_get::<i32, _>("org_id") {
-                        if org_id != auth_org_id {
-                            return HttpResponse::Forbidden().json(serde_json::json!({
-                                "error": "You don't have permission to access this problem log"
-                            }));
-                        }
-                    }
-                }
-            } else {
-                // Skip security check and log warning
-                eprintln!("Warning: org_id column not found in problem_logs table - cannot verify organization access");
-            }
-            record
-        },
-        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"})),
+    .execute(&mut *tx)
+    .await
+    {
+        eprintln!("Failed to update problem log status: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
+    }
+
+    let activity = match record_problem_log_activity(
+        &mut tx, id, ActivityType::StatusChange, Some(from), Some(to), comment.or(resolution_notes), transition.employee_id,
+    ).await {
+        Ok(activity) => activity,
         Err(e) => {
-            eprintln!("Database error: {}", e);
+            eprintln!("Failed to record problem log activity: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(activity)
+}
+
+async fn add_problem_log_comment(
+    path: web::Path<i32>,
+    comment: web::Json<ProblemLogCommentInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let body = comment.body.trim();
+    if body.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": "Comments need a body"}));
+    }
+
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
             return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
     };
 
-    // Get the assigned employees
-    let assigned_employees = match sqlx::query!(
-        "SELECT employee_id FROM problem_logs_employees WHERE problem_log_id = $1",
-        id
-    )
-    .fetch_all(&data.db_pool)
-    .await
+    match sqlx::query!("SELECT id FROM problem_logs WHERE id = $1", id)
+        .fetch_optional(&mut *tx)
+        .await
     {
-        Ok(records) => records.into_iter().map(|r| r.employee_id).collect(),
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"}));
+        }
         Err(e) => {
-            eprintln!("Database error when fetching assigned employees: {}", e);
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
             return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
         }
-    };
+    }
 
-    // Create the complete problem log object
-    let problem_log = ProblemLog {
-        id: Some(problem_log_record.id),
-        is_open: problem_log_record.is_open,
-        date_opened: problem_log_record.date_opened.unwrap_or_default(),
-        customer_name: problem_log_record.customer_name,
-        problem_type: problem_log_record.problem_type,
-        assigned_to: assigned_employees,
-        problem_description: problem_log_record.problem_description,
-        recall: problem_log_record.recall,
-        date_resolved: problem_log_record.date_resolved,
+    if let Err(response) = problem_log_employee_check(&mut tx, id, comment.employee_id).await {
+        let _ = tx.rollback().await;
+        return response;
+    }
+
+    let activity = match record_problem_log_activity(
+        &mut tx, id, ActivityType::Comment, None, None, Some(body), comment.employee_id,
+    ).await {
+        Ok(activity) => activity,
+        Err(e) => {
+            eprintln!("Failed to record problem log activity: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to add comment"}));
+        }
     };
 
-    HttpResponse::Ok().json(problem_log)
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Created().json(activity)
 }
 
-async fn get_all_problem_logs(
+// The activity thread of a problem log, oldest first. Entries are never
+// edited or removed.
+async fn get_problem_log_activity(
     req: HttpRequest,
+    path: web::Path<i32>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    // Extract auth token from request
-    let token = match req.headers().get("Authorization") {
-        Some(header) => {
-            match header.to_str() {
-                Ok(header_str) => {
-                    if header_str.starts_with("Bearer ") {
-                        &header_str[7..]
-                    } else {
-                        return HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid authorization header format"
-                        }));
-                    }
-                },
-                Err(_) => {
-                    return HttpResponse::Unauthorized().json(serde_json::json!({
-                        "error": "Invalid authorization header"
-                    }));
-                }
-            }
-        },
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authorization header is missing"
-            }));
-        }
+    let id = path.into_inner();
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
-    
-    // Verify token and get organization ID
-    let claims = match auth::verify_token(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Invalid auth token"
+
+    match sqlx::query!("SELECT org_id FROM problem_logs WHERE id = $1", id)
+        .fetch_optional(&data.db_pool)
+        .await
+    {
+        Ok(Some(record)) if record.org_id.is_some_and(|org_id| org_id != auth_org_id) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "You don't have permission to access this problem log"
             }));
         }
-    };
-    
-    let org_id = claims.org_id;
-
-    // First, check if org_id column exists in problem_logs table
-    let column_check = sqlx::query!("
-        SELECT column_name 
-        FROM information_schema.columns 
-        WHERE table_name = 'problem_logs' AND column_name = 'org_id'
-    ")
-    .fetch_optional(&data.db_pool)
-    .await;
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    }
 
-    // Build the right query based on whether org_id column exists
-    let sql_query = if let Ok(Some(_)) = column_check {
-        format!("SELECT id, is_open, date_opened::text as date_opened, customer_name, problem_type, problem_description, 
-                recall, date_resolved::text as date_resolved 
-                FROM problem_logs 
-                WHERE org_id = {} 
-                ORDER BY id DESC", org_id)
-    } else {
-        // Fall back to getting all problem logs if org_id column doesn't exist
-        eprintln!("Warning: org_id column not found in problem_logs table - cannot filter by organization");
-        "SELECT id, is_open, date_opened::text as date_opened, customer_name, problem_type, problem_description, 
-        recall, date_resolved::text as date_resolved 
-        FROM problem_logs 
-        ORDER BY id DESC".to_string()
-    };
-    
-    // Use direct SQL query
-    let problem_log_records = match sqlx::query(&sql_query)
+    match sqlx::query!(
+        "SELECT id, activity_type, from_status, to_status, body, employee_id, created_at::text as \"created_at!\"
+         FROM problem_log_activity WHERE problem_log_id = $1 ORDER BY created_at, id",
+        id
+    )
     .fetch_all(&data.db_pool)
     .await
     {
-        Ok(records) => records,
+        Ok(records) => {
+            let activity: Vec<ProblemLogActivity> = records.into_iter()
+                .map(|record| ProblemLogActivity {
+                    id: record.id,
+                    problem_log_id: id,
+                    activity_type: ActivityType::parse(&record.activity_type).unwrap_or(ActivityType::Comment),
+                    from_status: record.from_status.as_deref().and_then(ProblemStatus::parse),
+                    to_status: record.to_status.as_deref().and_then(ProblemStatus::parse),
+                    body: record.body,
+                    employee_id: record.employee_id,
+                    created_at: record.created_at,
+                })
+                .collect();
+            HttpResponse::Ok().json(activity)
+        }
         Err(e) => {
             eprintln!("Database error: {}", e);
-            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch problem log activity"}))
         }
-    };
+    }
+}
 
-    // Create a vector to hold all problem logs
-    let mut problem_logs = Vec::new();
+// Problem log SLA endpoints
+
+// Recompute the due dates of an organization's unresolved logs of a problem
+// type after its SLA changes
+async fn refresh_sla_due_dates(conn: &mut PgConnection, org_id: i32, problem_type: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE problem_logs pl SET response_due_at = pl.opened_at + make_interval(hours => s.response_hours),
+         resolution_due_at = pl.opened_at + make_interval(hours => s.resolution_hours)
+         FROM problem_logs p
+         LEFT JOIN problem_log_slas s ON s.org_id = p.org_id AND LOWER(s.problem_type) = LOWER(p.problem_type)
+         WHERE p.org_id = $1 AND LOWER(p.problem_type) = LOWER($2) AND p.status NOT IN ('resolved', 'closed')
+         AND pl.id = p.id",
+        org_id,
+        problem_type
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
 
-    // For each problem log, get its assigned employees
-    for record in problem_log_records {
-        // Get fields from the PgRow
-        let problem_log_id: i32 = record.try_get("id").unwrap_or_default();
-        let is_open: bool = record.try_get("is_open").unwrap_or_default();
-        let date_opened: Option<String> = record.try_get("date_opened").unwrap_or_default();
-        let customer_name: String = record.try_get("customer_name").unwrap_or_default();
-        let problem_type: String = record.try_get("problem_type").unwrap_or_default();
-        let problem_description: String = record.try_get("problem_description").unwrap_or_default();
-        let recall: bool = record.try_get("recall").unwrap_or_default();
-        let date_resolved: Option<String> = record.try_get("date_resolved").unwrap_or_default();
+async fn create_problem_log_sla(
+    sla: web::Json<ProblemLogSlaInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = problemlogs::check_sla(&sla.problem_type, sla.response_hours, sla.resolution_hours) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    }
+    let problem_type = sla.problem_type.trim();
 
-        // Get the assigned employees
-        let assigned_employees = match sqlx::query!(
-            "SELECT employee_id FROM problem_logs_employees WHERE problem_log_id = $1",
-            problem_log_id
-        )
-        .fetch_all(&data.db_pool)
-        .await
-        {
-            Ok(records) => records.into_iter().map(|r| r.employee_id).collect(),
-            Err(e) => {
-                eprintln!("Database error when fetching assigned employees for problem log {}: {}", problem_log_id, e);
-                continue; // Skip this problem log if we can't get its assigned employees
+    let mut tx = match data.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let created = match sqlx::query_as!(
+        ProblemLogSla,
+        "INSERT INTO problem_log_slas (org_id, problem_type, response_hours, resolution_hours)
+         VALUES ($1, $2, $3, $4)
+         RETURNING id, org_id, problem_type, response_hours, resolution_hours",
+        sla.org_id,
+        problem_type,
+        sla.response_hours,
+        sla.resolution_hours
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(created) => created,
+        Err(e) => {
+            let _ = tx.rollback().await;
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("There is already an SLA for {}", problem_type)
+                }));
             }
-        };
+            eprintln!("Failed to create problem log SLA: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create SLA"}));
+        }
+    };
 
-        // Create the complete problem log object
-        let problem_log = ProblemLog {
-            id: Some(problem_log_id),
-            is_open,
-            date_opened: date_opened.unwrap_or_default(),
-            customer_name,
-            problem_type,
-            assigned_to: assigned_employees,
-            problem_description,
-            recall,
-            date_resolved,
-        };
+    if let Err(e) = refresh_sla_due_dates(&mut tx, sla.org_id, problem_type).await {
+        eprintln!("Failed to update problem log due dates: {}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create SLA"}));
+    }
 
-        problem_logs.push(problem_log);
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
     }
 
-    HttpResponse::Ok().json(problem_logs)
+    HttpResponse::Created().json(created)
 }
 
-async fn update_problem_log(
+async fn get_problem_log_sla(
+    req: HttpRequest,
     path: web::Path<i32>,
-    problem_log: web::Json<ProblemLogInput>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
-    
-    // Parse date_opened string to NaiveDate
-    let date_opened = match NaiveDate::parse_from_str(&problem_log.date_opened, "%Y-%m-%d") {
-        Ok(date) => date,
-        Err(_) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid date_opened format. Use YYYY-MM-DD"
-            }));
+
+    let auth_org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        ProblemLogSla,
+        "SELECT id, org_id, problem_type, response_hours, resolution_hours FROM problem_log_slas WHERE id = $1",
+        id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(sla)) if sla.org_id == auth_org_id => HttpResponse::Ok().json(sla),
+        Ok(Some(_)) => HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "You don't have permission to access this SLA"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "SLA not found"})),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
         }
+    }
+}
+
+async fn get_all_problem_log_slas(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
     };
 
-    // Parse date_resolved string to NaiveDate if it exists
-    let date_resolved = match &problem_log.date_resolved {
-        Some(date_str) => {
-            match NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
-                Ok(date) => Some(date),
-                Err(_) => {
-                    return HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Invalid date_resolved format. Use YYYY-MM-DD"
-                    }));
-                }
-            }
-        },
-        None => None
-    };
+    match sqlx::query_as!(
+        ProblemLogSla,
+        "SELECT id, org_id, problem_type, response_hours, resolution_hours FROM problem_log_slas
+         WHERE org_id = $1 ORDER BY problem_type, id",
+        org_id
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(slas) => HttpResponse::Ok().json(slas),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch SLAs"}))
+        }
+    }
+}
+
+async fn update_problem_log_sla(
+    path: web::Path<i32>,
+    sla: web::Json<ProblemLogSlaInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    if let Err(e) = problemlogs::check_sla(&sla.problem_type, sla.response_hours, sla.resolution_hours) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+    }
+    let problem_type = sla.problem_type.trim();
 
-    // Start a transaction
     let mut tx = match data.db_pool.begin().await {
         Ok(tx) => tx,
         Err(e) => {
@@ -2337,89 +12848,74 @@ async fn update_problem_log(
         }
     };
 
-    // Update the problem log
-    let update_result = sqlx::query!(
-        "UPDATE problem_logs SET is_open = $1, date_opened = $2, customer_name = $3, problem_type = $4, 
-         problem_description = $5, recall = $6, date_resolved = $7 WHERE id = $8 RETURNING id",
-        problem_log.is_open,
-        date_opened,
-        problem_log.customer_name,
-        problem_log.problem_type,
-        problem_log.problem_description,
-        problem_log.recall,
-        date_resolved,
-        id
+    let previous_type = match sqlx::query_scalar!(
+        "SELECT problem_type FROM problem_log_slas WHERE id = $1 AND org_id = $2 FOR UPDATE",
+        id,
+        sla.org_id
     )
     .fetch_optional(&mut *tx)
-    .await;
-
-    match update_result {
-        Ok(Some(_)) => {
-            // Delete existing problem log-employee relationships
-            if let Err(e) = sqlx::query!("DELETE FROM problem_logs_employees WHERE problem_log_id = $1", id)
-                .execute(&mut *tx)
-                .await
-            {
-                eprintln!("Failed to delete employee relations: {}", e);
-                let _ = tx.rollback().await;
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
-            }
-
-            // Insert new problem log-employee relationships
-            for employee_id in &problem_log.assigned_to {
-                if let Err(e) = sqlx::query!(
-                    "INSERT INTO problem_logs_employees (problem_log_id, employee_id) VALUES ($1, $2)",
-                    id,
-                    employee_id
-                )
-                .execute(&mut *tx)
-                .await
-                {
-                    eprintln!("Failed to link employee to problem log: {}", e);
-                    let _ = tx.rollback().await;
-                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update problem log"}));
-                }
-            }
-
-            // Commit the transaction
-            if let Err(e) = tx.commit().await {
-                eprintln!("Failed to commit transaction: {}", e);
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
-            }
-
-            // Return the updated problem log
-            let updated_problem_log = ProblemLog {
-                id: Some(id),
-                is_open: problem_log.is_open,
-                date_opened: problem_log.date_opened.clone(),
-                customer_name: problem_log.customer_name.clone(),
-                problem_type: problem_log.problem_type.clone(),
-                assigned_to: problem_log.assigned_to.clone(),
-                problem_description: problem_log.problem_description.clone(),
-                recall: problem_log.recall,
-                date_resolved: problem_log.date_resolved.clone(),
-            };
-            HttpResponse::Ok().json(updated_problem_log)
-        },
+    .await
+    {
+        Ok(Some(previous_type)) => previous_type,
         Ok(None) => {
             let _ = tx.rollback().await;
-            HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"}))
-        },
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "SLA not found"}));
+        }
         Err(e) => {
             eprintln!("Database error: {}", e);
             let _ = tx.rollback().await;
-            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    let updated = match sqlx::query_as!(
+        ProblemLogSla,
+        "UPDATE problem_log_slas SET problem_type = $1, response_hours = $2, resolution_hours = $3
+         WHERE id = $4
+         RETURNING id, org_id, problem_type, response_hours, resolution_hours",
+        problem_type,
+        sla.response_hours,
+        sla.resolution_hours,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(updated) => updated,
+        Err(e) => {
+            let _ = tx.rollback().await;
+            if e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23505") {
+                return HttpResponse::Conflict().json(serde_json::json!({
+                    "error": format!("There is already an SLA for {}", problem_type)
+                }));
+            }
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    for problem_type in [previous_type.as_str(), problem_type] {
+        if let Err(e) = refresh_sla_due_dates(&mut tx, sla.org_id, problem_type).await {
+            eprintln!("Failed to update problem log due dates: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to update SLA"}));
         }
     }
+
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::Ok().json(updated)
 }
 
-async fn delete_problem_log(
+async fn delete_problem_log_sla(
     path: web::Path<i32>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let id = path.into_inner();
-    
-    // Start a transaction
+
     let mut tx = match data.db_pool.begin().await {
         Ok(tx) => tx,
         Err(e) => {
@@ -2428,36 +12924,255 @@ async fn delete_problem_log(
         }
     };
 
-    // Delete problem log-employee relationships first
-    if let Err(e) = sqlx::query!("DELETE FROM problem_logs_employees WHERE problem_log_id = $1", id)
-        .execute(&mut *tx)
+    let deleted = match sqlx::query!("DELETE FROM problem_log_slas WHERE id = $1 RETURNING org_id, problem_type", id)
+        .fetch_optional(&mut *tx)
         .await
     {
-        eprintln!("Failed to delete employee relations: {}", e);
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "SLA not found"}));
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+        }
+    };
+
+    // Open logs of the type no longer have due dates
+    if let Err(e) = refresh_sla_due_dates(&mut tx, deleted.org_id, &deleted.problem_type).await {
+        eprintln!("Failed to update problem log due dates: {}", e);
         let _ = tx.rollback().await;
-        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete problem log"}));
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to delete SLA"}));
     }
 
-    // Delete the problem log
-    match sqlx::query!("DELETE FROM problem_logs WHERE id = $1 RETURNING id", id)
+    if let Err(e) = tx.commit().await {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+// Unresolved problem logs of the organization that have missed their
+// response or resolution due date, most overdue first
+async fn get_overdue_problem_logs(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    let now = chrono::Utc::now();
+    match sqlx::query!(
+        "SELECT pl.id, pl.problem_type, pl.status, pl.customer_name, pl.date_opened::text as \"date_opened!\",
+         pl.responded_at, pl.response_due_at, pl.resolution_due_at, pl.escalation_level,
+         pl.responded_at::text as responded_at_text, pl.response_due_at::text as response_due_at_text,
+         pl.resolution_due_at::text as resolution_due_at_text,
+         ARRAY(SELECT employee_id FROM problem_logs_employees WHERE problem_log_id = pl.id ORDER BY employee_id)
+            as \"assigned_to!\"
+         FROM problem_logs pl
+         WHERE pl.org_id = $1 AND pl.status NOT IN ('resolved', 'closed')
+         AND ((pl.responded_at IS NULL AND pl.response_due_at < $2) OR pl.resolution_due_at < $2)
+         ORDER BY LEAST(CASE WHEN pl.responded_at IS NULL THEN pl.response_due_at END, pl.resolution_due_at), pl.id",
+        org_id,
+        now
+    )
+    .fetch_all(&data.db_pool)
+    .await
+    {
+        Ok(records) => {
+            let overdue: Vec<OverdueProblemLog> = records.into_iter()
+                .filter_map(|record| {
+                    let status = ProblemStatus::parse(&record.status).unwrap_or_default();
+                    let breach = problemlogs::sla_breach(
+                        status, record.responded_at, record.response_due_at, record.resolution_due_at, now,
+                    )?;
+                    Some(OverdueProblemLog {
+                        id: record.id,
+                        problem_type: record.problem_type,
+                        status,
+                        customer_name: record.customer_name,
+                        date_opened: record.date_opened,
+                        assigned_to: record.assigned_to,
+                        breach,
+                        response_due_at: record.response_due_at_text,
+                        resolution_due_at: record.resolution_due_at_text,
+                        responded_at: record.responded_at_text,
+                        escalation_level: record.escalation_level,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(overdue)
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch overdue problem logs"}))
+        }
+    }
+}
+
+// Escalate every overdue problem log one step as of `now`: notify the
+// assigned employees, then the organization's QA employees. Returns how many
+// logs were escalated. Run periodically by the server.
+pub async fn escalate_overdue_problem_logs(
+    pool: &Pool<Postgres>,
+    settings: &EscalationSettings,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<usize, sqlx::Error> {
+    let overdue = sqlx::query!(
+        "SELECT pl.id, pl.org_id as \"org_id!\", pl.problem_type, pl.status, pl.responded_at, pl.response_due_at,
+         pl.resolution_due_at, pl.escalation_level, pl.escalated_at,
+         ARRAY(SELECT employee_id FROM problem_logs_employees WHERE problem_log_id = pl.id ORDER BY employee_id)
+            as \"assigned_to!\"
+         FROM problem_logs pl
+         WHERE pl.org_id IS NOT NULL AND pl.status NOT IN ('resolved', 'closed') AND pl.escalation_level < 2
+         AND ((pl.responded_at IS NULL AND pl.response_due_at < $1) OR pl.resolution_due_at < $1)
+         ORDER BY pl.id",
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut escalated = 0;
+    for log in overdue {
+        let status = ProblemStatus::parse(&log.status).unwrap_or_default();
+        let breach = match problemlogs::sla_breach(status, log.responded_at, log.response_due_at, log.resolution_due_at, now) {
+            Some(breach) => breach,
+            None => continue,
+        };
+        let target = match problemlogs::next_escalation(
+            log.escalation_level, log.escalated_at, !log.assigned_to.is_empty(), settings.qa_delay, now,
+        ) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        let mut tx = pool.begin().await?;
+
+        // Another server may have escalated the log in the meantime
+        let claimed = sqlx::query!(
+            "UPDATE problem_logs SET escalation_level = $1, escalated_at = $2
+             WHERE id = $3 AND escalation_level = $4 RETURNING id",
+            target.level(),
+            now,
+            log.id,
+            log.escalation_level
+        )
         .fetch_optional(&mut *tx)
-        .await
+        .await?;
+        if claimed.is_none() {
+            tx.rollback().await?;
+            continue;
+        }
+
+        let recipients = match target {
+            EscalationTarget::AssignedEmployees => log.assigned_to.clone(),
+            EscalationTarget::QualityAssurance => sqlx::query_scalar!(
+                "SELECT id FROM employees WHERE org_id = $1 AND LOWER(role) = LOWER($2) ORDER BY id",
+                log.org_id,
+                settings.qa_role
+            )
+            .fetch_all(&mut *tx)
+            .await?,
+        };
+        if recipients.is_empty() {
+            eprintln!("No {} employees to notify about overdue problem log {}", settings.qa_role, log.id);
+        }
+
+        let message = match target {
+            EscalationTarget::AssignedEmployees => format!(
+                "Problem log {} ({}) has missed its {} due date",
+                log.id, log.problem_type, breach.as_str()
+            ),
+            EscalationTarget::QualityAssurance => format!(
+                "Problem log {} ({}) has missed its {} due date and is escalated to {}",
+                log.id, log.problem_type, breach.as_str(), settings.qa_role
+            ),
+        };
+        for employee_id in &recipients {
+            sqlx::query!(
+                "INSERT INTO notifications (org_id, employee_id, problem_log_id, message) VALUES ($1, $2, $3, $4)",
+                log.org_id,
+                employee_id,
+                log.id,
+                message
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        record_problem_log_activity(&mut tx, log.id, ActivityType::Escalation, None, None, Some(&message), None).await?;
+        tx.commit().await?;
+        escalated += 1;
+    }
+
+    Ok(escalated)
+}
+
+// Notification endpoints
+async fn get_notifications(
+    req: HttpRequest,
+    query: web::Query<NotificationQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Notification,
+        "SELECT id, org_id, employee_id, problem_log_id, message, created_at::text as \"created_at!\", read_at::text
+         FROM notifications
+         WHERE org_id = $1 AND ($2::int IS NULL OR employee_id = $2) AND (NOT $3 OR read_at IS NULL)
+         ORDER BY created_at DESC, id DESC",
+        org_id,
+        query.employee_id,
+        query.unread
+    )
+    .fetch_all(&data.db_pool)
+    .await
     {
-        Ok(Some(_)) => {
-            // Commit the transaction
-            if let Err(e) = tx.commit().await {
-                eprintln!("Failed to commit transaction: {}", e);
-                return HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}));
-            }
-            HttpResponse::NoContent().finish()
-        },
-        Ok(None) => {
-            let _ = tx.rollback().await;
-            HttpResponse::NotFound().json(serde_json::json!({"error": "Problem log not found"}))
-        },
+        Ok(notifications) => HttpResponse::Ok().json(notifications),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to fetch notifications"}))
+        }
+    }
+}
+
+async fn mark_notification_read(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let org_id = match auth::org_id_from_request(&req) {
+        Ok(org_id) => org_id,
+        Err(e) => return HttpResponse::Unauthorized().json(serde_json::json!({"error": e.to_string()})),
+    };
+
+    match sqlx::query_as!(
+        Notification,
+        "UPDATE notifications SET read_at = COALESCE(read_at, CURRENT_TIMESTAMP)
+         WHERE id = $1 AND org_id = $2
+         RETURNING id, org_id, employee_id, problem_log_id, message, created_at::text as \"created_at!\",
+         read_at::text",
+        id,
+        org_id
+    )
+    .fetch_optional(&data.db_pool)
+    .await
+    {
+        Ok(Some(notification)) => HttpResponse::Ok().json(notification),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({"error": "Notification not found"})),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            let _ = tx.rollback().await;
             HttpResponse::InternalServerError().json(serde_json::json!({"error": "Internal server error"}))
         }
     }
@@ -2468,6 +13183,8 @@ pub fn configure_app(config: &mut web::ServiceConfig, db_pool: Pool<Postgres>) {
     config
         .app_data(web::Data::new(AppState {
             db_pool: db_pool.clone(),
+            document_storage: documents::storage_from_env().ok(),
+            complaint_limiter: complaints::limiter_from_env(),
         }))
         .route("/health", web::get().to(health_check))
         .service(
@@ -2487,6 +13204,8 @@ pub fn configure_app(config: &mut web::ServiceConfig, db_pool: Pool<Postgres>) {
                         .route("/{id}", web::get().to(get_organization))
                         .route("/{id}", web::put().to(update_organization))
                         .route("/{id}", web::delete().to(delete_organization))
+                        .route("/{id}/publicslug", web::get().to(get_public_slug))
+                        .route("/{id}/publicslug", web::put().to(update_public_slug))
                 )
                 // Employee endpoints
                 .service(
@@ -2505,15 +13224,30 @@ pub fn configure_app(config: &mut web::ServiceConfig, db_pool: Pool<Postgres>) {
                         .route("/{id}", web::get().to(get_recipe))
                         .route("/{id}", web::put().to(update_recipe))
                         .route("/{id}", web::delete().to(delete_recipe))
+                        .route("/{id}/scale", web::post().to(scale_recipe))
+                        .route("/{id}/versions", web::get().to(get_recipe_versions))
+                        .route("/{id}/versions/{version}", web::get().to(get_recipe_version))
+                        .route("/{id}/diff", web::get().to(diff_recipe))
                 )
                 // Ingredient endpoints
                 .service(
                     web::scope("/ingredients")
                         .route("", web::post().to(create_ingredient))
                         .route("", web::get().to(get_all_ingredients))
+                        .route("/expiring", web::get().to(get_expiring_ingredients))
                         .route("/{id}", web::get().to(get_ingredient))
                         .route("/{id}", web::put().to(update_ingredient))
                         .route("/{id}", web::delete().to(delete_ingredient))
+                        .route("/{id}/receiving", web::get().to(get_ingredient_receiving_history))
+                        .route("/{id}/status", web::get().to(get_ingredient_status_history))
+                        .route("/{id}/status", web::put().to(update_ingredient_status))
+                )
+                // Shelf life endpoints
+                .service(
+                    web::scope("/shelflife")
+                        .route("", web::post().to(create_shelf_life_default))
+                        .route("", web::get().to(get_shelf_life_defaults))
+                        .route("/{id}", web::delete().to(delete_shelf_life_default))
                 )
                 // Batch endpoints
                 .service(
@@ -2523,21 +13257,192 @@ pub fn configure_app(config: &mut web::ServiceConfig, db_pool: Pool<Postgres>) {
                         .route("/{id}", web::get().to(get_batch))
                         .route("/{id}", web::put().to(update_batch))
                         .route("/{id}", web::delete().to(delete_batch))
+                        .route("/{id}/status", web::get().to(get_batch_status_history))
+                        .route("/{id}/status", web::put().to(update_batch_status))
+                        .route("/{id}/shipments", web::get().to(get_batch_shipments))
+                        .route("/{id}/label", web::get().to(get_batch_label))
+                )
+                // Hold list endpoints
+                .service(
+                    web::scope("/holds")
+                        .route("", web::get().to(get_hold_list))
+                )
+                // Inventory endpoints
+                .service(
+                    web::scope("/inventory")
+                        .route("/adjustments", web::post().to(create_inventory_adjustment))
+                        .route("/lots", web::get().to(get_lot_inventory))
+                        .route("/lots/{id}", web::get().to(get_lot_inventory_detail))
+                        .route("/ingredients", web::get().to(get_ingredient_inventory))
+                )
+                // Allergen changeover endpoints
+                .service(
+                    web::scope("/changeovers")
+                        .route("", web::post().to(create_allergen_changeover))
+                        .route("", web::get().to(get_all_allergen_changeovers))
+                        .route("/{id}", web::get().to(get_allergen_changeover))
                 )
                 // Problem Log endpoints
                 .service(
                     web::scope("/problemlogs")
                         .route("", web::post().to(create_problem_log))
                         .route("", web::get().to(get_all_problem_logs))
+                        .route("/overdue", web::get().to(get_overdue_problem_logs))
                         .route("/{id}", web::get().to(get_problem_log))
                         .route("/{id}", web::put().to(update_problem_log))
                         .route("/{id}", web::delete().to(delete_problem_log))
+                        .route("/{id}/transitions", web::post().to(transition_problem_log))
+                        .route("/{id}/comments", web::post().to(add_problem_log_comment))
+                        .route("/{id}/activity", web::get().to(get_problem_log_activity))
+                )
+                // Problem log SLA endpoints
+                .service(
+                    web::scope("/problemlogslas")
+                        .route("", web::post().to(create_problem_log_sla))
+                        .route("", web::get().to(get_all_problem_log_slas))
+                        .route("/{id}", web::get().to(get_problem_log_sla))
+                        .route("/{id}", web::put().to(update_problem_log_sla))
+                        .route("/{id}", web::delete().to(delete_problem_log_sla))
+                )
+                // Notification endpoints
+                .service(
+                    web::scope("/notifications")
+                        .route("", web::get().to(get_notifications))
+                        .route("/{id}/read", web::post().to(mark_notification_read))
+                )
+                // Unit conversion endpoints
+                .service(
+                    web::scope("/units")
+                        .route("/convert", web::post().to(convert_quantity))
+                )
+                // Supplier endpoints
+                .service(
+                    web::scope("/suppliers")
+                        .route("", web::post().to(create_supplier))
+                        .route("", web::get().to(get_all_suppliers))
+                        .route("/{id}", web::get().to(get_supplier))
+                        .route("/{id}", web::put().to(update_supplier))
+                        .route("/{id}", web::delete().to(delete_supplier))
+                )
+                // Customer endpoints
+                .service(
+                    web::scope("/customers")
+                        .route("", web::post().to(create_customer))
+                        .route("", web::get().to(get_all_customers))
+                        .route("/{id}", web::get().to(get_customer))
+                        .route("/{id}", web::put().to(update_customer))
+                        .route("/{id}", web::delete().to(delete_customer))
+                )
+                // Shipment endpoints
+                .service(
+                    web::scope("/shipments")
+                        .route("", web::post().to(create_shipment))
+                        .route("", web::get().to(get_all_shipments))
+                        .route("/{id}", web::get().to(get_shipment))
+                        .route("/{id}", web::put().to(update_shipment))
+                        .route("/{id}", web::delete().to(delete_shipment))
+                )
+                // Traceability endpoints
+                .service(
+                    web::scope("/traceability")
+                        .route("/fsma204", web::get().to(export_fsma204))
+                )
+                // EPCIS endpoints
+                .service(
+                    web::scope("/epcis")
+                        .route("/events", web::get().to(export_epcis))
+                        .route("/import", web::post().to(import_epcis))
+                )
+                // Label endpoints
+                .service(
+                    web::scope("/labeltemplates")
+                        .route("", web::post().to(create_label_template))
+                        .route("", web::get().to(get_all_label_templates))
+                        .route("/{id}", web::get().to(get_label_template))
+                        .route("/{id}", web::put().to(update_label_template))
+                        .route("/{id}", web::delete().to(delete_label_template))
+                )
+                // Lot code endpoints
+                .service(
+                    web::scope("/lotcodeschemes")
+                        .route("", web::post().to(create_lot_code_scheme))
+                        .route("", web::get().to(get_all_lot_code_schemes))
+                        .route("/{id}", web::get().to(get_lot_code_scheme))
+                        .route("/{id}", web::put().to(update_lot_code_scheme))
+                        .route("/{id}", web::delete().to(delete_lot_code_scheme))
+                )
+                // HACCP plan endpoints
+                .service(
+                    web::scope("/haccpplans")
+                        .route("", web::post().to(create_haccp_plan))
+                        .route("", web::get().to(get_all_haccp_plans))
+                        .route("/{id}", web::get().to(get_haccp_plan))
+                        .route("/{id}", web::put().to(update_haccp_plan))
+                        .route("/{id}", web::delete().to(delete_haccp_plan))
+                        .route("/{id}/versions", web::get().to(get_haccp_plan_versions))
+                        .route("/{id}/versions/{version}", web::get().to(get_haccp_plan_version))
+                )
+                // CCP monitoring endpoints
+                .service(
+                    web::scope("/ccpmonitoring")
+                        .route("", web::post().to(create_ccp_monitoring_record))
+                        .route("", web::get().to(get_all_ccp_monitoring_records))
+                        .route("/{id}", web::get().to(get_ccp_monitoring_record))
+                )
+                // CAPA endpoints
+                .service(
+                    web::scope("/capas")
+                        .route("", web::post().to(create_capa))
+                        .route("", web::get().to(get_all_capas))
+                        .route("/{id}", web::get().to(get_capa))
+                        .route("/{id}", web::put().to(update_capa))
+                        .route("/{id}", web::delete().to(delete_capa))
+                )
+                // Complaint endpoints
+                .service(
+                    web::scope("/complaints")
+                        .route("", web::get().to(get_all_complaints))
+                        .route("/trends", web::get().to(get_complaint_trends))
+                        .route("/alerts", web::get().to(get_complaint_alerts))
+                        .route("/{id}", web::get().to(get_complaint))
+                )
+                // Complaint category endpoints
+                .service(
+                    web::scope("/complaintcategories")
+                        .route("", web::post().to(create_complaint_category))
+                        .route("", web::get().to(get_all_complaint_categories))
+                        .route("/{id}", web::get().to(get_complaint_category))
+                        .route("/{id}", web::put().to(update_complaint_category))
+                        .route("/{id}", web::delete().to(delete_complaint_category))
+                )
+                // Public endpoints, no authentication
+                .route("/public/{slug}/complaints", web::post().to(submit_complaint))
+                // Document endpoints
+                .service(
+                    web::scope("/documents")
+                        .app_data(web::PayloadConfig::new(documents::max_bytes_from_env()))
+                        .route("", web::post().to(upload_document))
+                        .route("", web::get().to(get_documents))
+                        .route("/expiring", web::get().to(get_expiring_documents))
+                        .route("/{id}", web::get().to(get_document))
+                        .route("/{id}", web::delete().to(delete_document))
+                        .route("/{id}/content", web::get().to(get_document_content))
+                )
+                // Receiving Checklist endpoints
+                .service(
+                    web::scope("/receivingchecklists")
+                        .route("", web::post().to(create_receiving_checklist))
+                        .route("", web::get().to(get_all_receiving_checklists))
+                        .route("/{id}", web::get().to(get_receiving_checklist))
+                        .route("/{id}", web::put().to(update_receiving_checklist))
+                        .route("/{id}", web::delete().to(delete_receiving_checklist))
                 )
                 // Receiving Log endpoints
                 .service(
                     web::scope("/receivinglogs")
                         .route("", web::post().to(create_receiving_log))
                         .route("", web::get().to(get_all_receiving_logs))
+                        .route("/scan", web::post().to(scan_receiving_label))
                         .route("/{id}", web::get().to(get_receiving_log))
                         .route("/{id}", web::put().to(update_receiving_log))
                         .route("/{id}", web::delete().to(delete_receiving_log))
@@ -2569,6 +13474,26 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to run database migrations");
     
+    // Refuse to start with a document storage backend we cannot use
+    if let Err(e) = documents::storage_from_env() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()));
+    }
+    
+    // Escalate overdue problem logs in the background
+    let escalation_pool = db_pool.clone();
+    let escalation_settings = EscalationSettings::from_env();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(escalation_settings.interval);
+        loop {
+            interval.tick().await;
+            match escalate_overdue_problem_logs(&escalation_pool, &escalation_settings, chrono::Utc::now()).await {
+                Ok(0) => {}
+                Ok(escalated) => println!("Escalated {} overdue problem log(s)", escalated),
+                Err(e) => eprintln!("Failed to escalate overdue problem logs: {}", e),
+            }
+        }
+    });
+    
     let host = env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     let server_url = format!("{}:{}", host, port);
@@ -0,0 +1,253 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// Error types
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum WorkflowError {
+    #[error("A problem log cannot move from {} to {}", .from.as_str(), .to.as_str())]
+    InvalidTransition { from: ProblemStatus, to: ProblemStatus },
+
+    #[error("Resolution notes are required to move a problem log to {}", .0.as_str())]
+    MissingResolutionNotes(ProblemStatus),
+
+    #[error("A comment is required to move a problem log to {}", .0.as_str())]
+    MissingComment(ProblemStatus),
+
+    #[error("SLAs need a problem type and positive response and resolution hours")]
+    InvalidSla,
+
+    #[error("The resolution SLA cannot be shorter than the response SLA")]
+    ResolutionBeforeResponse,
+}
+
+// new -> investigating <-> awaiting_customer -> resolved -> closed, with
+// resolved and closed logs able to be reopened. New logs can be resolved or
+// closed straight away, e.g. duplicates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemStatus {
+    #[default]
+    New,
+    Investigating,
+    AwaitingCustomer,
+    Resolved,
+    Closed,
+    Reopened,
+}
+
+impl ProblemStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProblemStatus::New => "new",
+            ProblemStatus::Investigating => "investigating",
+            ProblemStatus::AwaitingCustomer => "awaiting_customer",
+            ProblemStatus::Resolved => "resolved",
+            ProblemStatus::Closed => "closed",
+            ProblemStatus::Reopened => "reopened",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ProblemStatus> {
+        match value {
+            "new" => Some(ProblemStatus::New),
+            "investigating" => Some(ProblemStatus::Investigating),
+            "awaiting_customer" => Some(ProblemStatus::AwaitingCustomer),
+            "resolved" => Some(ProblemStatus::Resolved),
+            "closed" => Some(ProblemStatus::Closed),
+            "reopened" => Some(ProblemStatus::Reopened),
+            _ => None,
+        }
+    }
+
+    // Everything but closed counts as open
+    pub fn is_open(self) -> bool {
+        self != ProblemStatus::Closed
+    }
+
+    // Resolved and closed logs carry a resolution date
+    pub fn is_resolved(self) -> bool {
+        matches!(self, ProblemStatus::Resolved | ProblemStatus::Closed)
+    }
+
+    pub fn can_move_to(self, to: ProblemStatus) -> bool {
+        use ProblemStatus::*;
+        match self {
+            New => matches!(to, Investigating | AwaitingCustomer | Resolved | Closed),
+            Investigating | Reopened => matches!(to, Investigating | AwaitingCustomer | Resolved) && to != self,
+            AwaitingCustomer => matches!(to, Investigating | Resolved),
+            Resolved => matches!(to, Closed | Reopened),
+            Closed => to == Reopened,
+        }
+    }
+}
+
+// Entries of the activity thread of a problem log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityType {
+    Created,
+    Comment,
+    StatusChange,
+    Escalation,
+}
+
+impl ActivityType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActivityType::Created => "created",
+            ActivityType::Comment => "comment",
+            ActivityType::StatusChange => "status_change",
+            ActivityType::Escalation => "escalation",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ActivityType> {
+        match value {
+            "created" => Some(ActivityType::Created),
+            "comment" => Some(ActivityType::Comment),
+            "status_change" => Some(ActivityType::StatusChange),
+            "escalation" => Some(ActivityType::Escalation),
+            _ => None,
+        }
+    }
+}
+
+fn is_blank(value: Option<&str>) -> bool {
+    value.is_none_or(|value| value.trim().is_empty())
+}
+
+// Check a problem log may move from one status to another with the notes
+// given. Resolving and closing need resolution notes; waiting on the customer
+// and reopening need a comment saying why.
+pub fn check_transition(
+    from: ProblemStatus,
+    to: ProblemStatus,
+    resolution_notes: Option<&str>,
+    comment: Option<&str>,
+) -> Result<(), WorkflowError> {
+    if !from.can_move_to(to) {
+        return Err(WorkflowError::InvalidTransition { from, to });
+    }
+    match to {
+        ProblemStatus::Resolved | ProblemStatus::Closed if is_blank(resolution_notes) => {
+            Err(WorkflowError::MissingResolutionNotes(to))
+        }
+        ProblemStatus::AwaitingCustomer | ProblemStatus::Reopened if is_blank(comment) => {
+            Err(WorkflowError::MissingComment(to))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Check the response and resolution targets of a problem type
+pub fn check_sla(problem_type: &str, response_hours: i32, resolution_hours: i32) -> Result<(), WorkflowError> {
+    if problem_type.trim().is_empty() || response_hours <= 0 || resolution_hours <= 0 {
+        return Err(WorkflowError::InvalidSla);
+    }
+    if resolution_hours < response_hours {
+        return Err(WorkflowError::ResolutionBeforeResponse);
+    }
+    Ok(())
+}
+
+// Which target an overdue problem log has missed. A missed response is
+// reported first since it is the earlier deadline.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlaBreach {
+    Response,
+    Resolution,
+}
+
+impl SlaBreach {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SlaBreach::Response => "response",
+            SlaBreach::Resolution => "resolution",
+        }
+    }
+}
+
+// The target a problem log has missed at `now`, if any. Resolved and closed
+// logs have met their targets.
+pub fn sla_breach(
+    status: ProblemStatus,
+    responded_at: Option<DateTime<Utc>>,
+    response_due_at: Option<DateTime<Utc>>,
+    resolution_due_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Option<SlaBreach> {
+    if status.is_resolved() {
+        return None;
+    }
+    if responded_at.is_none() && response_due_at.is_some_and(|due| due < now) {
+        return Some(SlaBreach::Response);
+    }
+    if resolution_due_at.is_some_and(|due| due < now) {
+        return Some(SlaBreach::Resolution);
+    }
+    None
+}
+
+// Who hears about an overdue problem log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationTarget {
+    AssignedEmployees,
+    QualityAssurance,
+}
+
+impl EscalationTarget {
+    // The escalation_level recorded once the target has been notified
+    pub fn level(self) -> i16 {
+        match self {
+            EscalationTarget::AssignedEmployees => 1,
+            EscalationTarget::QualityAssurance => 2,
+        }
+    }
+}
+
+// How overdue problem logs are escalated, from SLA_ESCALATION_INTERVAL_SECS
+// (how often to check, default 300), SLA_QA_ROLE (employee role notified
+// second, default "QA") and SLA_QA_ESCALATION_HOURS (how long the assigned
+// employees have before QA is notified, default 24)
+#[derive(Debug, Clone)]
+pub struct EscalationSettings {
+    pub interval: std::time::Duration,
+    pub qa_role: String,
+    pub qa_delay: Duration,
+}
+
+impl EscalationSettings {
+    pub fn from_env() -> EscalationSettings {
+        let number = |name: &str, default: i64| {
+            env::var(name).ok().and_then(|value| value.parse().ok()).filter(|value| *value > 0).unwrap_or(default)
+        };
+        EscalationSettings {
+            interval: std::time::Duration::from_secs(number("SLA_ESCALATION_INTERVAL_SECS", 300) as u64),
+            qa_role: env::var("SLA_QA_ROLE").unwrap_or_else(|_| "QA".to_string()),
+            qa_delay: Duration::hours(number("SLA_QA_ESCALATION_HOURS", 24)),
+        }
+    }
+}
+
+// The next escalation of an overdue problem log. The assigned employees are
+// told first; QA is told once they have had qa_delay to act, or straight away
+// when nobody is assigned.
+pub fn next_escalation(
+    escalation_level: i16,
+    escalated_at: Option<DateTime<Utc>>,
+    has_assigned_employees: bool,
+    qa_delay: Duration,
+    now: DateTime<Utc>,
+) -> Option<EscalationTarget> {
+    match escalation_level {
+        0 if has_assigned_employees => Some(EscalationTarget::AssignedEmployees),
+        0 => Some(EscalationTarget::QualityAssurance),
+        1 if escalated_at.is_none_or(|escalated_at| now - escalated_at >= qa_delay) => {
+            Some(EscalationTarget::QualityAssurance)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+// Critical Tracking Events recorded under the FDA Food Traceability Rule
+// (21 CFR Part 1, Subpart S)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackingEvent {
+    Receiving,
+    Transformation,
+    Shipping,
+}
+
+impl TrackingEvent {
+    pub fn label(self) -> &'static str {
+        match self {
+            TrackingEvent::Receiving => "Receiving",
+            TrackingEvent::Transformation => "Transformation",
+            TrackingEvent::Shipping => "Shipping",
+        }
+    }
+}
+
+// One row of the electronic sortable spreadsheet: the Key Data Elements of a
+// tracking event. Transformations get one row per input lot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceabilityRecord {
+    pub event_type: TrackingEvent,
+    pub traceability_lot_code: String,
+    pub product_description: String,
+    pub quantity: Option<String>,
+    pub unit_of_measure: Option<String>,
+    pub event_date: String,
+    // Where the event took place
+    pub location_description: String,
+    // Receiving only: who the food came from
+    pub immediate_previous_source: Option<String>,
+    pub ship_from_location: Option<String>,
+    pub ship_to_location: Option<String>,
+    // Where the traceability lot code was assigned
+    pub traceability_lot_code_source: String,
+    // Transformation only: the lot used and how much
+    pub input_traceability_lot_code: Option<String>,
+    pub input_product_description: Option<String>,
+    pub input_quantity: Option<String>,
+    pub input_unit_of_measure: Option<String>,
+    pub reference_document_type: String,
+    pub reference_document_number: String,
+}
+
+pub const SPREADSHEET_COLUMNS: [&str; 17] = [
+    "Event Type",
+    "Traceability Lot Code",
+    "Product Description",
+    "Quantity",
+    "Unit of Measure",
+    "Event Date",
+    "Location Description",
+    "Immediate Previous Source",
+    "Ship From Location",
+    "Ship To Location",
+    "Traceability Lot Code Source",
+    "Input Traceability Lot Code",
+    "Input Product Description",
+    "Input Quantity",
+    "Input Unit of Measure",
+    "Reference Document Type",
+    "Reference Document Number",
+];
+
+// Order rows by date, then event, then lot so the spreadsheet reads as a
+// timeline
+pub fn sort_records(records: &mut [TraceabilityRecord]) {
+    records.sort_by(|a, b| {
+        (&a.event_date, a.event_type, &a.traceability_lot_code, &a.input_traceability_lot_code)
+            .cmp(&(&b.event_date, b.event_type, &b.traceability_lot_code, &b.input_traceability_lot_code))
+    });
+}
+
+// Render records as CSV with a header row
+pub fn to_csv(records: &[TraceabilityRecord]) -> String {
+    let mut csv = csv_row(SPREADSHEET_COLUMNS.iter().copied());
+    for record in records {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let fields = [
+            record.event_type.label().to_string(),
+            record.traceability_lot_code.clone(),
+            record.product_description.clone(),
+            optional(&record.quantity),
+            optional(&record.unit_of_measure),
+            record.event_date.clone(),
+            record.location_description.clone(),
+            optional(&record.immediate_previous_source),
+            optional(&record.ship_from_location),
+            optional(&record.ship_to_location),
+            record.traceability_lot_code_source.clone(),
+            optional(&record.input_traceability_lot_code),
+            optional(&record.input_product_description),
+            optional(&record.input_quantity),
+            optional(&record.input_unit_of_measure),
+            record.reference_document_type.clone(),
+            record.reference_document_number.clone(),
+        ];
+        csv.push_str(&csv_row(fields.iter().map(String::as_str)));
+    }
+    csv
+}
+
+fn csv_row<'a, I>(fields: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let fields: Vec<String> = fields.into_iter().map(csv_field).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+// Quote fields containing separators, quotes or line breaks. Fields a
+// spreadsheet would read as a formula are prefixed with an apostrophe.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+// A location as name and address, as far as they are known
+pub fn location_description(name: &str, address: Option<&str>) -> String {
+    match address.map(str::trim).filter(|address| !address.is_empty()) {
+        Some(address) => format!("{}, {}", name, address),
+        None => name.to_string(),
+    }
+}
@@ -0,0 +1,432 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// Error types
+#[derive(Debug, thiserror::Error)]
+pub enum UnitError {
+    #[error("Unknown unit: {0}")]
+    UnknownUnit(String),
+
+    #[error("Invalid quantity: {0}")]
+    InvalidQuantity(String),
+
+    #[error("Cannot convert {from} to {to}")]
+    Incompatible { from: Unit, to: Unit },
+
+    #[error("A density is required to convert {from} to {to}")]
+    MissingDensity { from: Unit, to: Unit },
+
+    #[error("Quantity is too large to convert {from} to {to}")]
+    Overflow { from: Unit, to: Unit },
+
+    #[error("Temperature is too large to convert {from} to {to}")]
+    TemperatureOverflow { from: TemperatureUnit, to: TemperatureUnit },
+}
+
+// The physical dimension a unit measures
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dimension {
+    Mass,
+    Volume,
+    Count,
+}
+
+// Supported units of measure. Mass is based on grams, volume on milliliters
+// and count on single items.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Unit {
+    Milligram,
+    Gram,
+    Kilogram,
+    Ounce,
+    Pound,
+    Milliliter,
+    Liter,
+    Teaspoon,
+    Tablespoon,
+    FluidOunce,
+    Cup,
+    Pint,
+    Quart,
+    Gallon,
+    Each,
+    Dozen,
+}
+
+impl Unit {
+    // Parse a unit symbol or common spelling, e.g. "lbs", "Pounds" or "kg"
+    pub fn parse(input: &str) -> Result<Unit, UnitError> {
+        let normalized = input.trim().to_lowercase().replace('.', "");
+        let unit = match normalized.as_str() {
+            "mg" | "milligram" | "milligrams" => Unit::Milligram,
+            "g" | "gr" | "gram" | "grams" => Unit::Gram,
+            "kg" | "kgs" | "kilo" | "kilos" | "kilogram" | "kilograms" => Unit::Kilogram,
+            "oz" | "ounce" | "ounces" => Unit::Ounce,
+            "lb" | "lbs" | "pound" | "pounds" => Unit::Pound,
+            "ml" | "milliliter" | "milliliters" | "millilitre" | "millilitres" => Unit::Milliliter,
+            "l" | "liter" | "liters" | "litre" | "litres" => Unit::Liter,
+            "tsp" | "teaspoon" | "teaspoons" => Unit::Teaspoon,
+            "tbsp" | "tablespoon" | "tablespoons" => Unit::Tablespoon,
+            "fl oz" | "fl_oz" | "floz" | "fluid ounce" | "fluid ounces" => Unit::FluidOunce,
+            "cup" | "cups" => Unit::Cup,
+            "pt" | "pint" | "pints" => Unit::Pint,
+            "qt" | "quart" | "quarts" => Unit::Quart,
+            "gal" | "gallon" | "gallons" => Unit::Gallon,
+            "ea" | "each" | "pc" | "pcs" | "piece" | "pieces" | "unit" | "units" | "ct" | "count" => Unit::Each,
+            "dz" | "doz" | "dozen" | "dozens" => Unit::Dozen,
+            _ => return Err(UnitError::UnknownUnit(input.trim().to_string())),
+        };
+        Ok(unit)
+    }
+
+    // Canonical symbol used for storage and API output
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Milligram => "mg",
+            Unit::Gram => "g",
+            Unit::Kilogram => "kg",
+            Unit::Ounce => "oz",
+            Unit::Pound => "lb",
+            Unit::Milliliter => "ml",
+            Unit::Liter => "l",
+            Unit::Teaspoon => "tsp",
+            Unit::Tablespoon => "tbsp",
+            Unit::FluidOunce => "fl_oz",
+            Unit::Cup => "cup",
+            Unit::Pint => "pt",
+            Unit::Quart => "qt",
+            Unit::Gallon => "gal",
+            Unit::Each => "each",
+            Unit::Dozen => "dozen",
+        }
+    }
+
+    pub fn dimension(self) -> Dimension {
+        match self {
+            Unit::Milligram | Unit::Gram | Unit::Kilogram | Unit::Ounce | Unit::Pound => Dimension::Mass,
+            Unit::Each | Unit::Dozen => Dimension::Count,
+            _ => Dimension::Volume,
+        }
+    }
+
+    // How many base units (g, ml or each) one of this unit is
+    fn base_factor(self) -> Decimal {
+        match self {
+            Unit::Milligram => Decimal::new(1, 3),
+            Unit::Gram => Decimal::ONE,
+            Unit::Kilogram => Decimal::new(1000, 0),
+            Unit::Ounce => Decimal::new(28_349_523_125, 9),
+            Unit::Pound => Decimal::new(45_359_237, 5),
+            Unit::Milliliter => Decimal::ONE,
+            Unit::Liter => Decimal::new(1000, 0),
+            Unit::Teaspoon => Decimal::new(492_892_159_375, 11),
+            Unit::Tablespoon => Decimal::new(1_478_676_478_125, 11),
+            Unit::FluidOunce => Decimal::new(295_735_295_625, 10),
+            Unit::Cup => Decimal::new(2_365_882_365, 7),
+            Unit::Pint => Decimal::new(473_176_473, 6),
+            Unit::Quart => Decimal::new(946_352_946, 6),
+            Unit::Gallon => Decimal::new(3_785_411_784, 6),
+            Unit::Each => Decimal::ONE,
+            Unit::Dozen => Decimal::new(12, 0),
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl TryFrom<String> for Unit {
+    type Error = UnitError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Unit::parse(&value)
+    }
+}
+
+impl From<Unit> for String {
+    fn from(unit: Unit) -> Self {
+        unit.symbol().to_string()
+    }
+}
+
+// A decimal amount together with its unit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "QuantityInput")]
+pub struct Quantity {
+    pub value: Decimal,
+    pub unit: Unit,
+}
+
+// Quantities are accepted either as text ("20 lbs") or as {"value": 20, "unit": "lb"}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QuantityInput {
+    Text(String),
+    Parts { value: Decimal, unit: String },
+}
+
+impl TryFrom<QuantityInput> for Quantity {
+    type Error = UnitError;
+
+    fn try_from(input: QuantityInput) -> Result<Self, Self::Error> {
+        let quantity = match input {
+            QuantityInput::Text(text) => Quantity::parse(&text)?,
+            QuantityInput::Parts { value, unit } => Quantity::new(value, Unit::parse(&unit)?),
+        };
+        if quantity.value.is_sign_negative() {
+            return Err(UnitError::InvalidQuantity("quantities cannot be negative".to_string()));
+        }
+        Ok(quantity)
+    }
+}
+
+impl Quantity {
+    pub fn new(value: Decimal, unit: Unit) -> Quantity {
+        Quantity { value, unit }
+    }
+
+    // Parse text such as "20 lbs", "1.5kg" or "12 each"
+    pub fn parse(input: &str) -> Result<Quantity, UnitError> {
+        let trimmed = input.trim();
+        let split_at = trimmed
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split_at);
+
+        if number.is_empty() || unit.trim().is_empty() {
+            return Err(UnitError::InvalidQuantity(trimmed.to_string()));
+        }
+
+        let value = Decimal::from_str(number)
+            .map_err(|_| UnitError::InvalidQuantity(trimmed.to_string()))?;
+        Ok(Quantity::new(value, Unit::parse(unit)?))
+    }
+
+    // Build a quantity from database columns, ignoring rows without a recognised unit
+    pub fn from_parts(value: Option<Decimal>, unit: Option<&str>) -> Option<Quantity> {
+        match (value, unit) {
+            (Some(value), Some(unit)) => Unit::parse(unit).ok().map(|unit| Quantity::new(value, unit)),
+            _ => None,
+        }
+    }
+
+    // Convert to another unit. Converting between mass and volume needs the
+    // ingredient density in grams per milliliter.
+    pub fn convert(&self, to: Unit, density: Option<Decimal>) -> Result<Quantity, UnitError> {
+        let overflow = || UnitError::Overflow { from: self.unit, to };
+        let base = self.value.checked_mul(self.unit.base_factor()).ok_or_else(overflow)?;
+        let target_base = match (self.unit.dimension(), to.dimension()) {
+            (from, target) if from == target => Some(base),
+            (Dimension::Mass, Dimension::Volume) => match density {
+                Some(density) if density > Decimal::ZERO => base.checked_div(density),
+                _ => return Err(UnitError::MissingDensity { from: self.unit, to }),
+            },
+            (Dimension::Volume, Dimension::Mass) => match density {
+                Some(density) if density > Decimal::ZERO => base.checked_mul(density),
+                _ => return Err(UnitError::MissingDensity { from: self.unit, to }),
+            },
+            _ => return Err(UnitError::Incompatible { from: self.unit, to }),
+        };
+
+        let value = target_base.and_then(|base| base.checked_div(to.base_factor())).ok_or_else(overflow)?;
+        Ok(Quantity::new(value.round_dp(4).normalize(), to))
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value.normalize(), self.unit)
+    }
+}
+
+// A quantity whose unit may be left out. Older clients send a plain number
+// (20 or "20"); the caller decides which unit it is counted in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum Amount {
+    Quantity(Quantity),
+    Number(Decimal),
+}
+
+impl Amount {
+    pub fn with_default_unit(self, unit: Unit) -> Result<Quantity, UnitError> {
+        match self {
+            Amount::Quantity(quantity) => Ok(quantity),
+            Amount::Number(value) if value.is_sign_negative() => {
+                Err(UnitError::InvalidQuantity("quantities cannot be negative".to_string()))
+            }
+            Amount::Number(value) => Ok(Quantity::new(value, unit)),
+        }
+    }
+}
+
+// Temperature scales used for receiving and storage temperatures
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    // Parse "C", "°F", "celsius" and similar
+    pub fn parse(input: &str) -> Result<TemperatureUnit, UnitError> {
+        let normalized = input.trim().trim_start_matches('°').trim().to_lowercase();
+        match normalized.as_str() {
+            "c" | "celsius" | "centigrade" => Ok(TemperatureUnit::Celsius),
+            "f" | "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+            _ => Err(UnitError::UnknownUnit(input.trim().to_string())),
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "C",
+            TemperatureUnit::Fahrenheit => "F",
+        }
+    }
+}
+
+impl fmt::Display for TemperatureUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl TryFrom<String> for TemperatureUnit {
+    type Error = UnitError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        TemperatureUnit::parse(&value)
+    }
+}
+
+impl From<TemperatureUnit> for String {
+    fn from(unit: TemperatureUnit) -> Self {
+        unit.symbol().to_string()
+    }
+}
+
+// A temperature reading. Accepted as text ("4C", "38 °F") or as
+// {"value": 4, "unit": "C"}.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "TemperatureInput")]
+pub struct Temperature {
+    pub value: Decimal,
+    pub unit: TemperatureUnit,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TemperatureInput {
+    Text(String),
+    Parts { value: Decimal, unit: String },
+}
+
+impl TryFrom<TemperatureInput> for Temperature {
+    type Error = UnitError;
+
+    fn try_from(input: TemperatureInput) -> Result<Self, Self::Error> {
+        match input {
+            TemperatureInput::Text(text) => Temperature::parse(&text),
+            TemperatureInput::Parts { value, unit } => Ok(Temperature::new(value, TemperatureUnit::parse(&unit)?)),
+        }
+    }
+}
+
+impl Temperature {
+    pub fn new(value: Decimal, unit: TemperatureUnit) -> Temperature {
+        Temperature { value, unit }
+    }
+
+    // Parse text such as "4C", "-18 °C" or "38F". The unit is required.
+    pub fn parse(input: &str) -> Result<Temperature, UnitError> {
+        let trimmed = input.trim();
+        let split_at = trimmed
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split_at);
+
+        if number.is_empty() || unit.trim().is_empty() {
+            return Err(UnitError::InvalidQuantity(trimmed.to_string()));
+        }
+
+        let value = Decimal::from_str(number)
+            .map_err(|_| UnitError::InvalidQuantity(trimmed.to_string()))?;
+        Ok(Temperature::new(value, TemperatureUnit::parse(unit)?))
+    }
+
+    // Build a temperature from database columns
+    pub fn from_parts(value: Option<Decimal>, unit: Option<&str>) -> Option<Temperature> {
+        match (value, unit) {
+            (Some(value), Some(unit)) => TemperatureUnit::parse(unit).ok().map(|unit| Temperature::new(value, unit)),
+            _ => None,
+        }
+    }
+
+    pub fn convert(&self, to: TemperatureUnit) -> Result<Temperature, UnitError> {
+        let overflow = || UnitError::TemperatureOverflow { from: self.unit, to };
+        let nine_fifths = Decimal::new(18, 1);
+        let freezing = Decimal::new(32, 0);
+        let value = match (self.unit, to) {
+            (TemperatureUnit::Celsius, TemperatureUnit::Fahrenheit) => self.value
+                .checked_mul(nine_fifths)
+                .and_then(|value| value.checked_add(freezing))
+                .ok_or_else(overflow)?,
+            (TemperatureUnit::Fahrenheit, TemperatureUnit::Celsius) => self.value
+                .checked_sub(freezing)
+                .and_then(|value| value.checked_div(nine_fifths))
+                .ok_or_else(overflow)?,
+            _ => self.value,
+        };
+        Ok(Temperature::new(value.round_dp(2).normalize(), to))
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.value.normalize(), self.unit)
+    }
+}
+
+// A delivery temperature as written on the receiving record. Older clients
+// send free text such as "40" or "chilled"; that text is kept as written
+// but has no reading to check against a checklist.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TemperatureRecord {
+    Reading(Temperature),
+    Text(String),
+}
+
+impl TemperatureRecord {
+    pub fn reading(&self) -> Option<Temperature> {
+        match self {
+            TemperatureRecord::Reading(temperature) => Some(*temperature),
+            TemperatureRecord::Text(_) => None,
+        }
+    }
+}
+
+impl From<Temperature> for TemperatureRecord {
+    fn from(temperature: Temperature) -> TemperatureRecord {
+        TemperatureRecord::Reading(temperature)
+    }
+}
+
+impl fmt::Display for TemperatureRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemperatureRecord::Reading(temperature) => write!(f, "{}", temperature),
+            TemperatureRecord::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
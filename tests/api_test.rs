@@ -1,5 +1,9 @@
-use actix_web::{test, App, web};
-use crud_hz_api::crud_hz_api_main::{configure_app, Organization};
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::body::MessageBody;
+use actix_http::Request;
+use actix_web::{test, App};
+use crud_hz_api::crud_hz_api_main::{configure_app, escalate_overdue_problem_logs, Organization};
+use crud_hz_api::crud_hz_api_main::problemlogs::EscalationSettings;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 use dotenv::dotenv;
 use std::env;
@@ -27,16 +31,78 @@ async fn setup_test_db() -> Pool<Postgres> {
     pool
 }
 
+// Register a fresh organization and return its bearer token and id
+async fn register_test_org<S, B>(app: &S) -> (String, i32)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let req = test::TestRequest::post()
+        .uri("/api/auth/register")
+        .set_json(serde_json::json!({
+            "name": format!("Test Org {}", Uuid::new_v4()),
+            "email": format!("test{}@example.com", Uuid::new_v4()),
+            "password": "password"
+        }))
+        .to_request();
+
+    let response: serde_json::Value = test::call_and_read_body_json(app, req).await;
+    let token = response["token"].as_str().unwrap().to_string();
+    let org_id = response["organization"]["id"].as_i64().unwrap() as i32;
+
+    (token, org_id)
+}
+
+// Put stock on an ingredient lot so batches can consume it
+async fn stock_test_lot<S, B>(app: &S, org_id: i32, ingredient_id: &serde_json::Value, quantity: i32, unit: &str)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let req = test::TestRequest::post()
+        .uri("/api/inventory/adjustments")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "ingredient_id": ingredient_id,
+            "kind": "adjustment",
+            "quantity": quantity,
+            "unit": unit,
+            "reason": "Opening stock"
+        }))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(resp.status(), 201);
+}
+
+// Register an approved supplier so deliveries from it are accepted
+async fn register_test_supplier<S, B>(app: &S, org_id: i32, name: &str) -> serde_json::Value
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let req = test::TestRequest::post()
+        .uri("/api/suppliers")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "name": name,
+            "status": "approved",
+            "approval_date": "2024-01-01"
+        }))
+        .to_request();
+    let supplier: serde_json::Value = test::call_and_read_body_json(app, req).await;
+    supplier["id"].clone()
+}
+
 #[actix_rt::test]
 async fn test_health_endpoint() {
     let db_pool = setup_test_db().await;
     
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new().configure(|config| configure_app(config, db_pool.clone()))
     ).await;
     
     let req = test::TestRequest::get().uri("/health").to_request();
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     assert!(resp.status().is_success());
     
@@ -50,7 +116,7 @@ async fn test_health_endpoint() {
 async fn test_create_organization() {
     let db_pool = setup_test_db().await;
     
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new().configure(|config| configure_app(config, db_pool.clone()))
     ).await;
     
@@ -70,7 +136,7 @@ async fn test_create_organization() {
         .set_json(&org)
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     assert!(resp.status().is_success());
     
@@ -87,7 +153,7 @@ async fn test_create_organization() {
         .uri(&format!("/api/orgs/{}", id))
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     assert!(resp.status().is_success());
     
@@ -103,7 +169,7 @@ async fn test_create_organization() {
 async fn test_update_organization() {
     let db_pool = setup_test_db().await;
     
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new().configure(|config| configure_app(config, db_pool.clone()))
     ).await;
     
@@ -122,7 +188,7 @@ async fn test_update_organization() {
         .set_json(&org)
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     let body = test::read_body(resp).await;
     let created_org: Organization = serde_json::from_slice(&body).unwrap();
     let id = created_org.id.unwrap();
@@ -142,7 +208,7 @@ async fn test_update_organization() {
         .set_json(&updated_org)
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     assert!(resp.status().is_success());
     
@@ -158,7 +224,7 @@ async fn test_update_organization() {
 async fn test_delete_organization() {
     let db_pool = setup_test_db().await;
     
-    let mut app = test::init_service(
+    let app = test::init_service(
         App::new().configure(|config| configure_app(config, db_pool.clone()))
     ).await;
     
@@ -177,7 +243,7 @@ async fn test_delete_organization() {
         .set_json(&org)
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     let body = test::read_body(resp).await;
     let created_org: Organization = serde_json::from_slice(&body).unwrap();
     let id = created_org.id.unwrap();
@@ -187,7 +253,7 @@ async fn test_delete_organization() {
         .uri(&format!("/api/orgs/{}", id))
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     assert_eq!(resp.status(), 204); // No Content
     
@@ -196,7 +262,3308 @@ async fn test_delete_organization() {
         .uri(&format!("/api/orgs/{}", id))
         .to_request();
     
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     assert_eq!(resp.status(), 404); // Not Found
-}
\ No newline at end of file
+}
+
+#[actix_rt::test]
+async fn test_scale_recipe() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    // Create two ingredients for the recipe
+    let mut ingredient_ids = Vec::new();
+    for name in ["Flour", "Sugar"] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingredients")
+            .set_json(serde_json::json!({
+                "lotcode": format!("LOT-{}", Uuid::new_v4()),
+                "name": name,
+                "date": "2025-03-01",
+                "org_id": org_id
+            }))
+            .to_request();
+        let ingredient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        ingredient_ids.push(ingredient["id"].as_i64().unwrap());
+    }
+    // Lines keep the order they are entered in, not ingredient id order
+    ingredient_ids.reverse();
+    
+    // Create a recipe yielding 10 kg
+    let req = test::TestRequest::post()
+        .uri("/api/recipes")
+        .set_json(serde_json::json!({
+            "lotcode": "R-100",
+            "name": "Cookie Dough",
+            "date_made": "2025-03-02",
+            "org_id": org_id,
+            "ingredients": ingredient_ids,
+            "amount_ingredients": [2.5, 6.0],
+            "ingredient_units": ["kg", "kg"],
+            "yield_quantity": 10,
+            "yield_unit": "kg",
+            "steps": [
+                {"step_number": 2, "instruction": "Fold in sugar"},
+                {"step_number": 1, "instruction": "Sift flour"}
+            ],
+            "description": "Test recipe"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let recipe: serde_json::Value = test::read_body_json(resp).await;
+    let recipe_id = recipe["id"].as_i64().unwrap();
+    
+    // Scale it to 25 kg
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/recipes/{}/scale", recipe_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"target_yield": 25, "unit": "kg"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    
+    let scaled: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(scaled["scale_factor"].as_f64().unwrap(), 2.5);
+    assert_eq!(scaled["ingredients"][0]["quantity"].as_f64().unwrap(), 6.25);
+    assert_eq!(scaled["ingredients"][1]["quantity"].as_f64().unwrap(), 15.0);
+    assert_eq!(scaled["steps"][0]["instruction"], "Sift flour");
+    
+    let req = test::TestRequest::get()
+        .uri("/api/recipes")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let recipes: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(recipes[0]["ingredients"], serde_json::json!(ingredient_ids));
+    
+    // A target yield too large to scale to is rejected rather than overflowing
+    sqlx::query("UPDATE recipes SET yield_quantity = 0.5 WHERE id = $1")
+        .bind(recipe_id)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/recipes/{}/scale", recipe_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"target_yield": 7e28}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Target yield is too large");
+    
+    // A target unit cannot be honoured when the yield unit is not a known one
+    sqlx::query("UPDATE recipes SET yield_unit = 'sack' WHERE id = $1")
+        .bind(recipe_id)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/recipes/{}/scale", recipe_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"target_yield": 25, "unit": "kg"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // Each ingredient is listed once
+    let req = test::TestRequest::post()
+        .uri("/api/recipes")
+        .set_json(serde_json::json!({
+            "lotcode": "R-101",
+            "name": "Cookie Dough",
+            "date_made": "2025-03-02",
+            "org_id": org_id,
+            "ingredients": [ingredient_ids[0], ingredient_ids[0]],
+            "amount_ingredients": [2.5, 6.0],
+            "ingredient_units": ["kg", "kg"]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_rt::test]
+async fn test_batch_quantities_are_normalized() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Butter",
+            "date": "2025-03-01",
+            "org_id": org_id
+        }))
+        .to_request();
+    let ingredient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, org_id, &ingredient["id"], 10, "lb").await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": "R-100",
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [ingredient["id"]],
+            "amount_ingredients": [2.5],
+            "ingredient_units": ["Pounds"],
+            "date_made": "2025-03-02",
+            "amount_made": "20 lbs"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let batch: serde_json::Value = test::read_body_json(resp).await;
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    assert_eq!(fetched["amount_made"]["value"].as_f64().unwrap(), 20.0);
+    assert_eq!(fetched["amount_made"]["unit"], "lb");
+    assert_eq!(fetched["amount_ingredients"][0].as_f64().unwrap(), 2.5);
+    assert_eq!(fetched["ingredient_units"][0], "lb");
+    
+    // A lot is listed once per batch
+    let repeated = serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-100",
+        "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+        "ingredients": [ingredient["id"], ingredient["id"]],
+        "amount_ingredients": [1, 1.5],
+        "ingredient_units": ["lb", "lb"],
+        "date_made": "2025-03-02",
+        "amount_made": "20 lbs"
+    });
+    let req = test::TestRequest::post().uri("/api/batches").set_json(&repeated).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}", batch["id"]))
+        .set_json(&repeated)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_rt::test]
+async fn test_recipe_versions_and_diff() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let recipe_lotcode = format!("R-{}", Uuid::new_v4());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Salt",
+            "date": "2025-03-01",
+            "org_id": org_id
+        }))
+        .to_request();
+    let ingredient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, org_id, &ingredient["id"], 10, "kg").await;
+    
+    let mut recipe = serde_json::json!({
+        "lotcode": recipe_lotcode,
+        "name": "Brine",
+        "date_made": "2025-03-02",
+        "org_id": org_id,
+        "ingredients": [ingredient["id"]],
+        "amount_ingredients": [1],
+        "ingredient_units": ["kg"],
+        "yield_quantity": 20,
+        "yield_unit": "l",
+        "steps": [{"step_number": 1, "instruction": "Dissolve salt"}],
+        "description": "Version one"
+    });
+    let req = test::TestRequest::post().uri("/api/recipes").set_json(&recipe).to_request();
+    let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let recipe_id = created["id"].as_i64().unwrap();
+    assert_eq!(created["version"], 1);
+    
+    // A batch made now pins version 1
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": recipe_lotcode,
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [ingredient["id"]],
+            "amount_ingredients": [1],
+            "ingredient_units": ["kg"],
+            "date_made": "2025-03-03",
+            "amount_made": "20 l"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(batch["recipe_version"], 1);
+    
+    // Editing the recipe creates version 2
+    recipe["amount_ingredients"] = serde_json::json!([1.2]);
+    recipe["steps"] = serde_json::json!([
+        {"step_number": 1, "instruction": "Dissolve salt"},
+        {"step_number": 2, "instruction": "Chill to 4C"}
+    ]);
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/recipes/{}", recipe_id))
+        .set_json(&recipe)
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["version"], 2);
+    
+    // The batch still follows version 1, which remains readable
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched["recipe_version"], 1);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/recipes/{}/versions/1", recipe_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let version_one: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(version_one["ingredients"][0]["quantity"].as_f64().unwrap(), 1.0);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/recipes/{}/diff?from=1&to=2", recipe_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let diff: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(diff["changed_ingredients"][0]["to"]["quantity"].as_f64().unwrap(), 1.2);
+    assert_eq!(diff["added_steps"][0]["step_number"], 2);
+    assert_eq!(diff["changed_fields"].as_array().unwrap().len(), 0);
+}
+
+#[actix_rt::test]
+async fn test_batch_flags_undeclared_allergens() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let recipe_lotcode = format!("R-{}", Uuid::new_v4());
+    
+    // The recipe's flour declares wheat; the substitute lot also carries sesame
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Flour",
+            "date": "2025-03-01",
+            "org_id": org_id,
+            "allergens": [{"allergen": "Wheat"}, {"allergen": "soy", "presence": "may_contain"}]
+        }))
+        .to_request();
+    let flour: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, org_id, &flour["id"], 10, "kg").await;
+    assert_eq!(flour["allergens"][0]["allergen"], "soybeans");
+    assert_eq!(flour["allergens"][0]["presence"], "may_contain");
+    assert_eq!(flour["allergens"][1]["major"], true);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Flour",
+            "date": "2025-03-02",
+            "org_id": org_id,
+            "allergens": [{"allergen": "wheat"}, {"allergen": "sesame", "presence": "may_contain"}]
+        }))
+        .to_request();
+    let substitute: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, org_id, &substitute["id"], 10, "kg").await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/recipes")
+        .set_json(serde_json::json!({
+            "lotcode": recipe_lotcode,
+            "name": "Bread",
+            "date_made": "2025-03-02",
+            "org_id": org_id,
+            "ingredients": [flour["id"]],
+            "amount_ingredients": [1],
+            "ingredient_units": ["kg"],
+            "description": "Plain loaf"
+        }))
+        .to_request();
+    let recipe: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(recipe["allergens"].as_array().unwrap().len(), 2);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": recipe_lotcode,
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [substitute["id"]],
+            "amount_ingredients": [1],
+            "ingredient_units": ["kg"],
+            "date_made": "2025-03-03",
+            "amount_made": "1 each"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(batch["undeclared_allergens"].as_array().unwrap().len(), 1);
+    assert_eq!(batch["undeclared_allergens"][0]["allergen"], "sesame");
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched["allergens"].as_array().unwrap().len(), 2);
+    assert_eq!(fetched["undeclared_allergens"][0]["allergen"], "sesame");
+}
+
+#[actix_rt::test]
+async fn test_allergen_changeover_required_between_batches() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let line = format!("LINE-{}", Uuid::new_v4());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "Sanitation Lead", "role": "QA", "org_id": org_id}))
+        .to_request();
+    let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let mut ingredient_ids = Vec::new();
+    for allergens in [serde_json::json!([{"allergen": "peanuts"}]), serde_json::json!([])] {
+        let req = test::TestRequest::post()
+            .uri("/api/ingredients")
+            .set_json(serde_json::json!({
+                "lotcode": format!("LOT-{}", Uuid::new_v4()),
+                "name": "Base",
+                "date": "2025-03-01",
+                "org_id": org_id,
+                "allergens": allergens
+            }))
+            .to_request();
+        let ingredient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        stock_test_lot(&app, org_id, &ingredient["id"], 10, "kg").await;
+        ingredient_ids.push(ingredient["id"].clone());
+    }
+    
+    let batch = |ingredient_id: &serde_json::Value, date_made: &str| serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-UNLISTED",
+        "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+        "ingredients": [ingredient_id],
+        "amount_ingredients": [1],
+        "ingredient_units": ["kg"],
+        "date_made": date_made,
+        "amount_made": "1 kg",
+        "line": line
+    });
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(batch(&ingredient_ids[0], "2025-03-03"))
+        .to_request();
+    let peanut_batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    // Going allergen-free on the same line without a changeover is refused
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(batch(&ingredient_ids[1], "2025-03-04"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/changeovers")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "from_batch_id": peanut_batch["id"],
+            "cleaning_steps": ["Dry clean", "Wash and sanitize"],
+            "verification_method": "allergen_swab",
+            "result": "pass",
+            "employee_id": employee["id"]
+        }))
+        .to_request();
+    let changeover: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(changeover["line"], line.as_str());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(batch(&ingredient_ids[1], "2025-03-04"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let next_batch: serde_json::Value = test::read_body_json(resp).await;
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/changeovers/{}", changeover["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched["to_batch_id"], next_batch["id"]);
+    
+    // Updating the batch keeps the changeover it used
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}", next_batch["id"]))
+        .set_json(batch(&ingredient_ids[1], "2025-03-04"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    
+    // Switching a later batch to allergen-free lots needs a changeover too
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(batch(&ingredient_ids[0], "2025-03-05"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(batch(&ingredient_ids[0], "2025-03-06"))
+        .to_request();
+    let last_batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}", last_batch["id"]))
+        .set_json(batch(&ingredient_ids[1], "2025-03-06"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[actix_rt::test]
+async fn test_inventory_ledger() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    register_test_supplier(&app, org_id, "Sweet Co").await;
+    let lotcode = format!("LOT-{}", Uuid::new_v4());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": lotcode,
+            "name": "Sugar",
+            "date": "2025-03-01",
+            "org_id": org_id
+        }))
+        .to_request();
+    let ingredient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    // Receiving the lot puts it in stock
+    let receipt = |quantity: &str| serde_json::json!({
+        "lotcode": lotcode,
+        "company_name": "Sweet Co",
+        "item_name": "Sugar",
+        "temperature": "20C",
+        "date": "2025-03-01",
+        "org_id": org_id,
+        "quantity": quantity
+    });
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(receipt("10 kg"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let receiving_log: serde_json::Value = test::read_body_json(resp).await;
+    
+    let batch = |amount: i32| serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-UNLISTED",
+        "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+        "ingredients": [ingredient["id"]],
+        "amount_ingredients": [amount],
+        "ingredient_units": ["g"],
+        "date_made": "2025-03-02",
+        "amount_made": "1 kg"
+    });
+    
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(2000)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    // Consuming more than is on hand is refused
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(9000)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/inventory/adjustments")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "ingredient_id": ingredient["id"],
+            "kind": "waste",
+            "quantity": 1,
+            "unit": "kg",
+            "reason": "Bag split"
+        }))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(lot["on_hand"]["value"].as_f64().unwrap(), 7.0);
+    assert_eq!(lot["on_hand"]["unit"], "kg");
+    
+    // Older payloads leave out units; amounts are taken in the lot's unit
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": "R-UNLISTED",
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [ingredient["id"]],
+            "amount_ingredients": [1],
+            "date_made": "2025-03-02",
+            "amount_made": 5
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let legacy_batch: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(legacy_batch["ingredient_units"][0], "kg");
+    assert_eq!(legacy_batch["untracked_stock"], serde_json::json!([]));
+    assert_eq!(legacy_batch["amount_made"]["value"].as_f64().unwrap(), 5.0);
+    assert_eq!(legacy_batch["amount_made"]["unit"], "each");
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", ingredient["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let detail: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(detail["transactions"].as_array().unwrap().len(), 4);
+    assert_eq!(detail["transactions"][1]["kind"], "consumption");
+    
+    let req = test::TestRequest::get()
+        .uri("/api/inventory/ingredients")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let totals: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(totals[0]["name"], "Sugar");
+    assert_eq!(totals[0]["on_hand"]["value"].as_f64().unwrap(), 6.0);
+    
+    // The receipt cannot shrink below what was already used from it
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/receivinglogs/{}", receiving_log["id"]))
+        .set_json(receipt("1 kg"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/receivinglogs/{}", receiving_log["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    // Lots that were never stocked, such as those from before the ledger, are
+    // not tracked, so batches using them can be created and updated but flag them
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Salt",
+            "date": "2025-03-01",
+            "org_id": org_id
+        }))
+        .to_request();
+    let unstocked: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let mut unstocked_batch = batch(100);
+    unstocked_batch["ingredients"] = serde_json::json!([unstocked["id"]]);
+    let req = test::TestRequest::post().uri("/api/batches").set_json(unstocked_batch.clone()).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let untracked_batch: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(untracked_batch["untracked_stock"], serde_json::json!([unstocked["id"]]));
+    
+    unstocked_batch["amount_ingredients"] = serde_json::json!([200]);
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}", untracked_batch["id"]))
+        .set_json(unstocked_batch)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let updated_batch: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(updated_batch["untracked_stock"], serde_json::json!([unstocked["id"]]));
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", unstocked["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let detail: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(detail["transactions"].as_array().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn test_batches_cannot_use_other_organizations_lots() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (_, org_id) = register_test_org(&app).await;
+    let (_, other_org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Sugar",
+            "date": "2025-03-01",
+            "org_id": other_org_id
+        }))
+        .to_request();
+    let foreign_lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, other_org_id, &foreign_lot["id"], 10, "kg").await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Sugar",
+            "date": "2025-03-01",
+            "org_id": org_id
+        }))
+        .to_request();
+    let own_lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, org_id, &own_lot["id"], 10, "kg").await;
+    
+    let batch = |ingredient_id: &serde_json::Value| serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-UNLISTED",
+        "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+        "ingredients": [ingredient_id],
+        "amount_ingredients": [1],
+        "ingredient_units": ["kg"],
+        "date_made": "2025-03-02",
+        "amount_made": "1 kg"
+    });
+    
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(&foreign_lot["id"])).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(&own_lot["id"])).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let own_batch: serde_json::Value = test::read_body_json(resp).await;
+    
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}", own_batch["id"]))
+        .set_json(batch(&foreign_lot["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // The other organization's stock is untouched
+    let req = test::TestRequest::post()
+        .uri("/api/inventory/adjustments")
+        .set_json(serde_json::json!({
+            "org_id": other_org_id,
+            "ingredient_id": foreign_lot["id"],
+            "kind": "waste",
+            "quantity": 10,
+            "unit": "kg",
+            "reason": "Count"
+        }))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(lot["on_hand"]["value"].as_f64().unwrap(), 0.0);
+}
+
+#[actix_rt::test]
+async fn test_receiving_links_ingredient_lots() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    register_test_supplier(&app, org_id, "Mill Co").await;
+    let lotcode = format!("LOT-{}", Uuid::new_v4());
+    
+    let receiving = |ingredient_id: serde_json::Value, date: &str| serde_json::json!({
+        "lotcode": lotcode,
+        "company_name": "Mill Co",
+        "item_name": "Rye Flour",
+        "temperature": "20C",
+        "date": date,
+        "org_id": org_id,
+        "quantity": "25 kg",
+        "ingredient_id": ingredient_id
+    });
+    
+    // An unknown lot code creates the ingredient lot
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(receiving(serde_json::Value::Null, "2025-03-01"))
+        .to_request();
+    let first: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let ingredient_id = first["ingredient_id"].clone();
+    assert!(ingredient_id.is_i64());
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/ingredients/{}", ingredient_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let ingredient: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ingredient["name"], "Rye Flour");
+    assert_eq!(ingredient["lotcode"], lotcode.as_str());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(receiving(ingredient_id.clone(), "2025-03-05"))
+        .to_request();
+    let second: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(second["ingredient_id"], ingredient_id);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/ingredients/{}/receiving", ingredient_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let history: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    assert_eq!(history[0]["id"], second["id"]);
+    
+    // Both receipts are on hand
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", ingredient_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(lot["on_hand"]["value"].as_f64().unwrap(), 50.0);
+    
+    // The same supplier lot code on another item is a separate lot
+    let mut other_item = receiving(serde_json::Value::Null, "2025-03-06");
+    other_item["item_name"] = serde_json::json!("Spelt Flour");
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(other_item)
+        .to_request();
+    let third: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_ne!(third["ingredient_id"], ingredient_id);
+}
+
+#[actix_rt::test]
+async fn test_receiving_enforces_approved_suppliers() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/suppliers")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "name": "Valley Dairy",
+            "contact_email": "qa@valleydairy.example",
+            "status": "approved",
+            "approval_date": "2024-01-15",
+            "audit_expiry": "2025-02-28",
+            "products": ["Cream", "Butter"]
+        }))
+        .to_request();
+    let supplier: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(supplier["products"][1], "Butter");
+    
+    let receiving = |date: &str, override_check: bool| serde_json::json!({
+        "lotcode": format!("LOT-{}", Uuid::new_v4()),
+        "item_name": "Cream",
+        "temperature": "3C",
+        "date": date,
+        "org_id": org_id,
+        "supplier_id": supplier["id"],
+        "override_supplier_check": override_check
+    });
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(receiving("2025-02-01", false))
+        .to_request();
+    let accepted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(accepted["company_name"], "Valley Dairy");
+    assert!(accepted["supplier_warning"].is_null());
+    
+    // After the audit expires deliveries are refused unless overridden
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(receiving("2025-03-10", false))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    // Overriding needs a reason
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(receiving("2025-03-10", true))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let mut overridden = receiving("2025-03-10", true);
+    overridden["override_reason"] = serde_json::json!("Audit booked for next week");
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(&overridden)
+        .to_request();
+    let flagged: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(flagged["supplier_warning"].as_str().unwrap().contains("audit expired"));
+    assert_eq!(flagged["supplier_override_reason"], "Audit booked for next week");
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/receivinglogs/{}", flagged["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched["supplier_id"], supplier["id"]);
+    assert_eq!(fetched["supplier_warning"], flagged["supplier_warning"]);
+    assert_eq!(fetched["supplier_override_reason"], "Audit booked for next week");
+    
+    // Free-text company names are checked against the registry
+    let mut by_name = receiving("2025-02-01", false);
+    by_name["supplier_id"] = serde_json::Value::Null;
+    by_name["company_name"] = serde_json::json!("valley dairy");
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(&by_name)
+        .to_request();
+    let named: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(named["supplier_id"], supplier["id"]);
+    
+    by_name["company_name"] = serde_json::json!("Unknown Creamery");
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(&by_name)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[actix_rt::test]
+async fn test_document_attachments() {
+    let db_pool = setup_test_db().await;
+    let storage_dir = std::env::temp_dir().join(format!("documents-{}", Uuid::new_v4()));
+    std::env::set_var("DOCUMENT_STORAGE_DIR", &storage_dir);
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/suppliers")
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Valley Dairy", "status": "approved", "approval_date": "2019-06-01"}))
+        .to_request();
+    let supplier: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let pdf = b"%PDF-1.7\nletter of guarantee\n%%EOF".to_vec();
+    let req = test::TestRequest::post()
+        .uri(&format!(
+            "/api/documents?entity_type=supplier&entity_id={}&document_type=letter_of_guarantee&file_name=log.pdf&expires_on=2020-01-31",
+            supplier["id"]
+        ))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("Content-Type", "application/pdf"))
+        .set_payload(pdf.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let document: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(document["size_bytes"], pdf.len());
+    assert_eq!(document["expiry_status"], "expired");
+    
+    // Contents must match the declared type
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/documents?entity_type=supplier&entity_id={}&file_name=spec.pdf", supplier["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("Content-Type", "application/pdf"))
+        .set_payload("not a pdf")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 415);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/documents/{}/content", document["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("X-Checksum-SHA256").unwrap().to_str().unwrap(), document["sha256"]);
+    let body = test::read_body(resp).await;
+    assert_eq!(body.to_vec(), pdf);
+    
+    let req = test::TestRequest::get()
+        .uri("/api/documents/expiring?days=30")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let expiring: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(expiring.as_array().unwrap().len(), 1);
+    assert_eq!(expiring[0]["id"], document["id"]);
+    
+    // Deleting the supplier removes its documents and their contents
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/suppliers/{}", supplier["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 204);
+    
+    let req = test::TestRequest::get()
+        .uri("/api/documents")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let remaining: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(remaining.as_array().unwrap().len(), 0);
+    let stored_files = std::fs::read_dir(storage_dir.join(org_id.to_string())).unwrap().count();
+    assert_eq!(stored_files, 0);
+    
+    let _ = std::fs::remove_dir_all(&storage_dir);
+}
+
+#[actix_rt::test]
+async fn test_receiving_checklist_dispositions() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    register_test_supplier(&app, org_id, "Valley Dairy").await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivingchecklists")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "name": "Chilled deliveries",
+            "is_default": true,
+            "items": [
+                {"label": "Temperature 0-5C", "kind": "temperature", "min_temperature": 0, "max_temperature": 5, "temperature_unit": "C"},
+                {"label": "Packaging intact", "kind": "yes_no"},
+                {"label": "Truck clean", "kind": "yes_no"}
+            ]
+        }))
+        .to_request();
+    let checklist: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let packaging = checklist["items"][1]["id"].clone();
+    let truck = checklist["items"][2]["id"].clone();
+    
+    let delivery = |temperature: &str, packaging_ok: bool, disposition: Option<&str>| serde_json::json!({
+        "lotcode": format!("LOT-{}", Uuid::new_v4()),
+        "company_name": "Valley Dairy",
+        "item_name": "Cream",
+        "temperature": temperature,
+        "date": "2025-03-01",
+        "org_id": org_id,
+        "quantity": "10 l",
+        "checks": [
+            {"item_id": packaging, "passed": packaging_ok},
+            {"item_id": truck, "passed": true}
+        ],
+        "disposition": disposition,
+        "disposition_reason": if packaging_ok { None } else { Some("Crushed cases") }
+    });
+    
+    // 38F is within 0-5C, so the default checklist passes
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(delivery("38F", true, None))
+        .to_request();
+    let accepted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(accepted["disposition"], "accept");
+    assert_eq!(accepted["checklist_id"], checklist["id"]);
+    assert_eq!(accepted["temperature"]["unit"], "F");
+    assert!(accepted["checks"].as_array().unwrap().iter().all(|check| check["passed"] == true));
+    
+    // A warm delivery is held for review
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(delivery("9C", true, None))
+        .to_request();
+    let held: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(held["disposition"], "hold");
+    assert_eq!(held["checks"][0]["passed"], false);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/ingredients/{}", held["ingredient_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let held_lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(held_lot["status"], "on_hold");
+    
+    // Held deliveries stay out of stock until the lot is released
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", held["ingredient_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(lot["transactions"].as_array().unwrap().is_empty());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "QA Manager", "role": "QA", "org_id": org_id}))
+        .to_request();
+    let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/ingredients/{}/status", held["ingredient_id"]))
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "status": "released",
+            "reason": "Probed at 4C",
+            "employee_id": employee["id"]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", held["ingredient_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(lot["on_hand"]["value"].as_f64().unwrap(), 10.0);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/receivinglogs/{}", held["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let released: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(released["disposition"], "accept");
+    
+    // Rejecting opens a problem log and keeps the delivery out of stock
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(delivery("2C", false, Some("reject")))
+        .to_request();
+    let rejected: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rejected["disposition"], "reject");
+    assert!(rejected["problem_log_id"].is_number());
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}", rejected["problem_log_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let problem_log: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(problem_log["problem_type"], "Rejected delivery");
+    assert!(problem_log["problem_description"].as_str().unwrap().contains("Packaging intact"));
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", rejected["ingredient_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(lot["transactions"].as_array().unwrap().is_empty());
+    
+    // Rejecting a later delivery of a stocked lot leaves the lot and its stock alone
+    let mut second_delivery = delivery("2C", false, Some("reject"));
+    second_delivery["lotcode"] = accepted["lotcode"].clone();
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(second_delivery)
+        .to_request();
+    let second: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(second["ingredient_id"], accepted["ingredient_id"]);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/inventory/lots/{}", accepted["ingredient_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(lot["on_hand"]["value"].as_f64().unwrap(), 10.0);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/ingredients/{}", accepted["ingredient_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let accepted_lot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(accepted_lot["status"], "released");
+    
+    // Accepting the delivery after all closes its problem log
+    let mut accepted_after_review = delivery("2C", false, Some("accept"));
+    accepted_after_review["lotcode"] = rejected["lotcode"].clone();
+    accepted_after_review["disposition_reason"] = serde_json::json!("Cases repacked by the driver");
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/receivinglogs/{}", rejected["id"]))
+        .set_json(accepted_after_review)
+        .to_request();
+    let reviewed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reviewed["disposition"], "accept");
+    assert!(reviewed["problem_log_id"].is_null());
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}", rejected["problem_log_id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let problem_log: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(problem_log["status"], "closed");
+    
+    // Free-text temperatures from older clients are kept but fail the temperature check
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(delivery("chilled", true, None))
+        .to_request();
+    let legacy: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(legacy["disposition"], "hold");
+    assert_eq!(legacy["checks"][0]["passed"], false);
+    assert!(legacy["temperature"].is_null());
+    
+    // A reading too large to convert to the checklist's scale is refused
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(delivery("-79228162514264337593543950335F", true, None))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // Unanswered checklist items are refused
+    let mut unanswered = delivery("2C", true, None);
+    unanswered["checks"] = serde_json::json!([]);
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(unanswered)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_rt::test]
+async fn test_held_lots_cannot_be_used() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "QA Manager", "role": "QA", "org_id": org_id}))
+        .to_request();
+    let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": "Flour",
+            "date": "2025-03-01",
+            "org_id": org_id
+        }))
+        .to_request();
+    let flour: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(flour["status"], "released");
+    stock_test_lot(&app, org_id, &flour["id"], 10, "kg").await;
+    
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/ingredients/{}/status", flour["id"]))
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "status": "quarantined",
+            "reason": "Supplier recall notice",
+            "employee_id": employee["id"]
+        }))
+        .to_request();
+    let change: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(change["from_status"], "released");
+    assert_eq!(change["employee_name"], "QA Manager");
+    
+    let batch = serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-UNLISTED",
+        "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+        "ingredients": [flour["id"]],
+        "amount_ingredients": [1],
+        "ingredient_units": ["kg"],
+        "date_made": "2025-03-03",
+        "amount_made": "1 kg"
+    });
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(&batch)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["lots"][0]["status"], "quarantined");
+    
+    let req = test::TestRequest::get()
+        .uri("/api/holds")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let holds: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(holds[0]["id"], flour["id"]);
+    assert_eq!(holds[0]["reason"], "Supplier recall notice");
+    
+    // Once released the lot can be used again
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/ingredients/{}/status", flour["id"]))
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "status": "released",
+            "reason": "Supplier confirmed lot unaffected",
+            "employee_id": employee["id"]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(&batch)
+        .to_request();
+    let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(created["status"], "released");
+    
+    // A batch cannot be edited while a lot it already used is on hold
+    for (status, reason) in [("on_hold", "Foreign body complaint"), ("released", "Complaint not substantiated")] {
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/ingredients/{}/status", flour["id"]))
+            .set_json(serde_json::json!({
+                "org_id": org_id,
+                "status": status,
+                "reason": reason,
+                "employee_id": employee["id"]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/batches/{}", created["id"]))
+            .set_json(&batch)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), if status == "on_hold" { 409 } else { 200 });
+    }
+    
+    // Destroyed is final
+    for (status, expected) in [("destroyed", 200), ("released", 409)] {
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/batches/{}/status", created["id"]))
+            .set_json(serde_json::json!({
+                "org_id": org_id,
+                "status": status,
+                "reason": "Failed metal detection",
+                "employee_id": employee["id"]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), expected);
+    }
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}/status", created["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let history: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(history.as_array().unwrap().len(), 1);
+    assert_eq!(history[0]["status"], "destroyed");
+}
+
+#[actix_rt::test]
+async fn test_lot_expiry_and_shelf_life() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/shelflife")
+        .set_json(serde_json::json!({"org_id": org_id, "ingredient_type": "Dairy", "shelf_life_days": 10}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let create_lot = |name: &str, extra: serde_json::Value| {
+        let mut lot = serde_json::json!({
+            "lotcode": format!("LOT-{}", Uuid::new_v4()),
+            "name": name,
+            "received_date": "2025-03-01",
+            "org_id": org_id
+        });
+        for (key, value) in extra.as_object().unwrap() {
+            lot[key] = value.clone();
+        }
+        test::TestRequest::post().uri("/api/ingredients").set_json(lot).to_request()
+    };
+    
+    let today = chrono::Local::now().date_naive();
+    let day = |offset: i64| (today + chrono::Duration::days(offset)).to_string();
+    
+    // The default shelf life counts from the manufactured date when known
+    let req = create_lot("Cream", serde_json::json!({"ingredient_type": "dairy", "manufactured_date": day(-2)}));
+    let cream: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cream["expiry_date"], day(8));
+    
+    let req = create_lot("Strawberries", serde_json::json!({"best_by_date": day(1), "expiry_date": day(2)}));
+    let berries: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = create_lot("Sugar", serde_json::json!({"expiry_date": day(365)}));
+    let sugar: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = create_lot("Milk", serde_json::json!({"expiry_date": day(-1)}));
+    let milk: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    for lot in [&cream, &berries, &sugar, &milk] {
+        stock_test_lot(&app, org_id, &lot["id"], 10, "kg").await;
+    }
+    
+    let batch = |date_made: &str| serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-UNLISTED",
+        "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+        "ingredients": [cream["id"], berries["id"], sugar["id"]],
+        "amount_ingredients": [1, 1, 1],
+        "ingredient_units": ["kg", "kg", "kg"],
+        "date_made": date_made,
+        "amount_made": "3 kg"
+    });
+    
+    let made_batch = batch(&day(1));
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(&made_batch)
+        .to_request();
+    let made: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(made["expiry_date"], day(2));
+    
+    // The strawberries have expired by the third day
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(batch(&day(3)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["lots"][0]["ingredient_id"], berries["id"]);
+    
+    // Moving an existing batch's date_made rechecks the lots it already used
+    let mut moved = made_batch.clone();
+    moved["date_made"] = serde_json::json!(day(3));
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}", made["id"]))
+        .set_json(&moved)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    // A backdated batch cannot draw on a lot that has expired since
+    let mut backdated = batch(&day(-5));
+    backdated["ingredients"] = serde_json::json!([milk["id"]]);
+    backdated["amount_ingredients"] = serde_json::json!([1]);
+    backdated["ingredient_units"] = serde_json::json!(["kg"]);
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(backdated)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::get()
+        .uri("/api/ingredients/expiring?days=30")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let expiring: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&serde_json::Value> = expiring.as_array().unwrap().iter().map(|lot| &lot["ingredient_id"]).collect();
+    assert_eq!(ids, vec![&milk["id"], &berries["id"], &cream["id"]]);
+    assert_eq!(expiring[0]["expired"], true);
+    assert_eq!(expiring[1]["expired"], false);
+    
+    // Lots are still returned with their received date as `date`
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/ingredients/{}", sugar["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched["date"], "2025-03-01");
+    
+    // Shelf lives are capped, and one stored before the cap cannot overflow intake
+    let req = test::TestRequest::post()
+        .uri("/api/shelflife")
+        .set_json(serde_json::json!({"org_id": org_id, "ingredient_type": "Frozen", "shelf_life_days": 2000000000}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    sqlx::query("INSERT INTO shelf_life_defaults (org_id, ingredient_type, shelf_life_days) VALUES ($1, 'Frozen', 2000000000)")
+        .bind(org_id)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let req = create_lot("Peas", serde_json::json!({"ingredient_type": "Frozen"}));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_rt::test]
+async fn test_shipments_trace_batches_to_customers() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let mut customers = Vec::new();
+    for name in ["Corner Grocery", "Hillside Cafe"] {
+        let req = test::TestRequest::post()
+            .uri("/api/customers")
+            .set_json(serde_json::json!({
+                "org_id": org_id,
+                "name": name,
+                "contact_email": "orders@example.com"
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201);
+        let customer: serde_json::Value = test::read_body_json(resp).await;
+        customers.push(customer);
+    }
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": "R-UNLISTED",
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [],
+            "amount_ingredients": [],
+            "ingredient_units": [],
+            "date_made": "2025-04-01",
+            "amount_made": "100 kg"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    for (customer, ship_date, quantity) in [(&customers[0], "2025-04-02", "40 kg"), (&customers[1], "2025-04-03", "50 kg")] {
+        let req = test::TestRequest::post()
+            .uri("/api/shipments")
+            .set_json(serde_json::json!({
+                "org_id": org_id,
+                "customer_id": customer["id"],
+                "ship_date": ship_date,
+                "reference": "PO-1001",
+                "lines": [{"batch_id": batch["id"], "quantity": quantity}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201);
+        let shipment: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(shipment["customer_name"], customer["name"]);
+        assert_eq!(shipment["lines"][0]["batch_lot_code"], batch["batch_lot_code"]);
+    }
+    
+    // Forward trace
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}/shipments", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let trace: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(trace.as_array().unwrap().len(), 2);
+    assert_eq!(trace[0]["customer_name"], "Corner Grocery");
+    assert_eq!(trace[1]["customer_name"], "Hillside Cafe");
+    assert_eq!(trace[1]["quantity"]["unit"], "kg");
+    
+    // Shipped batches and customers with shipments stay traceable
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/batches/{}", batch["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/customers/{}", customers[0]["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/shipments/{}", trace[0]["shipment_id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    // A batch cannot ship more than was made; planned shipments can be deleted
+    let next_month = (chrono::Local::now().date_naive() + chrono::Duration::days(30)).to_string();
+    for (quantity, expected) in [("20 kg", 409), ("10000 g", 201)] {
+        let req = test::TestRequest::post()
+            .uri("/api/shipments")
+            .set_json(serde_json::json!({
+                "org_id": org_id,
+                "customer_id": customers[0]["id"],
+                "ship_date": next_month,
+                "lines": [{"batch_id": batch["id"], "quantity": quantity}]
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), expected);
+        if expected == 201 {
+            let planned: serde_json::Value = test::read_body_json(resp).await;
+            let req = test::TestRequest::delete()
+                .uri(&format!("/api/shipments/{}", planned["id"]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 204);
+        }
+    }
+    
+    // Problem logs name the referenced customer
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogs")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "is_open": true,
+            "date_opened": "2025-04-05",
+            "customer_id": customers[1]["id"],
+            "problem_type": "Complaint",
+            "assigned_to": [],
+            "problem_description": "Foreign material reported",
+            "recall": false
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let problem_log: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(problem_log["customer_id"], customers[1]["id"]);
+    assert_eq!(problem_log["customer_name"], "Hillside Cafe");
+    
+    // Held batches cannot be shipped
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "QA Manager", "role": "QA", "org_id": org_id}))
+        .to_request();
+    let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/batches/{}/status", batch["id"]))
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "status": "on_hold",
+            "reason": "Complaint under investigation",
+            "employee_id": employee["id"]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let req = test::TestRequest::post()
+        .uri("/api/shipments")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "customer_id": customers[0]["id"],
+            "ship_date": "2025-04-06",
+            "lines": [{"batch_id": batch["id"], "quantity": "1 kg"}]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[actix_rt::test]
+async fn test_fsma204_export() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    register_test_supplier(&app, org_id, "Green Leaf Farms").await;
+    let lotcode = format!("LOT-{}", Uuid::new_v4());
+    let batch_lot_code = format!("B-{}", Uuid::new_v4());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs")
+        .set_json(serde_json::json!({
+            "lotcode": lotcode,
+            "company_name": "Green Leaf Farms",
+            "item_name": "Romaine Lettuce",
+            "temperature": "3C",
+            "date": "2025-04-01",
+            "org_id": org_id,
+            "quantity": "50 lb"
+        }))
+        .to_request();
+    let receipt: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": "R-SALAD",
+            "batch_lot_code": batch_lot_code,
+            "ingredients": [receipt["ingredient_id"]],
+            "amount_ingredients": [20],
+            "ingredient_units": ["lb"],
+            "date_made": "2025-04-02",
+            "amount_made": "40 each"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/customers")
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Corner Grocery", "address": "1 Main St, Springfield"}))
+        .to_request();
+    let customer: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/shipments")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "customer_id": customer["id"],
+            "ship_date": "2025-04-03",
+            "reference": "BOL-77",
+            "lines": [{"batch_id": batch["id"], "quantity": "40 each"}]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    // A lot export follows the lot through the batch to the customer
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/traceability/fsma204?lot={}&format=json", lotcode))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let records: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let events: Vec<&str> = records.as_array().unwrap().iter()
+        .map(|record| record["event_type"].as_str().unwrap())
+        .collect();
+    assert_eq!(events, ["receiving", "transformation", "shipping"]);
+    assert_eq!(records[0]["immediate_previous_source"], "Green Leaf Farms");
+    assert_eq!(records[0]["quantity"], "50");
+    assert_eq!(records[1]["traceability_lot_code"], batch_lot_code.as_str());
+    assert_eq!(records[1]["input_traceability_lot_code"], lotcode.as_str());
+    assert_eq!(records[2]["ship_to_location"], "Corner Grocery, 1 Main St, Springfield");
+    assert_eq!(records[2]["reference_document_number"], "BOL-77");
+    
+    // The date range export is the sortable spreadsheet
+    let req = test::TestRequest::get()
+        .uri("/api/traceability/fsma204?from=2025-04-02&to=2025-04-03")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "text/csv; charset=utf-8");
+    let body = test::read_body(resp).await;
+    let csv = String::from_utf8(body.to_vec()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[0].starts_with("Event Type,Traceability Lot Code,Product Description"));
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with(&format!("Transformation,{},R-SALAD,40,each,2025-04-02", batch_lot_code)));
+    assert!(lines[2].contains("\"Corner Grocery, 1 Main St, Springfield\""));
+    
+    // A batch used as an ingredient lot of another batch is traced through both levels
+    let req = test::TestRequest::post()
+        .uri("/api/ingredients")
+        .set_json(serde_json::json!({
+            "lotcode": batch_lot_code,
+            "name": "Salad Mix",
+            "received_date": "2025-04-04",
+            "org_id": org_id
+        }))
+        .to_request();
+    let mix: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    stock_test_lot(&app, org_id, &mix["id"], 10, "each").await;
+    let kit_lot_code = format!("B-{}", Uuid::new_v4());
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": "R-KIT",
+            "batch_lot_code": kit_lot_code,
+            "ingredients": [mix["id"]],
+            "amount_ingredients": [10],
+            "ingredient_units": ["each"],
+            "date_made": "2025-04-04",
+            "amount_made": "10 each"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/traceability/fsma204?lot={}&format=json", lotcode))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let records: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(records.as_array().unwrap().iter().any(|record| record["traceability_lot_code"] == kit_lot_code.as_str()));
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/traceability/fsma204?lot={}&format=json", kit_lot_code))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let records: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let events: Vec<&str> = records.as_array().unwrap().iter()
+        .map(|record| record["event_type"].as_str().unwrap())
+        .collect();
+    // The mix's shipment to the grocery is not part of the kit's trace
+    assert_eq!(events, ["receiving", "transformation", "transformation"]);
+    assert_eq!(records[0]["traceability_lot_code"], lotcode.as_str());
+    
+    let req = test::TestRequest::get()
+        .uri("/api/traceability/fsma204?from=2025-04-02")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_rt::test]
+async fn test_epcis_export_and_import() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let lotcode = format!("LOT-{}", Uuid::new_v4());
+    
+    // A supplier's shipping event becomes a receiving record
+    let document = serde_json::json!({
+        "@context": ["https://ref.gs1.org/standards/epcis/2.0.0/epcis-context.jsonld"],
+        "type": "EPCISDocument",
+        "schemaVersion": "2.0",
+        "creationDate": "2025-04-01T08:00:00.000Z",
+        "epcisBody": {"eventList": [{
+            "type": "ObjectEvent",
+            "eventID": format!("ni:///sha-256;{}", Uuid::new_v4()),
+            "eventTime": "2025-04-01T06:00:00.000Z",
+            "eventTimeZoneOffset": "+00:00",
+            "action": "OBSERVE",
+            "bizStep": "shipping",
+            "disposition": "in_transit",
+            "epcList": [],
+            "quantityList": [{"epcClass": format!("urn:epc:class:lgtin:0614141.011111.{}", lotcode), "quantity": 120, "uom": "KGM"}],
+            "sourceList": [{"type": "owning_party", "source": "urn:epc:id:pgln:0614141.00001"}],
+            "sensorElementList": [{"sensorReport": [{"type": "gs1:Temperature", "value": 3.5, "uom": "CEL"}]}]
+        }]}
+    });
+    // The source party is not a registered supplier
+    let req = test::TestRequest::post()
+        .uri("/api/epcis/import")
+        .set_json(serde_json::json!({"org_id": org_id, "date": "2025-04-02", "document": document}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let supplier_id = register_test_supplier(&app, org_id, "Valley Farms").await;
+    let import = serde_json::json!({"org_id": org_id, "supplier_id": supplier_id, "date": "2025-04-02", "document": document});
+    let req = test::TestRequest::post()
+        .uri("/api/epcis/import")
+        .set_json(&import)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let result: serde_json::Value = test::read_body_json(resp).await;
+    let receipt = &result["created"][0];
+    assert_eq!(receipt["lotcode"], lotcode.as_str());
+    assert_eq!(receipt["company_name"], "Valley Farms");
+    assert_eq!(receipt["quantity"]["unit"], "kg");
+    assert_eq!(receipt["temperature"]["unit"], "C");
+    // The event time wins over the date given with the import
+    assert_eq!(receipt["date"], "2025-04-01");
+    
+    // Importing the same events again records nothing new
+    let req = test::TestRequest::post()
+        .uri("/api/epcis/import")
+        .set_json(&import)
+        .to_request();
+    let again: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(again["created"].as_array().unwrap().len(), 0);
+    assert_eq!(again["skipped"][0]["lotcode"], lotcode.as_str());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/epcis/import")
+        .set_json(serde_json::json!({"org_id": org_id, "document": {"type": "Something"}}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // Shipments to another organization are refused
+    let mut elsewhere = document.clone();
+    let event = &mut elsewhere["epcisBody"]["eventList"][0];
+    event["eventID"] = serde_json::json!(format!("ni:///sha-256;{}", Uuid::new_v4()));
+    event["quantityList"][0]["epcClass"] = serde_json::json!(format!("urn:epc:class:lgtin:0614141.011111.LOT-{}", Uuid::new_v4()));
+    event["destinationList"] = serde_json::json!([{"type": "owning_party", "destination": format!("urn:crud-hz:org:{}", org_id + 1)}]);
+    let req = test::TestRequest::post()
+        .uri("/api/epcis/import")
+        .set_json(serde_json::json!({"org_id": org_id, "supplier_id": supplier_id, "document": elsewhere}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": "R-STEW",
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [receipt["ingredient_id"]],
+            "amount_ingredients": [100],
+            "ingredient_units": ["kg"],
+            "date_made": "2025-04-03",
+            "amount_made": "200 each"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/epcis/events?lot={}", lotcode))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "application/ld+json");
+    let exported: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(exported["type"], "EPCISDocument");
+    assert_eq!(exported["schemaVersion"], "2.0");
+    let events = exported["epcisBody"]["eventList"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["type"], "ObjectEvent");
+    assert_eq!(events[0]["bizStep"], "receiving");
+    assert_eq!(events[0]["quantityList"][0]["uom"], "KGM");
+    assert_eq!(events[0]["eventTime"], "2025-04-01T00:00:00.000Z");
+    assert_eq!(events[1]["type"], "TransformationEvent");
+    assert_eq!(events[1]["inputQuantityList"][0]["epcClass"], events[0]["quantityList"][0]["epcClass"]);
+    assert_eq!(events[1]["outputQuantityList"][0]["uom"], "H87");
+    assert_eq!(events[1]["hz:productName"], "R-STEW");
+}
+
+#[actix_rt::test]
+async fn test_receiving_from_gs1_scan() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (_token, org_id) = register_test_org(&app).await;
+    register_test_supplier(&app, org_id, "Legume Co").await;
+    let lotcode = format!("L{}", &Uuid::new_v4().simple().to_string()[..12]);
+    let scan = format!("]d2010950110153000317251231310200250010{}\u{1d}21SN001", lotcode);
+    
+    // A first scan of an unknown item only pre-fills what the label says
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs/scan")
+        .set_json(serde_json::json!({"org_id": org_id, "scan": scan}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let prefilled: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(prefilled["scan"]["gtin"], "09501101530003");
+    assert_eq!(prefilled["receiving_log"]["lotcode"], lotcode.as_str());
+    assert_eq!(prefilled["receiving_log"]["expiry_date"], "2025-12-31");
+    assert_eq!(prefilled["receiving_log"]["quantity"]["value"], 25.0);
+    assert_eq!(prefilled["missing"], serde_json::json!(["item_name", "company_name or supplier_id", "temperature"]));
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs/scan")
+        .set_json(serde_json::json!({"org_id": org_id, "scan": scan, "create": true}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs/scan")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "scan": scan,
+            "create": true,
+            "item_name": "Chickpeas",
+            "company_name": "Legume Co",
+            "temperature": "18C"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let created: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(created["gtin"], "09501101530003");
+    assert_eq!(created["serial_number"], "SN001");
+    assert_eq!(created["quantity"]["unit"], "kg");
+    
+    // The next case of the same item needs only a temperature
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs/scan")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "scan": "(01)09501101530003(10)NEXTLOT(21)SN002",
+            "create": true,
+            "temperature": "17C"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let next: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(next["item_name"], "Chickpeas");
+    assert_eq!(next["company_name"], "Legume Co");
+    
+    let req = test::TestRequest::post()
+        .uri("/api/receivinglogs/scan")
+        .set_json(serde_json::json!({"org_id": org_id, "scan": "(99)INTERNAL"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Unsupported application identifier (99)");
+}
+
+#[actix_web::test]
+async fn test_batch_labels() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let recipe_lotcode = format!("R-{}", Uuid::new_v4());
+    
+    let recipe = serde_json::json!({
+        "lotcode": recipe_lotcode,
+        "name": "Hummus",
+        "date_made": "2025-04-01",
+        "org_id": org_id,
+        "ingredients": [],
+        "amount_ingredients": [],
+        "ingredient_units": [],
+        "description": "Test recipe",
+        "gtin": "123"
+    });
+    let req = test::TestRequest::post().uri("/api/recipes").set_json(&recipe).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // A GTIN-13 is stored as 14 digits
+    let mut recipe = recipe;
+    recipe["gtin"] = serde_json::json!("9501101530003");
+    let req = test::TestRequest::post().uri("/api/recipes").set_json(&recipe).to_request();
+    let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(created["gtin"], "09501101530003");
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Test Employee",
+            "recipe_lotcode": recipe_lotcode,
+            "batch_lot_code": "HUM-0401",
+            "ingredients": [],
+            "amount_ingredients": [],
+            "ingredient_units": [],
+            "date_made": "2025-04-01",
+            "amount_made": "20 kg"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/labeltemplates")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "name": "Tub lid",
+            "is_default": true,
+            "width_mm": 50.8,
+            "height_mm": 50.8,
+            "header": "Hillside Kitchen",
+            "best_by_days": 14,
+            "link_url": "https://example.com/lots/{lot}"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let template: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(template["dpi"], 203);
+    assert_eq!(template["show_qr"], true);
+    
+    // Shelf lives beyond any real best-by date are refused
+    let req = test::TestRequest::post()
+        .uri("/api/labeltemplates")
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Forever", "best_by_days": 2000000000}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // A second default replaces the first
+    let req = test::TestRequest::post()
+        .uri("/api/labeltemplates")
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Case", "is_default": true, "show_qr": false}))
+        .to_request();
+    let case: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::get()
+        .uri("/api/labeltemplates")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let templates: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(templates[0]["id"], case["id"]);
+    assert_eq!(templates[1]["is_default"], false);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}/label?template_id={}", batch["id"], template["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let zpl = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(zpl.starts_with("^XA"));
+    assert!(zpl.ends_with("^XZ\n") || zpl.ends_with("^XZ"));
+    assert!(zpl.contains("^PW406"));
+    assert!(zpl.contains("Hillside Kitchen"));
+    assert!(zpl.contains("Hummus"));
+    assert!(zpl.contains(">;>80109501101530003"));
+    assert!(zpl.contains("2025-04-15"));
+    assert!(zpl.contains("https://example.com/lots/HUM-0401"));
+    
+    // The default template prints without a QR code
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}/label", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let zpl = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert!(!zpl.contains("^BQN"));
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}/label?format=pdf", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/pdf");
+    let pdf = test::read_body(resp).await;
+    assert!(pdf.starts_with(b"%PDF-1.4"));
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}/label?format=png", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_generated_batch_lot_codes() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let batch = |batch_lot_code: Option<&str>, line: &str| serde_json::json!({
+        "org_id": org_id,
+        "employee": "Test Employee",
+        "recipe_lotcode": "R-UNLISTED",
+        "batch_lot_code": batch_lot_code,
+        "ingredients": [],
+        "amount_ingredients": [],
+        "ingredient_units": [],
+        "date_made": "2025-04-10",
+        "amount_made": "10 kg",
+        "line": line
+    });
+    
+    // Without a scheme the lot code must be given
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(None, "L1")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/lotcodeschemes")
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Production", "template": "{YY}{julian}"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/lotcodeschemes")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "name": "Production",
+            "template": "{YY}{julian}{line}-{seq}",
+            "is_default": true
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let scheme: serde_json::Value = test::read_body_json(resp).await;
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/lotcodeschemes/{}", scheme["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let fetched: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fetched["is_default"], true);
+    
+    // A hand-entered code that the scheme would produce next is skipped
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(Some("25100L1-002"), "L1")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let mut lot_codes = Vec::new();
+    for line in ["L1", "L1", "Line 2"] {
+        let req = test::TestRequest::post().uri("/api/batches").set_json(batch(None, line)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201);
+        let created: serde_json::Value = test::read_body_json(resp).await;
+        lot_codes.push(created["batch_lot_code"].as_str().unwrap().to_string());
+    }
+    assert_eq!(lot_codes, ["25100L1-001", "25100L1-003", "25100LINE2-004"]);
+    
+    // Lot codes are unique within the organization
+    let req = test::TestRequest::post().uri("/api/batches").set_json(batch(Some("25100L1-001"), "L1")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let (_, other_org_id) = register_test_org(&app).await;
+    let mut other = batch(Some("25100L1-001"), "L1");
+    other["org_id"] = serde_json::json!(other_org_id);
+    let req = test::TestRequest::post().uri("/api/batches").set_json(&other).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let ingredient = serde_json::json!({
+        "org_id": org_id,
+        "lotcode": "FLOUR-1",
+        "name": "Flour",
+        "received_date": "2025-04-01"
+    });
+    let req = test::TestRequest::post().uri("/api/ingredients").set_json(&ingredient).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    // Supplier lot codes may repeat across items
+    let mut sugar = ingredient.clone();
+    sugar["name"] = serde_json::json!("Sugar");
+    let req = test::TestRequest::post().uri("/api/ingredients").set_json(&sugar).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    // but not within the same item
+    let req = test::TestRequest::post().uri("/api/ingredients").set_json(&ingredient).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[actix_web::test]
+async fn test_haccp_plan_versions() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/recipes")
+        .set_json(serde_json::json!({
+            "lotcode": format!("R-{}", Uuid::new_v4()),
+            "name": "Chicken Soup",
+            "date_made": "2025-04-01",
+            "org_id": org_id,
+            "ingredients": [],
+            "amount_ingredients": [],
+            "ingredient_units": [],
+            "description": "Test recipe"
+        }))
+        .to_request();
+    let recipe: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let mut plan = serde_json::json!({
+        "org_id": org_id,
+        "name": "Chicken Soup HACCP",
+        "recipe_id": recipe["id"],
+        "product_description": "Ready-to-eat soup, chilled",
+        "steps": [
+            {"step_number": 1, "name": "Receive chicken"},
+            {"step_number": 2, "name": "Cook", "description": "Simmer in kettle"}
+        ],
+        "hazards": [
+            {"step_number": 1, "hazard_type": "biological", "description": "Salmonella", "likelihood": 4, "severity": 4,
+             "control_measure": "Cooking at step 2"},
+            {"step_number": 2, "hazard_type": "physical", "description": "Bone fragments", "likelihood": 2, "severity": 3}
+        ],
+        "ccps": [{
+            "ccp_number": "CCP-1B",
+            "step_number": 2,
+            "hazard": "Salmonella survival",
+            "critical_limits": [{"parameter": "Core temperature", "min_value": 74, "unit": "C"}],
+            "monitoring": {"what": "Core temperature", "how": "Probe thermometer", "frequency": "Every batch"},
+            "corrective_actions": ["Continue cooking", "Hold product"],
+            "verification_activities": [{"activity": "Calibrate thermometer", "frequency": "Weekly"}]
+        }]
+    });
+    let req = test::TestRequest::post().uri("/api/haccpplans").set_json(&plan).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let created: serde_json::Value = test::read_body_json(resp).await;
+    let plan_id = created["id"].as_i64().unwrap();
+    assert_eq!(created["version"], 1);
+    assert_eq!(created["hazards"][0]["risk_score"], 16);
+    assert_eq!(created["ccps"][0]["critical_limits"][0]["min_value"], 74.0);
+    assert!(created["ccps"][0]["critical_limits"][0]["id"].is_i64());
+    assert_eq!(created["ccps"][0]["verification_activities"][0]["frequency"], "Weekly");
+    
+    // A hazard on a step that is not in the plan
+    let mut invalid = plan.clone();
+    invalid["hazards"][0]["step_number"] = serde_json::json!(7);
+    let req = test::TestRequest::post().uri("/api/haccpplans").set_json(&invalid).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    plan["ccps"][0]["critical_limits"][0]["min_value"] = serde_json::json!(75);
+    plan["change_summary"] = serde_json::json!("Raised cooking limit");
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/haccpplans/{}", plan_id))
+        .set_json(&plan)
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["version"], 2);
+    assert_eq!(updated["change_summary"], "Raised cooking limit");
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/haccpplans/{}/versions", plan_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let versions: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(versions.as_array().unwrap().len(), 2);
+    assert_eq!(versions[0]["ccps"][0]["critical_limits"][0]["min_value"], 74.0);
+    assert_eq!(versions[1]["ccps"][0]["critical_limits"][0]["min_value"], 75.0);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/haccpplans?recipe_id={}", recipe["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let plans: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(plans.as_array().unwrap().len(), 1);
+    assert_eq!(plans[0]["version"], 2);
+    
+    let (other_token, _) = register_test_org(&app).await;
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/haccpplans/{}", plan_id))
+        .insert_header(("Authorization", format!("Bearer {}", other_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+    
+    let req = test::TestRequest::delete().uri(&format!("/api/haccpplans/{}", plan_id)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 204);
+}
+
+#[actix_web::test]
+async fn test_ccp_monitoring_deviation_opens_problem_log() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "Line Cook", "role": "cook", "org_id": org_id}))
+        .to_request();
+    let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let plan = serde_json::json!({
+        "org_id": org_id,
+        "name": "Soup HACCP",
+        "steps": [{"step_number": 1, "name": "Cook"}, {"step_number": 2, "name": "Metal detection"}],
+        "ccps": [{
+            "ccp_number": "CCP-1",
+            "step_number": 1,
+            "hazard": "Pathogen survival",
+            "critical_limits": [
+                {"parameter": "Core temperature", "min_value": 74, "unit": "C"},
+                {"parameter": "Hold time", "min_value": 1, "unit": "min"}
+            ],
+            "monitoring": {"what": "Core temperature", "how": "Probe", "frequency": "Every batch"},
+            "corrective_actions": ["Continue cooking"]
+        }, {
+            "ccp_number": "CCP-2",
+            "step_number": 2,
+            "hazard": "Metal fragments",
+            "critical_limits": [{"parameter": "Test pieces rejected", "min_value": 1}],
+            "monitoring": {"what": "Test pieces", "how": "Pass test pieces", "frequency": "Hourly"},
+            "corrective_actions": ["Re-screen product since last good check"]
+        }]
+    });
+    let req = test::TestRequest::post().uri("/api/haccpplans").set_json(&plan).to_request();
+    let plan: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let cooking = &plan["ccps"][0];
+    let detector = &plan["ccps"][1];
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Line Cook",
+            "recipe_lotcode": "R-UNLISTED",
+            "batch_lot_code": format!("B-{}", Uuid::new_v4()),
+            "ingredients": [],
+            "amount_ingredients": [],
+            "ingredient_units": [],
+            "date_made": "2025-04-08",
+            "amount_made": "50 kg"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    // Every limit needs a reading
+    let req = test::TestRequest::post()
+        .uri("/api/ccpmonitoring")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "ccp_id": cooking["id"],
+            "batch_id": batch["id"],
+            "readings": [{"limit_id": cooking["critical_limits"][0]["id"], "value": 75}]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // Checks cannot be logged ahead of time
+    let req = test::TestRequest::post()
+        .uri("/api/ccpmonitoring")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "ccp_id": cooking["id"],
+            "monitored_at": (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339(),
+            "readings": [
+                {"limit_id": cooking["critical_limits"][0]["id"], "value": 75},
+                {"limit_id": cooking["critical_limits"][1]["id"], "value": 2}
+            ]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ccpmonitoring")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "ccp_id": cooking["id"],
+            "batch_id": batch["id"],
+            "employee_id": employee["id"],
+            "monitored_at": "2025-04-08T10:00:00Z",
+            "readings": [
+                {"limit_id": cooking["critical_limits"][0]["id"], "value": "170F"},
+                {"limit_id": cooking["critical_limits"][1]["id"], "value": 2}
+            ]
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let passed: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(passed["within_limits"], true);
+    assert_eq!(passed["readings"][0]["value"], 76.67);
+    assert_eq!(passed["readings"][0]["measured"], "170F");
+    assert!(passed["problem_log_id"].is_null());
+    
+    let req = test::TestRequest::post()
+        .uri("/api/ccpmonitoring")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "ccp_id": detector["id"],
+            "batch_id": batch["id"],
+            "employee_id": employee["id"],
+            "monitored_at": "2025-04-08T11:00:00Z",
+            "readings": [{"limit_id": detector["critical_limits"][0]["id"], "value": false}],
+            "notes": "Ferrous test piece passed through"
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let deviation: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(deviation["within_limits"], false);
+    assert_eq!(deviation["readings"][0]["within_limit"], false);
+    let problem_log_id = deviation["problem_log_id"].as_i64().unwrap();
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let problem_log: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(problem_log["problem_type"], "CCP deviation");
+    assert_eq!(problem_log["batch_id"], batch["id"]);
+    assert_eq!(problem_log["corrective_action_required"], true);
+    assert!(problem_log["problem_description"].as_str().unwrap().starts_with("CCP-2 (Metal fragments) out of critical limits"));
+    
+    // The batch is held
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/batches/{}", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let held: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(held["status"], "on_hold");
+    
+    // Closing needs the corrective action
+    let close = serde_json::json!({"status": "closed", "resolution_notes": "Product re-screened"});
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/transitions", problem_log_id))
+        .set_json(&close)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let mut updated = problem_log.clone();
+    updated["corrective_action"] = serde_json::json!("Re-screened 40 kg since last good check; detector recalibrated");
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/problemlogs/{}", problem_log_id))
+        .set_json(&updated)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/transitions", problem_log_id))
+        .set_json(&close)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/ccpmonitoring?batch_id={}&deviations=true", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let deviations: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(deviations.as_array().unwrap().len(), 1);
+    assert_eq!(deviations[0]["ccp_number"], "CCP-2");
+    
+    // Plans with monitoring records are kept
+    let req = test::TestRequest::delete().uri(&format!("/api/haccpplans/{}", plan["id"])).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[actix_web::test]
+async fn test_capa_blocks_problem_log_closure() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let mut employee_ids = Vec::new();
+    for (name, role) in [("Maintenance Lead", "maintenance"), ("QA Manager", "qa")] {
+        let req = test::TestRequest::post()
+            .uri("/api/employees")
+            .set_json(serde_json::json!({"name": name, "role": role, "org_id": org_id}))
+            .to_request();
+        let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        employee_ids.push(employee["id"].as_i64().unwrap());
+    }
+    let (owner_id, qa_id) = (employee_ids[0], employee_ids[1]);
+    
+    let problem_log = serde_json::json!({
+        "date_opened": "2025-04-01",
+        "customer_name": "Corner Deli",
+        "problem_type": "Foreign matter",
+        "assigned_to": [qa_id],
+        "problem_description": "Rubber fragment found in sauce",
+        "recall": false
+    });
+    let req = test::TestRequest::post().uri("/api/problemlogs").set_json(&problem_log).to_request();
+    let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let problem_log_id = created["id"].as_i64().unwrap();
+    
+    // Owners must work for the organization
+    let mut capa = serde_json::json!({
+        "org_id": org_id,
+        "problem_log_id": problem_log_id,
+        "actions": [{
+            "action_type": "corrective",
+            "description": "Replace worn filler seal",
+            "owner_id": 999999,
+            "due_date": "2025-04-05"
+        }]
+    });
+    let req = test::TestRequest::post().uri("/api/capas").set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    capa["actions"][0]["owner_id"] = serde_json::json!(owner_id);
+    let req = test::TestRequest::post().uri("/api/capas").set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let created_capa: serde_json::Value = test::read_body_json(resp).await;
+    let capa_id = created_capa["id"].as_i64().unwrap();
+    assert_eq!(created_capa["status"], "open");
+    assert_eq!(created_capa["overdue"], true);
+    
+    // Another organization's problem logs cannot be given CAPAs
+    let (_, other_org_id) = register_test_org(&app).await;
+    let mut other_problem_log = problem_log.clone();
+    other_problem_log["org_id"] = serde_json::json!(other_org_id);
+    other_problem_log["assigned_to"] = serde_json::json!([]);
+    let req = test::TestRequest::post().uri("/api/problemlogs").set_json(&other_problem_log).to_request();
+    let other_created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let mut foreign_capa = capa.clone();
+    foreign_capa["problem_log_id"] = other_created["id"].clone();
+    let req = test::TestRequest::post().uri("/api/capas").set_json(&foreign_capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    // The open CAPA holds the problem log open
+    let close = serde_json::json!({"status": "closed", "resolution_notes": "Seal replaced and PM schedule updated"});
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/transitions", problem_log_id))
+        .set_json(&close)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/capas?owner_id={}&overdue=true", owner_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let overdue: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(overdue.as_array().unwrap().len(), 1);
+    
+    // Skipping implementation is not allowed
+    capa["status"] = serde_json::json!("verified");
+    let req = test::TestRequest::put().uri(&format!("/api/capas/{}", capa_id)).set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    capa["status"] = serde_json::json!("in_progress");
+    let req = test::TestRequest::put().uri(&format!("/api/capas/{}", capa_id)).set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    
+    // Implementation needs a root cause and completed actions
+    capa["status"] = serde_json::json!("implemented");
+    let req = test::TestRequest::put().uri(&format!("/api/capas/{}", capa_id)).set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    capa["root_cause"] = serde_json::json!("Seal used past its replacement interval");
+    capa["actions"][0]["completed_on"] = serde_json::json!("2025-04-04");
+    capa["actions"].as_array_mut().unwrap().push(serde_json::json!({
+        "action_type": "preventive",
+        "description": "Add seal replacement to the monthly PM schedule",
+        "owner_id": owner_id,
+        "due_date": "2025-04-10",
+        "completed_on": "2025-04-08"
+    }));
+    let req = test::TestRequest::put().uri(&format!("/api/capas/{}", capa_id)).set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let implemented: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(implemented["status"], "implemented");
+    assert_eq!(implemented["overdue"], false);
+    assert_eq!(implemented["actions"].as_array().unwrap().len(), 2);
+    
+    capa["status"] = serde_json::json!("verified");
+    capa["effectiveness_check"] = serde_json::json!("No fragments found in 30 days of sieve checks");
+    capa["effective"] = serde_json::json!(true);
+    capa["verified_by"] = serde_json::json!(qa_id);
+    let req = test::TestRequest::put().uri(&format!("/api/capas/{}", capa_id)).set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let verified: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(verified["status"], "verified");
+    assert!(verified["verified_on"].is_string());
+    
+    // Verified CAPAs are final
+    let req = test::TestRequest::put().uri(&format!("/api/capas/{}", capa_id)).set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/transitions", problem_log_id))
+        .set_json(&close)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    // No new CAPAs on a closed problem log, and owners of actions are kept
+    capa["status"] = serde_json::Value::Null;
+    let req = test::TestRequest::post().uri("/api/capas").set_json(&capa).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    let req = test::TestRequest::delete().uri(&format!("/api/employees/{}", owner_id)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[actix_web::test]
+async fn test_problem_log_workflow() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "QA Lead", "role": "qa", "org_id": org_id}))
+        .to_request();
+    let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let problem_log = serde_json::json!({
+        "date_opened": "2025-04-10",
+        "customer_name": "Harbor Cafe",
+        "problem_type": "Quality",
+        "assigned_to": [employee["id"]],
+        "problem_description": "Soup arrived separated",
+        "recall": false
+    });
+    let req = test::TestRequest::post().uri("/api/problemlogs").set_json(&problem_log).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let created: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(created["status"], "new");
+    assert_eq!(created["is_open"], true);
+    let problem_log_id = created["id"].as_i64().unwrap();
+    let transition = |body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(&format!("/api/problemlogs/{}/transitions", problem_log_id))
+            .set_json(body)
+            .to_request()
+    };
+    
+    let resp = test::call_service(&app, transition(serde_json::json!({"status": "investigating", "employee_id": employee["id"]}))).await;
+    assert_eq!(resp.status(), 201);
+    
+    // Waiting on the customer needs a comment, resolving needs notes
+    let resp = test::call_service(&app, transition(serde_json::json!({"status": "awaiting_customer"}))).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, transition(serde_json::json!({
+        "status": "awaiting_customer",
+        "comment": "Asked for storage temperatures and photos"
+    }))).await;
+    assert_eq!(resp.status(), 201);
+    let resp = test::call_service(&app, transition(serde_json::json!({"status": "closed", "resolution_notes": "Done"}))).await;
+    assert_eq!(resp.status(), 409);
+    let resp = test::call_service(&app, transition(serde_json::json!({"status": "resolved"}))).await;
+    assert_eq!(resp.status(), 400);
+    let resp = test::call_service(&app, transition(serde_json::json!({
+        "status": "resolved",
+        "resolution_notes": "Product was frozen in transit; customer credited"
+    }))).await;
+    assert_eq!(resp.status(), 201);
+    
+    // Editing the log leaves the workflow fields alone
+    let mut edited = problem_log.clone();
+    edited["is_open"] = serde_json::json!(false);
+    edited["problem_description"] = serde_json::json!("Soup arrived separated after freezing");
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/problemlogs/{}", problem_log_id))
+        .set_json(&edited)
+        .to_request();
+    let updated: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["status"], "resolved");
+    assert_eq!(updated["is_open"], true);
+    assert!(updated["date_resolved"].is_string());
+    
+    // Closing keeps the resolution notes given when resolving
+    let resp = test::call_service(&app, transition(serde_json::json!({"status": "closed"}))).await;
+    assert_eq!(resp.status(), 201);
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let closed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(closed["status"], "closed");
+    assert_eq!(closed["is_open"], false);
+    assert_eq!(closed["resolution_notes"], "Product was frozen in transit; customer credited");
+    assert_eq!(closed["date_resolved"], updated["date_resolved"]);
+    
+    let resp = test::call_service(&app, transition(serde_json::json!({"status": "reopened", "comment": "Happened again"}))).await;
+    assert_eq!(resp.status(), 201);
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let reopened: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reopened["is_open"], true);
+    assert!(reopened["date_resolved"].is_null());
+    assert!(reopened["resolution_notes"].is_null());
+    
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/comments", problem_log_id))
+        .set_json(serde_json::json!({"employee_id": employee["id"], "body": "Checking the carrier's reefer logs"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}/activity", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let activity: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let activity = activity.as_array().unwrap();
+    let kinds: Vec<&str> = activity.iter().map(|entry| entry["activity_type"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["created", "status_change", "status_change", "status_change", "status_change", "status_change", "comment"]);
+    assert_eq!(activity[2]["from_status"], "investigating");
+    assert_eq!(activity[2]["to_status"], "awaiting_customer");
+    assert_eq!(activity[2]["body"], "Asked for storage temperatures and photos");
+    assert_eq!(activity[1]["employee_id"], employee["id"]);
+}
+
+#[actix_web::test]
+async fn test_problem_log_activity_is_scoped_to_organization() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let (other_token, other_org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/employees")
+        .set_json(serde_json::json!({"name": "Outside Auditor", "role": "qa", "org_id": other_org_id}))
+        .to_request();
+    let outsider: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogs")
+        .set_json(serde_json::json!({
+            "date_opened": "2025-04-10",
+            "customer_name": "Harbor Cafe",
+            "problem_type": "Quality",
+            "assigned_to": [],
+            "problem_description": "Label smudged",
+            "recall": false,
+            "org_id": org_id
+        }))
+        .to_request();
+    let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let problem_log_id = created["id"].as_i64().unwrap();
+    
+    // Employees of another organization cannot comment or move the log
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/comments", problem_log_id))
+        .set_json(serde_json::json!({"employee_id": outsider["id"], "body": "Looks fine to me"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/transitions", problem_log_id))
+        .set_json(serde_json::json!({"status": "investigating", "employee_id": outsider["id"]}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}/activity", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", other_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}/activity", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let activity: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(activity.as_array().unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn test_public_complaint_intake() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let slug = format!("org-{}", Uuid::new_v4());
+    
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/orgs/{}/publicslug", org_id))
+        .set_json(serde_json::json!({"public_slug": "not a slug"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/orgs/{}/publicslug", org_id))
+        .set_json(serde_json::json!({"public_slug": slug.to_uppercase()}))
+        .to_request();
+    let saved: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(saved["public_slug"], slug.as_str());
+    
+    // Slugs are unique
+    let (_, other_org_id) = register_test_org(&app).await;
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/orgs/{}/publicslug", other_org_id))
+        .set_json(serde_json::json!({"public_slug": slug}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let lot_code = format!("SOUP-{}", Uuid::new_v4().simple()).to_uppercase();
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Line Cook",
+            "recipe_lotcode": "R-UNLISTED",
+            "batch_lot_code": lot_code,
+            "ingredients": [],
+            "amount_ingredients": [],
+            "ingredient_units": [],
+            "date_made": "2025-04-11",
+            "amount_made": "50 kg"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let complaint = serde_json::json!({
+        "product": "Tomato Soup 500g",
+        "lot_code": lot_code.to_lowercase(),
+        "purchase_location": "Corner Market, Main St",
+        "purchase_date": "2025-04-14",
+        "description": "Found a piece of blue plastic in the soup",
+        "contact_name": "Sam Lee",
+        "contact_email": "sam@example.com"
+    });
+    
+    let req = test::TestRequest::post().uri("/api/public/no-such-org/complaints").set_json(&complaint).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+    
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/public/{}/complaints", slug))
+        .set_json(&complaint)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let receipt: serde_json::Value = test::read_body_json(resp).await;
+    let reference = receipt["reference"].as_i64().unwrap();
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}", reference))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let problem_log: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(problem_log["status"], "new");
+    assert_eq!(problem_log["org_id"], org_id);
+    assert_eq!(problem_log["problem_type"], "Customer complaint");
+    assert_eq!(problem_log["customer_name"], "Sam Lee");
+    assert_eq!(problem_log["batch_id"], batch["id"]);
+    assert!(problem_log["problem_description"].as_str().unwrap().contains("Purchased at: Corner Market, Main St"));
+    
+    // Resubmitting the form returns the same reference
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/public/{}/complaints", slug))
+        .set_json(&complaint)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let resubmitted: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(resubmitted["reference"], reference);
+    
+    // Bots fill in the hidden website field
+    let mut spam = complaint.clone();
+    spam["website"] = serde_json::json!("http://cheap-pills.example");
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/public/{}/complaints", slug))
+        .set_json(&spam)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/complaints?batch_id={}", batch["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let complaints: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(complaints.as_array().unwrap().len(), 1);
+    assert_eq!(complaints[0]["lot_code"], lot_code.to_lowercase());
+    assert_eq!(complaints[0]["purchase_date"], "2025-04-14");
+    
+    // Five submissions an hour per client
+    let mut statuses = Vec::new();
+    for n in 0..3 {
+        let mut another = complaint.clone();
+        another["description"] = serde_json::json!(format!("Soup tasted sour, can number {}", n));
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/public/{}/complaints", slug))
+            .set_json(&another)
+            .to_request();
+        statuses.push(test::call_service(&app, req).await.status().as_u16());
+    }
+    assert_eq!(statuses, [201, 201, 429]);
+}
+
+#[actix_web::test]
+async fn test_complaint_categories_and_trends() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    let (other_token, other_org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/complaintcategories")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "name": "Foreign matter",
+            "alert_threshold": 1,
+            "alert_window_days": 7
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let foreign_matter: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(foreign_matter["active"], true);
+    
+    // Names are unique per organization, ignoring case
+    let req = test::TestRequest::post()
+        .uri("/api/complaintcategories")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"org_id": org_id, "name": "foreign matter"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/complaintcategories")
+        .insert_header(("Authorization", format!("Bearer {}", other_token)))
+        .set_json(serde_json::json!({"org_id": other_org_id, "name": "Taste"}))
+        .to_request();
+    let other_category: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    // Categories are managed by their own organization only
+    let req = test::TestRequest::post()
+        .uri("/api/complaintcategories")
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Texture"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+    let req = test::TestRequest::post()
+        .uri("/api/complaintcategories")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"org_id": other_org_id, "name": "Texture"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/complaintcategories/{}", other_category["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/complaintcategories/{}", other_category["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Taste"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/batches")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "employee": "Line Cook",
+            "recipe_lotcode": "R-UNLISTED",
+            "batch_lot_code": format!("SOUP-{}", Uuid::new_v4().simple()),
+            "ingredients": [],
+            "amount_ingredients": [],
+            "ingredient_units": [],
+            "date_made": chrono::Local::now().date_naive().to_string(),
+            "amount_made": "500 kg"
+        }))
+        .to_request();
+    let batch: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    
+    let today = chrono::Local::now().date_naive().to_string();
+    let problem_log = |category_id: &serde_json::Value| serde_json::json!({
+        "org_id": org_id,
+        "date_opened": today,
+        "customer_name": "Sam Lee",
+        "problem_type": "Customer complaint",
+        "assigned_to": [],
+        "problem_description": "Found plastic in the soup",
+        "recall": false,
+        "batch_id": batch["id"],
+        "category_id": category_id
+    });
+    
+    // Categories of other organizations cannot be used
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogs")
+        .set_json(problem_log(&other_category["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    for _ in 0..2 {
+        let req = test::TestRequest::post()
+            .uri("/api/problemlogs")
+            .set_json(problem_log(&foreign_matter["id"]))
+            .to_request();
+        let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(created["category_id"], foreign_matter["id"]);
+    }
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/complaints/trends?group_by=category&from={}", today))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let trends: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(trends.as_array().unwrap().len(), 1);
+    assert_eq!(trends[0]["group"], "Foreign matter");
+    assert_eq!(trends[0]["complaints"], 2);
+    assert_eq!(trends[0]["units_produced"]["unit"], "kg");
+    assert_eq!(trends[0]["complaints_per_million"], 4000.0);
+    
+    let req = test::TestRequest::get()
+        .uri("/api/complaints/trends?group_by=batch")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let trends: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(trends[0]["id"], batch["id"]);
+    assert_eq!(trends[0]["group"], batch["batch_lot_code"]);
+    
+    // Two complaints in a week is over the threshold of one
+    let req = test::TestRequest::get()
+        .uri("/api/complaints/alerts")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let alerts: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(alerts.as_array().unwrap().len(), 1);
+    assert_eq!(alerts[0]["category_id"], foreign_matter["id"]);
+    assert_eq!(alerts[0]["complaints"], 2);
+    
+    // Categories in use are retired rather than deleted
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/complaintcategories/{}", foreign_matter["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/complaintcategories/{}", foreign_matter["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({"org_id": org_id, "name": "Foreign matter", "active": false}))
+        .to_request();
+    let retired: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(retired["active"], false);
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogs")
+        .set_json(problem_log(&foreign_matter["id"]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_problem_log_sla_escalation() {
+    let db_pool = setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new().configure(|config| configure_app(config, db_pool.clone()))
+    ).await;
+    
+    let (token, org_id) = register_test_org(&app).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogslas")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "problem_type": "Foreign Material",
+            "response_hours": 48,
+            "resolution_hours": 24
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogslas")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "problem_type": "Foreign Material",
+            "response_hours": 4,
+            "resolution_hours": 72
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    
+    let mut employee_ids = Vec::new();
+    for (name, role) in [("Line Lead", "Supervisor"), ("Quinn", "qa")] {
+        let req = test::TestRequest::post()
+            .uri("/api/employees")
+            .set_json(serde_json::json!({"name": name, "role": role, "org_id": org_id}))
+            .to_request();
+        let employee: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        employee_ids.push(employee["id"].as_i64().unwrap() as i32);
+    }
+    let (assignee_id, qa_id) = (employee_ids[0], employee_ids[1]);
+    
+    // SLAs match the problem type ignoring case
+    let req = test::TestRequest::post()
+        .uri("/api/problemlogs")
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "date_opened": chrono::Local::now().date_naive().to_string(),
+            "customer_name": "Sam Lee",
+            "problem_type": "foreign material",
+            "assigned_to": [assignee_id],
+            "problem_description": "Metal shaving found in a jar",
+            "recall": false
+        }))
+        .to_request();
+    let problem_log: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(problem_log["response_due_at"].is_string());
+    assert!(problem_log["resolution_due_at"].is_string());
+    let problem_log_id = problem_log["id"].as_i64().unwrap() as i32;
+    
+    let overdue_logs = || test::TestRequest::get()
+        .uri("/api/problemlogs/overdue")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let overdue: serde_json::Value = test::call_and_read_body_json(&app, overdue_logs()).await;
+    assert_eq!(overdue.as_array().unwrap().len(), 0);
+    
+    // Recorded five hours ago, so the response is an hour late
+    sqlx::query("UPDATE problem_logs SET opened_at = opened_at - INTERVAL '5 hours' WHERE id = $1")
+        .bind(problem_log_id)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/problemlogs/{}", problem_log_id))
+        .set_json(serde_json::json!({
+            "org_id": org_id,
+            "date_opened": problem_log["date_opened"],
+            "customer_name": "Sam Lee",
+            "problem_type": "foreign material",
+            "assigned_to": [assignee_id],
+            "problem_description": "Metal shaving found in a jar",
+            "recall": false
+        }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    
+    let overdue: serde_json::Value = test::call_and_read_body_json(&app, overdue_logs()).await;
+    assert_eq!(overdue.as_array().unwrap().len(), 1);
+    assert_eq!(overdue[0]["id"], problem_log_id);
+    assert_eq!(overdue[0]["breach"], "response");
+    assert_eq!(overdue[0]["assigned_to"], serde_json::json!([assignee_id]));
+    
+    let settings = EscalationSettings {
+        interval: std::time::Duration::from_secs(300),
+        qa_role: "QA".to_string(),
+        qa_delay: chrono::Duration::hours(24),
+    };
+    let now = chrono::Utc::now();
+    escalate_overdue_problem_logs(&db_pool, &settings, now).await.unwrap();
+    // Nothing more until QA's turn
+    escalate_overdue_problem_logs(&db_pool, &settings, now + chrono::Duration::hours(1)).await.unwrap();
+    
+    let notifications_for = |employee_id: i32| test::TestRequest::get()
+        .uri(&format!("/api/notifications?employee_id={}&unread=true", employee_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let notifications: serde_json::Value = test::call_and_read_body_json(&app, notifications_for(assignee_id)).await;
+    assert_eq!(notifications.as_array().unwrap().len(), 1);
+    assert_eq!(notifications[0]["problem_log_id"], problem_log_id);
+    let notifications: serde_json::Value = test::call_and_read_body_json(&app, notifications_for(qa_id)).await;
+    assert_eq!(notifications.as_array().unwrap().len(), 0);
+    
+    escalate_overdue_problem_logs(&db_pool, &settings, now + chrono::Duration::hours(25)).await.unwrap();
+    let notifications: serde_json::Value = test::call_and_read_body_json(&app, notifications_for(qa_id)).await;
+    assert_eq!(notifications.as_array().unwrap().len(), 1);
+    
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/notifications/{}/read", notifications[0]["id"]))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let read: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(read["read_at"].is_string());
+    let notifications: serde_json::Value = test::call_and_read_body_json(&app, notifications_for(qa_id)).await;
+    assert_eq!(notifications.as_array().unwrap().len(), 0);
+    
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/problemlogs/{}/activity", problem_log_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let activity: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let escalations = activity.as_array().unwrap().iter().filter(|entry| entry["activity_type"] == "escalation").count();
+    assert_eq!(escalations, 2);
+    
+    // A comment is the response
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/problemlogs/{}/comments", problem_log_id))
+        .set_json(serde_json::json!({"employee_id": assignee_id, "body": "Pulled the jar lot for inspection"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let overdue: serde_json::Value = test::call_and_read_body_json(&app, overdue_logs()).await;
+    assert_eq!(overdue.as_array().unwrap().len(), 0);
+    
+    // Escalation starts over once the log is responded to, so a missed
+    // resolution notifies the assignee again
+    escalate_overdue_problem_logs(&db_pool, &settings, now + chrono::Duration::hours(68)).await.unwrap();
+    let notifications: serde_json::Value = test::call_and_read_body_json(&app, notifications_for(assignee_id)).await;
+    assert_eq!(notifications.as_array().unwrap().len(), 2);
+    assert!(notifications[0]["message"].as_str().unwrap().contains("resolution"));
+}
@@ -0,0 +1,61 @@
+use chrono::NaiveDate;
+use crud_hz_api::crud_hz_api_main::capa::{self, ActionType, CapaAction, CapaDetails, CapaError, CapaStatus};
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, 4, day).unwrap()
+}
+
+fn action(action_type: ActionType, completed_on: Option<NaiveDate>) -> CapaAction {
+    CapaAction {
+        id: None,
+        action_type,
+        description: "Replace worn seal on filler head".to_string(),
+        owner_id: 1,
+        due_date: date(15),
+        completed_on,
+    }
+}
+
+#[test]
+fn test_capa_workflow() {
+    use CapaStatus::*;
+    let mut details = CapaDetails::default();
+    assert_eq!(capa::check_transition(None, Open, &details), Ok(()));
+    assert_eq!(capa::check_transition(None, InProgress, &details), Err(CapaError::NotOpen));
+    assert_eq!(capa::check_transition(Some(Open), InProgress, &details), Ok(()));
+    assert_eq!(
+        capa::check_transition(Some(Open), Verified, &details),
+        Err(CapaError::InvalidTransition { from: Open, to: Verified })
+    );
+
+    // Implementation needs the root cause and completed corrective actions
+    assert_eq!(capa::check_transition(Some(InProgress), Implemented, &details), Err(CapaError::MissingRootCause));
+    details.root_cause = Some("Seal worn past service interval".to_string());
+    assert_eq!(capa::check_transition(Some(InProgress), Implemented, &details), Err(CapaError::MissingCorrectiveAction));
+    details.actions = vec![action(ActionType::Corrective, Some(date(10))), action(ActionType::Preventive, None)];
+    assert_eq!(capa::check_transition(Some(InProgress), Implemented, &details), Err(CapaError::IncompleteActions));
+    details.actions[1].completed_on = Some(date(12));
+    assert_eq!(capa::check_transition(Some(InProgress), Implemented, &details), Ok(()));
+
+    // Verification needs an effective check by someone
+    assert_eq!(capa::check_transition(Some(Implemented), Verified, &details), Err(CapaError::MissingVerification));
+    details.effectiveness_check = Some("No leaks in 30 days of line checks".to_string());
+    details.verified_by = Some(2);
+    details.effective = Some(false);
+    assert_eq!(capa::check_transition(Some(Implemented), Verified, &details), Err(CapaError::NotEffective));
+    assert_eq!(capa::check_transition(Some(Implemented), InProgress, &details), Ok(()));
+    details.effective = Some(true);
+    assert_eq!(capa::check_transition(Some(Implemented), Verified, &details), Ok(()));
+
+    assert_eq!(capa::check_transition(Some(Verified), Verified, &details), Err(CapaError::Closed(Verified)));
+    assert_eq!(capa::check_transition(Some(Cancelled), Open, &details), Err(CapaError::Closed(Cancelled)));
+    assert!(Verified.is_closed() && Cancelled.is_closed() && !Implemented.is_closed());
+}
+
+#[test]
+fn test_overdue_actions() {
+    let open = action(ActionType::Corrective, None);
+    assert!(!open.is_overdue(date(15)));
+    assert!(open.is_overdue(date(16)));
+    assert!(!action(ActionType::Corrective, Some(date(20))).is_overdue(date(30)));
+}
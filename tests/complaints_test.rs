@@ -0,0 +1,160 @@
+use chrono::NaiveDate;
+use crud_hz_api::crud_hz_api_main::complaints::{
+    self, ComplaintError, ComplaintFact, ComplaintSubmission, ProductionFact, RateLimiter, TrendGrouping,
+};
+use crud_hz_api::crud_hz_api_main::units::{Quantity, Unit};
+use rust_decimal::Decimal;
+use std::time::{Duration, Instant};
+
+fn complaint() -> ComplaintSubmission {
+    ComplaintSubmission {
+        product: "Tomato Soup 500g".to_string(),
+        lot_code: Some("25034A-001".to_string()),
+        description: "Found a piece of plastic in the soup".to_string(),
+        contact_name: "Sam Lee".to_string(),
+        contact_email: Some("sam@example.com".to_string()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_complaint_checks() {
+    assert_eq!(complaints::check_submission(&complaint()), Ok(()));
+
+    let honeypot = ComplaintSubmission { website: Some("http://cheap-pills.example".to_string()), ..complaint() };
+    assert_eq!(complaints::check_submission(&honeypot), Err(ComplaintError::Rejected));
+
+    let links = ComplaintSubmission {
+        description: "Great deals http://a.example http://b.example https://c.example".to_string(),
+        ..complaint()
+    };
+    assert_eq!(complaints::check_submission(&links), Err(ComplaintError::Rejected));
+
+    let short = ComplaintSubmission { description: "bad".to_string(), ..complaint() };
+    assert_eq!(complaints::check_submission(&short), Err(ComplaintError::DescriptionTooShort));
+
+    let anonymous = ComplaintSubmission { contact_email: None, ..complaint() };
+    assert_eq!(complaints::check_submission(&anonymous), Err(ComplaintError::MissingContact));
+    let by_phone = ComplaintSubmission { contact_phone: Some("555 0100".to_string()), ..anonymous };
+    assert_eq!(complaints::check_submission(&by_phone), Ok(()));
+
+    let bad_email = ComplaintSubmission { contact_email: Some("sam@localhost".to_string()), ..complaint() };
+    assert_eq!(complaints::check_submission(&bad_email), Err(ComplaintError::InvalidEmail));
+
+    let long_name = ComplaintSubmission { contact_name: "S".repeat(201), ..complaint() };
+    assert_eq!(complaints::check_submission(&long_name), Err(ComplaintError::FieldTooLong("contact_name")));
+    let long_lot = ComplaintSubmission { lot_code: Some("L".repeat(201)), ..complaint() };
+    assert_eq!(complaints::check_submission(&long_lot), Err(ComplaintError::FieldTooLong("lot_code")));
+}
+
+#[test]
+fn test_public_slugs() {
+    assert_eq!(complaints::normalize_slug(" Acme-Foods "), Ok("acme-foods".to_string()));
+    assert_eq!(complaints::normalize_slug("ab"), Err(ComplaintError::InvalidSlug));
+    assert_eq!(complaints::normalize_slug("-acme"), Err(ComplaintError::InvalidSlug));
+    assert_eq!(complaints::normalize_slug("acme foods"), Err(ComplaintError::InvalidSlug));
+}
+
+#[test]
+fn test_rate_limiter_window() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(60));
+    let start = Instant::now();
+    assert!(limiter.allow("1:10.0.0.1", start));
+    assert!(limiter.allow("1:10.0.0.1", start + Duration::from_secs(10)));
+    assert!(!limiter.allow("1:10.0.0.1", start + Duration::from_secs(20)));
+    // Other clients have their own allowance
+    assert!(limiter.allow("1:10.0.0.2", start + Duration::from_secs(20)));
+    // The first submission leaves the window
+    assert!(limiter.allow("1:10.0.0.1", start + Duration::from_secs(61)));
+    assert!(!limiter.allow("1:10.0.0.1", start + Duration::from_secs(62)));
+}
+
+fn date(text: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+}
+
+fn complaint_about(category: Option<(i32, &str)>, batch_id: Option<i32>, date_opened: &str) -> ComplaintFact {
+    ComplaintFact {
+        category_id: category.map(|(id, _)| id),
+        category: category.map(|(_, name)| name.to_string()),
+        batch_id,
+        product: None,
+        date_opened: date(date_opened),
+    }
+}
+
+fn batch(batch_id: i32, product: &str, date_made: &str, amount: Quantity, in_period: bool) -> ProductionFact {
+    ProductionFact {
+        batch_id,
+        batch_lot_code: format!("LOT-{}", batch_id),
+        product: product.to_string(),
+        date_made: date(date_made),
+        amount: Some(amount),
+        in_period,
+    }
+}
+
+#[test]
+fn test_complaint_trends() {
+    let foreign_matter = Some((1, "Foreign matter"));
+    let complaints = [
+        complaint_about(foreign_matter, Some(10), "2025-03-02"),
+        complaint_about(foreign_matter, Some(11), "2025-04-20"),
+        complaint_about(Some((2, "Taste")), Some(11), "2025-04-21"),
+        ComplaintFact { product: Some("Gift box".to_string()), ..complaint_about(None, None, "2025-04-22") },
+        ComplaintFact { product: Some(" chili ".to_string()), ..complaint_about(None, None, "2025-04-23") },
+    ];
+    let production = [
+        batch(10, "Tomato Soup", "2025-03-01", Quantity::new(Decimal::from(500), Unit::Kilogram), true),
+        batch(11, "Tomato Soup", "2025-04-01", Quantity::new(Decimal::from(1000), Unit::Pound), true),
+        batch(12, "Chili", "2025-04-02", Quantity::new(Decimal::from(2), Unit::Kilogram), true),
+    ];
+
+    let by_category = complaints::complaint_trends(TrendGrouping::Category, &complaints, &production);
+    assert_eq!(by_category.iter().map(|trend| trend.group.as_str()).collect::<Vec<_>>(),
+        ["Foreign matter", "Uncategorized", "Taste"]);
+    assert_eq!(by_category[0].id, Some(1));
+    assert_eq!(by_category[0].complaints, 2);
+    // 500 kg + 1000 lb + 2 kg = 955.5924 kg
+    assert_eq!(by_category[0].units_produced, Some(Quantity::new(Decimal::new(9555924, 4), Unit::Kilogram)));
+    assert_eq!(by_category[0].complaints_per_million, Some(Decimal::new(209294, 2)));
+
+    let by_product = complaints::complaint_trends(TrendGrouping::Product, &complaints, &production);
+    assert_eq!(by_product[0].group, "Tomato Soup");
+    assert_eq!(by_product[0].complaints, 3);
+    // Typed-in products are matched to the products made ignoring case
+    assert_eq!(by_product[1].group, "Chili");
+    assert_eq!(by_product[1].units_produced, Some(Quantity::new(Decimal::from(2), Unit::Kilogram)));
+    assert_eq!(by_product[2].group, "Gift box");
+    assert_eq!(by_product[2].units_produced, None);
+
+    let by_batch = complaints::complaint_trends(TrendGrouping::Batch, &complaints, &production);
+    assert_eq!(by_batch.len(), 2);
+    assert_eq!((by_batch[0].id, by_batch[0].group.as_str(), by_batch[0].complaints), (Some(11), "LOT-11", 2));
+    assert_eq!(by_batch[1].complaints_per_million, Some(Decimal::from(2000)));
+
+    let by_month = complaints::complaint_trends(TrendGrouping::Month, &complaints, &production);
+    assert_eq!(by_month.iter().map(|trend| (trend.group.as_str(), trend.complaints)).collect::<Vec<_>>(),
+        [("2025-03", 1), ("2025-04", 4)]);
+}
+
+#[test]
+fn test_production_totals() {
+    let kilograms = Quantity::new(Decimal::from(5), Unit::Kilogram);
+    let grams = Quantity::new(Decimal::from(500), Unit::Gram);
+    assert_eq!(complaints::total_production([&kilograms, &grams]),
+        Some(Quantity::new(Decimal::new(55, 1), Unit::Kilogram)));
+    // Mass and counts cannot be added up
+    let cans = Quantity::new(Decimal::from(24), Unit::Each);
+    assert_eq!(complaints::total_production([&kilograms, &cans]), None);
+    assert_eq!(complaints::total_production([]), None);
+    assert_eq!(complaints::complaints_per_million(3, Decimal::ZERO), None);
+}
+
+#[test]
+fn test_category_checks() {
+    assert_eq!(complaints::check_category("Foreign matter", Some(3), 30), Ok(()));
+    assert_eq!(complaints::check_category("  ", None, 30), Err(ComplaintError::MissingCategoryName));
+    assert_eq!(complaints::check_category("Taste", Some(0), 30), Err(ComplaintError::InvalidAlertThreshold));
+    assert_eq!(complaints::check_category("Taste", None, 0), Err(ComplaintError::InvalidAlertThreshold));
+}
@@ -0,0 +1,66 @@
+use chrono::NaiveDate;
+use crud_hz_api::crud_hz_api_main::gs1::{self, Gs1Error};
+use crud_hz_api::crud_hz_api_main::units::Unit;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+#[test]
+fn test_parse_raw_and_bracketed_scans() {
+    let raw = gs1::parse("]C1010950110153000317250600310301250010LOT-42\u{1d}21CASE7").unwrap();
+    assert_eq!(raw.gtin.as_deref(), Some("09501101530003"));
+    assert_eq!(raw.expiry_date, NaiveDate::from_ymd_opt(2025, 6, 30));
+    assert_eq!(raw.lot.as_deref(), Some("LOT-42"));
+    assert_eq!(raw.serial.as_deref(), Some("CASE7"));
+    let weight = raw.net_weight.unwrap();
+    assert_eq!(weight.value, Decimal::from_str("12.5").unwrap());
+    assert_eq!(weight.unit, Unit::Kilogram);
+
+    let bracketed = gs1::parse("(01)09501101530003(17)250600(3103)012500(10)LOT-42(21)CASE7").unwrap();
+    assert_eq!(bracketed, raw);
+
+    let pounds = gs1::parse("(3202)002500").unwrap();
+    assert_eq!(pounds.net_weight.unwrap().unit, Unit::Pound);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(gs1::parse("  "), Err(Gs1Error::Empty));
+    assert_eq!(gs1::parse("(00)106141411234567897"), Err(Gs1Error::UnsupportedAi("00".to_string())));
+    assert_eq!(gs1::parse("0109501101530003179912"), Err(Gs1Error::Truncated("17".to_string())));
+    assert!(matches!(gs1::parse("(01)09501101530004"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "01"));
+    assert!(matches!(gs1::parse("(17)251301"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "17"));
+    assert_eq!(gs1::parse("(10)A(10)B"), Err(Gs1Error::Duplicate("10".to_string())));
+}
+
+#[test]
+fn test_parse_rejects_signed_weights() {
+    assert!(matches!(gs1::parse("(3103)-01250"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "3103"));
+    assert!(matches!(gs1::parse("(3202)+02500"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "3202"));
+    assert!(matches!(gs1::parse("3103-01250"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "3103"));
+}
+
+#[test]
+fn test_lot_and_serial_use_the_gs1_character_set() {
+    assert_eq!(gs1::parse("(10)LOT-1/A").unwrap().lot.as_deref(), Some("LOT-1/A"));
+    assert!(matches!(gs1::parse("(10)LOT#1"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "10"));
+    assert!(matches!(gs1::parse("21SN~7"), Err(Gs1Error::InvalidValue { ai, .. }) if ai == "21"));
+}
+
+#[test]
+fn test_unsupported_ais_are_reported_in_full() {
+    assert_eq!(gs1::parse("2411234"), Err(Gs1Error::UnsupportedAi("241".to_string())));
+    assert_eq!(gs1::parse("4001234"), Err(Gs1Error::UnsupportedAi("400".to_string())));
+    assert_eq!(gs1::parse("7003123"), Err(Gs1Error::UnsupportedAi("7003".to_string())));
+}
+
+#[test]
+fn test_expiry_century_window() {
+    let today = NaiveDate::from_ymd_opt(2025, 4, 1).unwrap();
+    assert_eq!(gs1::parse_date("750101", today), NaiveDate::from_ymd_opt(2075, 1, 1));
+    assert_eq!(gs1::parse_date("760101", today), NaiveDate::from_ymd_opt(1976, 1, 1));
+    assert_eq!(gs1::parse_date("990200", today), NaiveDate::from_ymd_opt(1999, 2, 28));
+
+    let late_century = NaiveDate::from_ymd_opt(2090, 1, 1).unwrap();
+    assert_eq!(gs1::parse_date("390101", late_century), NaiveDate::from_ymd_opt(2139, 1, 1));
+    assert_eq!(gs1::parse_date("410101", late_century), NaiveDate::from_ymd_opt(2041, 1, 1));
+}
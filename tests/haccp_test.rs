@@ -0,0 +1,102 @@
+use crud_hz_api::crud_hz_api_main::haccp::{
+    self, CriticalControlPoint, CriticalLimit, Hazard, HazardType, MeasuredValue, MonitoringProcedure, ProcessStep,
+};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+fn steps() -> Vec<ProcessStep> {
+    vec![
+        ProcessStep { step_number: 1, name: "Receive".to_string(), description: None },
+        ProcessStep { step_number: 2, name: "Cook".to_string(), description: None },
+    ]
+}
+
+fn cooking_ccp() -> CriticalControlPoint {
+    CriticalControlPoint {
+        id: None,
+        ccp_number: "CCP-1".to_string(),
+        step_number: 2,
+        hazard: "Salmonella survival".to_string(),
+        critical_limits: vec![CriticalLimit {
+            id: None,
+            parameter: "Core temperature".to_string(),
+            min_value: Some(Decimal::new(74, 0)),
+            max_value: None,
+            unit: Some("C".to_string()),
+        }],
+        monitoring: MonitoringProcedure {
+            what: "Core temperature".to_string(),
+            how: "Probe thermometer".to_string(),
+            frequency: "Every batch".to_string(),
+            responsible: Some("Cook".to_string()),
+        },
+        corrective_actions: vec!["Continue cooking until 74 C is reached".to_string()],
+        verification_activities: vec![],
+    }
+}
+
+#[test]
+fn test_valid_plan_and_risk_score() {
+    let hazard = Hazard {
+        step_number: 2,
+        hazard_type: HazardType::Biological,
+        description: "Salmonella".to_string(),
+        likelihood: 3,
+        severity: 5,
+        risk_score: 0,
+        control_measure: Some("Cooking".to_string()),
+        justification: None,
+    };
+    assert_eq!(haccp::validate_plan(&steps(), std::slice::from_ref(&hazard), &[cooking_ccp()]), Ok(()));
+    assert_eq!(hazard.with_risk_score().risk_score, 15);
+}
+
+#[test]
+fn test_invalid_plans() {
+    let mut unknown_step = cooking_ccp();
+    unknown_step.step_number = 9;
+    assert_eq!(haccp::validate_plan(&steps(), &[], &[unknown_step]), Err("CCP-1 is at unknown step 9".to_string()));
+
+    let mut no_limit = cooking_ccp();
+    no_limit.critical_limits[0].min_value = None;
+    assert!(haccp::validate_plan(&steps(), &[], &[no_limit]).is_err());
+
+    let mut no_action = cooking_ccp();
+    no_action.corrective_actions = vec![" ".to_string()];
+    assert_eq!(
+        haccp::validate_plan(&steps(), &[], &[no_action]),
+        Err("CCP-1 needs at least one corrective action".to_string())
+    );
+
+    assert!(haccp::validate_plan(&steps(), &[], &[cooking_ccp(), cooking_ccp()]).is_err());
+}
+
+#[test]
+fn test_readings_in_limit_units() {
+    let cooking = &cooking_ccp().critical_limits[0];
+    let reading = |value: &str| haccp::value_in_limit_unit(cooking, &MeasuredValue::Text(value.to_string()));
+    assert_eq!(reading("74"), Ok(Decimal::new(74, 0)));
+    // 160 F is 71.11 C
+    let fahrenheit = reading("160F").unwrap();
+    assert_eq!(fahrenheit, Decimal::from_str("71.11").unwrap());
+    assert!(!haccp::within_limit(cooking, fahrenheit));
+    assert!(haccp::within_limit(cooking, reading("75 °C").unwrap()));
+    assert!(reading("hot").is_err());
+    // Readings too large to convert are refused rather than overflowing
+    assert!(reading("-79228162514264337593543950335F").is_err());
+
+    let ph = CriticalLimit {
+        id: None,
+        parameter: "pH".to_string(),
+        min_value: None,
+        max_value: Some(Decimal::from_str("4.6").unwrap()),
+        unit: Some("pH".to_string()),
+    };
+    let value = haccp::value_in_limit_unit(&ph, &MeasuredValue::Text("4.2 pH".to_string())).unwrap();
+    assert!(haccp::within_limit(&ph, value));
+
+    // Pass/fail checks read as 1 or 0 against a minimum of 1
+    let detector = CriticalLimit { min_value: Some(Decimal::ONE), max_value: None, unit: None, ..ph };
+    let failed = haccp::value_in_limit_unit(&detector, &MeasuredValue::Flag(false)).unwrap();
+    assert!(!haccp::within_limit(&detector, failed));
+}
@@ -0,0 +1,82 @@
+use chrono::NaiveDate;
+use crud_hz_api::crud_hz_api_main::labels::{self, LabelContent, LabelError, LabelLayout};
+
+fn content(lot_code: &str) -> LabelContent {
+    LabelContent {
+        product: "Granola_Bar".to_string(),
+        lot_code: lot_code.to_string(),
+        date_made: NaiveDate::from_ymd_opt(2025, 4, 1).unwrap(),
+        best_by: NaiveDate::from_ymd_opt(2025, 10, 1),
+        contains: vec!["Oats".to_string(), "Peanuts".to_string()],
+        may_contain: vec!["Tree nuts".to_string()],
+        gtin: Some("09501101530003".to_string()),
+        link: "https://example.com/batches/7".to_string(),
+    }
+}
+
+fn layout() -> LabelLayout {
+    LabelLayout {
+        width_mm: 101.6,
+        height_mm: 76.2,
+        dpi: 203,
+        header: None,
+        footer: Some("Keep refrigerated".to_string()),
+        show_allergens: true,
+        show_barcode: true,
+        show_qr: true,
+    }
+}
+
+#[test]
+fn test_gs1_element_strings() {
+    let elements = labels::gs1_elements(&content("B-42"));
+    assert_eq!(labels::gs1_text(&elements), "(01)09501101530003(11)250401(15)251001(10)B-42");
+}
+
+#[test]
+fn test_zpl_label() {
+    let zpl = labels::to_zpl(&content("B-42"), &layout()).unwrap();
+    assert!(zpl.starts_with("^XA\n^CI28\n^PW812\n^LL609\n"));
+    assert!(zpl.ends_with("^XZ\n"));
+    // Field data is hex-escaped
+    assert!(zpl.contains("^FDGranola_5FBar^FS"));
+    assert!(zpl.contains("Contains: Oats, Peanuts"));
+    assert!(zpl.contains("May contain: Tree nuts"));
+    // An alphanumeric lot switches the barcode to code set B
+    assert!(zpl.contains("^FD>;>80109501101530003112504011525100110>6B-42^FS"));
+    assert!(zpl.contains("^FDMA,https://example.com/batches/7^FS"));
+
+    let mut bare = layout();
+    bare.show_allergens = false;
+    bare.show_qr = false;
+    let zpl = labels::to_zpl(&content("2504"), &bare).unwrap();
+    assert!(!zpl.contains("Contains"));
+    assert!(!zpl.contains("^BQN"));
+    assert!(zpl.contains(">;>801095011015300031125040115251001102504^FS"));
+}
+
+#[test]
+fn test_pdf_label_and_unencodable_lots() {
+    let pdf = labels::to_pdf(&content("B-42"), &layout()).unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.4"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+
+    assert!(matches!(labels::to_zpl(&content("LOT é"), &layout()), Err(LabelError::UnencodableLot(_))));
+    assert!(matches!(labels::to_pdf(&content("LOT é"), &layout()), Err(LabelError::UnencodableLot(_))));
+}
+
+#[test]
+fn test_lots_outside_gs1_rules_are_unencodable() {
+    assert!(matches!(labels::to_zpl(&content("LOT 42"), &layout()), Err(LabelError::UnencodableLot(_))));
+    assert!(matches!(labels::to_pdf(&content("LOT#42"), &layout()), Err(LabelError::UnencodableLot(_))));
+    assert!(matches!(labels::to_zpl(&content(&"A".repeat(21)), &layout()), Err(LabelError::UnencodableLot(_))));
+    assert!(labels::to_zpl(&content(&"A".repeat(20)), &layout()).is_ok());
+}
+
+#[test]
+fn test_batch_link_encodes_lot() {
+    assert_eq!(
+        labels::batch_link("https://example.com/lots/{lot}?batch={id}", 7, "B 42/1"),
+        "https://example.com/lots/B%2042%2F1?batch=7"
+    );
+}
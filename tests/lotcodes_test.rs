@@ -0,0 +1,25 @@
+use chrono::NaiveDate;
+use crud_hz_api::crud_hz_api_main::lotcodes::{LotCodeError, LotCodeTemplate};
+
+#[test]
+fn test_render_lot_codes() {
+    let date = NaiveDate::from_ymd_opt(2025, 2, 3).unwrap();
+
+    let template = LotCodeTemplate::parse("{YY}{julian}{line}-{seq}").unwrap();
+    assert_eq!(template.render(date, Some("Line 2"), 7).unwrap(), "25034LINE2-007");
+    assert_eq!(template.render(date, None, 7), Err(LotCodeError::MissingLine));
+
+    let template = LotCodeTemplate::parse("B{YYYY}{MM}{DD}/{seq:2}").unwrap();
+    assert_eq!(template.render(date, None, 4).unwrap(), "B20250203/04");
+    // The running number grows past its padding rather than wrapping
+    assert_eq!(template.render(date, None, 123).unwrap(), "B20250203/123");
+}
+
+#[test]
+fn test_invalid_templates() {
+    assert_eq!(LotCodeTemplate::parse("{YY}{julian}"), Err(LotCodeError::MissingSequence));
+    assert_eq!(LotCodeTemplate::parse("{YY}{week}{seq}"), Err(LotCodeError::UnknownPlaceholder("week".to_string())));
+    assert_eq!(LotCodeTemplate::parse("{seq:0}"), Err(LotCodeError::UnknownPlaceholder("seq:0".to_string())));
+    assert_eq!(LotCodeTemplate::parse("{YY}{seq"), Err(LotCodeError::UnclosedPlaceholder));
+    assert_eq!(LotCodeTemplate::parse("LOT {seq}"), Err(LotCodeError::InvalidCharacter(' ')));
+}
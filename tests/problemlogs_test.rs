@@ -0,0 +1,72 @@
+use chrono::{Duration, TimeZone, Utc};
+use crud_hz_api::crud_hz_api_main::problemlogs::{self, EscalationTarget, ProblemStatus, SlaBreach, WorkflowError};
+
+#[test]
+fn test_problem_log_transitions() {
+    use ProblemStatus::*;
+    assert!(New.can_move_to(Investigating) && New.can_move_to(Closed));
+    assert!(Investigating.can_move_to(AwaitingCustomer) && AwaitingCustomer.can_move_to(Investigating));
+    assert!(!Investigating.can_move_to(Investigating));
+    assert!(!Investigating.can_move_to(Closed));
+    assert!(Resolved.can_move_to(Closed) && Resolved.can_move_to(Reopened));
+    assert!(!Closed.can_move_to(Investigating));
+    assert!(Reopened.can_move_to(Investigating));
+    assert!(!Closed.is_open() && Resolved.is_open());
+
+    assert_eq!(
+        problemlogs::check_transition(Closed, Resolved, Some("Fixed"), None),
+        Err(WorkflowError::InvalidTransition { from: Closed, to: Resolved })
+    );
+    assert_eq!(
+        problemlogs::check_transition(Investigating, Resolved, Some("  "), None),
+        Err(WorkflowError::MissingResolutionNotes(Resolved))
+    );
+    assert_eq!(problemlogs::check_transition(Investigating, Resolved, Some("Fixed"), None), Ok(()));
+    assert_eq!(
+        problemlogs::check_transition(Closed, Reopened, None, None),
+        Err(WorkflowError::MissingComment(Reopened))
+    );
+    assert_eq!(problemlogs::check_transition(Closed, Reopened, None, Some("Recurred")), Ok(()));
+}
+
+#[test]
+fn test_sla_checks() {
+    assert_eq!(problemlogs::check_sla("Customer complaint", 4, 72), Ok(()));
+    assert_eq!(problemlogs::check_sla(" ", 4, 72), Err(WorkflowError::InvalidSla));
+    assert_eq!(problemlogs::check_sla("Customer complaint", 0, 72), Err(WorkflowError::InvalidSla));
+    assert_eq!(problemlogs::check_sla("Customer complaint", 48, 24), Err(WorkflowError::ResolutionBeforeResponse));
+}
+
+#[test]
+fn test_sla_breaches() {
+    let opened = Utc.with_ymd_and_hms(2025, 4, 14, 9, 0, 0).unwrap();
+    let response_due = Some(opened + Duration::hours(4));
+    let resolution_due = Some(opened + Duration::hours(72));
+
+    let breach = |status, responded_at, hours| {
+        problemlogs::sla_breach(status, responded_at, response_due, resolution_due, opened + Duration::hours(hours))
+    };
+    assert_eq!(breach(ProblemStatus::New, None, 2), None);
+    assert_eq!(breach(ProblemStatus::New, None, 5), Some(SlaBreach::Response));
+    assert_eq!(breach(ProblemStatus::Investigating, Some(opened + Duration::hours(1)), 5), None);
+    assert_eq!(breach(ProblemStatus::Investigating, Some(opened + Duration::hours(1)), 73), Some(SlaBreach::Resolution));
+    assert_eq!(breach(ProblemStatus::Resolved, None, 73), None);
+    // Logs without an SLA are never overdue
+    assert_eq!(problemlogs::sla_breach(ProblemStatus::New, None, None, None, opened + Duration::days(365)), None);
+}
+
+#[test]
+fn test_escalation_steps() {
+    let now = Utc.with_ymd_and_hms(2025, 4, 15, 9, 0, 0).unwrap();
+    let delay = Duration::hours(24);
+
+    assert_eq!(problemlogs::next_escalation(0, None, true, delay, now), Some(EscalationTarget::AssignedEmployees));
+    // Nobody to tell first, so QA hears straight away
+    assert_eq!(problemlogs::next_escalation(0, None, false, delay, now), Some(EscalationTarget::QualityAssurance));
+    assert_eq!(problemlogs::next_escalation(1, Some(now - Duration::hours(2)), true, delay, now), None);
+    assert_eq!(
+        problemlogs::next_escalation(1, Some(now - Duration::hours(24)), true, delay, now),
+        Some(EscalationTarget::QualityAssurance)
+    );
+    assert_eq!(problemlogs::next_escalation(2, Some(now - Duration::days(7)), true, delay, now), None);
+}
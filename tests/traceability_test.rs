@@ -0,0 +1,56 @@
+use crud_hz_api::crud_hz_api_main::traceability::{self, TraceabilityRecord, TrackingEvent};
+
+fn receiving(lot_code: &str, source: &str) -> TraceabilityRecord {
+    TraceabilityRecord {
+        event_type: TrackingEvent::Receiving,
+        traceability_lot_code: lot_code.to_string(),
+        product_description: "Romaine Lettuce".to_string(),
+        quantity: Some("50".to_string()),
+        unit_of_measure: Some("lb".to_string()),
+        event_date: "2025-04-01".to_string(),
+        location_description: "Test Kitchen".to_string(),
+        immediate_previous_source: Some(source.to_string()),
+        ship_from_location: Some(source.to_string()),
+        ship_to_location: Some("Test Kitchen".to_string()),
+        traceability_lot_code_source: source.to_string(),
+        input_traceability_lot_code: None,
+        input_product_description: None,
+        input_quantity: None,
+        input_unit_of_measure: None,
+        reference_document_type: "Receiving Record".to_string(),
+        reference_document_number: "1".to_string(),
+    }
+}
+
+#[test]
+fn test_csv_quotes_fields() {
+    let csv = traceability::to_csv(&[receiving("LOT-1", "Green Leaf Farms, 2 Farm Rd")]);
+    let lines: Vec<&str> = csv.split("\r\n").collect();
+    assert!(lines[0].starts_with("Event Type,Traceability Lot Code,"));
+    assert!(lines[1].starts_with("Receiving,LOT-1,Romaine Lettuce,50,lb,2025-04-01,"));
+    assert!(lines[1].contains(",\"Green Leaf Farms, 2 Farm Rd\","));
+}
+
+#[test]
+fn test_csv_neutralises_formulas() {
+    let csv = traceability::to_csv(&[receiving("=HYPERLINK(\"http://example.com\")", "@SUM(A1)")]);
+    let row = csv.split("\r\n").nth(1).unwrap();
+    assert!(row.starts_with("Receiving,\"'=HYPERLINK(\"\"http://example.com\"\")\","));
+    assert!(row.contains(",'@SUM(A1),"));
+
+    for prefix in ["+", "-", "\t"] {
+        let csv = traceability::to_csv(&[receiving(&format!("{}1", prefix), "Farm")]);
+        assert!(csv.contains(&format!(",'{}1,", prefix)));
+    }
+    let csv = traceability::to_csv(&[receiving("\r1", "Farm")]);
+    assert!(csv.contains(",\"'\r1\","));
+}
+
+#[test]
+fn test_sort_records_by_date() {
+    let mut later = receiving("LOT-2", "Farm");
+    later.event_date = "2025-04-02".to_string();
+    let mut records = vec![later, receiving("LOT-1", "Farm")];
+    traceability::sort_records(&mut records);
+    assert_eq!(records[0].traceability_lot_code, "LOT-1");
+}
@@ -0,0 +1,117 @@
+use crud_hz_api::crud_hz_api_main::units::{Amount, Quantity, Temperature, TemperatureRecord, TemperatureUnit, Unit, UnitError};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+#[test]
+fn test_parse_quantity_text() {
+    let quantity = Quantity::parse("20 lbs").unwrap();
+    assert_eq!(quantity.value, dec("20"));
+    assert_eq!(quantity.unit, Unit::Pound);
+
+    let quantity = Quantity::parse("1.5kg").unwrap();
+    assert_eq!(quantity.value, dec("1.5"));
+    assert_eq!(quantity.unit, Unit::Kilogram);
+
+    assert!(matches!(Quantity::parse("20"), Err(UnitError::InvalidQuantity(_))));
+    assert!(matches!(Quantity::parse("20 bushels"), Err(UnitError::UnknownUnit(_))));
+}
+
+#[test]
+fn test_deserialize_quantity() {
+    let text: Quantity = serde_json::from_str("\"3 Gallons\"").unwrap();
+    assert_eq!(text.unit, Unit::Gallon);
+
+    let parts: Quantity = serde_json::from_str(r#"{"value": 2.5, "unit": "kg"}"#).unwrap();
+    assert_eq!(parts.value, dec("2.5"));
+    assert_eq!(serde_json::to_value(parts).unwrap()["unit"], "kg");
+
+    assert!(serde_json::from_str::<Quantity>(r#"{"value": -1, "unit": "kg"}"#).is_err());
+}
+
+#[test]
+fn test_deserialize_amount_without_unit() {
+    let number: Amount = serde_json::from_str("20").unwrap();
+    assert_eq!(number.with_default_unit(Unit::Each).unwrap(), Quantity::new(dec("20"), Unit::Each));
+
+    let text: Amount = serde_json::from_str("\"12.5\"").unwrap();
+    assert_eq!(text.with_default_unit(Unit::Kilogram).unwrap(), Quantity::new(dec("12.5"), Unit::Kilogram));
+
+    let quantity: Amount = serde_json::from_str("\"20 lbs\"").unwrap();
+    assert_eq!(quantity.with_default_unit(Unit::Each).unwrap(), Quantity::new(dec("20"), Unit::Pound));
+
+    let negative: Amount = serde_json::from_str("-3").unwrap();
+    assert!(matches!(negative.with_default_unit(Unit::Each), Err(UnitError::InvalidQuantity(_))));
+}
+
+#[test]
+fn test_convert_within_dimension() {
+    let pounds = Quantity::new(dec("10"), Unit::Pound);
+    let kilograms = pounds.convert(Unit::Kilogram, None).unwrap();
+    assert_eq!(kilograms.value, dec("4.5359"));
+
+    let dozen = Quantity::new(dec("3"), Unit::Dozen);
+    assert_eq!(dozen.convert(Unit::Each, None).unwrap().value, dec("36"));
+}
+
+#[test]
+fn test_convert_between_mass_and_volume() {
+    let liters = Quantity::new(dec("2"), Unit::Liter);
+
+    // Honey is roughly 1.42 g/ml
+    let kilograms = liters.convert(Unit::Kilogram, Some(dec("1.42"))).unwrap();
+    assert_eq!(kilograms.value, dec("2.84"));
+
+    assert!(matches!(
+        liters.convert(Unit::Kilogram, None),
+        Err(UnitError::MissingDensity { .. })
+    ));
+    assert!(matches!(
+        liters.convert(Unit::Each, Some(dec("1.42"))),
+        Err(UnitError::Incompatible { .. })
+    ));
+}
+
+#[test]
+fn test_convert_reports_overflow() {
+    let huge = Quantity::new(Decimal::MAX, Unit::Kilogram);
+    assert!(matches!(huge.convert(Unit::Gram, None), Err(UnitError::Overflow { .. })));
+
+    let huge = Quantity::new(Decimal::MAX, Unit::Milliliter);
+    assert!(matches!(huge.convert(Unit::Gram, Some(dec("1.42"))), Err(UnitError::Overflow { .. })));
+}
+
+#[test]
+fn test_parse_and_convert_temperature() {
+    let chilled = Temperature::parse("3.5 °C").unwrap();
+    assert_eq!(chilled.value, dec("3.5"));
+    assert_eq!(chilled.unit, TemperatureUnit::Celsius);
+    assert_eq!(chilled.convert(TemperatureUnit::Fahrenheit).unwrap().value, dec("38.3"));
+
+    let frozen: Temperature = serde_json::from_str(r#"{"value": 0, "unit": "F"}"#).unwrap();
+    assert_eq!(frozen.convert(TemperatureUnit::Celsius).unwrap().value, dec("-17.78"));
+    assert_eq!(Temperature::parse("-18C").unwrap().to_string(), "-18C");
+
+    assert!(matches!(Temperature::parse("cold"), Err(UnitError::InvalidQuantity(_))));
+    assert!(matches!(Temperature::parse("40"), Err(UnitError::InvalidQuantity(_))));
+    assert!(matches!(Temperature::parse("40K"), Err(UnitError::UnknownUnit(_))));
+
+    let scorching = Temperature::new(Decimal::MAX, TemperatureUnit::Celsius);
+    assert!(matches!(scorching.convert(TemperatureUnit::Fahrenheit), Err(UnitError::TemperatureOverflow { .. })));
+    let freezing = Temperature::new(Decimal::MIN, TemperatureUnit::Fahrenheit);
+    assert!(matches!(freezing.convert(TemperatureUnit::Celsius), Err(UnitError::TemperatureOverflow { .. })));
+}
+
+#[test]
+fn test_deserialize_legacy_temperature_text() {
+    let reading: TemperatureRecord = serde_json::from_str(r#""4C""#).unwrap();
+    assert_eq!(reading.reading(), Some(Temperature::new(dec("4"), TemperatureUnit::Celsius)));
+
+    let legacy: TemperatureRecord = serde_json::from_str(r#""chilled""#).unwrap();
+    assert_eq!(legacy, TemperatureRecord::Text("chilled".to_string()));
+    assert_eq!(legacy.reading(), None);
+    assert_eq!(legacy.to_string(), "chilled");
+}